@@ -0,0 +1,103 @@
+//! Activity across clients for a range of transaction ids, for investigating a batch of
+//! transactions flagged by an external system.
+
+use std::{error::Error, ffi::OsString, ops::RangeInclusive};
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::engine::types::{ClientKey, ClientState, Decision, Transaction};
+use crate::io::{fixed_width, fixed_width_opt, output_amount, process_csv};
+use crate::replay::{replay, ReplayObserver};
+use crate::statement::{annotate, describe};
+
+/// A transaction referring to an id in the range, with the balances of its client afterwards.
+#[derive(Debug, Serialize)]
+struct ActivityLineEntity {
+    row: usize,
+    client: ClientKey,
+    #[serde(rename = "type")]
+    tx_type: &'static str,
+    tx: u32,
+    #[serde(serialize_with = "fixed_width_opt")]
+    amount: Option<Decimal>,
+    decision: String,
+    #[serde(serialize_with = "fixed_width")]
+    available: Decimal,
+    #[serde(serialize_with = "fixed_width")]
+    held: Decimal,
+    #[serde(serialize_with = "fixed_width")]
+    total: Decimal,
+    locked: bool,
+    note: String,
+}
+
+struct ActivityCollector<'a> {
+    txs: RangeInclusive<u32>,
+    config: &'a Config,
+    lines: Vec<ActivityLineEntity>,
+}
+
+impl ReplayObserver for ActivityCollector<'_> {
+    fn observe(
+        &mut self,
+        row: usize,
+        transaction: &Transaction,
+        decision: Decision,
+        before: &ClientState,
+        after: &ClientState,
+    ) {
+        let tx = transaction.tx_id().value();
+        if !self.txs.contains(&tx) {
+            return;
+        }
+        let (tx_type, amount) = describe(transaction);
+        let decision = match decision {
+            Decision::Ignore(reason) => format!("{}: {}", decision, reason),
+            _ => decision.to_string(),
+        };
+        self.lines.push(ActivityLineEntity {
+            row,
+            client: transaction.client_id().value(),
+            tx_type,
+            tx,
+            amount: amount.map(|a| output_amount(a.value(), self.config)),
+            decision,
+            available: output_amount(after.available.value().value(), self.config),
+            held: output_amount(after.held.value().value(), self.config),
+            total: output_amount(after.total().value(), self.config),
+            locked: after.is_locked,
+            note: annotate(transaction, before, after),
+        });
+    }
+}
+
+/// Lists as csv every transaction whose id is in the range, across all clients and in input
+/// order, with the decision the engine took and the balances of the client afterwards. Dispute
+/// operations are included by the id of the transaction they refer to.
+pub fn tx_range_activity(
+    csv_path: &OsString,
+    txs: RangeInclusive<u32>,
+    config: &Config,
+) -> Result<String, Box<dyn Error>> {
+    let input = process_csv(csv_path, config)?;
+    let mut collector = ActivityCollector {
+        txs,
+        config,
+        lines: Vec::new(),
+    };
+    replay(
+        &input.transactions,
+        &input.transaction_rows,
+        &input.end_of_day_rows,
+        config,
+        &mut [&mut collector],
+    );
+    let mut wtr = csv::Writer::from_writer(vec![]);
+    for line in collector.lines {
+        wtr.serialize(line)?
+    }
+    wtr.flush()?;
+    Ok(String::from_utf8(wtr.into_inner()?)?)
+}
@@ -0,0 +1,104 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    error::Error,
+    ffi::OsString,
+};
+
+use rust_decimal::Decimal;
+
+use crate::config::Config;
+use crate::engine::types::{AccountActivity, ClientKey, Transaction};
+use crate::error::{RejectedRow, RowRejection};
+use crate::io::{process_csv, ParsedInput};
+use crate::warning::{Warning, WarningKind};
+
+/// Flags deposits larger than the cap, if one is given, and withdrawals larger than the client's
+/// opening balance and overdraft limit plus every deposit before them, as a sign of a corrupted
+/// row. Flagged deposits do not count towards the deposits of the client.
+pub fn suspect_amounts(
+    transactions: &[Transaction],
+    transaction_rows: &[usize],
+    opening_balance: Decimal,
+    deposit_cap: Option<Decimal>,
+    overdraft_limits: &BTreeMap<ClientKey, Decimal>,
+) -> Vec<Warning> {
+    let mut deposits: HashMap<ClientKey, Decimal> = HashMap::new();
+    let mut warnings = Vec::new();
+    for (transaction, row) in transactions.iter().zip(transaction_rows) {
+        let Transaction::Activity(activity) = transaction else {
+            continue;
+        };
+        let client = activity.client_id().value();
+        let amount = activity.amount().value();
+        let kind = match activity {
+            AccountActivity::Deposit(..) if deposit_cap.is_some_and(|cap| amount > cap) => {
+                Some(WarningKind::DepositOverCap)
+            }
+            AccountActivity::Deposit(..) => {
+                let deposited = deposits.entry(client.to_owned()).or_default();
+                *deposited = deposited.saturating_add(amount);
+                None
+            }
+            AccountActivity::Withdrawal(..) => {
+                let deposited = deposits.get(&client).copied().unwrap_or_default();
+                let overdraft = overdraft_limits.get(&client).copied().unwrap_or_default();
+                (amount
+                    > opening_balance
+                        .saturating_add(deposited)
+                        .saturating_add(overdraft))
+                .then_some(WarningKind::WithdrawalExceedsDeposits)
+            }
+        };
+        if let Some(kind) = kind {
+            warnings.push(Warning {
+                row: *row,
+                client,
+                tx: activity.tx_id().value(),
+                kind,
+            });
+        }
+    }
+    warnings
+}
+
+/// Moves the transactions flagged by [`suspect_amounts`] from the input to its rejected rows.
+pub fn reject_suspect_amounts(input: ParsedInput, suspect: &[Warning]) -> ParsedInput {
+    let suspect_rows: HashSet<usize> = suspect.iter().map(|w| w.row).collect();
+    let (transactions, transaction_rows) = input
+        .transactions
+        .into_iter()
+        .zip(input.transaction_rows)
+        .filter(|(_, row)| !suspect_rows.contains(row))
+        .unzip();
+    let mut rejected_rows = input.rejected_rows;
+    rejected_rows.extend(suspect.iter().map(|w| RejectedRow {
+        row: w.row,
+        reason: RowRejection::SuspectAmount,
+    }));
+    rejected_rows.sort_by_key(|r| r.row);
+    ParsedInput {
+        transactions,
+        transaction_rows,
+        rejected_rows,
+        ..input
+    }
+}
+
+/// Reads the input, rejecting rows with suspect amounts when configured to.
+pub fn process_checked_csv(
+    csv_path: &OsString,
+    config: &Config,
+) -> Result<ParsedInput, Box<dyn Error>> {
+    let input = process_csv(csv_path, config)?;
+    if !config.reject_suspect_amounts {
+        return Ok(input);
+    }
+    let suspect = suspect_amounts(
+        &input.transactions,
+        &input.transaction_rows,
+        config.opening_balance,
+        config.deposit_cap,
+        &config.overdraft_limits,
+    );
+    Ok(reject_suspect_amounts(input, &suspect))
+}
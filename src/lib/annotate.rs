@@ -0,0 +1,112 @@
+//! Row level feedback for partners: the input file re-emitted with the outcome of each row.
+
+use std::{collections::HashMap, error::Error, ffi::OsString, io::Write};
+
+use crate::amount_checks::process_checked_csv;
+use crate::config::Config;
+use crate::engine::types::{ClientState, Decision, Transaction};
+use crate::paths::open_input;
+use crate::replay::{replay, ReplayObserver};
+use crate::statement::annotate;
+
+/// Describes what became of a transaction, using the reason the engine gave for ignoring it or
+/// otherwise the notes of a client statement. A row which
+/// produced several transactions, e.g. a batch of disputes, gets one outcome per transaction.
+#[derive(Default)]
+struct OutcomeCollector {
+    outcomes: HashMap<usize, Vec<String>>,
+}
+
+fn outcome(
+    transaction: &Transaction,
+    decision: Decision,
+    before: &ClientState,
+    after: &ClientState,
+) -> String {
+    let note = annotate(transaction, before, after);
+    if let Decision::Ignore(reason) = decision {
+        format!("ignored: {}", reason)
+    } else if decision == Decision::IgnoreRepeat {
+        String::from("ignored: repeated operation")
+    } else if ["ignored", "rejected", "queued"]
+        .iter()
+        .any(|prefix| note.starts_with(prefix))
+    {
+        note
+    } else if !decision.is_applied() {
+        String::from("ignored")
+    } else if note.is_empty() {
+        String::from("applied")
+    } else {
+        format!("applied: {}", note)
+    }
+}
+
+impl ReplayObserver for OutcomeCollector {
+    fn observe(
+        &mut self,
+        row: usize,
+        transaction: &Transaction,
+        decision: Decision,
+        before: &ClientState,
+        after: &ClientState,
+    ) {
+        self.outcomes
+            .entry(row)
+            .or_default()
+            .push(outcome(transaction, decision, before, after));
+    }
+}
+
+/// Writes the input with an `outcome` column appended to every row: whether it was applied,
+/// rejected or ignored, and why. Rows are copied as they were read, streamed from a second read
+/// of the input so that only the outcomes are held in memory.
+pub fn annotate_input(
+    csv_path: &OsString,
+    config: &Config,
+    writer: impl Write,
+) -> Result<(), Box<dyn Error>> {
+    let input = process_checked_csv(csv_path, config)?;
+    let mut collector = OutcomeCollector::default();
+    replay(
+        &input.transactions,
+        &input.transaction_rows,
+        &input.end_of_day_rows,
+        config,
+        &mut [&mut collector],
+    );
+    let mut outcomes: HashMap<usize, String> = collector
+        .outcomes
+        .into_iter()
+        .map(|(row, outcomes)| (row, outcomes.join("; ")))
+        .collect();
+    for rejected in input.rejected_rows {
+        outcomes.insert(rejected.row, format!("rejected: {}", rejected.reason));
+    }
+    // unparseable rows are only known by their line, and only when quarantined
+    let unparseable: HashMap<u64, String> = input
+        .quarantined_rows
+        .into_iter()
+        .map(|row| (row.line, format!("rejected: {}", row.reason)))
+        .collect();
+
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(open_input(csv_path)?);
+    let mut writer = csv::WriterBuilder::new().flexible(true).from_writer(writer);
+    let mut headers = reader.byte_headers()?.clone();
+    headers.push_field(b"outcome");
+    writer.write_byte_record(&headers)?;
+    for (idx, record) in reader.into_byte_records().enumerate() {
+        let mut record = record?;
+        let line = record.position().map_or(0, |p| p.line());
+        let outcome = outcomes
+            .remove(&(idx + 1))
+            .or_else(|| unparseable.get(&line).cloned())
+            .unwrap_or_default();
+        record.push_field(outcome.as_bytes());
+        writer.write_byte_record(&record)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
@@ -0,0 +1,140 @@
+use std::{collections::VecDeque, fmt};
+
+use im::HashMap;
+use rust_decimal::Decimal;
+
+use crate::engine::types::{AccountActivity, ClientId, ClientKey, Transaction};
+
+/// Why a transaction was flagged as unusual for its client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnomalyReason {
+    /// A deposit at least `multiple` times the mean of the client's previous deposits.
+    LargeDeposit { multiple: Decimal },
+    /// `disputes` disputes within the client's last `window` transactions.
+    DisputeBurst { disputes: usize, window: usize },
+}
+
+impl fmt::Display for AnomalyReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnomalyReason::LargeDeposit { multiple } => {
+                write!(f, "deposit {:.1}x the client's mean deposit", multiple)
+            }
+            AnomalyReason::DisputeBurst { disputes, window } => write!(
+                f,
+                "{} disputes within the client's last {} transactions",
+                disputes, window
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Anomaly {
+    pub row: usize,
+    pub client: ClientKey,
+    pub tx: u32,
+    pub reason: AnomalyReason,
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "client {} flagged at row {} (tx {}): {}",
+            self.client, self.row, self.tx, self.reason
+        )
+    }
+}
+
+/// Invoked with every transaction in input order, before it is applied to the ledger.
+pub trait AnomalyDetector {
+    fn inspect(&mut self, transaction: &Transaction) -> Option<AnomalyReason>;
+}
+
+#[derive(Default, Clone)]
+struct ClientStats {
+    deposits: Decimal,
+    deposit_count: u32,
+    // whether each of the client's most recent transactions was a dispute
+    recent_disputes: VecDeque<bool>,
+}
+
+/// Flags deposits far above a client's mean deposit, and bursts of disputes among a client's
+/// recent transactions.
+pub struct StatisticalDetector {
+    pub deposit_multiple: Decimal,
+    pub burst_disputes: usize,
+    pub burst_window: usize,
+    clients: HashMap<ClientId, ClientStats>,
+}
+
+impl Default for StatisticalDetector {
+    fn default() -> Self {
+        Self {
+            deposit_multiple: Decimal::ONE_HUNDRED,
+            burst_disputes: 3,
+            burst_window: 10,
+            clients: HashMap::default(),
+        }
+    }
+}
+
+impl AnomalyDetector for StatisticalDetector {
+    fn inspect(&mut self, transaction: &Transaction) -> Option<AnomalyReason> {
+        let c_id = transaction.client_id();
+        let mut stats = self.clients.get(&c_id).cloned().unwrap_or_default();
+
+        let is_dispute = matches!(transaction, Transaction::Dispute(d) if d.is_dispute());
+        stats.recent_disputes.push_back(is_dispute);
+        if stats.recent_disputes.len() > self.burst_window {
+            stats.recent_disputes.pop_front();
+        }
+        let disputes = stats.recent_disputes.iter().filter(|d| **d).count();
+
+        let reason = match transaction {
+            Transaction::Activity(AccountActivity::Deposit(_, _, amount)) => {
+                let amount = amount.value();
+                let large_deposit = (stats.deposit_count > 0)
+                    .then(|| stats.deposits / Decimal::from(stats.deposit_count))
+                    .filter(|mean| *mean > Decimal::ZERO)
+                    // a multiple too large to represent is still far above the mean
+                    .map(|mean| amount.checked_div(mean).unwrap_or(Decimal::MAX))
+                    .filter(|multiple| *multiple >= self.deposit_multiple)
+                    .map(|multiple| AnomalyReason::LargeDeposit { multiple });
+                stats.deposits = stats.deposits.saturating_add(amount);
+                stats.deposit_count += 1;
+                large_deposit
+            }
+            _ if is_dispute && disputes >= self.burst_disputes => {
+                Some(AnomalyReason::DisputeBurst {
+                    disputes,
+                    window: self.burst_window,
+                })
+            }
+            _ => None,
+        };
+
+        self.clients.insert(c_id, stats);
+        reason
+    }
+}
+
+pub fn detect_anomalies(
+    transactions: &[Transaction],
+    transaction_rows: &[usize],
+    detector: &mut impl AnomalyDetector,
+) -> Vec<Anomaly> {
+    transactions
+        .iter()
+        .zip(transaction_rows)
+        .filter_map(|(transaction, row)| {
+            detector.inspect(transaction).map(|reason| Anomaly {
+                row: *row,
+                client: transaction.client_id().value(),
+                tx: transaction.tx_id().value(),
+                reason,
+            })
+        })
+        .collect()
+}
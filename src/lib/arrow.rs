@@ -0,0 +1,192 @@
+//! Processing of Arrow record batches, so that columnar pipelines such as Spark jobs can hand
+//! transactions to the engine and read back the ledger without going through csv files.
+//!
+//! The input batch has the columns of the csv input, `type`, `client`, `tx` and `amount`. Ids may
+//! be any integer type or strings, and amounts strings or decimals. Each row is parsed as a csv
+//! row would be, so rejections and limits behave the same.
+
+use std::{error::Error, str::FromStr, sync::Arc};
+
+use arrow_array::{
+    cast::AsArray,
+    types::{Decimal128Type, Int16Type, Int32Type, Int64Type, UInt16Type, UInt32Type, UInt64Type},
+    Array, ArrayRef, BooleanArray, Decimal128Array, RecordBatch,
+};
+use arrow_schema::{DataType, Field, Schema};
+use rust_decimal::Decimal;
+
+use crate::config::Config;
+use crate::engine::transactions::create_ledger;
+use crate::engine::types::{ClientKey, ClientLedger};
+use crate::error::UnparseableRow;
+use crate::io::{
+    collect_rows, invalid_field, output_amount, parse_amount, write_quarantine, RawTxRowEntity,
+};
+
+/// Scale of the amounts in the output batch, that of the csv output.
+const OUTPUT_SCALE: i8 = 4;
+
+/// Value of each row of a column as text, None where it is null. Only the types a transaction
+/// field can sensibly arrive as are accepted.
+fn column_text(batch: &RecordBatch, name: &str) -> Result<Vec<Option<String>>, Box<dyn Error>> {
+    let Some(column) = batch.column_by_name(name) else {
+        return Ok(vec![None; batch.num_rows()]);
+    };
+    fn texts<T: ToString>(values: impl Iterator<Item = Option<T>>) -> Vec<Option<String>> {
+        values.map(|v| v.map(|v| v.to_string())).collect()
+    }
+    Ok(match column.data_type() {
+        DataType::Utf8 => texts(column.as_string::<i32>().iter()),
+        DataType::LargeUtf8 => texts(column.as_string::<i64>().iter()),
+        DataType::Int16 => texts(column.as_primitive::<Int16Type>().iter()),
+        DataType::Int32 => texts(column.as_primitive::<Int32Type>().iter()),
+        DataType::Int64 => texts(column.as_primitive::<Int64Type>().iter()),
+        DataType::UInt16 => texts(column.as_primitive::<UInt16Type>().iter()),
+        DataType::UInt32 => texts(column.as_primitive::<UInt32Type>().iter()),
+        DataType::UInt64 => texts(column.as_primitive::<UInt64Type>().iter()),
+        DataType::Decimal128(_, scale) if *scale >= 0 => texts(
+            column
+                .as_primitive::<Decimal128Type>()
+                .iter()
+                .map(|v| v.map(|v| Decimal::from_i128_with_scale(v, *scale as u32))),
+        ),
+        other => return Err(format!("unsupported type {} for column {}", other, name).into()),
+    })
+}
+
+fn parse_row(
+    tx_type: &Option<String>,
+    client: &Option<String>,
+    tx: &Option<String>,
+    amount: &Option<String>,
+) -> Result<RawTxRowEntity, String> {
+    Ok(RawTxRowEntity {
+        tx_type: tx_type.as_deref().unwrap_or_default().trim().to_owned(),
+        client: match client.as_deref().map(str::trim) {
+            None | Some("") => None,
+            Some(client) => {
+                Some(ClientKey::from_str(client).map_err(|e| format!("invalid client: {}", e))?)
+            }
+        },
+        tx: match tx.as_deref().map(str::trim) {
+            None | Some("") => Vec::new(),
+            Some(tx) => tx
+                .split(';')
+                .map(|id| id.trim().parse::<u32>())
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("invalid tx: {}", e))?,
+        },
+        amount: match amount.as_deref().map(str::trim) {
+            None | Some("") => None,
+            Some(amount) => Some(parse_amount(amount)?),
+        },
+    })
+}
+
+#[cfg(not(feature = "string-client-ids"))]
+fn client_column(clients: &[ClientLedger]) -> (DataType, ArrayRef) {
+    let ids = clients.iter().map(|c| c.id.value()).collect::<Vec<_>>();
+    (
+        DataType::UInt16,
+        Arc::new(arrow_array::UInt16Array::from(ids)),
+    )
+}
+
+#[cfg(feature = "string-client-ids")]
+fn client_column(clients: &[ClientLedger]) -> (DataType, ArrayRef) {
+    let ids = clients.iter().map(|c| c.id.value()).collect::<Vec<_>>();
+    (
+        DataType::Utf8,
+        Arc::new(arrow_array::StringArray::from(ids)),
+    )
+}
+
+fn amount_column(
+    clients: &[ClientLedger],
+    config: &Config,
+    amount: impl Fn(&ClientLedger) -> Decimal,
+) -> Result<ArrayRef, Box<dyn Error>> {
+    let values = clients
+        .iter()
+        .map(|c| {
+            let mut rounded = output_amount(amount(c), config);
+            rounded.rescale(OUTPUT_SCALE as u32);
+            // rescaling keeps a smaller scale when the digits do not fit
+            match rounded.scale() == OUTPUT_SCALE as u32 {
+                true => Ok(rounded.mantissa()),
+                false => Err(format!(
+                    "amount {} of client {} has too many digits for {} decimal places",
+                    amount(c),
+                    c.id.value(),
+                    OUTPUT_SCALE
+                )),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Arc::new(
+        Decimal128Array::from_iter_values(values).with_precision_and_scale(38, OUTPUT_SCALE)?,
+    ))
+}
+
+/// Runs the transactions of a batch through the engine, returning the ledger as a batch with the
+/// columns `client`, `available`, `held`, `total` and `locked`, ordered by client. Amounts are
+/// decimals rounded as in the csv output, and a balance too large to have 4 decimal places fails
+/// the batch. Rows which cannot be parsed fail the batch unless a quarantine file is configured,
+/// in which case they are written to it.
+pub fn process_record_batch(
+    batch: &RecordBatch,
+    config: &Config,
+) -> Result<RecordBatch, Box<dyn Error>> {
+    let (types, clients, txs, amounts) = (
+        column_text(batch, "type")?,
+        column_text(batch, "client")?,
+        column_text(batch, "tx")?,
+        column_text(batch, "amount")?,
+    );
+    let rows = (0..batch.num_rows()).map(|idx| {
+        parse_row(&types[idx], &clients[idx], &txs[idx], &amounts[idx]).map_err(|reason| {
+            let fields = [&types[idx], &clients[idx], &txs[idx], &amounts[idx]]
+                .map(|field| field.clone().unwrap_or_default());
+            let field = invalid_field(
+                ["client", "tx", "amount"]
+                    .into_iter()
+                    .zip(fields[1..].iter().map(|value| value.trim())),
+            );
+            // counting the header as line 1, as for csv input
+            let line = idx as u64 + 2;
+            let raw = fields.join(",");
+            Box::new(UnparseableRow {
+                line,
+                raw,
+                reason,
+                field,
+            }) as Box<dyn Error>
+        })
+    });
+    let input = collect_rows(rows, config, batch.num_rows(), None)?;
+    if let Some(path) = &config.quarantine_file {
+        write_quarantine(path, &input.quarantined_rows)?;
+    }
+    let mut ledger = create_ledger(Box::new(input.transactions.into_iter()), &config.policy()).0;
+    ledger.sort_by_key(|c| c.id.value());
+
+    let (client_type, client_column) = client_column(&ledger);
+    let amount_type = DataType::Decimal128(38, OUTPUT_SCALE);
+    let schema = Schema::new(vec![
+        Field::new("client", client_type, false),
+        Field::new("available", amount_type.clone(), false),
+        Field::new("held", amount_type.clone(), false),
+        Field::new("total", amount_type, false),
+        Field::new("locked", DataType::Boolean, false),
+    ]);
+    let columns = vec![
+        client_column,
+        amount_column(&ledger, config, |c| c.available.value())?,
+        amount_column(&ledger, config, |c| c.held.value())?,
+        amount_column(&ledger, config, |c| c.total.value())?,
+        Arc::new(BooleanArray::from(
+            ledger.iter().map(|c| c.is_locked).collect::<Vec<_>>(),
+        )),
+    ];
+    Ok(RecordBatch::try_new(Arc::new(schema), columns)?)
+}
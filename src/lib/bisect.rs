@@ -0,0 +1,85 @@
+use std::{collections::BTreeMap, error::Error, ffi::OsString};
+
+use crate::config::Config;
+use crate::engine::transactions::client_state_or_opening;
+use crate::engine::types::{ClientId, ClientKey, ClientLedger, Transaction};
+use crate::io::{process_csv, read_snapshot, ParsedInput, SnapshotRowEntity};
+use crate::replay::replay;
+
+/// State of the client after the input rows up to `row`, replayed with the config of the run,
+/// including the business days closed by `eod` rows. Other clients' transactions cannot change
+/// the client's state, so they are left out.
+fn observed_after(
+    input: &ParsedInput,
+    row: usize,
+    client: ClientId,
+    config: &Config,
+) -> ClientLedger {
+    let (transactions, rows): (Vec<Transaction>, Vec<usize>) = input
+        .transactions
+        .iter()
+        .zip(&input.transaction_rows)
+        .filter(|(transaction, r)| **r <= row && transaction.client_id() == client)
+        .map(|(transaction, r)| (transaction.clone(), *r))
+        .unzip();
+    let days = input.end_of_day_rows.partition_point(|r| *r <= row);
+    let states = replay(
+        &transactions,
+        &rows,
+        &input.end_of_day_rows[..days],
+        config,
+        &mut [],
+    );
+    let state = client_state_or_opening(&states, &client, &config.policy());
+    ClientLedger::from_state(client, state)
+}
+
+fn diverges(input: &ParsedInput, expected: &SnapshotRowEntity, config: &Config) -> bool {
+    let observed = observed_after(
+        input,
+        expected.row,
+        ClientId::new(expected.client.to_owned()),
+        config,
+    );
+    !expected.matches(&observed)
+}
+
+/// Binary searches the snapshot entries of each client for the first expected state which the
+/// engine does not reproduce, returning the earliest input row after which a divergence was
+/// observed.
+///
+/// As with `git bisect`, this assumes that once a client has diverged from the snapshot it does
+/// not converge again. Clients diverge independently, so each is searched on its own. Listing an
+/// expected state after every row pinpoints the offending transaction, sparser snapshots narrow
+/// it down to the rows between two snapshot entries.
+fn first_divergence(
+    input: &ParsedInput,
+    snapshot: &[SnapshotRowEntity],
+    config: &Config,
+) -> Option<usize> {
+    let mut by_client: BTreeMap<&ClientKey, Vec<&SnapshotRowEntity>> = BTreeMap::new();
+    for expected in snapshot {
+        by_client
+            .entry(&expected.client)
+            .or_default()
+            .push(expected);
+    }
+    by_client
+        .into_values()
+        .filter_map(|mut entries| {
+            entries.sort_by_key(|expected| expected.row);
+            let idx = entries.partition_point(|expected| !diverges(input, expected, config));
+            entries.get(idx).map(|expected| expected.row)
+        })
+        .min()
+}
+
+pub fn bisect_payments(
+    csv_path: &OsString,
+    snapshot_path: &OsString,
+    config: &Config,
+) -> Result<Option<usize>, Box<dyn Error>> {
+    let input = process_csv(csv_path, config)?;
+    let snapshot = read_snapshot(snapshot_path)?;
+    Ok(first_divergence(&input, &snapshot, config))
+}
@@ -0,0 +1,671 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    error::Error,
+    ffi::OsString,
+    io::Read,
+    path::PathBuf,
+    str::FromStr,
+};
+
+use im::HashMap;
+use rust_decimal::Decimal;
+use serde::{de, forward_to_deserialize_any, Deserialize, Deserializer};
+
+use crate::engine::policy::client_table;
+use crate::engine::types::{ClientFlags, ClientKey};
+use crate::engine::{DisputePolicy, Policy, RetryPolicy};
+use crate::faults::FaultInjection;
+use crate::filter::LedgerFilter;
+use crate::paths::open_input;
+
+/// Layout of the client ledger output. New columns are only added in new versions so that
+/// existing consumers are unaffected.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputSchema {
+    /// `client,available,held,total,locked`
+    #[default]
+    V1,
+    /// As `V1`, followed by `open_disputes,rejected_withdrawals,last_tx_id`
+    V2,
+}
+
+impl FromStr for OutputSchema {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "v1" => Ok(OutputSchema::V1),
+            "v2" => Ok(OutputSchema::V2),
+            _ => Err(format!("Unknown output schema {}", s)),
+        }
+    }
+}
+
+/// How the client ledger is written.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    /// Columns padded to line up, for reading in a terminal.
+    Table,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "table" => Ok(OutputFormat::Table),
+            _ => Err(format!("Unknown output format {}", s)),
+        }
+    }
+}
+
+/// Formatting of the client ledger output, e.g. `;` delimited with comma decimal separators for
+/// European tools.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputOptions {
+    pub format: OutputFormat,
+    /// Separates the columns of csv output. Must be an ASCII character.
+    pub delimiter: char,
+    /// Written between the integer and fractional digits of amounts.
+    pub decimal_separator: char,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        Self {
+            format: OutputFormat::Csv,
+            delimiter: ',',
+            decimal_separator: '.',
+        }
+    }
+}
+
+impl OutputOptions {
+    pub fn with_format(self, format: OutputFormat) -> Self {
+        Self { format, ..self }
+    }
+
+    pub fn with_delimiter(self, delimiter: char) -> Self {
+        Self { delimiter, ..self }
+    }
+
+    pub fn with_decimal_separator(self, decimal_separator: char) -> Self {
+        Self {
+            decimal_separator,
+            ..self
+        }
+    }
+}
+
+/// Prefix of the environment variables read by [`Config::from_toml_with_env`].
+pub const ENV_PREFIX: &str = "PAYMENTS_";
+
+/// An environment variable's value as a TOML value, e.g. a number or boolean, falling back to a
+/// string so that values such as `hold-available` need no quotes.
+fn env_value(value: String) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or(toml::Value::String(value))
+}
+
+/// Asks a type for the fields of the struct it deserializes from, failing once they are known.
+struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+impl<'de> Deserializer<'de> for FieldNames<'_> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("not a struct"))
+    }
+
+    fn deserialize_struct<V: de::Visitor<'de>>(
+        self,
+        _: &'static str,
+        fields: &'static [&'static str],
+        _: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = fields;
+        Err(de::Error::custom("fields collected"))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option
+        unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+    }
+}
+
+/// Options controlling how a batch of payments is processed and reported. Can be read from a TOML
+/// file whose keys are the field names, options not in the file keep their defaults.
+#[derive(Default, Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Columns of the client ledger output, `v1` unless consumers have moved to a newer layout.
+    pub schema: OutputSchema,
+    /// Read from an `[output]` table in TOML.
+    pub output: OutputOptions,
+    /// How disputes of deposits whose funds were already withdrawn are treated. `allow-negative`,
+    /// the default, holds the full amount and lets available funds go negative. `hold-available`
+    /// holds what is available and takes the shortfall from available funds on a chargeback.
+    /// `hold-unspent` holds and charges back only the part of the deposit not yet withdrawn.
+    /// `reject` ignores the dispute.
+    pub dispute_policy: DisputePolicy,
+    /// Available funds of a client before their first transaction, e.g. an opening credit line.
+    pub opening_balance: Decimal,
+    /// Read from a `[retry]` table in TOML.
+    pub retry: RetryPolicy,
+    /// Faults injected while reading the input, for resilience testing. Read from a `[faults]`
+    /// table in TOML, and needs the `fault-injection` feature.
+    pub faults: FaultInjection,
+    /// Unlocks an account when the chargeback which locked it is reversed.
+    pub unlock_on_chargeback_reversal: bool,
+    /// Largest share of a client's total funds, as a percentage, which disputes may hold.
+    pub max_held_percent: Option<Decimal>,
+    /// Largest number of deposits and withdrawals kept per client for disputes, bounding the
+    /// memory a single client can take. Later ones apply but cannot be disputed.
+    pub max_client_history: Option<usize>,
+    /// Number of `eod` rows after which an open dispute is resolved automatically.
+    pub dispute_expiry_days: Option<u32>,
+    /// Writes a ledger snapshot at every `eod` row, to the snapshot directory.
+    pub eod_snapshots: bool,
+    /// Adds the count and total amount of rejected withdrawals still awaiting backfill to each
+    /// client row of the output, and lists the retry queue.
+    pub show_pending_withdrawals: bool,
+    /// Leaves out of the output accounts with no funds which were never disputed.
+    pub omit_empty_accounts: bool,
+    /// Writes only the client rows selected by the filter, e.g. `total > 1000 && locked == true`.
+    /// Balances, hashes and system accounts still cover every client.
+    pub filter: Option<LedgerFilter>,
+    /// Flags clients with unusually large deposits or bursts of disputes.
+    pub detect_anomalies: bool,
+    /// Adds a `risk_score` column to the output under the v2 schema.
+    pub risk_scores: bool,
+    /// Reports ignored dispute operations, duplicate transaction ids and activity on locked
+    /// accounts.
+    pub collect_warnings: bool,
+    /// Warns of withdrawals larger than the client's opening balance and earlier deposits, and of
+    /// deposits over the `deposit_cap`.
+    pub check_amounts: bool,
+    /// Largest deposit the amount checks accept.
+    pub deposit_cap: Option<Decimal>,
+    /// Rejects transactions failing the amount checks rather than warning of them.
+    pub reject_suspect_amounts: bool,
+    /// Reports the chargeback which locked each locked account.
+    pub report_frozen_accounts: bool,
+    /// Writes amounts at the precision they were computed at rather than rounding them to four
+    /// decimal places.
+    pub preserve_scale: bool,
+    /// Counts applied and ignored transactions of each type.
+    pub collect_metrics: bool,
+    /// Writes the ledger to a timestamped file after every given number of transactions.
+    pub snapshot_every: Option<usize>,
+    /// Directory snapshots are written to, the working directory if not set.
+    pub snapshot_dir: Option<PathBuf>,
+    /// Writes the ledger to this many files split by client id range, with a manifest, rather
+    /// than returning it as the output.
+    pub partitions: Option<usize>,
+    /// Directory partitions are written to, the working directory if not set.
+    pub partition_dir: Option<PathBuf>,
+    /// Records time spent in each phase of the run.
+    pub profile: bool,
+    /// Also writes the ledger to this file, or object when an `s3://` or `gs://` URL, which needs
+    /// the `object-store` feature.
+    pub output_file: Option<PathBuf>,
+    /// Writes a manifest of SHA-256 hashes of the input and the canonicalized output to this file.
+    pub manifest_file: Option<PathBuf>,
+    /// Signs the manifest with the hex encoded ed25519 secret key in this file. Needs the
+    /// `signing` feature.
+    pub signing_key_file: Option<PathBuf>,
+    /// Aborts processing at the first deposit, withdrawal or hold whose transaction id is not
+    /// greater than that of the one before it.
+    pub strict_ordering: bool,
+    /// Copies rows which cannot be parsed, e.g. with a malformed number, to this file along with
+    /// their line number and the reason, and carries on with the rest of the input. Without it
+    /// such a row aborts processing.
+    pub quarantine_file: Option<PathBuf>,
+    /// Copies disputes, resolves, chargebacks and chargeback reversals referring to a transaction
+    /// the client never made to this file, along with their row and the reason.
+    pub dead_letter_file: Option<PathBuf>,
+    /// Writes the engine version, policy, input hash, row counts and time of the run to this
+    /// file.
+    pub metadata_file: Option<PathBuf>,
+    /// Treats deposits and withdrawals without an amount as zero rather than rejecting the row.
+    pub missing_amount_as_zero: bool,
+    /// Takes the client of a dispute, resolve or chargeback whose client is blank or zero from the
+    /// deposit or withdrawal it refers to.
+    pub lookup_dispute_clients: bool,
+    /// Expands dispute, resolve and chargeback rows listing several semicolon separated
+    /// transaction ids into one operation per id.
+    pub batch_dispute_rows: bool,
+    /// Other names partner files use for transaction types, e.g. `credit = "deposit"`. Read from
+    /// a `[tx_type_aliases]` table in TOML. Types and aliases match ignoring case and any `-`,
+    /// `_` or spaces, and rows of any other type are rejected.
+    pub tx_type_aliases: BTreeMap<String, String>,
+    /// Clients allowed to transact, e.g. those known to the ledger of record. Rows of any other
+    /// client are rejected rather than opening an account. Any client may transact if not set.
+    pub registered_clients: Option<HashSet<ClientKey>>,
+    /// How far clients with a credit agreement may overdraw, read from an `[overdraft_limits]`
+    /// table in TOML, e.g. `1 = 50.0`. Their withdrawals may take available funds negative down to
+    /// minus the limit. Other clients cannot overdraw.
+    #[serde(with = "client_table")]
+    pub overdraft_limits: BTreeMap<ClientKey, Decimal>,
+    /// Flags of clients, e.g. `vip` or `under_review`, read from a sidecar file by the command
+    /// line. Clients flagged `under_review` cannot withdraw.
+    #[serde(skip)]
+    pub client_flags: HashMap<ClientKey, ClientFlags>,
+    /// Maximum size of the input file in bytes.
+    pub max_bytes: Option<u64>,
+    /// Maximum number of transaction rows in the input.
+    pub max_rows: Option<usize>,
+    /// Maximum number of distinct clients in the input.
+    pub max_clients: Option<usize>,
+    /// Scans the input to estimate memory use before processing it.
+    pub estimate_memory: bool,
+    /// Maximum estimated memory use in bytes, checked by a scan of the input before it is
+    /// processed.
+    pub max_memory: Option<u64>,
+}
+
+impl Config {
+    /// The options which change how transactions affect balances.
+    pub fn policy(&self) -> Policy {
+        Policy {
+            dispute_policy: self.dispute_policy,
+            opening_balance: self.opening_balance,
+            retry: self.retry,
+            unlock_on_chargeback_reversal: self.unlock_on_chargeback_reversal,
+            max_held_percent: self.max_held_percent,
+            max_client_history: self.max_client_history,
+            dispute_expiry_days: self.dispute_expiry_days,
+            client_flags: self.client_flags.clone(),
+            overdraft_limits: self.overdraft_limits.clone(),
+        }
+    }
+
+    pub fn from_toml(toml: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(toml::from_str(toml)?)
+    }
+
+    pub fn from_toml_file(config_path: &OsString) -> Result<Self, Box<dyn Error>> {
+        let mut toml = String::new();
+        open_input(config_path)?.read_to_string(&mut toml)?;
+        Self::from_toml(&toml)
+    }
+
+    /// Reads options from TOML, overridden by environment variables named after the keys with
+    /// the [`ENV_PREFIX`], e.g. `PAYMENTS_DISPUTE_POLICY=reject`. Keys in a table are separated by
+    /// a double underscore, e.g. `PAYMENTS_OUTPUT__DELIMITER=;`. Values are read as TOML values,
+    /// or as strings when they are not valid TOML.
+    ///
+    /// Unlike keys in the TOML, variables which name no option, e.g. those of another service
+    /// sharing the prefix, are ignored. Their names are returned along with the options.
+    pub fn from_toml_with_env(
+        toml: &str,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<(Self, Vec<String>), Box<dyn Error>> {
+        let mut options: &'static [&'static str] = &[];
+        let _ = Config::deserialize(FieldNames(&mut options));
+        let mut table: toml::Table = toml::from_str(toml)?;
+        let mut ignored = Vec::new();
+        for (name, value) in vars {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let key = key.to_lowercase();
+            let mut path: Vec<&str> = key.split("__").collect();
+            if !options.contains(&path[0]) {
+                ignored.push(name);
+                continue;
+            }
+            let field = path.pop().unwrap_or_default();
+            let mut target = &mut table;
+            for name in path {
+                target = target
+                    .entry(name)
+                    .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+                    .as_table_mut()
+                    .ok_or_else(|| format!("{} is not a table of options", name))?;
+            }
+            target.insert(field.to_owned(), env_value(value));
+        }
+        Ok((toml::Value::Table(table).try_into()?, ignored))
+    }
+
+    /// As [`Config::from_toml_with_env`], reading the TOML from a file if one is given.
+    pub fn from_toml_file_with_env(
+        config_path: Option<&OsString>,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<(Self, Vec<String>), Box<dyn Error>> {
+        let mut toml = String::new();
+        if let Some(config_path) = config_path {
+            open_input(config_path)?.read_to_string(&mut toml)?;
+        }
+        Self::from_toml_with_env(&toml, vars)
+    }
+
+    pub fn with_schema(self, schema: OutputSchema) -> Self {
+        Self { schema, ..self }
+    }
+
+    pub fn with_output_options(self, output: OutputOptions) -> Self {
+        Self { output, ..self }
+    }
+
+    pub fn with_dispute_policy(self, dispute_policy: DisputePolicy) -> Self {
+        Self {
+            dispute_policy,
+            ..self
+        }
+    }
+
+    pub fn with_opening_balance(self, opening_balance: Decimal) -> Self {
+        Self {
+            opening_balance,
+            ..self
+        }
+    }
+
+    pub fn with_retry(self, retry: RetryPolicy) -> Self {
+        Self { retry, ..self }
+    }
+
+    pub fn with_faults(self, faults: FaultInjection) -> Self {
+        Self { faults, ..self }
+    }
+
+    pub fn with_max_held_percent(self, max_held_percent: Decimal) -> Self {
+        Self {
+            max_held_percent: Some(max_held_percent),
+            ..self
+        }
+    }
+
+    pub fn with_max_client_history(self, max_client_history: usize) -> Self {
+        Self {
+            max_client_history: Some(max_client_history),
+            ..self
+        }
+    }
+
+    pub fn with_dispute_expiry_days(self, dispute_expiry_days: u32) -> Self {
+        Self {
+            dispute_expiry_days: Some(dispute_expiry_days),
+            ..self
+        }
+    }
+
+    pub fn with_eod_snapshots(self, dir: PathBuf) -> Self {
+        Self {
+            eod_snapshots: true,
+            snapshot_dir: Some(dir),
+            ..self
+        }
+    }
+
+    pub fn with_unlock_on_chargeback_reversal(self, unlock_on_chargeback_reversal: bool) -> Self {
+        Self {
+            unlock_on_chargeback_reversal,
+            ..self
+        }
+    }
+
+    pub fn with_lookup_dispute_clients(self, lookup_dispute_clients: bool) -> Self {
+        Self {
+            lookup_dispute_clients,
+            ..self
+        }
+    }
+
+    pub fn with_pending_withdrawals(self, show_pending_withdrawals: bool) -> Self {
+        Self {
+            show_pending_withdrawals,
+            ..self
+        }
+    }
+
+    pub fn with_filter(self, filter: LedgerFilter) -> Self {
+        Self {
+            filter: Some(filter),
+            ..self
+        }
+    }
+
+    pub fn with_omit_empty_accounts(self, omit_empty_accounts: bool) -> Self {
+        Self {
+            omit_empty_accounts,
+            ..self
+        }
+    }
+
+    pub fn with_risk_scores(self, risk_scores: bool) -> Self {
+        Self {
+            risk_scores,
+            ..self
+        }
+    }
+
+    pub fn with_detect_anomalies(self, detect_anomalies: bool) -> Self {
+        Self {
+            detect_anomalies,
+            ..self
+        }
+    }
+
+    pub fn with_collect_warnings(self, collect_warnings: bool) -> Self {
+        Self {
+            collect_warnings,
+            ..self
+        }
+    }
+
+    pub fn with_check_amounts(self, check_amounts: bool) -> Self {
+        Self {
+            check_amounts,
+            ..self
+        }
+    }
+
+    pub fn with_deposit_cap(self, deposit_cap: Decimal) -> Self {
+        Self {
+            deposit_cap: Some(deposit_cap),
+            ..self
+        }
+    }
+
+    pub fn with_reject_suspect_amounts(self, reject_suspect_amounts: bool) -> Self {
+        Self {
+            reject_suspect_amounts,
+            ..self
+        }
+    }
+
+    pub fn with_report_frozen_accounts(self, report_frozen_accounts: bool) -> Self {
+        Self {
+            report_frozen_accounts,
+            ..self
+        }
+    }
+
+    pub fn with_preserve_scale(self, preserve_scale: bool) -> Self {
+        Self {
+            preserve_scale,
+            ..self
+        }
+    }
+
+    pub fn with_collect_metrics(self, collect_metrics: bool) -> Self {
+        Self {
+            collect_metrics,
+            ..self
+        }
+    }
+
+    pub fn with_snapshots(self, every: usize, dir: PathBuf) -> Self {
+        Self {
+            snapshot_every: Some(every),
+            snapshot_dir: Some(dir),
+            ..self
+        }
+    }
+
+    pub fn with_partitions(self, partitions: usize, dir: PathBuf) -> Self {
+        Self {
+            partitions: Some(partitions),
+            partition_dir: Some(dir),
+            ..self
+        }
+    }
+
+    pub fn with_profile(self, profile: bool) -> Self {
+        Self { profile, ..self }
+    }
+
+    pub fn with_missing_amount_as_zero(self, missing_amount_as_zero: bool) -> Self {
+        Self {
+            missing_amount_as_zero,
+            ..self
+        }
+    }
+
+    pub fn with_batch_dispute_rows(self, batch_dispute_rows: bool) -> Self {
+        Self {
+            batch_dispute_rows,
+            ..self
+        }
+    }
+
+    pub fn with_max_bytes(self, max_bytes: u64) -> Self {
+        Self {
+            max_bytes: Some(max_bytes),
+            ..self
+        }
+    }
+
+    pub fn with_max_rows(self, max_rows: usize) -> Self {
+        Self {
+            max_rows: Some(max_rows),
+            ..self
+        }
+    }
+
+    pub fn with_max_clients(self, max_clients: usize) -> Self {
+        Self {
+            max_clients: Some(max_clients),
+            ..self
+        }
+    }
+
+    pub fn with_tx_type_alias(self, alias: impl Into<String>, tx_type: impl Into<String>) -> Self {
+        let mut tx_type_aliases = self.tx_type_aliases;
+        tx_type_aliases.insert(alias.into(), tx_type.into());
+        Self {
+            tx_type_aliases,
+            ..self
+        }
+    }
+
+    pub fn with_registered_clients(self, clients: impl IntoIterator<Item = ClientKey>) -> Self {
+        Self {
+            registered_clients: Some(clients.into_iter().collect()),
+            ..self
+        }
+    }
+
+    pub fn with_overdraft_limit(self, client: ClientKey, limit: Decimal) -> Self {
+        let mut overdraft_limits = self.overdraft_limits;
+        overdraft_limits.insert(client, limit);
+        Self {
+            overdraft_limits,
+            ..self
+        }
+    }
+
+    pub fn with_overdraft_limits(
+        self,
+        overdraft_limits: impl IntoIterator<Item = (ClientKey, Decimal)>,
+    ) -> Self {
+        Self {
+            overdraft_limits: overdraft_limits.into_iter().collect(),
+            ..self
+        }
+    }
+
+    pub fn with_client_flags(
+        self,
+        client_flags: impl IntoIterator<Item = (ClientKey, ClientFlags)>,
+    ) -> Self {
+        Self {
+            client_flags: client_flags.into_iter().collect(),
+            ..self
+        }
+    }
+
+    pub fn with_output_file(self, output_file: PathBuf) -> Self {
+        Self {
+            output_file: Some(output_file),
+            ..self
+        }
+    }
+
+    pub fn with_manifest_file(self, manifest_file: PathBuf) -> Self {
+        Self {
+            manifest_file: Some(manifest_file),
+            ..self
+        }
+    }
+
+    pub fn with_signing_key_file(self, signing_key_file: PathBuf) -> Self {
+        Self {
+            signing_key_file: Some(signing_key_file),
+            ..self
+        }
+    }
+
+    pub fn with_strict_ordering(self, strict_ordering: bool) -> Self {
+        Self {
+            strict_ordering,
+            ..self
+        }
+    }
+
+    pub fn with_quarantine_file(self, quarantine_file: PathBuf) -> Self {
+        Self {
+            quarantine_file: Some(quarantine_file),
+            ..self
+        }
+    }
+
+    pub fn with_dead_letter_file(self, dead_letter_file: PathBuf) -> Self {
+        Self {
+            dead_letter_file: Some(dead_letter_file),
+            ..self
+        }
+    }
+
+    pub fn with_metadata_file(self, metadata_file: PathBuf) -> Self {
+        Self {
+            metadata_file: Some(metadata_file),
+            ..self
+        }
+    }
+
+    pub fn with_estimate_memory(self, estimate_memory: bool) -> Self {
+        Self {
+            estimate_memory,
+            ..self
+        }
+    }
+
+    pub fn with_max_memory(self, max_memory: u64) -> Self {
+        Self {
+            max_memory: Some(max_memory),
+            ..self
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! Dispute operations referring to transactions the client never made, set aside so that
+//! upstream teams can fix the gaps in their exports.
+
+use std::{error::Error, fmt, path::Path};
+
+use crate::engine::types::{
+    ClientKey, ClientState, Decision, DisputeOperation, Ignored, Transaction,
+};
+use crate::replay::ReplayObserver;
+use crate::warning::dispute_operation;
+
+/// A dispute, resolve, chargeback or chargeback reversal which was ignored as the transaction it
+/// refers to is unknown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeadLetter {
+    pub row: usize,
+    pub operation: DisputeOperation,
+    pub client: ClientKey,
+    pub tx: u32,
+    pub reason: Ignored,
+}
+
+impl fmt::Display for DeadLetter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at row {} (client {}, tx {}) dead lettered: {}",
+            self.operation, self.row, self.client, self.tx, self.reason
+        )
+    }
+}
+
+/// Collects the dispute operations which referred to unknown transactions during a replay.
+#[derive(Default)]
+pub struct DeadLetterCollector {
+    pub dead_letters: Vec<DeadLetter>,
+}
+
+impl ReplayObserver for DeadLetterCollector {
+    fn observe(
+        &mut self,
+        row: usize,
+        transaction: &Transaction,
+        decision: Decision,
+        _before: &ClientState,
+        _after: &ClientState,
+    ) {
+        let Some(operation) = dispute_operation(transaction) else {
+            return;
+        };
+        if let Decision::Ignore(reason @ Ignored::UnknownTransaction) = decision {
+            self.dead_letters.push(DeadLetter {
+                row,
+                operation,
+                client: transaction.client_id().value(),
+                tx: transaction.tx_id().value(),
+                reason,
+            });
+        }
+    }
+}
+
+/// Writes the dead lettered operations to a CSV file with their input row and reason.
+pub fn write_dead_letters(path: &Path, dead_letters: &[DeadLetter]) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_path(path)?;
+    wtr.write_record(["row", "type", "client", "tx", "reason"])?;
+    for dead_letter in dead_letters {
+        wtr.write_record([
+            dead_letter.row.to_string(),
+            dead_letter.operation.to_string(),
+            dead_letter.client.to_string(),
+            dead_letter.tx.to_string(),
+            dead_letter.reason.to_string(),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
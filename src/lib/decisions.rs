@@ -0,0 +1,139 @@
+//! The engine's decision table: what it does with each type of transaction in each account state,
+//! for reviewers who need the rules without reading the code.
+
+use std::{error::Error, str::FromStr};
+
+use im::HashMap;
+use rust_decimal::Decimal;
+
+use crate::config::Config;
+use crate::engine::transactions::{decide_transaction, resolve_transaction};
+use crate::engine::types::{
+    AccountActivity, AdminHold, ClientId, ClientKey, ClientState, Decision, DisputeManagement,
+    MonetaryAmount, Transaction, TransactionId,
+};
+
+/// The deposit which the account states are built from, and which disputes refer to.
+const DEPOSIT_TX: u32 = 1;
+const PROBE_TX: u32 = 2;
+const HOLD_TX: u32 = 3;
+
+fn amount(value: i64) -> MonetaryAmount {
+    MonetaryAmount::from(Decimal::from(value))
+}
+
+fn deposit(c_id: &ClientId, tx: u32, value: i64) -> Transaction {
+    Transaction::Activity(AccountActivity::Deposit(
+        c_id.to_owned(),
+        TransactionId::new(tx),
+        amount(value),
+    ))
+}
+
+fn withdrawal(c_id: &ClientId, value: i64) -> Transaction {
+    Transaction::Activity(AccountActivity::Withdrawal(
+        c_id.to_owned(),
+        TransactionId::new(PROBE_TX),
+        amount(value),
+    ))
+}
+
+fn dispute_management(
+    operation: fn(ClientId, TransactionId) -> DisputeManagement,
+    c_id: &ClientId,
+) -> Transaction {
+    Transaction::Dispute(operation(c_id.to_owned(), TransactionId::new(DEPOSIT_TX)))
+}
+
+fn hold(c_id: &ClientId) -> Transaction {
+    Transaction::Admin(AdminHold::Hold(
+        c_id.to_owned(),
+        TransactionId::new(HOLD_TX),
+        amount(5),
+    ))
+}
+
+/// Each account state, with the transactions which bring a new account to it.
+fn account_states(c_id: &ClientId) -> Vec<(&'static str, Vec<Transaction>)> {
+    let funded = || deposit(c_id, DEPOSIT_TX, 10);
+    vec![
+        ("new", vec![]),
+        ("funded", vec![funded()]),
+        (
+            "disputed",
+            vec![
+                funded(),
+                dispute_management(DisputeManagement::Dispute, c_id),
+            ],
+        ),
+        (
+            "charged_back",
+            vec![
+                funded(),
+                dispute_management(DisputeManagement::Dispute, c_id),
+                dispute_management(DisputeManagement::Chargeback, c_id),
+            ],
+        ),
+        ("on_hold", vec![funded(), hold(c_id)]),
+    ]
+}
+
+/// Each type of transaction, with withdrawals split by whether the funds cover them.
+fn probes(c_id: &ClientId) -> Vec<(&'static str, Transaction)> {
+    vec![
+        ("deposit", deposit(c_id, PROBE_TX, 5)),
+        ("withdrawal_within_funds", withdrawal(c_id, 1)),
+        ("withdrawal_over_funds", withdrawal(c_id, 50)),
+        (
+            "dispute",
+            dispute_management(DisputeManagement::Dispute, c_id),
+        ),
+        (
+            "resolve",
+            dispute_management(DisputeManagement::Resolve, c_id),
+        ),
+        (
+            "chargeback",
+            dispute_management(DisputeManagement::Chargeback, c_id),
+        ),
+        (
+            "chargeback_reversal",
+            dispute_management(DisputeManagement::ChargebackReversal, c_id),
+        ),
+        ("hold", hold(c_id)),
+        (
+            "release",
+            Transaction::Admin(AdminHold::Release(
+                c_id.to_owned(),
+                TransactionId::new(HOLD_TX),
+            )),
+        ),
+    ]
+}
+
+/// Runs every type of transaction against every account state through the engine, listing the
+/// decision taken for each as csv, with the reason when it was ignored. Policies set in the config, e.g. the dispute policy, apply.
+pub fn decision_table(config: &Config) -> Result<String, Box<dyn Error>> {
+    let policy = config.policy();
+    let c_id = ClientId::new(ClientKey::from_str("1")?);
+    let mut table = String::from("state,transaction,decision,reason\n");
+    for (state, setup) in account_states(&c_id) {
+        let ledger: HashMap<ClientId, ClientState> = setup
+            .into_iter()
+            .fold(HashMap::default(), |ledger, transaction| {
+                resolve_transaction(transaction, ledger, &policy)
+            });
+        for (transaction_type, transaction) in probes(&c_id) {
+            let (_, decision) = decide_transaction(transaction, ledger.clone(), &policy);
+            let reason = match decision {
+                Decision::Ignore(reason) => reason.to_string(),
+                _ => String::new(),
+            };
+            table.push_str(&format!(
+                "{},{},{},{}\n",
+                state, transaction_type, decision, reason
+            ));
+        }
+    }
+    Ok(table)
+}
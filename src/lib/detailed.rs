@@ -0,0 +1,104 @@
+//! Per row outcomes alongside the ledger, for embedders and tests which need to know what became
+//! of each row rather than only the final balances.
+
+use std::{error::Error, ffi::OsString};
+
+use rust_decimal::Decimal;
+
+use crate::amount_checks::process_checked_csv;
+use crate::config::Config;
+use crate::engine::types::{ClientKey, ClientState, Decision, Ledger, Transaction};
+use crate::error::RowRejection;
+use crate::replay::{replay, ReplayObserver};
+
+/// What became of an input row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RowStatus {
+    /// The transaction changed the client's account.
+    Applied(Decision),
+    /// The transaction was processed but left the account as it was, e.g. a withdrawal without
+    /// the funds or a dispute of an unknown transaction. The decision gives the reason.
+    Ignored(Decision),
+    /// The row was skipped before reaching the engine.
+    Rejected(RowRejection),
+}
+
+/// Balances of a client after a transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientBalances {
+    pub client: ClientKey,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+/// The outcome of a single input row. A row which produced several transactions, e.g. a batch
+/// of disputes, has one outcome per transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RowOutcome {
+    pub row: usize,
+    pub status: RowStatus,
+    /// Balances of the transaction's client afterwards, absent for rejected rows.
+    pub balances: Option<ClientBalances>,
+}
+
+#[derive(Default)]
+struct RowOutcomeCollector {
+    outcomes: Vec<RowOutcome>,
+}
+
+impl ReplayObserver for RowOutcomeCollector {
+    fn observe(
+        &mut self,
+        row: usize,
+        transaction: &Transaction,
+        decision: Decision,
+        _before: &ClientState,
+        after: &ClientState,
+    ) {
+        let status = if decision.is_applied() {
+            RowStatus::Applied(decision)
+        } else {
+            RowStatus::Ignored(decision)
+        };
+        self.outcomes.push(RowOutcome {
+            row,
+            status,
+            balances: Some(ClientBalances {
+                client: transaction.client_id().value(),
+                available: after.available.value().value(),
+                held: after.held.value().value(),
+                total: after.total().value(),
+                locked: after.is_locked,
+            }),
+        });
+    }
+}
+
+/// Processes the input, returning the ledger along with the outcome of every row in input
+/// order. Rows which cannot be parsed at all abort processing, or when quarantined are left out
+/// as they are only known by their line.
+pub fn process_transactions_detailed(
+    csv_path: &OsString,
+    config: &Config,
+) -> Result<(Ledger, Vec<RowOutcome>), Box<dyn Error>> {
+    let input = process_checked_csv(csv_path, config)?;
+    let mut collector = RowOutcomeCollector::default();
+    let states = replay(
+        &input.transactions,
+        &input.transaction_rows,
+        &input.end_of_day_rows,
+        config,
+        &mut [&mut collector],
+    );
+    let mut outcomes = collector.outcomes;
+    outcomes.extend(input.rejected_rows.into_iter().map(|rejected| RowOutcome {
+        row: rejected.row,
+        status: RowStatus::Rejected(rejected.reason),
+        balances: None,
+    }));
+    // stable, so the transactions of a batch row keep their order
+    outcomes.sort_by_key(|outcome| outcome.row);
+    Ok((Ledger::from_states(states), outcomes))
+}
@@ -0,0 +1,207 @@
+//! Processing of csv files dropped into a directory, so that a long running process can replace
+//! cron jobs which poll for new files.
+//!
+//! Files are picked up from the top of the drop-box directory. The ledger of each is written to
+//! `output/` under the same name, the file is recorded in `checkpoint.toml`, and it is then moved
+//! to `done/`, or to `failed/` when it could not be processed. Writers should drop files under
+//! another extension and rename them to `.csv` once complete, so partial files are not read.
+//!
+//! Other files a run writes, e.g. the manifest, are named after the input file so that files
+//! dropped together do not overwrite each other's.
+
+use std::{
+    error::Error,
+    ffi::OsString,
+    fmt, fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::engine::clock::Clock;
+use crate::manifest::sha256_hex;
+use crate::{process_payments_with_hooks, RunHooks};
+
+const CHECKPOINT_FILE: &str = "checkpoint.toml";
+
+/// An input file which has been processed from the drop-box.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessedFile {
+    pub file: String,
+    /// SHA-256 of the input file, hex encoded.
+    pub input_sha256: String,
+    pub output: PathBuf,
+    /// Seconds since the Unix epoch at which the file was processed.
+    pub processed_at: u64,
+}
+
+/// Files processed from the drop-box, oldest first. A file found in the checkpoint is moved to
+/// `done/` without being processed again, e.g. after the process was killed before moving it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DropboxCheckpoint {
+    #[serde(default)]
+    pub processed: Vec<ProcessedFile>,
+}
+
+impl DropboxCheckpoint {
+    fn contains(&self, file: &str, input_sha256: &str) -> bool {
+        self.processed
+            .iter()
+            .any(|p| p.file == file && p.input_sha256 == input_sha256)
+    }
+}
+
+/// What became of a file found in the drop-box.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DropboxOutcome {
+    /// The ledger was written to the given output file.
+    Processed(String, PathBuf),
+    /// The file was already in the checkpoint, it was only moved to `done/`.
+    AlreadyProcessed(String),
+    /// The file could not be processed and was moved to `failed/`.
+    Failed(String, String),
+}
+
+impl fmt::Display for DropboxOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DropboxOutcome::Processed(file, output) => {
+                write!(f, "{}: ledger written to {}", file, output.display())
+            }
+            DropboxOutcome::AlreadyProcessed(file) => {
+                write!(f, "{}: already processed, moved to done", file)
+            }
+            DropboxOutcome::Failed(file, error) => write!(f, "{}: failed: {}", file, error),
+        }
+    }
+}
+
+/// Writes under a temporary name and renames, so a killed process never leaves a partial file.
+fn write_atomically(path: &Path, contents: String) -> Result<(), Box<dyn Error>> {
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, contents)?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+pub fn read_checkpoint(dir: &Path) -> Result<DropboxCheckpoint, Box<dyn Error>> {
+    match fs::read_to_string(dir.join(CHECKPOINT_FILE)) {
+        Ok(contents) => Ok(toml::from_str(&contents)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(DropboxCheckpoint::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Csv files waiting in the drop-box, in name order.
+fn pending_files(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut files = fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "csv"))
+        .collect::<Vec<_>>();
+    files.sort();
+    Ok(files)
+}
+
+fn move_to(path: &Path, dir: &Path) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+    fs::rename(path, dir.join(path.file_name().unwrap_or_default()))?;
+    Ok(())
+}
+
+/// The configured path with the stem of the input file appended to its name, e.g.
+/// `manifest_day1.toml` for `manifest.toml` and `day1.csv`.
+fn per_file_path(path: &Path, input: &Path) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_owned();
+    name.push("_");
+    name.push(input.file_stem().unwrap_or_default());
+    if let Some(ext) = path.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    path.with_file_name(name)
+}
+
+/// The config with every file a run writes named after the input file.
+fn per_file_config(config: &Config, input: &Path) -> Config {
+    let per_file = |path: &Option<PathBuf>| path.as_deref().map(|p| per_file_path(p, input));
+    Config {
+        output_file: per_file(&config.output_file),
+        manifest_file: per_file(&config.manifest_file),
+        metadata_file: per_file(&config.metadata_file),
+        quarantine_file: per_file(&config.quarantine_file),
+        dead_letter_file: per_file(&config.dead_letter_file),
+        ..config.clone()
+    }
+}
+
+/// Processes a single file of the drop-box. Errors processing its transactions are an outcome,
+/// whereas errors reading, writing or moving files are returned.
+fn process_file(
+    dir: &Path,
+    path: &Path,
+    file: &str,
+    config: &Config,
+    clock: &dyn Clock,
+    checkpoint: &mut DropboxCheckpoint,
+) -> Result<DropboxOutcome, Box<dyn Error>> {
+    let input_sha256 = sha256_hex(&mut fs::File::open(path)?)?;
+    if checkpoint.contains(file, &input_sha256) {
+        move_to(path, &dir.join("done"))?;
+        return Ok(DropboxOutcome::AlreadyProcessed(file.to_owned()));
+    }
+    let hooks = RunHooks::default().with_clock(clock);
+    let config = per_file_config(config, path);
+    let report = match process_payments_with_hooks(&OsString::from(path), &config, hooks) {
+        Ok(report) => report,
+        Err(e) => {
+            move_to(path, &dir.join("failed"))?;
+            return Ok(DropboxOutcome::Failed(file.to_owned(), e.to_string()));
+        }
+    };
+    let output = dir.join("output").join(file);
+    write_atomically(&output, report.output)?;
+    checkpoint.processed.push(ProcessedFile {
+        file: file.to_owned(),
+        input_sha256,
+        output: output.clone(),
+        processed_at: clock.now().duration_since(UNIX_EPOCH)?.as_secs(),
+    });
+    write_atomically(&dir.join(CHECKPOINT_FILE), toml::to_string(checkpoint)?)?;
+    move_to(path, &dir.join("done"))?;
+    Ok(DropboxOutcome::Processed(file.to_owned(), output))
+}
+
+/// Processes every csv file currently in the drop-box directory. Each output and the updated
+/// checkpoint are written before the input is moved, so a file is never lost between runs. A
+/// file which cannot be read, written or moved is moved to `failed/` where possible and reported
+/// as failed, without stopping the other files.
+pub fn process_dropbox(
+    dir: &Path,
+    config: &Config,
+    clock: &dyn Clock,
+) -> Result<Vec<DropboxOutcome>, Box<dyn Error>> {
+    fs::create_dir_all(dir.join("output"))?;
+    let mut checkpoint = read_checkpoint(dir)?;
+    let mut outcomes = Vec::new();
+    for path in pending_files(dir)? {
+        let file = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let outcome = process_file(dir, &path, &file, config, clock, &mut checkpoint)
+            .unwrap_or_else(|e| {
+                let error = match move_to(&path, &dir.join("failed")) {
+                    Ok(()) => e.to_string(),
+                    Err(move_error) => format!("{}, and not moved: {}", e, move_error),
+                };
+                DropboxOutcome::Failed(file, error)
+            });
+        outcomes.push(outcome);
+    }
+    Ok(outcomes)
+}
@@ -0,0 +1,53 @@
+use std::{
+    cell::Cell,
+    time::{Duration, SystemTime},
+};
+
+/// Source of the current time, injected wherever the engine needs one so that tests can fix it.
+pub trait Clock {
+    fn now(&self) -> SystemTime;
+}
+
+/// The system's wall clock, used by default.
+#[derive(Default, Clone, Copy, Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Always reports the same time.
+#[derive(Clone, Copy, Debug)]
+pub struct FixedClock(pub SystemTime);
+
+impl Clock for FixedClock {
+    fn now(&self) -> SystemTime {
+        self.0
+    }
+}
+
+/// Reports `start` on the first call, advancing by `step` on each call after.
+#[derive(Debug)]
+pub struct StepClock {
+    next: Cell<SystemTime>,
+    step: Duration,
+}
+
+impl StepClock {
+    pub fn new(start: SystemTime, step: Duration) -> Self {
+        Self {
+            next: Cell::new(start),
+            step,
+        }
+    }
+}
+
+impl Clock for StepClock {
+    fn now(&self) -> SystemTime {
+        let now = self.next.get();
+        self.next.set(now + self.step);
+        now
+    }
+}
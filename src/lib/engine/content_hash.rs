@@ -0,0 +1,323 @@
+//! Content hashes of client states and ledgers, stable across runs and platforms, so that large
+//! numbers of states can be compared without asserting on each field. The immutable maps iterate
+//! in an arbitrary order, so their entries are hashed sorted by transaction id.
+
+use std::fmt::Display;
+
+use sha2::{Digest, Sha256};
+
+use crate::engine::types::{AccountActivity, ClientState, Ledger, MonetaryAmount};
+
+pub type ContentHash = [u8; 32];
+
+/// Feeds values to the hash unambiguously by prefixing each with its length.
+struct Canonical(Sha256);
+
+impl Canonical {
+    fn new(tag: &str) -> Self {
+        let mut canonical = Canonical(Sha256::new());
+        canonical.field(tag);
+        canonical
+    }
+
+    fn field(&mut self, value: impl Display) -> &mut Self {
+        let value = value.to_string();
+        self.0.update((value.len() as u64).to_le_bytes());
+        self.0.update(value);
+        self
+    }
+
+    /// Amounts which are equal hash the same whatever their scale, e.g. `1.5` and `1.50`.
+    fn amount(&mut self, amount: MonetaryAmount) -> &mut Self {
+        self.field(amount.value().normalize())
+    }
+
+    fn finish(self) -> ContentHash {
+        self.0.finalize().into()
+    }
+}
+
+fn sorted<V>(entries: impl Iterator<Item = (u32, V)>) -> Vec<(u32, V)> {
+    let mut entries: Vec<(u32, V)> = entries.collect();
+    entries.sort_unstable_by_key(|(tx, _)| *tx);
+    entries
+}
+
+impl ClientState {
+    /// Hash of the balances, lock and full history of the client. Equal states have equal
+    /// hashes.
+    pub fn content_hash(&self) -> ContentHash {
+        let history = &self.history;
+        let mut c = Canonical::new("client state");
+        c.amount(self.available.value())
+            .amount(self.held.value())
+            .field(self.is_locked);
+
+        let activity = sorted(
+            history
+                .account_activity
+                .iter()
+                .map(|(tx, a)| (tx.value(), a)),
+        );
+        c.field(activity.len());
+        for (tx, activity) in activity {
+            let kind = match activity {
+                AccountActivity::Deposit(..) => "deposit",
+                AccountActivity::Withdrawal(..) => "withdrawal",
+            };
+            c.field(tx).field(kind).amount(activity.amount());
+        }
+
+        let disputed = sorted(history.disputed_txs.iter().map(|tx| (tx.value(), ())));
+        c.field(disputed.len());
+        for (tx, _) in disputed {
+            c.field(tx);
+        }
+
+        let ages = sorted(
+            history
+                .dispute_ages
+                .iter()
+                .map(|(tx, age)| (tx.value(), *age)),
+        );
+        c.field(ages.len());
+        for (tx, age) in ages {
+            c.field(tx).field(age);
+        }
+
+        let charged_back = sorted(history.charged_back_txs.iter().map(|tx| (tx.value(), ())));
+        c.field(charged_back.len());
+        for (tx, _) in charged_back {
+            c.field(tx);
+        }
+
+        c.field(history.queued_disputes.len());
+        for tx in &history.queued_disputes {
+            c.field(tx.value());
+        }
+
+        let shortfalls = sorted(
+            history
+                .dispute_shortfalls
+                .iter()
+                .map(|(tx, a)| (tx.value(), *a)),
+        );
+        c.field(shortfalls.len());
+        for (tx, amount) in shortfalls {
+            c.field(tx).amount(amount);
+        }
+
+        c.field(history.unspent_deposits.len());
+        for (tx, amount) in &history.unspent_deposits {
+            c.field(tx.value()).amount(*amount);
+        }
+
+        c.field(history.rejected_withdrawals);
+        let counts = history.counts;
+        c.field(counts.deposits)
+            .field(counts.withdrawals)
+            .field(counts.disputes)
+            .field(counts.chargebacks);
+        c.field(history.rejected_txs.len());
+        for rejected in &history.rejected_txs {
+            let snapshot = sorted(
+                rejected
+                    .disputed_transaction_snapshot
+                    .iter()
+                    .map(|tx| (tx.value(), ())),
+            );
+            c.field(rejected.activity.tx_id().value())
+                .amount(rejected.activity.amount())
+                .field(rejected.attempts)
+                .field(snapshot.len());
+            for (tx, _) in snapshot {
+                c.field(tx);
+            }
+        }
+
+        match history.last_tx_id {
+            Some(tx) => c.field("last tx").field(tx.value()),
+            None => c.field("no last tx"),
+        };
+
+        let operations = sorted(
+            history
+                .last_dispute_operations
+                .iter()
+                .map(|(tx, op)| (tx.value(), *op)),
+        );
+        c.field(operations.len());
+        for (tx, operation) in operations {
+            c.field(tx).field(operation);
+        }
+
+        let holds = sorted(history.admin_holds.iter().map(|(tx, a)| (tx.value(), *a)));
+        c.field(holds.len());
+        for (tx, amount) in holds {
+            c.field(tx).amount(amount);
+        }
+
+        // the remaining records are kept in the order they happened, and describe themselves
+        c.field(history.retried_operations.len());
+        for retried in &history.retried_operations {
+            c.field(retried);
+        }
+        c.field(history.rolled_back_txs.len());
+        for rolled_back in &history.rolled_back_txs {
+            c.field(rolled_back);
+        }
+        c.field(history.abandoned_retries.len());
+        for abandoned in &history.abandoned_retries {
+            c.field(abandoned);
+        }
+        c.finish()
+    }
+}
+
+impl Ledger {
+    /// Hash of every client's row of the ledger, whatever order the clients are in, as a ledger
+    /// may be assembled by hand rather than with [`Ledger::from_states`].
+    pub fn content_hash(&self) -> ContentHash {
+        let mut clients: Vec<_> = self.0.iter().collect();
+        clients.sort_unstable_by_key(|client| client.id.value());
+        let mut c = Canonical::new("ledger");
+        c.field(clients.len());
+        for client in clients {
+            c.field(client.id.value())
+                .amount(client.available)
+                .amount(client.held)
+                .amount(client.total)
+                .field(client.is_locked)
+                .field(client.pending_withdrawals)
+                .amount(client.pending_withdrawal_amount)
+                .field(client.open_disputes)
+                .field(client.had_dispute)
+                .field(client.transactions)
+                .field(client.disputed_txs)
+                .field(client.chargebacks)
+                .field(client.counts.disputes)
+                .field(client.counts.chargebacks)
+                .field(client.rejected_withdrawals);
+            match client.last_tx_id {
+                Some(tx) => c.field("last tx").field(tx.value()),
+                None => c.field("no last tx"),
+            };
+            for records in [
+                client
+                    .retried_operations
+                    .iter()
+                    .map(|r| r.to_string())
+                    .collect::<Vec<_>>(),
+                client
+                    .rolled_back_txs
+                    .iter()
+                    .map(|r| r.to_string())
+                    .collect(),
+                client.retry_queue.iter().map(|r| r.to_string()).collect(),
+                client
+                    .abandoned_retries
+                    .iter()
+                    .map(|r| r.to_string())
+                    .collect(),
+                client
+                    .queued_disputes
+                    .iter()
+                    .map(|r| r.to_string())
+                    .collect(),
+            ] {
+                c.field(records.len());
+                for record in records {
+                    c.field(record);
+                }
+            }
+        }
+        c.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use im::HashMap;
+    use rust_decimal::Decimal;
+
+    use crate::engine::policy::Policy;
+    use crate::engine::transactions::{create_ledger, fold_transactions};
+    use crate::engine::types::{
+        key, AccountActivity, ClientId, DisputeManagement, Ledger, MonetaryAmount, Transaction,
+        TransactionId,
+    };
+
+    fn deposit(client: u16, tx: u32, amount: Decimal) -> Transaction {
+        Transaction::Activity(AccountActivity::Deposit(
+            ClientId::new(key(client)),
+            TransactionId::new(tx),
+            MonetaryAmount::from(amount),
+        ))
+    }
+
+    fn transactions() -> Vec<Transaction> {
+        vec![
+            deposit(1, 1, Decimal::new(15, 1)),
+            deposit(2, 2, Decimal::new(3, 0)),
+            deposit(1, 3, Decimal::new(2, 0)),
+            Transaction::Dispute(DisputeManagement::Dispute(
+                ClientId::new(key(1)),
+                TransactionId::new(1),
+            )),
+        ]
+    }
+
+    #[test]
+    fn equal_contents_hash_equal() {
+        let ledger = create_ledger(Box::new(transactions().into_iter()), &Policy::default());
+        let hash = ledger.content_hash();
+        let reversed = Ledger(ledger.0.into_iter().rev().collect());
+        assert_eq!(hash, reversed.content_hash());
+
+        // the same amount at a different scale
+        let mut rescaled = transactions();
+        rescaled[0] = deposit(1, 1, Decimal::new(150, 2));
+        let states = fold_transactions(
+            HashMap::new(),
+            transactions().into_iter(),
+            &Policy::default(),
+            &mut [],
+        );
+        let rescaled_states = fold_transactions(
+            HashMap::new(),
+            rescaled.into_iter(),
+            &Policy::default(),
+            &mut [],
+        );
+        for (id, state) in &states {
+            assert_eq!(state.content_hash(), rescaled_states[id].content_hash());
+        }
+    }
+
+    #[cfg(not(feature = "no-history"))]
+    #[test]
+    fn different_contents_hash_differently() {
+        let undisputed: Vec<Transaction> = transactions().into_iter().take(3).collect();
+        let states = fold_transactions(
+            HashMap::new(),
+            transactions().into_iter(),
+            &Policy::default(),
+            &mut [],
+        );
+        let undisputed_states = fold_transactions(
+            HashMap::new(),
+            undisputed.clone().into_iter(),
+            &Policy::default(),
+            &mut [],
+        );
+        let client = ClientId::new(key(1));
+        assert_ne!(
+            states[&client].content_hash(),
+            undisputed_states[&client].content_hash()
+        );
+
+        let ledger = create_ledger(Box::new(transactions().into_iter()), &Policy::default());
+        let undisputed_ledger = create_ledger(Box::new(undisputed.into_iter()), &Policy::default());
+        assert_ne!(ledger.content_hash(), undisputed_ledger.content_hash());
+    }
+}
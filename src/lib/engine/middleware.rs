@@ -0,0 +1,20 @@
+use crate::engine::types::{ClientState, SourceMetadata, Transaction};
+
+/// A hook run around every transaction applied to the ledger, e.g. to enforce custom limits or
+/// log activity. Middleware run in order, the first to veto a transaction stops it reaching the
+/// ledger and any later middleware.
+pub trait TxMiddleware {
+    /// Called before [`TxMiddleware::before`] with where the transaction was read from, when it is
+    /// applied from a [`TransactionEnvelope`](crate::engine::types::TransactionEnvelope).
+    fn source(&mut self, _metadata: &SourceMetadata) {}
+
+    /// Called with the state of the client before the transaction is applied. Returning `false`
+    /// vetoes the transaction.
+    fn before(&mut self, _transaction: &Transaction, _state: &ClientState) -> bool {
+        true
+    }
+
+    /// Called once a transaction which was not vetoed has been applied, whether or not it
+    /// changed the state of the client.
+    fn after(&mut self, _transaction: &Transaction, _before: &ClientState, _after: &ClientState) {}
+}
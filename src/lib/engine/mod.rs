@@ -0,0 +1,14 @@
+//! The ledger itself: domain types and the rules for applying transactions to client states.
+//! Nothing here reads or writes files, or depends on how the engine is configured beyond the
+//! [`Policy`] affecting balances, so it can be embedded without the csv front end. It still needs
+//! std, as the immutable collections it is built on do.
+
+pub mod clock;
+pub mod content_hash;
+pub mod middleware;
+pub mod policy;
+pub mod transactions;
+pub mod types;
+pub mod utils;
+
+pub use policy::{DisputePolicy, Policy, RetryPolicy};
@@ -0,0 +1,201 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+use im::HashMap;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::engine::types::{ClientFlags, ClientKey};
+
+/// How a dispute is treated when the client no longer has the disputed funds available, e.g.
+/// because they were withdrawn after the deposit.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DisputePolicy {
+    /// Hold the full disputed amount, allowing available funds to go negative.
+    #[default]
+    AllowNegative,
+    /// Hold only the funds still available and track the remainder as a shortfall. The
+    /// shortfall is debited from available funds if the dispute ends in a chargeback.
+    HoldAvailable,
+    /// Hold only the portion of the deposit which has not been withdrawn, spending deposits
+    /// oldest first, and charge back only what was held. The client is never left owing the
+    /// withdrawn portion, so a chargeback cannot take available funds negative.
+    HoldUnspent,
+    /// Ignore the dispute.
+    Reject,
+}
+
+impl FromStr for DisputePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow-negative" => Ok(DisputePolicy::AllowNegative),
+            "hold-available" => Ok(DisputePolicy::HoldAvailable),
+            "hold-unspent" => Ok(DisputePolicy::HoldUnspent),
+            "reject" => Ok(DisputePolicy::Reject),
+            _ => Err(format!("Unknown dispute policy {}", s)),
+        }
+    }
+}
+
+/// How withdrawals rejected for lack of available funds are queued and retried.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// Drops a queued withdrawal once it has been retried this many times without the funds
+    /// being available. Retried indefinitely if not set.
+    pub max_attempts: Option<u32>,
+    /// Drops a queued withdrawal once a transaction for the same client is applied whose id
+    /// exceeds the withdrawal's by more than this. Never expires if not set.
+    pub expiry: Option<u32>,
+    /// Also queues withdrawals rejected while no dispute is open, retrying queued withdrawals
+    /// after every deposit as well as after resolves.
+    pub on_deposit: bool,
+}
+
+impl RetryPolicy {
+    pub fn with_max_attempts(self, max_attempts: u32) -> Self {
+        Self {
+            max_attempts: Some(max_attempts),
+            ..self
+        }
+    }
+
+    pub fn with_expiry(self, expiry: u32) -> Self {
+        Self {
+            expiry: Some(expiry),
+            ..self
+        }
+    }
+
+    pub fn with_on_deposit(self, on_deposit: bool) -> Self {
+        Self { on_deposit, ..self }
+    }
+}
+
+/// The options which change how transactions affect balances.
+#[derive(Default, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Policy {
+    pub dispute_policy: DisputePolicy,
+    /// Available funds of a client before their first transaction.
+    pub opening_balance: Decimal,
+    pub retry: RetryPolicy,
+    /// Unlocks an account when the chargeback which locked it is reversed.
+    pub unlock_on_chargeback_reversal: bool,
+    /// Largest share of a client's total funds, as a percentage, which disputes may hold. Disputes
+    /// beyond it are queued until an earlier dispute is resolved.
+    pub max_held_percent: Option<Decimal>,
+    /// Largest number of deposits and withdrawals kept per client for later dispute operations.
+    /// Beyond it transactions still apply but are not recorded, so they cannot be disputed. It
+    /// also bounds the client's queue of withdrawals to retry, further ones being rejected, and
+    /// the retried operations, rolled back transactions and abandoned retries kept for the report.
+    pub max_client_history: Option<usize>,
+    /// Number of end of day rows after which an open dispute is resolved automatically. Disputes
+    /// never expire if not set.
+    pub dispute_expiry_days: Option<u32>,
+    /// Flags of clients, given to their state before their first transaction. Clients flagged
+    /// `under_review` cannot withdraw.
+    #[serde(skip)]
+    pub client_flags: HashMap<ClientKey, ClientFlags>,
+    /// How far below zero withdrawals may take the available funds of a client.
+    #[serde(
+        default,
+        skip_serializing_if = "BTreeMap::is_empty",
+        with = "client_table"
+    )]
+    pub overdraft_limits: BTreeMap<ClientKey, Decimal>,
+}
+
+/// A TOML table keyed by client. Keys of TOML tables are always strings, so client ids are
+/// written as strings and parsed back.
+pub(crate) mod client_table {
+    use std::collections::BTreeMap;
+
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::engine::types::ClientKey;
+
+    pub fn serialize<S, V>(table: &BTreeMap<ClientKey, V>, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        V: Serialize,
+    {
+        s.collect_map(
+            table
+                .iter()
+                .map(|(client, value)| (client.to_string(), value)),
+        )
+    }
+
+    pub fn deserialize<'de, D, V>(d: D) -> Result<BTreeMap<ClientKey, V>, D::Error>
+    where
+        D: Deserializer<'de>,
+        V: Deserialize<'de>,
+    {
+        BTreeMap::<String, V>::deserialize(d)?
+            .into_iter()
+            .map(|(client, value)| match client.parse() {
+                Ok(client) => Ok((client, value)),
+                Err(_) => Err(de::Error::custom(format!("invalid client `{}`", client))),
+            })
+            .collect()
+    }
+}
+
+impl Policy {
+    /// How far below zero withdrawals may take the client's available funds, zero for clients
+    /// without an overdraft.
+    pub fn overdraft_limit(&self, client: &ClientKey) -> Decimal {
+        self.overdraft_limits
+            .get(client)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn with_dispute_policy(self, dispute_policy: DisputePolicy) -> Self {
+        Self {
+            dispute_policy,
+            ..self
+        }
+    }
+
+    pub fn with_opening_balance(self, opening_balance: Decimal) -> Self {
+        Self {
+            opening_balance,
+            ..self
+        }
+    }
+
+    pub fn with_retry(self, retry: RetryPolicy) -> Self {
+        Self { retry, ..self }
+    }
+
+    pub fn with_max_held_percent(self, max_held_percent: Decimal) -> Self {
+        Self {
+            max_held_percent: Some(max_held_percent),
+            ..self
+        }
+    }
+
+    pub fn with_max_client_history(self, max_client_history: usize) -> Self {
+        Self {
+            max_client_history: Some(max_client_history),
+            ..self
+        }
+    }
+
+    pub fn with_dispute_expiry_days(self, dispute_expiry_days: u32) -> Self {
+        Self {
+            dispute_expiry_days: Some(dispute_expiry_days),
+            ..self
+        }
+    }
+
+    pub fn with_unlock_on_chargeback_reversal(self, unlock_on_chargeback_reversal: bool) -> Self {
+        Self {
+            unlock_on_chargeback_reversal,
+            ..self
+        }
+    }
+}
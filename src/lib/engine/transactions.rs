@@ -0,0 +1,2527 @@
+use crate::engine::middleware::TxMiddleware;
+use crate::engine::policy::{DisputePolicy, Policy};
+use crate::engine::types::{
+    AbandonedRetry, AccountActivity, ActivityCounts, AdminHold, ClientId, ClientState, Decision,
+    DisputeManagement, DisputeOperation, Ignored, InvariantViolation, Ledger, MonetaryAmount,
+    Overflow, RejectedActivity, RejectedTx, RetriedOperation, RetryAbandonment,
+    RolledBackTransaction, Transaction, TransactionEnvelope, TransactionHistory, TransactionId,
+    Underflow, UNDER_REVIEW,
+};
+use crate::engine::utils::{OrDefault, PushImmut};
+use im::{HashMap, Vector};
+use rust_decimal::Decimal;
+
+/// Whether a deposit or withdrawal would be recorded for later dispute operations. Built with
+/// the `no-history` feature nothing is, and nothing beyond the per client cap.
+fn is_recorded(history: &TransactionHistory, policy: &Policy) -> bool {
+    !cfg!(feature = "no-history")
+        && policy
+            .max_client_history
+            .is_none_or(|max| history.account_activity.len() < max)
+}
+
+/// Records a deposit or withdrawal so that later dispute operations can refer to it, unless it
+/// could not be disputed anyway.
+fn record_activity(
+    history: &TransactionHistory,
+    tx_id: TransactionId,
+    activity: &AccountActivity,
+    policy: &Policy,
+) -> TransactionHistory {
+    if !is_recorded(history, policy) {
+        history.clone()
+    } else {
+        history.map_account_activity(|account_acc| account_acc.update(tx_id, activity.clone()))
+    }
+}
+
+/// Counts an applied deposit or withdrawal, whether or not it is recorded.
+fn count_activity(history: &TransactionHistory, activity: &AccountActivity) -> TransactionHistory {
+    history.map_counts(|counts| match activity {
+        AccountActivity::Deposit(..) => ActivityCounts {
+            deposits: counts.deposits + 1,
+            ..counts
+        },
+        AccountActivity::Withdrawal(..) => ActivityCounts {
+            withdrawals: counts.withdrawals + 1,
+            ..counts
+        },
+    })
+}
+
+/// Tracks the unspent portion of a deposit, needed only by the `hold-unspent` dispute policy.
+fn record_unspent(
+    history: &TransactionHistory,
+    tx_id: TransactionId,
+    amount: MonetaryAmount,
+    policy: DisputePolicy,
+) -> TransactionHistory {
+    if policy == DisputePolicy::HoldUnspent && !cfg!(feature = "no-history") {
+        history.map_unspent_deposits(|unspent| unspent.push((tx_id, amount)))
+    } else {
+        history.clone()
+    }
+}
+
+/// Adds funds to available, refusing to take the balance or total past the largest amount.
+fn credit(client_state: &ClientState, amount: MonetaryAmount) -> Result<ClientState, Ignored> {
+    let new_state = client_state
+        .try_map_avail(|a| a.checked_add(amount))
+        .map_err(|Overflow| Ignored::Overflow)?;
+    new_state
+        .checked_total()
+        .map_err(|Overflow| Ignored::Overflow)?;
+    Ok(new_state)
+}
+
+fn update_deposit(
+    client_state: ClientState,
+    activity: &AccountActivity,
+    tx_id: TransactionId,
+    amount: MonetaryAmount,
+    policy: &Policy,
+) -> (ClientState, Decision) {
+    if client_state.is_locked {
+        return (client_state, Decision::Ignore(Ignored::AccountLocked));
+    }
+    if amount < MonetaryAmount::default() {
+        return (client_state, Decision::Ignore(Ignored::NegativeAmount));
+    }
+    let new_state = match credit(&client_state, amount) {
+        // Deposits beyond the history cap cannot be disputed, so neither is their unspent portion
+        Ok(credited) if is_recorded(&credited.history, policy) => credited.map_history(|h| {
+            record_unspent(
+                &record_activity(h, tx_id, activity, policy),
+                tx_id,
+                amount,
+                policy.dispute_policy,
+            )
+        }),
+        Ok(credited) => credited,
+        Err(reason) => return (client_state, Decision::Ignore(reason)),
+    }
+    .map_history(|h| count_activity(h, activity));
+    if policy.retry.on_deposit {
+        (
+            retry_rejected(new_state, RetryTrigger::Deposit, policy),
+            Decision::Credit,
+        )
+    } else {
+        (new_state, Decision::Credit)
+    }
+}
+
+fn update_withdrawal(
+    client_state: ClientState,
+    activity: &AccountActivity,
+    tx_id: TransactionId,
+    amount: MonetaryAmount,
+    policy: &Policy,
+) -> (ClientState, Decision) {
+    let retry = policy.retry;
+    let overdraft = MonetaryAmount::from(policy.overdraft_limit(&activity.client_id().value()));
+    // Only compared with the amount, so a sum past the largest amount may stay at it
+    let spendable = client_state.available.value().saturating_add(overdraft);
+    // The resolutoin of disputes will not effect this transaction
+    let no_possible_withdrawal_backfill = !retry.on_deposit
+        && ((spendable < amount && client_state.history.disputed_txs.is_empty())
+            || client_state.total().saturating_add(overdraft) < amount);
+
+    if client_state.is_locked {
+        return (client_state, Decision::Ignore(Ignored::AccountLocked));
+    }
+    if amount < MonetaryAmount::default() {
+        return (client_state, Decision::Ignore(Ignored::NegativeAmount));
+    }
+    if client_state.flags.contains(UNDER_REVIEW) {
+        return (client_state, Decision::Ignore(Ignored::UnderReview));
+    }
+    if no_possible_withdrawal_backfill {
+        return (
+            client_state.map_history(|h| h.count_rejected_withdrawal()),
+            Decision::RejectWithdrawal,
+        );
+    }
+
+    // The resolutoin of disputes, or a deposit if configured, may effect this transaction
+    let potential_backfill =
+        spendable < amount && (retry.on_deposit || !client_state.history.disputed_txs.is_empty());
+
+    // The queue is bounded by the history cap, further withdrawals are rejected outright
+    let is_queue_full = policy
+        .max_client_history
+        .is_some_and(|max| client_state.history.rejected_txs.len() >= max);
+
+    if potential_backfill && !is_queue_full {
+        let disputed_transaction_snapshot = client_state.history.disputed_txs.clone();
+        let rejected_activity = RejectedTx {
+            activity: RejectedActivity::Withdrawal(activity.client_id(), tx_id, amount),
+            disputed_transaction_snapshot,
+            attempts: 0,
+        };
+        let new_state = client_state.map_history(|h| {
+            h.map_rejected_activity(|r| r.push(rejected_activity))
+                .count_rejected_withdrawal()
+        });
+        (new_state, Decision::QueueWithdrawal)
+    } else {
+        match client_state.try_map_avail(|a| a.sub_within_overdraft(amount, overdraft)) {
+            Ok(new_state) => {
+                let new_state = new_state.map_history(|h| {
+                    record_activity(&count_activity(h, activity), tx_id, activity, policy)
+                        .spend_deposits(amount)
+                });
+                (new_state, Decision::Debit)
+            }
+            Err(Underflow) => (
+                client_state.map_history(|h| h.count_rejected_withdrawal()),
+                Decision::RejectWithdrawal,
+            ),
+        }
+    }
+}
+
+fn hold_dispute(
+    client_state: ClientState,
+    tx_id: TransactionId,
+    policy: DisputePolicy,
+) -> Result<ClientState, Ignored> {
+    if client_state.is_locked {
+        return Err(Ignored::AccountLocked);
+    }
+    if client_state.history.disputed_txs.contains(&tx_id) {
+        return Err(Ignored::AlreadyDisputed);
+    }
+
+    let maybe_tx_amount = client_state.history.account_activity.get(&tx_id);
+    // Only deposits can be disputed (see readme).
+    if let Some(AccountActivity::Deposit(_, tx_id, amount)) = maybe_tx_amount {
+        // The disputed funds may have since been withdrawn
+        let available = client_state.available.value();
+        let shortfall = if policy == DisputePolicy::HoldUnspent {
+            *amount - client_state.history.unspent(tx_id)
+        } else if available < *amount {
+            *amount - available.max(MonetaryAmount::default())
+        } else {
+            MonetaryAmount::default()
+        };
+        let is_short = shortfall > MonetaryAmount::default();
+        let held_amount = match policy {
+            DisputePolicy::Reject if is_short => return Err(Ignored::InsufficientAvailable),
+            DisputePolicy::HoldAvailable | DisputePolicy::HoldUnspent => *amount - shortfall,
+            _ => *amount,
+        };
+        let held_state = client_state
+            .try_map_held(|h| h.checked_add(held_amount))
+            .map_err(|Overflow| Ignored::Overflow)?;
+        let new_state = match policy {
+            DisputePolicy::AllowNegative => held_state
+                .try_map_avail(|a| a.sub_allow_negative(held_amount))
+                .map_err(|Overflow| Ignored::Overflow)?,
+            _ => held_state
+                .try_map_avail(|a| a.checked_sub(held_amount))
+                .map_err(|Underflow| Ignored::InsufficientAvailable)?,
+        }
+        .map_history(|history| {
+            history
+                .map_disputed_tx(|disputed| disputed.update(*tx_id))
+                .map_dispute_ages(|ages| ages.without(tx_id))
+        });
+
+        let tracks_shortfall = matches!(
+            policy,
+            DisputePolicy::HoldAvailable | DisputePolicy::HoldUnspent
+        );
+        if tracks_shortfall && is_short {
+            Ok(new_state.map_history(|history| {
+                history.map_dispute_shortfalls(|shortfalls| shortfalls.update(*tx_id, shortfall))
+            }))
+        } else {
+            Ok(new_state)
+        }
+    } else {
+        Err(unknown_or_not_deposit(maybe_tx_amount))
+    }
+}
+
+/// Why a dispute operation referring to the given transaction cannot apply to it.
+fn unknown_or_not_deposit(activity: Option<&AccountActivity>) -> Ignored {
+    match activity {
+        Some(_) => Ignored::NotADeposit,
+        None => Ignored::UnknownTransaction,
+    }
+}
+
+/// Whether holding a dispute took held funds over the configured share of total funds. A dispute
+/// is only held back while earlier disputes are open, as otherwise nothing could settle to let it
+/// through.
+fn exceeds_held_limit(
+    before: &ClientState,
+    disputed: &ClientState,
+    policy: &Policy,
+) -> Result<bool, Ignored> {
+    match policy.max_held_percent {
+        Some(percent) if !before.history.disputed_txs.is_empty() => {
+            let held = disputed
+                .held
+                .value()
+                .value()
+                .checked_mul(Decimal::ONE_HUNDRED);
+            let limit = disputed.total().value().checked_mul(percent);
+            match (held, limit) {
+                (Some(held), Some(limit)) => Ok(held > limit),
+                _ => Err(Ignored::Overflow),
+            }
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Holds the disputed funds, or queues the dispute if that would exceed the held funds limit.
+fn update_dispute(
+    client_state: ClientState,
+    tx_id: TransactionId,
+    policy: &Policy,
+) -> Result<(ClientState, Decision), Ignored> {
+    if client_state.history.queued_disputes.contains(&tx_id) {
+        return Err(Ignored::AlreadyDisputed);
+    }
+    let disputed = hold_dispute(client_state.clone(), tx_id, policy.dispute_policy)?;
+    let count_dispute = |counts: ActivityCounts| ActivityCounts {
+        disputes: counts.disputes + 1,
+        ..counts
+    };
+    if exceeds_held_limit(&client_state, &disputed, policy)? {
+        let queued = client_state.map_history(|h| {
+            h.map_queued_disputes(|q| q.push(tx_id))
+                .map_counts(count_dispute)
+        });
+        Ok((queued, Decision::QueueDispute))
+    } else {
+        Ok((
+            disputed.map_history(|h| h.map_counts(count_dispute)),
+            Decision::HoldDispute,
+        ))
+    }
+}
+
+/// Retries queued disputes in the order they were queued, once an earlier dispute has settled.
+/// Disputes which still exceed the limit, or no longer apply, stay queued.
+fn hold_queued_disputes(client_state: ClientState, policy: &Policy) -> ClientState {
+    let queue = client_state.history.queued_disputes.clone();
+    let unqueued = client_state.map_history(|h| h.map_queued_disputes(|_| Vector::new()));
+    queue.into_iter().fold(unqueued, |acc, tx_id| {
+        match hold_dispute(acc.clone(), tx_id, policy.dispute_policy) {
+            Ok(disputed) if exceeds_held_limit(&acc, &disputed, policy) == Ok(false) => disputed,
+            _ => acc.map_history(|h| h.map_queued_disputes(|q| q.push(tx_id))),
+        }
+    })
+}
+
+/// An event after which queued withdrawals are retried.
+#[derive(Clone, Copy)]
+enum RetryTrigger {
+    Resolve(TransactionId),
+    Deposit,
+}
+
+/// Retries the queued withdrawals which the trigger may have freed funds for, in the order they
+/// were rejected. Withdrawals which still lack the funds stay queued unless out of attempts.
+fn retry_rejected(
+    client_state: ClientState,
+    trigger: RetryTrigger,
+    policy: &Policy,
+) -> ClientState {
+    let retry = policy.retry;
+    let queue = client_state.history.rejected_txs.clone();
+    let emptied = client_state.map_history(|h| h.map_rejected_activity(|_| Vector::new()));
+    queue.into_iter().fold(emptied, |acc, rejected_tx| {
+        let is_triggered = match trigger {
+            // Rejected transactions store all disputes that occured prior to their rejection. If
+            // the resolved tx is present here, the client may now have sufficient avaiable funds
+            // to enact the transaction
+            RetryTrigger::Resolve(resolved_tx) => rejected_tx
+                .disputed_transaction_snapshot
+                .contains(&resolved_tx),
+            RetryTrigger::Deposit => true,
+        };
+        if !is_triggered {
+            return acc.map_history(|h| h.map_rejected_activity(|r| r.push(rejected_tx)));
+        }
+        let overdraft = policy.overdraft_limit(&rejected_tx.activity.client_id().value());
+        let enacted = match rejected_tx.activity {
+            RejectedActivity::Withdrawal(_, _, amount) => acc
+                .try_map_avail(|a| a.sub_within_overdraft(amount, MonetaryAmount::from(overdraft)))
+                .map(|withdrawn| {
+                    withdrawn.map_history(|h| {
+                        h.spend_deposits(amount)
+                            .map_counts(|counts| ActivityCounts {
+                                withdrawals: counts.withdrawals + 1,
+                                ..counts
+                            })
+                    })
+                }),
+        };
+        match enacted {
+            // Previous rejected transaction is enacted and so leaves the queue
+            Ok(withdrawn_state) => withdrawn_state,
+            Err(Underflow) => {
+                let attempts = rejected_tx.attempts + 1;
+                if retry.max_attempts.is_some_and(|max| attempts >= max) {
+                    let abandoned = AbandonedRetry {
+                        client: rejected_tx.activity.client_id().value(),
+                        tx: rejected_tx.activity.tx_id().value(),
+                        reason: RetryAbandonment::MaxAttempts,
+                    };
+                    acc.map_history(|h| {
+                        h.map_abandoned_retries(|a| {
+                            a.push_capped(abandoned, policy.max_client_history)
+                        })
+                    })
+                } else {
+                    let requeued = RejectedTx {
+                        attempts,
+                        ..rejected_tx
+                    };
+                    acc.map_history(|h| h.map_rejected_activity(|r| r.push(requeued)))
+                }
+            }
+        }
+    })
+}
+
+/// Drops queued withdrawals whose id is further than the configured expiry behind the
+/// transaction being applied.
+fn expire_rejected(
+    client_state: ClientState,
+    tx_id: TransactionId,
+    policy: &Policy,
+) -> ClientState {
+    let Some(expiry) = policy.retry.expiry else {
+        return client_state;
+    };
+    let is_expired = |rejected_tx: &RejectedTx| {
+        tx_id.value() > rejected_tx.activity.tx_id().value().saturating_add(expiry)
+    };
+    if !client_state.history.rejected_txs.iter().any(is_expired) {
+        return client_state;
+    }
+    let (expired, queue): (Vector<RejectedTx>, Vector<RejectedTx>) = client_state
+        .history
+        .rejected_txs
+        .clone()
+        .into_iter()
+        .partition(is_expired);
+    let abandoned = expired.iter().map(|rejected_tx| AbandonedRetry {
+        client: rejected_tx.activity.client_id().value(),
+        tx: rejected_tx.activity.tx_id().value(),
+        reason: RetryAbandonment::Expired,
+    });
+    client_state.map_history(|h| {
+        h.map_rejected_activity(|_| queue)
+            .map_abandoned_retries(|a| {
+                abandoned.fold(a.clone(), |acc, abandoned| {
+                    acc.push_capped(abandoned, policy.max_client_history)
+                })
+            })
+    })
+}
+
+/// Resolves and chargebacks only apply to open disputes on unlocked accounts.
+fn check_disputed(client_state: &ClientState, tx_id: TransactionId) -> Result<(), Ignored> {
+    if client_state.is_locked {
+        Err(Ignored::AccountLocked)
+    } else if !client_state.history.account_activity.contains_key(&tx_id) {
+        Err(Ignored::UnknownTransaction)
+    } else if !client_state.history.disputed_txs.contains(&tx_id) {
+        Err(Ignored::NotDisputed)
+    } else {
+        Ok(())
+    }
+}
+
+fn update_resolve(
+    client_state: ClientState,
+    tx_id: TransactionId,
+    policy: &Policy,
+) -> Result<(ClientState, Decision), Ignored> {
+    // Resolving a dispute still in the queue withdraws it before any funds were held
+    if client_state.history.queued_disputes.contains(&tx_id) {
+        let unqueued = client_state.map_history(|h| {
+            h.map_queued_disputes(|q| q.iter().copied().filter(|id| *id != tx_id).collect())
+        });
+        return Ok((unqueued, Decision::DropQueuedDispute));
+    }
+    check_disputed(&client_state, tx_id)?;
+    let maybe_tx_amount = client_state.history.account_activity.get(&tx_id);
+    if let Some(AccountActivity::Deposit(_, tx_id, amount)) = maybe_tx_amount {
+        let shortfall = client_state
+            .history
+            .dispute_shortfalls
+            .get_or_default(tx_id);
+        let held_amount = *amount - shortfall;
+        let new_state = client_state
+            .try_map_held(|h| h.checked_sub(held_amount))
+            .map_err(|Underflow| Ignored::InsufficientHeld)?
+            .try_map_avail(|a| a.checked_add(held_amount))
+            .map_err(|Overflow| Ignored::Overflow)?
+            .map_history(|h| {
+                h.map_disputed_tx(|disputed| disputed.without(tx_id))
+                    .map_dispute_shortfalls(|shortfalls| shortfalls.without(tx_id))
+            });
+
+        let new_state = hold_queued_disputes(
+            retry_rejected(new_state, RetryTrigger::Resolve(*tx_id), policy),
+            policy,
+        );
+        Ok((new_state, Decision::ReleaseDispute))
+    } else {
+        Err(unknown_or_not_deposit(maybe_tx_amount))
+    }
+}
+
+fn update_chargeback(
+    client_state: ClientState,
+    tx_id: TransactionId,
+    policy: DisputePolicy,
+) -> Result<(ClientState, Decision), Ignored> {
+    check_disputed(&client_state, tx_id)?;
+    let maybe_tx_amount = client_state.history.account_activity.get(&tx_id);
+    if let Some(AccountActivity::Deposit(_, tx_id, amount)) = maybe_tx_amount {
+        let shortfall = client_state
+            .history
+            .dispute_shortfalls
+            .get_or_default(tx_id);
+        let held_state = client_state
+            .try_map_held(|h| h.checked_sub(*amount - shortfall))
+            .map_err(|Underflow| Ignored::InsufficientHeld)?;
+        let new_state = if policy == DisputePolicy::HoldUnspent {
+            // Only the unspent portion is charged back. The shortfall is kept so that a reversal
+            // returns no more than was taken.
+            held_state.map_history(|h| {
+                h.map_unspent_deposits(|unspent| {
+                    unspent
+                        .iter()
+                        .filter(|(id, _)| id != tx_id)
+                        .cloned()
+                        .collect()
+                })
+            })
+        } else {
+            // Funds which could not be held at the time of the dispute were already spent, so
+            // the chargeback takes available negative
+            held_state
+                .try_map_avail(|a| a.sub_allow_negative(shortfall))
+                .map_err(|Overflow| Ignored::Overflow)?
+                .map_history(|h| h.map_dispute_shortfalls(|shortfalls| shortfalls.without(tx_id)))
+        }
+        .map_history(|h| {
+            h.map_disputed_tx(|disputed| disputed.without(tx_id))
+                .map_charged_back_txs(|charged_back| charged_back.update(*tx_id))
+                .map_counts(|counts| ActivityCounts {
+                    chargebacks: counts.chargebacks + 1,
+                    ..counts
+                })
+        })
+        .update_locked(true);
+        Ok((new_state, Decision::ChargeBack))
+    } else {
+        Err(unknown_or_not_deposit(maybe_tx_amount))
+    }
+}
+
+/// Returns the amount taken by a chargeback to available funds, the full deposit unless only its
+/// unspent portion was charged back. Unlike other dispute operations it applies to locked
+/// accounts, as the chargeback is what locked them.
+fn update_chargeback_reversal(
+    client_state: ClientState,
+    tx_id: TransactionId,
+    policy: &Policy,
+) -> Result<(ClientState, Decision), Ignored> {
+    if !client_state.history.account_activity.contains_key(&tx_id) {
+        return Err(Ignored::UnknownTransaction);
+    }
+    if !client_state.history.charged_back_txs.contains(&tx_id) {
+        return Err(Ignored::NotChargedBack);
+    }
+    let maybe_tx_amount = client_state.history.account_activity.get(&tx_id);
+    if let Some(AccountActivity::Deposit(_, tx_id, amount)) = maybe_tx_amount {
+        let returned = *amount
+            - client_state
+                .history
+                .dispute_shortfalls
+                .get_or_default(tx_id);
+        let new_state = credit(&client_state, returned)?.map_history(|h| {
+            record_unspent(
+                &h.map_charged_back_txs(|charged_back| charged_back.without(tx_id))
+                    .map_dispute_shortfalls(|shortfalls| shortfalls.without(tx_id)),
+                *tx_id,
+                returned,
+                policy.dispute_policy,
+            )
+        });
+        // Stays locked while any other chargeback stands
+        let is_locked = new_state.is_locked
+            && !(policy.unlock_on_chargeback_reversal
+                && new_state.history.charged_back_txs.is_empty());
+        Ok((
+            new_state.update_locked(is_locked),
+            Decision::ReverseChargeback,
+        ))
+    } else {
+        Err(unknown_or_not_deposit(maybe_tx_amount))
+    }
+}
+
+fn update_hold(
+    client_state: ClientState,
+    tx_id: TransactionId,
+    amount: MonetaryAmount,
+) -> (ClientState, Decision) {
+    let ignored = if client_state.is_locked {
+        Some(Ignored::AccountLocked)
+    } else if client_state.history.admin_holds.contains_key(&tx_id) {
+        Some(Ignored::DuplicateHold)
+    } else if amount <= MonetaryAmount::default() {
+        Some(Ignored::NonPositiveAmount)
+    } else {
+        None
+    };
+    if let Some(reason) = ignored {
+        return (client_state, Decision::Ignore(reason));
+    }
+    let held_state = match client_state.try_map_avail(|a| a.checked_sub(amount)) {
+        Ok(new_state) => new_state.try_map_held(|h| h.checked_add(amount)),
+        Err(Underflow) => {
+            return (
+                client_state,
+                Decision::Ignore(Ignored::InsufficientAvailable),
+            )
+        }
+    };
+    match held_state {
+        Ok(new_state) => {
+            let new_state =
+                new_state.map_history(|h| h.map_admin_holds(|holds| holds.update(tx_id, amount)));
+            (new_state, Decision::PlaceHold)
+        }
+        Err(Overflow) => (client_state, Decision::Ignore(Ignored::Overflow)),
+    }
+}
+
+fn update_release(client_state: ClientState, tx_id: TransactionId) -> (ClientState, Decision) {
+    let maybe_amount = client_state.history.admin_holds.get(&tx_id).copied();
+    match maybe_amount {
+        Some(amount) if !client_state.is_locked => {
+            let released = client_state
+                .try_map_held(|h| h.checked_sub(amount))
+                .map_err(|Underflow| Ignored::InsufficientHeld)
+                .and_then(|s| {
+                    s.try_map_avail(|a| a.checked_add(amount))
+                        .map_err(|Overflow| Ignored::Overflow)
+                });
+            match released {
+                Ok(new_state) => {
+                    let new_state =
+                        new_state.map_history(|h| h.map_admin_holds(|holds| holds.without(&tx_id)));
+                    (new_state, Decision::ReleaseHold)
+                }
+                Err(reason) => (client_state, Decision::Ignore(reason)),
+            }
+        }
+        Some(_) => (client_state, Decision::Ignore(Ignored::AccountLocked)),
+        None => (client_state, Decision::Ignore(Ignored::UnknownHold)),
+    }
+}
+
+/// State of a known client, or the configured opening state for a client not yet seen.
+pub fn client_state_or_opening(
+    ledger: &HashMap<ClientId, ClientState>,
+    c_id: &ClientId,
+    policy: &Policy,
+) -> ClientState {
+    match ledger.get(c_id) {
+        Some(state) => state.clone(),
+        None => ClientState {
+            flags: policy
+                .client_flags
+                .get(&c_id.value())
+                .cloned()
+                .unwrap_or_default(),
+            ..ClientState::default()
+        }
+        .map_avail(|a| a + MonetaryAmount::from(policy.opening_balance)),
+    }
+}
+
+/// Whether a transaction changed held funds by as much as it changed the disputed and
+/// administratively held amounts. Only the change is checked so that states seeded with held
+/// funds, e.g. in tests, are accepted.
+fn held_tracks_disputes(before: Option<&ClientState>, after: &ClientState) -> bool {
+    let holding = |s: &ClientState| s.disputed_amount().checked_add(s.admin_held());
+    let (held, disputed) = match before {
+        Some(s) => (s.held.value(), holding(s)),
+        None => (MonetaryAmount::default(), Ok(MonetaryAmount::default())),
+    };
+    // Held funds and what holds them are never negative, so only their sums can overflow
+    match (disputed, holding(after)) {
+        (Ok(disputed), Ok(holding)) => after.held.value() - held == holding - disputed,
+        _ => false,
+    }
+}
+
+/// Checks the state a transaction produced before it is stored in the ledger.
+fn check_invariants(
+    before: Option<&ClientState>,
+    after: &ClientState,
+) -> Result<(), InvariantViolation> {
+    if after.held.value() < MonetaryAmount::default() {
+        Err(InvariantViolation::NegativeHeld)
+    } else if !held_tracks_disputes(before, after) {
+        Err(InvariantViolation::HeldDrift)
+    } else {
+        Ok(())
+    }
+}
+
+/// Stores the state a transaction produced. If it breaks an invariant the change is rolled back
+/// instead, and the transaction recorded against the client, so that a corrupted state is never
+/// carried through the rest of the fold.
+fn update_client(
+    ledger: HashMap<ClientId, ClientState>,
+    c_id: ClientId,
+    tx_id: TransactionId,
+    (state, decision): (ClientState, Decision),
+    policy: &Policy,
+) -> (HashMap<ClientId, ClientState>, Decision) {
+    match check_invariants(ledger.get(&c_id), &state) {
+        Ok(()) => {
+            let state = expire_rejected(state, tx_id, policy);
+            let state = match decision.is_applied() {
+                true => state.map_history(|h| h.update_last_tx_id(tx_id)),
+                false => state,
+            };
+            (ledger.update(c_id, state), decision)
+        }
+        Err(violation) => {
+            let rolled_back = RolledBackTransaction {
+                client: c_id.value(),
+                tx: tx_id.value(),
+                violation,
+            };
+            // a client whose first transaction is rolled back is left without an account, the
+            // rollback is then only reported by the decision
+            let ledger = match ledger.get(&c_id) {
+                Some(previous) => {
+                    let previous = previous.map_history(|h| {
+                        h.map_rolled_back_txs(|r| {
+                            r.push_capped(rolled_back, policy.max_client_history)
+                        })
+                    });
+                    ledger.update(c_id, previous)
+                }
+                None => ledger,
+            };
+            (ledger, Decision::RollBack)
+        }
+    }
+}
+
+/// Applies a dispute management operation unless it repeats the last operation applied to the
+/// same transaction, e.g. an upstream retry of a resolve. Repeats are recorded rather than
+/// relying on the state checks of each operation to ignore them.
+fn apply_dispute_operation<F>(
+    ledger: HashMap<ClientId, ClientState>,
+    c_id: ClientId,
+    tx_id: TransactionId,
+    operation: DisputeOperation,
+    policy: &Policy,
+    update: F,
+) -> (HashMap<ClientId, ClientState>, Decision)
+where
+    F: FnOnce(ClientState, TransactionId) -> Result<(ClientState, Decision), Ignored>,
+{
+    let client_state = client_state_or_opening(&ledger, &c_id, policy);
+    if client_state.history.last_dispute_operations.get(&tx_id) == Some(&operation) {
+        let retried = RetriedOperation {
+            client: c_id.value(),
+            tx: tx_id.value(),
+            operation,
+        };
+        let ledger = ledger.update(
+            c_id,
+            client_state.map_history(|h| {
+                h.map_retried_operations(|r| r.push_capped(retried, policy.max_client_history))
+            }),
+        );
+        return (ledger, Decision::IgnoreRepeat);
+    }
+    match update(client_state, tx_id) {
+        Ok((state, decision)) => update_client(
+            ledger,
+            c_id,
+            tx_id,
+            (
+                state.map_history(|h| {
+                    h.map_last_dispute_operations(|ops| ops.update(tx_id, operation))
+                }),
+                decision,
+            ),
+            policy,
+        ),
+        Err(reason) => (ledger, Decision::Ignore(reason)),
+    }
+}
+
+pub fn resolve_transaction(
+    transaction: Transaction,
+    ledger: HashMap<ClientId, ClientState>,
+    policy: &Policy,
+) -> HashMap<ClientId, ClientState> {
+    decide_transaction(transaction, ledger, policy).0
+}
+
+/// As [`resolve_transaction`], also returning the decision the engine took on the transaction.
+pub fn decide_transaction(
+    transaction: Transaction,
+    ledger: HashMap<ClientId, ClientState>,
+    policy: &Policy,
+) -> (HashMap<ClientId, ClientState>, Decision) {
+    match transaction {
+        Transaction::Activity(ref activity @ AccountActivity::Deposit(ref c_id, tx_id, amount)) => {
+            let client_state = client_state_or_opening(&ledger, c_id, policy);
+            let new_state = update_deposit(client_state, activity, tx_id, amount, policy);
+            update_client(ledger, c_id.to_owned(), tx_id, new_state, policy)
+        }
+        Transaction::Activity(
+            ref activity @ AccountActivity::Withdrawal(ref c_id, tx_id, amount),
+        ) => {
+            let client_state = client_state_or_opening(&ledger, c_id, policy);
+            let new_state = update_withdrawal(client_state, activity, tx_id, amount, policy);
+            update_client(ledger, c_id.to_owned(), tx_id, new_state, policy)
+        }
+        Transaction::Dispute(DisputeManagement::Dispute(c_id, tx_id)) => apply_dispute_operation(
+            ledger,
+            c_id,
+            tx_id,
+            DisputeOperation::Dispute,
+            policy,
+            |state, tx_id| update_dispute(state, tx_id, policy),
+        ),
+        Transaction::Dispute(DisputeManagement::Resolve(c_id, tx_id)) => apply_dispute_operation(
+            ledger,
+            c_id,
+            tx_id,
+            DisputeOperation::Resolve,
+            policy,
+            |state, tx_id| update_resolve(state, tx_id, policy),
+        ),
+        Transaction::Dispute(DisputeManagement::Chargeback(c_id, tx_id)) => {
+            apply_dispute_operation(
+                ledger,
+                c_id,
+                tx_id,
+                DisputeOperation::Chargeback,
+                policy,
+                |state, tx_id| update_chargeback(state, tx_id, policy.dispute_policy),
+            )
+        }
+        Transaction::Dispute(DisputeManagement::ChargebackReversal(c_id, tx_id)) => {
+            apply_dispute_operation(
+                ledger,
+                c_id,
+                tx_id,
+                DisputeOperation::ChargebackReversal,
+                policy,
+                |state, tx_id| update_chargeback_reversal(state, tx_id, policy),
+            )
+        }
+        Transaction::Admin(AdminHold::Hold(c_id, tx_id, amount)) => {
+            let client_state = client_state_or_opening(&ledger, &c_id, policy);
+            // an ignored hold leaves the ledger as it was, opening no account for the client
+            match update_hold(client_state, tx_id, amount) {
+                (_, decision @ Decision::Ignore(_)) => (ledger, decision),
+                new_state => update_client(ledger, c_id, tx_id, new_state, policy),
+            }
+        }
+        Transaction::Admin(AdminHold::Release(c_id, tx_id)) => {
+            let client_state = client_state_or_opening(&ledger, &c_id, policy);
+            match update_release(client_state, tx_id) {
+                (_, decision @ Decision::Ignore(_)) => (ledger, decision),
+                new_state => update_client(ledger, c_id, tx_id, new_state, policy),
+            }
+        }
+    }
+}
+
+fn resolve_with_middleware(
+    transaction: Transaction,
+    ledger: HashMap<ClientId, ClientState>,
+    policy: &Policy,
+    middleware: &mut [Box<dyn TxMiddleware>],
+) -> HashMap<ClientId, ClientState> {
+    if middleware.is_empty() {
+        return resolve_transaction(transaction, ledger, policy);
+    }
+    let c_id = transaction.client_id();
+    let before = client_state_or_opening(&ledger, &c_id, policy);
+    if !middleware
+        .iter_mut()
+        .all(|m| m.before(&transaction, &before))
+    {
+        return ledger;
+    }
+    let ledger = resolve_transaction(transaction.clone(), ledger, policy);
+    let after = client_state_or_opening(&ledger, &c_id, policy);
+    for m in middleware.iter_mut() {
+        m.after(&transaction, &before, &after);
+    }
+    ledger
+}
+
+pub fn fold_transactions(
+    init_ledger: HashMap<ClientId, ClientState>,
+    transactions: impl Iterator<Item = Transaction>,
+    policy: &Policy,
+    middleware: &mut [Box<dyn TxMiddleware>],
+) -> HashMap<ClientId, ClientState> {
+    transactions.fold(init_ledger, |acc, tx| {
+        resolve_with_middleware(tx, acc, policy, middleware)
+    })
+}
+
+/// Closes a business day: every open dispute ages by a day, and disputes which have reached the
+/// expiry in the policy are resolved as if by a resolve row, in order of client and transaction.
+pub fn end_of_day(
+    ledger: HashMap<ClientId, ClientState>,
+    policy: &Policy,
+    middleware: &mut [Box<dyn TxMiddleware>],
+) -> HashMap<ClientId, ClientState> {
+    let aged: HashMap<ClientId, ClientState> = ledger
+        .into_iter()
+        .map(|(c_id, state)| {
+            let history = &state.history;
+            let ages = history
+                .disputed_txs
+                .iter()
+                .map(|tx_id| (*tx_id, history.dispute_ages.get_or_default(tx_id) + 1))
+                .collect();
+            let state = state.map_history(|h| h.map_dispute_ages(|_| ages));
+            (c_id, state)
+        })
+        .collect();
+    let Some(expiry) = policy.dispute_expiry_days else {
+        return aged;
+    };
+    let mut expired: Vec<(ClientId, TransactionId)> = aged
+        .iter()
+        .flat_map(|(c_id, state)| {
+            state
+                .history
+                .dispute_ages
+                .iter()
+                .filter(|(_, age)| **age >= expiry)
+                .map(|(tx_id, _)| (c_id.to_owned(), *tx_id))
+        })
+        .collect();
+    expired.sort_by_key(|(c_id, tx_id)| (c_id.to_owned(), tx_id.value()));
+    let resolves = expired
+        .into_iter()
+        .map(|(c_id, tx_id)| Transaction::Dispute(DisputeManagement::Resolve(c_id, tx_id)));
+    fold_transactions(aged, resolves, policy, middleware)
+}
+
+/// As [`fold_transactions`], first passing where each transaction was read from to the
+/// middleware.
+pub fn fold_envelopes(
+    init_ledger: HashMap<ClientId, ClientState>,
+    envelopes: impl Iterator<Item = TransactionEnvelope>,
+    policy: &Policy,
+    middleware: &mut [Box<dyn TxMiddleware>],
+) -> HashMap<ClientId, ClientState> {
+    envelopes.fold(init_ledger, |acc, envelope| {
+        for m in middleware.iter_mut() {
+            m.source(&envelope.metadata);
+        }
+        resolve_with_middleware(envelope.transaction, acc, policy, middleware)
+    })
+}
+
+// Used for testing
+#[cfg_attr(not(feature = "arrow"), allow(dead_code))]
+fn create_ledger_with_init(
+    init_ledger: HashMap<ClientId, ClientState>,
+    transactions: Box<dyn Iterator<Item = Transaction>>,
+    policy: &Policy,
+) -> Ledger {
+    Ledger::from_states(fold_transactions(
+        init_ledger,
+        transactions,
+        policy,
+        &mut [],
+    ))
+}
+
+// public interface
+#[cfg_attr(not(feature = "arrow"), allow(dead_code))]
+pub fn create_ledger(
+    transactions: Box<dyn Iterator<Item = Transaction>>,
+    policy: &Policy,
+) -> Ledger {
+    create_ledger_with_init(HashMap::default(), transactions, policy)
+}
+
+pub fn create_ledger_with_middleware(
+    envelopes: Box<dyn Iterator<Item = TransactionEnvelope>>,
+    policy: &Policy,
+    middleware: &mut [Box<dyn TxMiddleware>],
+) -> Ledger {
+    Ledger::from_states(fold_envelopes(
+        HashMap::default(),
+        envelopes,
+        policy,
+        middleware,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::types::{
+        key, AccountActivity, AdminHold, AvailableAmount, ClientFlags, ClientId, ClientState,
+        Decision, DisputeManagement, HeldAmount, Ignored, MonetaryAmount, Transaction,
+        TransactionHistory, TransactionId, Underflow,
+    };
+    #[cfg(not(feature = "no-history"))]
+    use crate::engine::types::{InvariantViolation, RejectedActivity, RolledBackTransaction};
+    use im::HashMap;
+
+    use super::{create_ledger_with_init, decide_transaction, fold_transactions, update_client};
+    use crate::engine::policy::{DisputePolicy, Policy};
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn cannot_withdraw_under_avail() {
+        let client_id = ClientId::new(key(1));
+
+        let init_state = ClientState {
+            available: AvailableAmount::from(MonetaryAmount::new(5.0)),
+            held: HeldAmount::try_from(MonetaryAmount::new(5.0)).unwrap(),
+            history: TransactionHistory::default(),
+            is_locked: false,
+            flags: ClientFlags::default(),
+        };
+        let init_ledger: HashMap<ClientId, ClientState> =
+            [(client_id.to_owned(), init_state.clone())]
+                .into_iter()
+                .collect();
+
+        let transactions = vec![Transaction::Activity(AccountActivity::Withdrawal(
+            client_id.to_owned(),
+            TransactionId::new(1),
+            MonetaryAmount::new(6.0),
+        ))];
+
+        let final_ledger = create_ledger_with_init(
+            init_ledger,
+            Box::new(transactions.into_iter()),
+            &Policy::default(),
+        );
+
+        let client_ledger = final_ledger
+            .0
+            .into_iter()
+            .find(|x| x.id == client_id)
+            .unwrap();
+
+        assert_eq!(client_ledger.total, MonetaryAmount::new(10.0));
+        assert_eq!(client_ledger.available, MonetaryAmount::new(5.0));
+        assert_eq!(client_ledger.held, MonetaryAmount::new(5.0));
+    }
+
+    #[test]
+    fn can_withdraw_within_avail() {
+        let client_id = ClientId::new(key(1));
+
+        let init_state = ClientState {
+            available: AvailableAmount::from(MonetaryAmount::new(5.0)),
+            held: HeldAmount::try_from(MonetaryAmount::new(5.0)).unwrap(),
+            history: TransactionHistory::default(),
+            is_locked: false,
+            flags: ClientFlags::default(),
+        };
+        let init_ledger: HashMap<ClientId, ClientState> =
+            [(client_id.to_owned(), init_state.clone())]
+                .into_iter()
+                .collect();
+
+        let transactions = vec![Transaction::Activity(AccountActivity::Withdrawal(
+            client_id.to_owned(),
+            TransactionId::new(1),
+            MonetaryAmount::new(5.0),
+        ))];
+
+        let final_ledger = create_ledger_with_init(
+            init_ledger,
+            Box::new(transactions.into_iter()),
+            &Policy::default(),
+        );
+
+        let client_ledger = final_ledger
+            .0
+            .into_iter()
+            .find(|x| x.id == client_id)
+            .unwrap();
+
+        assert_eq!(client_ledger.total, MonetaryAmount::new(5.0));
+        assert_eq!(client_ledger.available, MonetaryAmount::new(0.0));
+        assert_eq!(client_ledger.held, MonetaryAmount::new(5.0));
+    }
+
+    #[test]
+    fn deposit_increases_total_and_avail() {
+        let client_id = ClientId::new(key(1));
+
+        let init_state = ClientState {
+            available: AvailableAmount::from(MonetaryAmount::new(5.0)),
+            held: HeldAmount::try_from(MonetaryAmount::new(5.0)).unwrap(),
+            history: TransactionHistory::default(),
+            is_locked: false,
+            flags: ClientFlags::default(),
+        };
+        let init_ledger: HashMap<ClientId, ClientState> =
+            [(client_id.to_owned(), init_state.clone())]
+                .into_iter()
+                .collect();
+
+        let transactions = vec![Transaction::Activity(AccountActivity::Deposit(
+            client_id.to_owned(),
+            TransactionId::new(1),
+            MonetaryAmount::new(5.0),
+        ))];
+
+        let final_ledger = create_ledger_with_init(
+            init_ledger,
+            Box::new(transactions.into_iter()),
+            &Policy::default(),
+        );
+
+        let client_ledger = final_ledger
+            .0
+            .into_iter()
+            .find(|x| x.id == client_id)
+            .unwrap();
+
+        assert_eq!(client_ledger.total, MonetaryAmount::new(15.0));
+        assert_eq!(client_ledger.available, MonetaryAmount::new(10.0));
+        assert_eq!(client_ledger.held, MonetaryAmount::new(5.0));
+    }
+
+    #[cfg(not(feature = "no-history"))]
+    #[test]
+    fn disputed_deposit_reduces_avail() {
+        let client_id = ClientId::new(key(1));
+
+        let init_state = ClientState {
+            available: AvailableAmount::from(MonetaryAmount::new(10.0)),
+            held: HeldAmount::try_from(MonetaryAmount::new(0.0)).unwrap(),
+            history: TransactionHistory::default(),
+            is_locked: false,
+            flags: ClientFlags::default(),
+        };
+        let init_ledger: HashMap<ClientId, ClientState> =
+            [(client_id.to_owned(), init_state.clone())]
+                .into_iter()
+                .collect();
+
+        let transactions = vec![
+            Transaction::Activity(AccountActivity::Deposit(
+                client_id.to_owned(),
+                TransactionId::new(1),
+                MonetaryAmount::new(5.0),
+            )),
+            Transaction::Dispute(DisputeManagement::Dispute(
+                client_id.to_owned(),
+                TransactionId::new(1),
+            )),
+        ];
+
+        let final_ledger = create_ledger_with_init(
+            init_ledger,
+            Box::new(transactions.into_iter()),
+            &Policy::default(),
+        );
+
+        let client_ledger = final_ledger
+            .0
+            .into_iter()
+            .find(|x| x.id == client_id)
+            .unwrap();
+
+        assert_eq!(client_ledger.available, MonetaryAmount::new(10.0));
+    }
+
+    #[test]
+    fn disputed_deposit_does_not_reduce_total() {
+        let client_id = ClientId::new(key(1));
+
+        let init_state = ClientState {
+            available: AvailableAmount::from(MonetaryAmount::new(10.0)),
+            held: HeldAmount::try_from(MonetaryAmount::new(0.0)).unwrap(),
+            history: TransactionHistory::default(),
+            is_locked: false,
+            flags: ClientFlags::default(),
+        };
+        let init_ledger: HashMap<ClientId, ClientState> =
+            [(client_id.to_owned(), init_state.clone())]
+                .into_iter()
+                .collect();
+
+        let transactions = vec![
+            Transaction::Activity(AccountActivity::Deposit(
+                client_id.to_owned(),
+                TransactionId::new(1),
+                MonetaryAmount::new(5.0),
+            )),
+            Transaction::Dispute(DisputeManagement::Dispute(
+                client_id.to_owned(),
+                TransactionId::new(1),
+            )),
+        ];
+
+        let final_ledger = create_ledger_with_init(
+            init_ledger,
+            Box::new(transactions.into_iter()),
+            &Policy::default(),
+        );
+
+        let client_ledger = final_ledger
+            .0
+            .into_iter()
+            .find(|x| x.id == client_id)
+            .unwrap();
+
+        assert_eq!(client_ledger.total, MonetaryAmount::new(15.0));
+    }
+
+    #[cfg(not(feature = "no-history"))]
+    #[test]
+    fn dispute_will_increase_held_amount() {
+        let client_id = ClientId::new(key(1));
+
+        let init_state = ClientState {
+            available: AvailableAmount::from(MonetaryAmount::new(10.0)),
+            held: HeldAmount::try_from(MonetaryAmount::new(0.0)).unwrap(),
+            history: TransactionHistory::default(),
+            is_locked: false,
+            flags: ClientFlags::default(),
+        };
+        let init_ledger: HashMap<ClientId, ClientState> =
+            [(client_id.to_owned(), init_state.clone())]
+                .into_iter()
+                .collect();
+
+        let transactions = vec![
+            Transaction::Activity(AccountActivity::Deposit(
+                client_id.to_owned(),
+                TransactionId::new(1),
+                MonetaryAmount::new(5.0),
+            )),
+            Transaction::Dispute(DisputeManagement::Dispute(
+                client_id.to_owned(),
+                TransactionId::new(1),
+            )),
+        ];
+
+        let final_ledger = create_ledger_with_init(
+            init_ledger,
+            Box::new(transactions.into_iter()),
+            &Policy::default(),
+        );
+
+        let client_ledger = final_ledger
+            .0
+            .into_iter()
+            .find(|x| x.id == client_id)
+            .unwrap();
+
+        assert_eq!(client_ledger.held, MonetaryAmount::new(5.0));
+    }
+
+    #[test]
+    fn disputes_against_withdrawals_are_ignored() {
+        let client_id = ClientId::new(key(1));
+
+        let init_state = ClientState {
+            available: AvailableAmount::from(MonetaryAmount::new(10.0)),
+            held: HeldAmount::try_from(MonetaryAmount::new(0.0)).unwrap(),
+            history: TransactionHistory::default(),
+            is_locked: false,
+            flags: ClientFlags::default(),
+        };
+        let init_ledger: HashMap<ClientId, ClientState> =
+            [(client_id.to_owned(), init_state.clone())]
+                .into_iter()
+                .collect();
+
+        let transactions = vec![
+            Transaction::Activity(AccountActivity::Withdrawal(
+                client_id.to_owned(),
+                TransactionId::new(1),
+                MonetaryAmount::new(5.0),
+            )),
+            Transaction::Dispute(DisputeManagement::Dispute(
+                client_id.to_owned(),
+                TransactionId::new(1),
+            )),
+        ];
+
+        let final_ledger = create_ledger_with_init(
+            init_ledger,
+            Box::new(transactions.into_iter()),
+            &Policy::default(),
+        );
+
+        let client_ledger = final_ledger
+            .0
+            .into_iter()
+            .find(|x| x.id == client_id)
+            .unwrap();
+
+        assert_eq!(client_ledger.total, MonetaryAmount::new(5.0));
+        assert_eq!(client_ledger.available, MonetaryAmount::new(5.0));
+        assert_eq!(client_ledger.held, MonetaryAmount::new(0.0));
+    }
+
+    #[test]
+    fn dispute_will_ignore_incorrect_tx() {
+        let client_id = ClientId::new(key(1));
+
+        let init_state = ClientState {
+            available: AvailableAmount::from(MonetaryAmount::new(10.0)),
+            held: HeldAmount::try_from(MonetaryAmount::new(0.0)).unwrap(),
+            history: TransactionHistory::default(),
+            is_locked: false,
+            flags: ClientFlags::default(),
+        };
+        let init_ledger: HashMap<ClientId, ClientState> =
+            [(client_id.to_owned(), init_state.clone())]
+                .into_iter()
+                .collect();
+
+        let transactions = vec![
+            Transaction::Activity(AccountActivity::Deposit(
+                client_id.to_owned(),
+                TransactionId::new(1),
+                MonetaryAmount::new(5.0),
+            )),
+            Transaction::Dispute(DisputeManagement::Dispute(
+                client_id.to_owned(),
+                TransactionId::new(2),
+            )),
+        ];
+
+        let final_ledger = create_ledger_with_init(
+            init_ledger,
+            Box::new(transactions.into_iter()),
+            &Policy::default(),
+        );
+
+        let client_ledger = final_ledger
+            .0
+            .into_iter()
+            .find(|x| x.id == client_id)
+            .unwrap();
+
+        assert_eq!(client_ledger.total, MonetaryAmount::new(15.0));
+        assert_eq!(client_ledger.available, MonetaryAmount::new(15.0));
+        assert_eq!(client_ledger.held, MonetaryAmount::new(0.0));
+    }
+
+    #[cfg(not(feature = "no-history"))]
+    #[test]
+    fn dispute_is_one_per_tx() {
+        let client_id = ClientId::new(key(1));
+
+        let init_state = ClientState {
+            available: AvailableAmount::from(MonetaryAmount::new(10.0)),
+            held: HeldAmount::try_from(MonetaryAmount::new(0.0)).unwrap(),
+            history: TransactionHistory::default(),
+            is_locked: false,
+            flags: ClientFlags::default(),
+        };
+        let init_ledger: HashMap<ClientId, ClientState> =
+            [(client_id.to_owned(), init_state.clone())]
+                .into_iter()
+                .collect();
+
+        let transactions = vec![
+            Transaction::Activity(AccountActivity::Deposit(
+                client_id.to_owned(),
+                TransactionId::new(1),
+                MonetaryAmount::new(5.0),
+            )),
+            Transaction::Dispute(DisputeManagement::Dispute(
+                client_id.to_owned(),
+                TransactionId::new(1),
+            )),
+            Transaction::Dispute(DisputeManagement::Dispute(
+                client_id.to_owned(),
+                TransactionId::new(1),
+            )),
+        ];
+
+        let final_ledger = create_ledger_with_init(
+            init_ledger,
+            Box::new(transactions.into_iter()),
+            &Policy::default(),
+        );
+
+        let client_ledger = final_ledger
+            .0
+            .into_iter()
+            .find(|x| x.id == client_id)
+            .unwrap();
+
+        assert_eq!(client_ledger.total, MonetaryAmount::new(15.0));
+        assert_eq!(client_ledger.available, MonetaryAmount::new(10.0));
+        assert_eq!(client_ledger.held, MonetaryAmount::new(5.0));
+    }
+
+    #[test]
+    fn resolve_will_release_held_funds() {
+        let client_id = ClientId::new(key(1));
+
+        let init_state = ClientState {
+            available: AvailableAmount::from(MonetaryAmount::new(10.0)),
+            held: HeldAmount::try_from(MonetaryAmount::new(0.0)).unwrap(),
+            history: TransactionHistory::default(),
+            is_locked: false,
+            flags: ClientFlags::default(),
+        };
+        let init_ledger: HashMap<ClientId, ClientState> =
+            [(client_id.to_owned(), init_state.clone())]
+                .into_iter()
+                .collect();
+
+        let transactions = vec![
+            Transaction::Activity(AccountActivity::Deposit(
+                client_id.to_owned(),
+                TransactionId::new(1),
+                MonetaryAmount::new(5.0),
+            )),
+            Transaction::Dispute(DisputeManagement::Dispute(
+                client_id.to_owned(),
+                TransactionId::new(1),
+            )),
+            Transaction::Dispute(DisputeManagement::Resolve(
+                client_id.to_owned(),
+                TransactionId::new(1),
+            )),
+        ];
+
+        let final_ledger = create_ledger_with_init(
+            init_ledger,
+            Box::new(transactions.into_iter()),
+            &Policy::default(),
+        );
+
+        let client_ledger = final_ledger
+            .0
+            .into_iter()
+            .find(|x| x.id == client_id)
+            .unwrap();
+
+        assert_eq!(client_ledger.total, MonetaryAmount::new(15.0));
+        assert_eq!(client_ledger.available, MonetaryAmount::new(15.0));
+        assert_eq!(client_ledger.held, MonetaryAmount::new(0.0));
+    }
+
+    #[test]
+    fn resolve_against_undisputed_tx_is_ignored() {
+        let client_id = ClientId::new(key(1));
+
+        let init_state = ClientState {
+            available: AvailableAmount::from(MonetaryAmount::new(10.0)),
+            held: HeldAmount::try_from(MonetaryAmount::new(0.0)).unwrap(),
+            history: TransactionHistory::default(),
+            is_locked: false,
+            flags: ClientFlags::default(),
+        };
+        let init_ledger: HashMap<ClientId, ClientState> =
+            [(client_id.to_owned(), init_state.clone())]
+                .into_iter()
+                .collect();
+
+        let transactions = vec![
+            Transaction::Activity(AccountActivity::Deposit(
+                client_id.to_owned(),
+                TransactionId::new(1),
+                MonetaryAmount::new(5.0),
+            )),
+            Transaction::Dispute(DisputeManagement::Resolve(
+                client_id.to_owned(),
+                TransactionId::new(1),
+            )),
+        ];
+
+        let final_ledger = create_ledger_with_init(
+            init_ledger,
+            Box::new(transactions.into_iter()),
+            &Policy::default(),
+        );
+
+        let client_ledger = final_ledger
+            .0
+            .into_iter()
+            .find(|x| x.id == client_id)
+            .unwrap();
+
+        assert_eq!(client_ledger.total, MonetaryAmount::new(15.0));
+        assert_eq!(client_ledger.available, MonetaryAmount::new(15.0));
+        assert_eq!(client_ledger.held, MonetaryAmount::new(0.0));
+    }
+
+    #[cfg(not(feature = "no-history"))]
+    #[test]
+    fn resolve_against_non_tx_is_ignored() {
+        let client_id = ClientId::new(key(1));
+
+        let init_state = ClientState {
+            available: AvailableAmount::from(MonetaryAmount::new(10.0)),
+            held: HeldAmount::try_from(MonetaryAmount::new(0.0)).unwrap(),
+            history: TransactionHistory::default(),
+            is_locked: false,
+            flags: ClientFlags::default(),
+        };
+        let init_ledger: HashMap<ClientId, ClientState> =
+            [(client_id.to_owned(), init_state.clone())]
+                .into_iter()
+                .collect();
+
+        let transactions = vec![
+            Transaction::Activity(AccountActivity::Deposit(
+                client_id.to_owned(),
+                TransactionId::new(1),
+                MonetaryAmount::new(5.0),
+            )),
+            Transaction::Dispute(DisputeManagement::Dispute(
+                client_id.to_owned(),
+                TransactionId::new(1),
+            )),
+            Transaction::Dispute(DisputeManagement::Resolve(
+                client_id.to_owned(),
+                TransactionId::new(2),
+            )),
+        ];
+
+        let final_ledger = create_ledger_with_init(
+            init_ledger,
+            Box::new(transactions.into_iter()),
+            &Policy::default(),
+        );
+
+        let client_ledger = final_ledger
+            .0
+            .into_iter()
+            .find(|x| x.id == client_id)
+            .unwrap();
+
+        assert_eq!(client_ledger.total, MonetaryAmount::new(15.0));
+        assert_eq!(client_ledger.available, MonetaryAmount::new(10.0));
+        assert_eq!(client_ledger.held, MonetaryAmount::new(5.0));
+    }
+
+    #[cfg(not(feature = "no-history"))]
+    #[test]
+    fn chargeback_locks_account() {
+        let client_id = ClientId::new(key(1));
+
+        let init_state = ClientState {
+            available: AvailableAmount::from(MonetaryAmount::new(10.0)),
+            held: HeldAmount::try_from(MonetaryAmount::new(0.0)).unwrap(),
+            history: TransactionHistory::default(),
+            is_locked: false,
+            flags: ClientFlags::default(),
+        };
+        let init_ledger: HashMap<ClientId, ClientState> =
+            [(client_id.to_owned(), init_state.clone())]
+                .into_iter()
+                .collect();
+
+        let transactions = vec![
+            Transaction::Activity(AccountActivity::Deposit(
+                client_id.to_owned(),
+                TransactionId::new(1),
+                MonetaryAmount::new(5.0),
+            )),
+            Transaction::Dispute(DisputeManagement::Dispute(
+                client_id.to_owned(),
+                TransactionId::new(1),
+            )),
+            Transaction::Dispute(DisputeManagement::Chargeback(
+                client_id.to_owned(),
+                TransactionId::new(1),
+            )),
+        ];
+
+        let final_ledger = create_ledger_with_init(
+            init_ledger,
+            Box::new(transactions.into_iter()),
+            &Policy::default(),
+        );
+
+        let client_ledger = final_ledger
+            .0
+            .into_iter()
+            .find(|x| x.id == client_id)
+            .unwrap();
+
+        assert!(client_ledger.is_locked);
+    }
+
+    #[cfg(not(feature = "no-history"))]
+    #[test]
+    fn chargeback_reduces_total() {
+        let client_id = ClientId::new(key(1));
+
+        let init_state = ClientState {
+            available: AvailableAmount::from(MonetaryAmount::new(10.0)),
+            held: HeldAmount::try_from(MonetaryAmount::new(0.0)).unwrap(),
+            history: TransactionHistory::default(),
+            is_locked: false,
+            flags: ClientFlags::default(),
+        };
+        let init_ledger: HashMap<ClientId, ClientState> =
+            [(client_id.to_owned(), init_state.clone())]
+                .into_iter()
+                .collect();
+
+        let transactions = vec![
+            Transaction::Activity(AccountActivity::Deposit(
+                client_id.to_owned(),
+                TransactionId::new(1),
+                MonetaryAmount::new(5.0),
+            )),
+            Transaction::Dispute(DisputeManagement::Dispute(
+                client_id.to_owned(),
+                TransactionId::new(1),
+            )),
+            Transaction::Dispute(DisputeManagement::Chargeback(
+                client_id.to_owned(),
+                TransactionId::new(1),
+            )),
+        ];
+
+        let final_ledger = create_ledger_with_init(
+            init_ledger,
+            Box::new(transactions.into_iter()),
+            &Policy::default(),
+        );
+
+        let client_ledger = final_ledger
+            .0
+            .into_iter()
+            .find(|x| x.id == client_id)
+            .unwrap();
+
+        assert_eq!(client_ledger.total, MonetaryAmount::new(10.));
+    }
+
+    #[test]
+    fn chargeback_reduces_held() {
+        let client_id = ClientId::new(key(1));
+
+        let init_state = ClientState {
+            available: AvailableAmount::from(MonetaryAmount::new(10.0)),
+            held: HeldAmount::try_from(MonetaryAmount::new(0.0)).unwrap(),
+            history: TransactionHistory::default(),
+            is_locked: false,
+            flags: ClientFlags::default(),
+        };
+        let init_ledger: HashMap<ClientId, ClientState> =
+            [(client_id.to_owned(), init_state.clone())]
+                .into_iter()
+                .collect();
+
+        let transactions = vec![
+            Transaction::Activity(AccountActivity::Deposit(
+                client_id.to_owned(),
+                TransactionId::new(1),
+                MonetaryAmount::new(5.0),
+            )),
+            Transaction::Dispute(DisputeManagement::Dispute(
+                client_id.to_owned(),
+                TransactionId::new(1),
+            )),
+            Transaction::Dispute(DisputeManagement::Chargeback(
+                client_id.to_owned(),
+                TransactionId::new(1),
+            )),
+        ];
+
+        let final_ledger = create_ledger_with_init(
+            init_ledger,
+            Box::new(transactions.into_iter()),
+            &Policy::default(),
+        );
+
+        let client_ledger = final_ledger
+            .0
+            .into_iter()
+            .find(|x| x.id == client_id)
+            .unwrap();
+
+        assert_eq!(client_ledger.held, MonetaryAmount::new(0.));
+    }
+
+    #[cfg(not(feature = "no-history"))]
+    #[test]
+    fn chargeback_ignored_if_tx_does_not_exist() {
+        let client_id = ClientId::new(key(1));
+
+        let init_state = ClientState {
+            available: AvailableAmount::from(MonetaryAmount::new(10.0)),
+            held: HeldAmount::try_from(MonetaryAmount::new(0.0)).unwrap(),
+            history: TransactionHistory::default(),
+            is_locked: false,
+            flags: ClientFlags::default(),
+        };
+        let init_ledger: HashMap<ClientId, ClientState> =
+            [(client_id.to_owned(), init_state.clone())]
+                .into_iter()
+                .collect();
+
+        let transactions = vec![
+            Transaction::Activity(AccountActivity::Deposit(
+                client_id.to_owned(),
+                TransactionId::new(1),
+                MonetaryAmount::new(5.0),
+            )),
+            Transaction::Dispute(DisputeManagement::Dispute(
+                client_id.to_owned(),
+                TransactionId::new(1),
+            )),
+            Transaction::Dispute(DisputeManagement::Chargeback(
+                client_id.to_owned(),
+                TransactionId::new(2),
+            )),
+        ];
+
+        let final_ledger = create_ledger_with_init(
+            init_ledger,
+            Box::new(transactions.into_iter()),
+            &Policy::default(),
+        );
+
+        let client_ledger = final_ledger
+            .0
+            .into_iter()
+            .find(|x| x.id == client_id)
+            .unwrap();
+
+        assert_eq!(client_ledger.total, MonetaryAmount::new(15.));
+        assert_eq!(client_ledger.available, MonetaryAmount::new(10.));
+        assert_eq!(client_ledger.held, MonetaryAmount::new(5.));
+    }
+
+    #[test]
+    fn chargeback_ignored_if_tx_undisputed() {
+        let client_id = ClientId::new(key(1));
+
+        let init_state = ClientState {
+            available: AvailableAmount::from(MonetaryAmount::new(10.0)),
+            held: HeldAmount::try_from(MonetaryAmount::new(0.0)).unwrap(),
+            history: TransactionHistory::default(),
+            is_locked: false,
+            flags: ClientFlags::default(),
+        };
+        let init_ledger: HashMap<ClientId, ClientState> =
+            [(client_id.to_owned(), init_state.clone())]
+                .into_iter()
+                .collect();
+
+        let transactions = vec![
+            Transaction::Activity(AccountActivity::Deposit(
+                client_id.to_owned(),
+                TransactionId::new(1),
+                MonetaryAmount::new(5.0),
+            )),
+            Transaction::Dispute(DisputeManagement::Chargeback(
+                client_id.to_owned(),
+                TransactionId::new(1),
+            )),
+        ];
+
+        let final_ledger = create_ledger_with_init(
+            init_ledger,
+            Box::new(transactions.into_iter()),
+            &Policy::default(),
+        );
+
+        let client_ledger = final_ledger
+            .0
+            .into_iter()
+            .find(|x| x.id == client_id)
+            .unwrap();
+
+        assert_eq!(client_ledger.total, MonetaryAmount::new(15.));
+        assert_eq!(client_ledger.available, MonetaryAmount::new(15.));
+        assert_eq!(client_ledger.held, MonetaryAmount::new(0.));
+    }
+
+    #[cfg(not(feature = "no-history"))]
+    #[test]
+    fn dispute_of_withdrawn_funds_allows_negative_avail() {
+        let client_id = ClientId::new(key(1));
+
+        let init_state = ClientState {
+            available: AvailableAmount::from(MonetaryAmount::new(10.0)),
+            held: HeldAmount::try_from(MonetaryAmount::new(0.0)).unwrap(),
+            history: TransactionHistory::default(),
+            is_locked: false,
+            flags: ClientFlags::default(),
+        };
+        let init_ledger: HashMap<ClientId, ClientState> =
+            [(client_id.to_owned(), init_state.clone())]
+                .into_iter()
+                .collect();
+
+        let transactions = vec![
+            Transaction::Activity(AccountActivity::Deposit(
+                client_id.to_owned(),
+                TransactionId::new(1),
+                MonetaryAmount::new(5.0),
+            )),
+            Transaction::Activity(AccountActivity::Withdrawal(
+                client_id.to_owned(),
+                TransactionId::new(2),
+                MonetaryAmount::new(12.0),
+            )),
+            Transaction::Dispute(DisputeManagement::Dispute(
+                client_id.to_owned(),
+                TransactionId::new(1),
+            )),
+        ];
+
+        let final_ledger = create_ledger_with_init(
+            init_ledger,
+            Box::new(transactions.into_iter()),
+            &Policy::default().with_dispute_policy(DisputePolicy::AllowNegative),
+        );
+
+        let client_ledger = final_ledger
+            .0
+            .into_iter()
+            .find(|x| x.id == client_id)
+            .unwrap();
+
+        assert_eq!(client_ledger.total, MonetaryAmount::new(3.0));
+        assert_eq!(client_ledger.available, MonetaryAmount::new(-2.0));
+        assert_eq!(client_ledger.held, MonetaryAmount::new(5.0));
+    }
+
+    #[cfg(not(feature = "no-history"))]
+    #[test]
+    fn dispute_of_withdrawn_funds_holds_only_avail() {
+        let client_id = ClientId::new(key(1));
+
+        let init_state = ClientState {
+            available: AvailableAmount::from(MonetaryAmount::new(10.0)),
+            held: HeldAmount::try_from(MonetaryAmount::new(0.0)).unwrap(),
+            history: TransactionHistory::default(),
+            is_locked: false,
+            flags: ClientFlags::default(),
+        };
+        let init_ledger: HashMap<ClientId, ClientState> =
+            [(client_id.to_owned(), init_state.clone())]
+                .into_iter()
+                .collect();
+
+        let transactions = vec![
+            Transaction::Activity(AccountActivity::Deposit(
+                client_id.to_owned(),
+                TransactionId::new(1),
+                MonetaryAmount::new(5.0),
+            )),
+            Transaction::Activity(AccountActivity::Withdrawal(
+                client_id.to_owned(),
+                TransactionId::new(2),
+                MonetaryAmount::new(12.0),
+            )),
+            Transaction::Dispute(DisputeManagement::Dispute(
+                client_id.to_owned(),
+                TransactionId::new(1),
+            )),
+        ];
+
+        let final_ledger = create_ledger_with_init(
+            init_ledger,
+            Box::new(transactions.into_iter()),
+            &Policy::default().with_dispute_policy(DisputePolicy::HoldAvailable),
+        );
+
+        let client_ledger = final_ledger
+            .0
+            .into_iter()
+            .find(|x| x.id == client_id)
+            .unwrap();
+
+        assert_eq!(client_ledger.total, MonetaryAmount::new(3.0));
+        assert_eq!(client_ledger.available, MonetaryAmount::new(0.0));
+        assert_eq!(client_ledger.held, MonetaryAmount::new(3.0));
+    }
+
+    #[test]
+    fn resolve_releases_only_held_portion_of_short_dispute() {
+        let client_id = ClientId::new(key(1));
+
+        let init_state = ClientState {
+            available: AvailableAmount::from(MonetaryAmount::new(10.0)),
+            held: HeldAmount::try_from(MonetaryAmount::new(0.0)).unwrap(),
+            history: TransactionHistory::default(),
+            is_locked: false,
+            flags: ClientFlags::default(),
+        };
+        let init_ledger: HashMap<ClientId, ClientState> =
+            [(client_id.to_owned(), init_state.clone())]
+                .into_iter()
+                .collect();
+
+        let transactions = vec![
+            Transaction::Activity(AccountActivity::Deposit(
+                client_id.to_owned(),
+                TransactionId::new(1),
+                MonetaryAmount::new(5.0),
+            )),
+            Transaction::Activity(AccountActivity::Withdrawal(
+                client_id.to_owned(),
+                TransactionId::new(2),
+                MonetaryAmount::new(12.0),
+            )),
+            Transaction::Dispute(DisputeManagement::Dispute(
+                client_id.to_owned(),
+                TransactionId::new(1),
+            )),
+            Transaction::Dispute(DisputeManagement::Resolve(
+                client_id.to_owned(),
+                TransactionId::new(1),
+            )),
+        ];
+
+        let final_ledger = create_ledger_with_init(
+            init_ledger,
+            Box::new(transactions.into_iter()),
+            &Policy::default().with_dispute_policy(DisputePolicy::HoldAvailable),
+        );
+
+        let client_ledger = final_ledger
+            .0
+            .into_iter()
+            .find(|x| x.id == client_id)
+            .unwrap();
+
+        assert_eq!(client_ledger.total, MonetaryAmount::new(3.0));
+        assert_eq!(client_ledger.available, MonetaryAmount::new(3.0));
+        assert_eq!(client_ledger.held, MonetaryAmount::new(0.0));
+    }
+
+    #[cfg(not(feature = "no-history"))]
+    #[test]
+    fn chargeback_of_short_dispute_debits_shortfall_from_avail() {
+        let client_id = ClientId::new(key(1));
+
+        let init_state = ClientState {
+            available: AvailableAmount::from(MonetaryAmount::new(10.0)),
+            held: HeldAmount::try_from(MonetaryAmount::new(0.0)).unwrap(),
+            history: TransactionHistory::default(),
+            is_locked: false,
+            flags: ClientFlags::default(),
+        };
+        let init_ledger: HashMap<ClientId, ClientState> =
+            [(client_id.to_owned(), init_state.clone())]
+                .into_iter()
+                .collect();
+
+        let transactions = vec![
+            Transaction::Activity(AccountActivity::Deposit(
+                client_id.to_owned(),
+                TransactionId::new(1),
+                MonetaryAmount::new(5.0),
+            )),
+            Transaction::Activity(AccountActivity::Withdrawal(
+                client_id.to_owned(),
+                TransactionId::new(2),
+                MonetaryAmount::new(12.0),
+            )),
+            Transaction::Dispute(DisputeManagement::Dispute(
+                client_id.to_owned(),
+                TransactionId::new(1),
+            )),
+            Transaction::Dispute(DisputeManagement::Chargeback(
+                client_id.to_owned(),
+                TransactionId::new(1),
+            )),
+        ];
+
+        let final_ledger = create_ledger_with_init(
+            init_ledger,
+            Box::new(transactions.into_iter()),
+            &Policy::default().with_dispute_policy(DisputePolicy::HoldAvailable),
+        );
+
+        let client_ledger = final_ledger
+            .0
+            .into_iter()
+            .find(|x| x.id == client_id)
+            .unwrap();
+
+        assert_eq!(client_ledger.total, MonetaryAmount::new(-2.0));
+        assert_eq!(client_ledger.available, MonetaryAmount::new(-2.0));
+        assert_eq!(client_ledger.held, MonetaryAmount::new(0.0));
+        assert!(client_ledger.is_locked);
+    }
+
+    #[cfg(not(feature = "no-history"))]
+    #[test]
+    fn hold_unspent_charges_back_only_what_was_not_withdrawn() {
+        let client_id = ClientId::new(key(1));
+
+        let transactions = vec![
+            Transaction::Activity(AccountActivity::Deposit(
+                client_id.to_owned(),
+                TransactionId::new(1),
+                MonetaryAmount::new(10.0),
+            )),
+            Transaction::Activity(AccountActivity::Deposit(
+                client_id.to_owned(),
+                TransactionId::new(2),
+                MonetaryAmount::new(5.0),
+            )),
+            // Spends all of tx 1 and 2.0 of tx 2
+            Transaction::Activity(AccountActivity::Withdrawal(
+                client_id.to_owned(),
+                TransactionId::new(3),
+                MonetaryAmount::new(12.0),
+            )),
+            Transaction::Dispute(DisputeManagement::Dispute(
+                client_id.to_owned(),
+                TransactionId::new(2),
+            )),
+            Transaction::Dispute(DisputeManagement::Chargeback(
+                client_id.to_owned(),
+                TransactionId::new(2),
+            )),
+        ];
+
+        let final_ledger = create_ledger_with_init(
+            HashMap::default(),
+            Box::new(transactions.into_iter()),
+            &Policy::default().with_dispute_policy(DisputePolicy::HoldUnspent),
+        );
+
+        let client_ledger = final_ledger
+            .0
+            .into_iter()
+            .find(|x| x.id == client_id)
+            .unwrap();
+
+        assert_eq!(client_ledger.total, MonetaryAmount::new(0.0));
+        assert_eq!(client_ledger.available, MonetaryAmount::new(0.0));
+        assert_eq!(client_ledger.held, MonetaryAmount::new(0.0));
+        assert!(client_ledger.is_locked);
+    }
+
+    #[test]
+    fn dispute_of_withdrawn_funds_is_rejected() {
+        let client_id = ClientId::new(key(1));
+
+        let init_state = ClientState {
+            available: AvailableAmount::from(MonetaryAmount::new(10.0)),
+            held: HeldAmount::try_from(MonetaryAmount::new(0.0)).unwrap(),
+            history: TransactionHistory::default(),
+            is_locked: false,
+            flags: ClientFlags::default(),
+        };
+        let init_ledger: HashMap<ClientId, ClientState> =
+            [(client_id.to_owned(), init_state.clone())]
+                .into_iter()
+                .collect();
+
+        let transactions = vec![
+            Transaction::Activity(AccountActivity::Deposit(
+                client_id.to_owned(),
+                TransactionId::new(1),
+                MonetaryAmount::new(5.0),
+            )),
+            Transaction::Activity(AccountActivity::Withdrawal(
+                client_id.to_owned(),
+                TransactionId::new(2),
+                MonetaryAmount::new(12.0),
+            )),
+            Transaction::Dispute(DisputeManagement::Dispute(
+                client_id.to_owned(),
+                TransactionId::new(1),
+            )),
+        ];
+
+        let final_ledger = create_ledger_with_init(
+            init_ledger,
+            Box::new(transactions.into_iter()),
+            &Policy::default().with_dispute_policy(DisputePolicy::Reject),
+        );
+
+        let client_ledger = final_ledger
+            .0
+            .into_iter()
+            .find(|x| x.id == client_id)
+            .unwrap();
+
+        assert_eq!(client_ledger.total, MonetaryAmount::new(3.0));
+        assert_eq!(client_ledger.available, MonetaryAmount::new(3.0));
+        assert_eq!(client_ledger.held, MonetaryAmount::new(0.0));
+    }
+
+    #[test]
+    fn unknown_client_starts_with_opening_balance() {
+        let client_id = ClientId::new(key(1));
+
+        let transactions = vec![Transaction::Activity(AccountActivity::Withdrawal(
+            client_id.to_owned(),
+            TransactionId::new(1),
+            MonetaryAmount::new(30.0),
+        ))];
+
+        let final_ledger = create_ledger_with_init(
+            HashMap::default(),
+            Box::new(transactions.into_iter()),
+            &Policy::default().with_opening_balance(Decimal::new(50, 0)),
+        );
+
+        let client_ledger = final_ledger
+            .0
+            .into_iter()
+            .find(|x| x.id == client_id)
+            .unwrap();
+
+        assert_eq!(client_ledger.total, MonetaryAmount::new(20.0));
+        assert_eq!(client_ledger.available, MonetaryAmount::new(20.0));
+        assert_eq!(client_ledger.held, MonetaryAmount::new(0.0));
+    }
+
+    #[test]
+    fn opening_balance_does_not_apply_to_known_client() {
+        let client_id = ClientId::new(key(1));
+
+        let init_state = ClientState {
+            available: AvailableAmount::from(MonetaryAmount::new(10.0)),
+            held: HeldAmount::try_from(MonetaryAmount::new(0.0)).unwrap(),
+            history: TransactionHistory::default(),
+            is_locked: false,
+            flags: ClientFlags::default(),
+        };
+        let init_ledger: HashMap<ClientId, ClientState> =
+            [(client_id.to_owned(), init_state.clone())]
+                .into_iter()
+                .collect();
+
+        let transactions = vec![Transaction::Activity(AccountActivity::Withdrawal(
+            client_id.to_owned(),
+            TransactionId::new(1),
+            MonetaryAmount::new(30.0),
+        ))];
+
+        let final_ledger = create_ledger_with_init(
+            init_ledger,
+            Box::new(transactions.into_iter()),
+            &Policy::default().with_opening_balance(Decimal::new(50, 0)),
+        );
+
+        let client_ledger = final_ledger
+            .0
+            .into_iter()
+            .find(|x| x.id == client_id)
+            .unwrap();
+
+        assert_eq!(client_ledger.available, MonetaryAmount::new(10.0));
+    }
+
+    #[cfg(not(feature = "no-history"))]
+    #[test]
+    fn held_matches_disputed_amount_through_short_dispute_and_chargeback() {
+        let client_id = ClientId::new(key(1));
+        let policy = Policy::default().with_dispute_policy(DisputePolicy::HoldAvailable);
+
+        let deposit = Transaction::Activity(AccountActivity::Deposit(
+            client_id.to_owned(),
+            TransactionId::new(1),
+            MonetaryAmount::new(10.0),
+        ));
+        let withdrawal = Transaction::Activity(AccountActivity::Withdrawal(
+            client_id.to_owned(),
+            TransactionId::new(2),
+            MonetaryAmount::new(4.0),
+        ));
+        let dispute = Transaction::Dispute(DisputeManagement::Dispute(
+            client_id.to_owned(),
+            TransactionId::new(1),
+        ));
+        let chargeback = Transaction::Dispute(DisputeManagement::Chargeback(
+            client_id.to_owned(),
+            TransactionId::new(1),
+        ));
+
+        let disputed = fold_transactions(
+            HashMap::default(),
+            vec![deposit, withdrawal, dispute].into_iter(),
+            &policy,
+            &mut [],
+        );
+        let state = disputed.get(&client_id).unwrap();
+        assert_eq!(state.disputed_amount(), MonetaryAmount::new(6.0));
+        assert!(state.is_held_consistent());
+
+        let charged_back =
+            fold_transactions(disputed, vec![chargeback].into_iter(), &policy, &mut []);
+        let state = charged_back.get(&client_id).unwrap();
+        assert!(state.history.disputed_txs.is_empty());
+        assert!(state.is_held_consistent());
+    }
+
+    #[test]
+    fn admin_hold_and_release_move_funds_independently_of_disputes() {
+        let client_id = ClientId::new(key(1));
+        let policy = Policy::default();
+
+        let deposit = Transaction::Activity(AccountActivity::Deposit(
+            client_id.to_owned(),
+            TransactionId::new(1),
+            MonetaryAmount::new(10.0),
+        ));
+        let hold = Transaction::Admin(AdminHold::Hold(
+            client_id.to_owned(),
+            TransactionId::new(2),
+            MonetaryAmount::new(3.0),
+        ));
+        let dispute = Transaction::Dispute(DisputeManagement::Dispute(
+            client_id.to_owned(),
+            TransactionId::new(1),
+        ));
+        let resolve = Transaction::Dispute(DisputeManagement::Resolve(
+            client_id.to_owned(),
+            TransactionId::new(1),
+        ));
+        let release = Transaction::Admin(AdminHold::Release(
+            client_id.to_owned(),
+            TransactionId::new(2),
+        ));
+
+        let held = fold_transactions(
+            HashMap::default(),
+            vec![deposit, hold].into_iter(),
+            &policy,
+            &mut [],
+        );
+        let state = held.get(&client_id).unwrap();
+        assert_eq!(state.available.value(), MonetaryAmount::new(7.0));
+        assert_eq!(state.held.value(), MonetaryAmount::new(3.0));
+        assert_eq!(state.total(), MonetaryAmount::new(10.0));
+        assert!(state.is_held_consistent());
+
+        // resolving a dispute releases only the disputed funds, leaving the hold in place
+        let disputed =
+            fold_transactions(held, vec![dispute, resolve].into_iter(), &policy, &mut []);
+        let state = disputed.get(&client_id).unwrap();
+        assert_eq!(state.held.value(), MonetaryAmount::new(3.0));
+
+        let released = fold_transactions(disputed, vec![release].into_iter(), &policy, &mut []);
+        let state = released.get(&client_id).unwrap();
+        assert_eq!(state.available.value(), MonetaryAmount::new(10.0));
+        assert_eq!(state.held.value(), MonetaryAmount::default());
+        assert!(state.history.admin_holds.is_empty());
+    }
+
+    #[cfg(not(feature = "no-history"))]
+    #[test]
+    fn dispute_leaving_negative_held_is_rolled_back() {
+        let client_id = ClientId::new(key(1));
+        let policy = Policy::default();
+
+        // negative deposits are ignored, but one recorded by a corrupted state would take held
+        // funds negative when disputed
+        let deposit = AccountActivity::Deposit(
+            client_id.to_owned(),
+            TransactionId::new(1),
+            MonetaryAmount::new(-5.0),
+        );
+        let init_state = ClientState {
+            available: AvailableAmount::from(MonetaryAmount::new(-5.0)),
+            held: HeldAmount::default(),
+            history: TransactionHistory::default().map_account_activity(|account_acc| {
+                account_acc.update(TransactionId::new(1), deposit)
+            }),
+            is_locked: false,
+            flags: ClientFlags::default(),
+        };
+        let init_ledger: HashMap<ClientId, ClientState> =
+            [(client_id.to_owned(), init_state)].into_iter().collect();
+        let dispute = Transaction::Dispute(DisputeManagement::Dispute(
+            client_id.to_owned(),
+            TransactionId::new(1),
+        ));
+
+        let ledger = fold_transactions(init_ledger, vec![dispute].into_iter(), &policy, &mut []);
+        let state = ledger.get(&client_id).unwrap();
+        assert_eq!(state.available.value(), MonetaryAmount::new(-5.0));
+        assert_eq!(state.held.value(), MonetaryAmount::default());
+        assert!(state.history.disputed_txs.is_empty());
+        assert_eq!(
+            state
+                .history
+                .rolled_back_txs
+                .iter()
+                .cloned()
+                .collect::<Vec<_>>(),
+            vec![RolledBackTransaction {
+                client: key(1),
+                tx: 1,
+                violation: InvariantViolation::NegativeHeld,
+            }]
+        );
+    }
+
+    #[test]
+    fn rolled_back_first_transaction_opens_no_account() {
+        let client_id = ClientId::new(key(1));
+
+        // held funds with nothing disputed or held drift from the disputes
+        let state = ClientState {
+            available: AvailableAmount::from(MonetaryAmount::default()),
+            held: HeldAmount::try_from(MonetaryAmount::new(5.0)).unwrap(),
+            history: TransactionHistory::default(),
+            is_locked: false,
+            flags: ClientFlags::default(),
+        };
+
+        let (ledger, decision) = update_client(
+            HashMap::default(),
+            client_id.to_owned(),
+            TransactionId::new(1),
+            (state, Decision::Credit),
+            &Policy::default(),
+        );
+        assert_eq!(decision, Decision::RollBack);
+        assert!(ledger.get(&client_id).is_none());
+    }
+
+    #[test]
+    fn admin_hold_over_available_or_unknown_release_is_ignored() {
+        let client_id = ClientId::new(key(1));
+        let policy = Policy::default();
+
+        let deposit = Transaction::Activity(AccountActivity::Deposit(
+            client_id.to_owned(),
+            TransactionId::new(1),
+            MonetaryAmount::new(10.0),
+        ));
+        let hold = Transaction::Admin(AdminHold::Hold(
+            client_id.to_owned(),
+            TransactionId::new(2),
+            MonetaryAmount::new(11.0),
+        ));
+        let release = Transaction::Admin(AdminHold::Release(
+            client_id.to_owned(),
+            TransactionId::new(1),
+        ));
+
+        let ledger = fold_transactions(
+            HashMap::default(),
+            vec![deposit, hold, release].into_iter(),
+            &policy,
+            &mut [],
+        );
+        let state = ledger.get(&client_id).unwrap();
+        assert_eq!(state.available.value(), MonetaryAmount::new(10.0));
+        assert_eq!(state.held.value(), MonetaryAmount::default());
+    }
+
+    #[test]
+    fn credits_which_would_overflow_are_ignored() {
+        let client_id = ClientId::new(key(1));
+        let policy = Policy::default();
+        let max = MonetaryAmount::from(Decimal::MAX);
+        let one = MonetaryAmount::from(Decimal::ONE);
+
+        // available and held each fit, but their total would not
+        let init_state = ClientState {
+            available: AvailableAmount::from(max - one),
+            held: HeldAmount::try_from(one).unwrap(),
+            history: TransactionHistory::default(),
+            is_locked: false,
+            flags: ClientFlags::default(),
+        };
+        let init_ledger: HashMap<ClientId, ClientState> =
+            [(client_id.to_owned(), init_state)].into_iter().collect();
+
+        for amount in [max, one] {
+            let deposit = Transaction::Activity(AccountActivity::Deposit(
+                client_id.to_owned(),
+                TransactionId::new(1),
+                amount,
+            ));
+            let (ledger, decision) = decide_transaction(deposit, init_ledger.clone(), &policy);
+            assert_eq!(decision, Decision::Ignore(Ignored::Overflow));
+            let state = ledger.get(&client_id).unwrap();
+            assert_eq!(state.available.value(), max - one);
+            assert_eq!(state.total(), max);
+        }
+    }
+
+    #[test]
+    fn negative_withdrawal_from_the_largest_balance_is_ignored() {
+        let client_id = ClientId::new(key(1));
+        let max = MonetaryAmount::from(Decimal::MAX);
+        let deposit = Transaction::Activity(AccountActivity::Deposit(
+            client_id.to_owned(),
+            TransactionId::new(1),
+            max,
+        ));
+        let withdrawal = Transaction::Activity(AccountActivity::Withdrawal(
+            client_id.to_owned(),
+            TransactionId::new(2),
+            MonetaryAmount::from(-Decimal::MAX),
+        ));
+
+        let (ledger, _) = decide_transaction(deposit, HashMap::default(), &Policy::default());
+        let (ledger, decision) = decide_transaction(withdrawal, ledger, &Policy::default());
+        assert_eq!(decision, Decision::Ignore(Ignored::NegativeAmount));
+        assert_eq!(ledger.get(&client_id).unwrap().available.value(), max);
+        // a subtraction past the most negative amount is an underflow rather than a panic
+        let most_negative = AvailableAmount::from(MonetaryAmount::from(Decimal::MIN));
+        assert_eq!(
+            most_negative.checked_sub(MonetaryAmount::from(Decimal::ONE)),
+            Err(Underflow)
+        );
+    }
+
+    #[test]
+    fn overdraft_withdrawal_from_the_largest_balance_applies() {
+        let client_id = ClientId::new(key(1));
+        let policy = Policy {
+            overdraft_limits: [(key(1), Decimal::TEN)].into_iter().collect(),
+            ..Policy::default()
+        };
+        let max = MonetaryAmount::from(Decimal::MAX);
+        let deposit = Transaction::Activity(AccountActivity::Deposit(
+            client_id.to_owned(),
+            TransactionId::new(1),
+            max,
+        ));
+        let withdrawal = Transaction::Activity(AccountActivity::Withdrawal(
+            client_id.to_owned(),
+            TransactionId::new(2),
+            MonetaryAmount::new(5.0),
+        ));
+
+        let (ledger, _) = decide_transaction(deposit, HashMap::default(), &policy);
+        let (ledger, decision) = decide_transaction(withdrawal, ledger, &policy);
+        assert_eq!(decision, Decision::Debit);
+        assert_eq!(
+            ledger.get(&client_id).unwrap().available.value(),
+            max - MonetaryAmount::new(5.0)
+        );
+    }
+
+    #[cfg(not(feature = "no-history"))]
+    #[test]
+    fn dispute_whose_held_share_overflows_is_ignored() {
+        let client_id = ClientId::new(key(1));
+        let policy = Policy::default().with_max_held_percent(Decimal::new(50, 0));
+        let large = MonetaryAmount::from(Decimal::from_i128_with_scale(10_i128.pow(27), 0));
+        let transactions = vec![
+            Transaction::Activity(AccountActivity::Deposit(
+                client_id.to_owned(),
+                TransactionId::new(1),
+                large,
+            )),
+            Transaction::Activity(AccountActivity::Deposit(
+                client_id.to_owned(),
+                TransactionId::new(2),
+                large,
+            )),
+            Transaction::Dispute(DisputeManagement::Dispute(
+                client_id.to_owned(),
+                TransactionId::new(1),
+            )),
+        ];
+        let ledger = fold_transactions(
+            HashMap::default(),
+            transactions.into_iter(),
+            &policy,
+            &mut [],
+        );
+        let dispute = Transaction::Dispute(DisputeManagement::Dispute(
+            client_id.to_owned(),
+            TransactionId::new(2),
+        ));
+
+        let (ledger, decision) = decide_transaction(dispute, ledger, &policy);
+        assert_eq!(decision, Decision::Ignore(Ignored::Overflow));
+        assert_eq!(ledger.get(&client_id).unwrap().held.value(), large);
+    }
+
+    #[cfg(not(feature = "no-history"))]
+    #[test]
+    fn withdrawal_queued_behind_dispute_is_typed_and_enacted_on_resolve() {
+        let client_id = ClientId::new(key(1));
+        let policy = Policy::default();
+        let transactions = vec![
+            Transaction::Activity(AccountActivity::Deposit(
+                client_id.to_owned(),
+                TransactionId::new(1),
+                MonetaryAmount::new(10.0),
+            )),
+            Transaction::Dispute(DisputeManagement::Dispute(
+                client_id.to_owned(),
+                TransactionId::new(1),
+            )),
+        ];
+        let ledger = fold_transactions(
+            HashMap::default(),
+            Box::new(transactions.into_iter()),
+            &policy,
+            &mut [],
+        );
+
+        let withdrawal = Transaction::Activity(AccountActivity::Withdrawal(
+            client_id.to_owned(),
+            TransactionId::new(2),
+            MonetaryAmount::new(6.0),
+        ));
+        let (ledger, decision) = decide_transaction(withdrawal, ledger, &policy);
+        assert_eq!(decision, Decision::QueueWithdrawal);
+        let queue = &ledger.get(&client_id).unwrap().history.rejected_txs;
+        assert_eq!(queue.len(), 1);
+        assert!(
+            queue[0].activity
+                == RejectedActivity::Withdrawal(
+                    client_id.to_owned(),
+                    TransactionId::new(2),
+                    MonetaryAmount::new(6.0)
+                )
+        );
+        assert!(queue[0]
+            .disputed_transaction_snapshot
+            .contains(&TransactionId::new(1)));
+
+        let resolve = Transaction::Dispute(DisputeManagement::Resolve(
+            client_id.to_owned(),
+            TransactionId::new(1),
+        ));
+        let (ledger, decision) = decide_transaction(resolve, ledger, &policy);
+        assert_eq!(decision, Decision::ReleaseDispute);
+        let state = ledger.get(&client_id).unwrap();
+        assert!(state.history.rejected_txs.is_empty());
+        assert_eq!(state.available.value(), MonetaryAmount::new(4.0));
+        assert_eq!(state.total(), MonetaryAmount::new(4.0));
+    }
+}
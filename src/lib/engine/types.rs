@@ -0,0 +1,1130 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    ops::{Add, Sub},
+    path::Path,
+    sync::Arc,
+    time::SystemTime,
+};
+
+use im::{HashMap, HashSet, OrdSet, Vector};
+use rust_decimal::Decimal;
+
+use crate::engine::utils::OrDefault;
+
+/// The raw client identifier as it appears in the input and output. Clients are numbered by
+/// default, the `string-client-ids` feature keys them by arbitrary strings such as UUIDs instead.
+#[cfg(not(feature = "string-client-ids"))]
+pub type ClientKey = u16;
+#[cfg(feature = "string-client-ids")]
+pub type ClientKey = String;
+
+/// The key of a numbered client under either kind of key, for tests which run with both.
+#[cfg(test)]
+pub(crate) fn key(id: u16) -> ClientKey {
+    id.to_string().parse().unwrap()
+}
+
+#[derive(Default, Hash, Eq, PartialEq, Ord, PartialOrd, Clone)]
+#[cfg_attr(not(feature = "string-client-ids"), derive(Copy))]
+pub struct ClientId(ClientKey);
+
+impl ClientId {
+    pub fn new(value: ClientKey) -> Self {
+        Self(value)
+    }
+
+    pub fn value(&self) -> ClientKey {
+        self.0.to_owned()
+    }
+}
+
+#[derive(Default, Hash, Eq, PartialEq, Clone, Copy)]
+pub struct TransactionId(u32);
+
+impl TransactionId {
+    pub fn new(value: u32) -> Self {
+        Self(value)
+    }
+
+    pub fn value(&self) -> u32 {
+        self.0
+    }
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Eq, PartialOrd, Debug)]
+pub struct MonetaryAmount(Decimal);
+
+impl MonetaryAmount {
+    pub fn new(value: f64) -> Self {
+        Self(
+            Decimal::from_f64_retain(value)
+                .unwrap_or_else(||panic!("Failed to parse {:#?} into Decimal", value)),
+        )
+    }
+
+    pub fn value(&self) -> Decimal {
+        self.0
+    }
+
+    pub fn max(self, other: Self) -> Self {
+        if self < other {
+            other
+        } else {
+            self
+        }
+    }
+
+    pub fn checked_add(self, rhs: Self) -> Result<Self, Overflow> {
+        self.0.checked_add(rhs.0).map(Self).ok_or(Overflow)
+    }
+
+    /// Adds amounts for reporting, staying at the largest amount rather than overflowing, e.g.
+    /// when summing several queued withdrawals each near the largest amount.
+    pub fn saturating_add(self, rhs: Self) -> Self {
+        Self(self.0.saturating_add(rhs.0))
+    }
+}
+
+impl From<Decimal> for MonetaryAmount {
+    fn from(value: Decimal) -> Self {
+        Self(value)
+    }
+}
+
+impl Add for MonetaryAmount {
+    type Output = MonetaryAmount;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        MonetaryAmount(self.value() + rhs.value())
+    }
+}
+
+impl Sub for MonetaryAmount {
+    type Output = MonetaryAmount;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        MonetaryAmount(self.value() - rhs.value())
+    }
+}
+
+/// Returned when a subtraction would take a balance below zero.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Underflow;
+
+/// Returned when an amount would exceed the largest, or most negative, value a balance can hold.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Overflow;
+
+fn checked_sub(value: MonetaryAmount, amount: MonetaryAmount) -> Result<MonetaryAmount, Underflow> {
+    checked_sub_to(value, amount, MonetaryAmount::default())
+}
+
+/// Subtracts the amount unless that takes the value below the floor. A result too far below zero
+/// to be held is below any floor, so is an underflow rather than an overflow.
+fn checked_sub_to(
+    value: MonetaryAmount,
+    amount: MonetaryAmount,
+    floor: MonetaryAmount,
+) -> Result<MonetaryAmount, Underflow> {
+    match value.0.checked_sub(amount.0).map(MonetaryAmount) {
+        Some(result) if result >= floor || amount <= MonetaryAmount::default() => Ok(result),
+        _ => Err(Underflow),
+    }
+}
+
+/// Funds a client is free to use. Subtractions are checked, a negative balance can only be
+/// reached through [`AvailableAmount::sub_allow_negative`] where a policy permits it, or within
+/// the client's overdraft through [`AvailableAmount::sub_within_overdraft`].
+#[derive(Default, Clone, Copy, PartialEq, Eq, PartialOrd, Debug)]
+pub struct AvailableAmount(MonetaryAmount);
+
+impl AvailableAmount {
+    pub fn value(&self) -> MonetaryAmount {
+        self.0
+    }
+
+    pub fn checked_sub(self, amount: MonetaryAmount) -> Result<Self, Underflow> {
+        checked_sub(self.0, amount).map(Self)
+    }
+
+    pub fn checked_add(self, amount: MonetaryAmount) -> Result<Self, Overflow> {
+        self.0.checked_add(amount).map(Self)
+    }
+
+    pub fn sub_within_overdraft(
+        self,
+        amount: MonetaryAmount,
+        overdraft: MonetaryAmount,
+    ) -> Result<Self, Underflow> {
+        checked_sub_to(self.0, amount, MonetaryAmount(-overdraft.0)).map(Self)
+    }
+
+    pub fn sub_allow_negative(self, amount: MonetaryAmount) -> Result<Self, Overflow> {
+        self.0
+            .value()
+            .checked_sub(amount.value())
+            .map(|value| Self(MonetaryAmount(value)))
+            .ok_or(Overflow)
+    }
+}
+
+impl From<MonetaryAmount> for AvailableAmount {
+    fn from(value: MonetaryAmount) -> Self {
+        Self(value)
+    }
+}
+
+impl Add<MonetaryAmount> for AvailableAmount {
+    type Output = AvailableAmount;
+
+    fn add(self, rhs: MonetaryAmount) -> Self::Output {
+        AvailableAmount(self.0 + rhs)
+    }
+}
+
+/// Funds held against open disputes. Can never be negative.
+#[derive(Default, Clone, Copy, PartialEq, Eq, PartialOrd, Debug)]
+pub struct HeldAmount(MonetaryAmount);
+
+impl HeldAmount {
+    pub fn value(&self) -> MonetaryAmount {
+        self.0
+    }
+
+    pub fn checked_sub(self, amount: MonetaryAmount) -> Result<Self, Underflow> {
+        checked_sub(self.0, amount).map(Self)
+    }
+
+    pub fn checked_add(self, amount: MonetaryAmount) -> Result<Self, Overflow> {
+        self.0.checked_add(amount).map(Self)
+    }
+}
+
+impl TryFrom<MonetaryAmount> for HeldAmount {
+    type Error = Underflow;
+
+    fn try_from(value: MonetaryAmount) -> Result<Self, Self::Error> {
+        if value < MonetaryAmount::default() {
+            Err(Underflow)
+        } else {
+            Ok(Self(value))
+        }
+    }
+}
+
+impl Add<MonetaryAmount> for HeldAmount {
+    type Output = HeldAmount;
+
+    fn add(self, rhs: MonetaryAmount) -> Self::Output {
+        HeldAmount(self.0 + rhs)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub enum AccountActivity {
+    /// Increases available and total funds by an amount.
+    Deposit(ClientId, TransactionId, MonetaryAmount),
+    /// Decreases available and total funds by an amount.
+    ///
+    /// Withdrawals can only taken against accounts with sufficient available funds. Withdrawals
+    /// against a disputed account may be enacted after a dispute is resolved, if the resolution
+    /// provides sufficient available funds.
+    Withdrawal(ClientId, TransactionId, MonetaryAmount),
+}
+
+impl AccountActivity {
+    pub fn amount(&self) -> MonetaryAmount {
+        match self {
+            AccountActivity::Deposit(_, _, amount) => *amount,
+            AccountActivity::Withdrawal(_, _, amount) => *amount,
+        }
+    }
+
+    pub fn client_id(&self) -> ClientId {
+        match self {
+            AccountActivity::Deposit(c_id, _, _) => c_id.to_owned(),
+            AccountActivity::Withdrawal(c_id, _, _) => c_id.to_owned(),
+        }
+    }
+
+    pub fn tx_id(&self) -> TransactionId {
+        match self {
+            AccountActivity::Deposit(_, tx_id, _) => *tx_id,
+            AccountActivity::Withdrawal(_, tx_id, _) => *tx_id,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum DisputeManagement {
+    /// Decreases available funds and increases held funds by the amount of the transaction indicated by the transaction id.
+    ///
+    /// Disputes against non-existing transaction or client will be ignored. Since disputes
+    /// _decrease_ available funds, only deposit transactions can be disputed (see readme regarding
+    /// assumptions made).
+    Dispute(ClientId, TransactionId),
+    /// Releases available funds and decreases held funds. Failed withdrawals will be backfilled
+    /// against the non-disputed available funds.
+    Resolve(ClientId, TransactionId),
+    /// Decreases held and total funds decrease by the disputed amount, and the account is frozen
+    Chargeback(ClientId, TransactionId),
+    /// Undoes a chargeback after the merchant won representment, restoring the charged back
+    /// amount to available and total funds. Applies to locked accounts, which may be unlocked
+    /// depending on the policy.
+    ChargebackReversal(ClientId, TransactionId),
+}
+
+impl DisputeManagement {
+    pub fn is_dispute(&self) -> bool {
+        matches!(self, DisputeManagement::Dispute(..))
+    }
+}
+
+/// Manual interventions by the risk team, holding funds independently of any dispute.
+#[derive(Clone)]
+pub enum AdminHold {
+    /// Moves an amount from available to held funds. The hold is referred to by its own
+    /// transaction id, not that of a deposit.
+    Hold(ClientId, TransactionId, MonetaryAmount),
+    /// Returns the full amount of an earlier hold to available funds.
+    Release(ClientId, TransactionId),
+}
+
+/// The kind of a [`DisputeManagement`] operation, without the ids it applies to.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DisputeOperation {
+    Dispute,
+    Resolve,
+    Chargeback,
+    ChargebackReversal,
+}
+
+impl fmt::Display for DisputeOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisputeOperation::Dispute => write!(f, "dispute"),
+            DisputeOperation::Resolve => write!(f, "resolve"),
+            DisputeOperation::Chargeback => write!(f, "chargeback"),
+            DisputeOperation::ChargebackReversal => write!(f, "chargeback reversal"),
+        }
+    }
+}
+
+/// Why the engine left an account as it was for a transaction.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Ignored {
+    AccountLocked,
+    /// No deposit or withdrawal with the transaction's id was made by the client.
+    UnknownTransaction,
+    /// Only deposits can be disputed.
+    NotADeposit,
+    AlreadyDisputed,
+    NotDisputed,
+    NotChargedBack,
+    InsufficientAvailable,
+    InsufficientHeld,
+    /// A balance would exceed the largest amount it can hold.
+    Overflow,
+    DuplicateHold,
+    UnknownHold,
+    NonPositiveAmount,
+    /// Deposits and withdrawals cannot be of a negative amount.
+    NegativeAmount,
+    /// The client is flagged as under review.
+    UnderReview,
+}
+
+impl fmt::Display for Ignored {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            Ignored::AccountLocked => "account locked",
+            Ignored::UnknownTransaction => "unknown transaction",
+            Ignored::NotADeposit => "not a deposit",
+            Ignored::AlreadyDisputed => "already disputed",
+            Ignored::NotDisputed => "not disputed",
+            Ignored::NotChargedBack => "not charged back",
+            Ignored::InsufficientAvailable => "insufficient available funds",
+            Ignored::InsufficientHeld => "insufficient held funds",
+            Ignored::Overflow => "balance overflow",
+            Ignored::DuplicateHold => "duplicate hold",
+            Ignored::UnknownHold => "unknown hold",
+            Ignored::NonPositiveAmount => "amount not positive",
+            Ignored::NegativeAmount => "amount negative",
+            Ignored::UnderReview => "client under review",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+/// What the engine did with a transaction, as decided by the update for its type.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Decision {
+    Credit,
+    Debit,
+    QueueWithdrawal,
+    RejectWithdrawal,
+    HoldDispute,
+    QueueDispute,
+    ReleaseDispute,
+    DropQueuedDispute,
+    ChargeBack,
+    ReverseChargeback,
+    PlaceHold,
+    ReleaseHold,
+    /// The transaction did not apply to the account in its current state.
+    Ignore(Ignored),
+    /// The dispute operation repeated the last one applied to the same transaction.
+    IgnoreRepeat,
+    /// The update broke an invariant, so the account was left as it was.
+    RollBack,
+}
+
+impl Decision {
+    /// Whether the transaction took effect, rather than being ignored, rejected, queued for retry
+    /// or rolled back.
+    pub fn is_applied(&self) -> bool {
+        !matches!(
+            self,
+            Decision::QueueWithdrawal
+                | Decision::RejectWithdrawal
+                | Decision::Ignore(_)
+                | Decision::IgnoreRepeat
+                | Decision::RollBack
+        )
+    }
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Decision::Credit => "credit",
+            Decision::Debit => "debit",
+            Decision::QueueWithdrawal => "queue_withdrawal",
+            Decision::RejectWithdrawal => "reject_withdrawal",
+            Decision::HoldDispute => "hold_dispute",
+            Decision::QueueDispute => "queue_dispute",
+            Decision::ReleaseDispute => "release_dispute",
+            Decision::DropQueuedDispute => "drop_queued_dispute",
+            Decision::ChargeBack => "charge_back",
+            Decision::ReverseChargeback => "reverse_chargeback",
+            Decision::PlaceHold => "place_hold",
+            Decision::ReleaseHold => "release_hold",
+            Decision::Ignore(_) => "ignore",
+            Decision::IgnoreRepeat => "ignore_repeat",
+            Decision::RollBack => "roll_back",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Where a transaction was read from, attached by the front end which read it.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SourceMetadata {
+    /// The file the transaction was read from, shared by all transactions read from it.
+    pub source: Arc<Path>,
+    /// Position of the transaction's row among the transaction rows of the file, starting from 1.
+    pub row: usize,
+    /// When the file was read.
+    pub ingested_at: SystemTime,
+}
+
+/// A transaction along with where it was read from, so that anything reporting on it can cite
+/// its exact provenance.
+#[derive(Clone)]
+pub struct TransactionEnvelope {
+    pub transaction: Transaction,
+    pub metadata: SourceMetadata,
+}
+
+/// A dispute held back at the end of the run as it would take the client's held funds over the
+/// limit.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct QueuedDispute {
+    pub client: ClientKey,
+    pub tx: u32,
+    pub amount: Decimal,
+}
+
+impl fmt::Display for QueuedDispute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "dispute of tx {} ({}) for client {} queued: held funds at limit",
+            self.tx, self.amount, self.client
+        )
+    }
+}
+
+/// A dispute management operation which repeated the last operation applied to the same
+/// transaction, and so was not applied again.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RetriedOperation {
+    pub client: ClientKey,
+    pub tx: u32,
+    pub operation: DisputeOperation,
+}
+
+impl fmt::Display for RetriedOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "repeated {} of tx {} for client {} ignored",
+            self.operation, self.tx, self.client
+        )
+    }
+}
+
+/// A post-update check on a client's state which a transaction failed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InvariantViolation {
+    /// Held funds would have gone negative.
+    NegativeHeld,
+    /// Held funds would have changed by a different amount than the disputed and
+    /// administratively held amounts.
+    HeldDrift,
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvariantViolation::NegativeHeld => write!(f, "held funds would be negative"),
+            InvariantViolation::HeldDrift => {
+                write!(f, "held funds would drift from the disputed amount")
+            }
+        }
+    }
+}
+
+/// A transaction whose state change was rolled back as it broke an invariant of the client's
+/// state.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RolledBackTransaction {
+    pub client: ClientKey,
+    pub tx: u32,
+    pub violation: InvariantViolation,
+}
+
+impl fmt::Display for RolledBackTransaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "tx {} for client {} rolled back: {}",
+            self.tx, self.client, self.violation
+        )
+    }
+}
+
+#[derive(Clone)]
+pub enum Transaction {
+    Activity(AccountActivity),
+    Dispute(DisputeManagement),
+    Admin(AdminHold),
+}
+
+impl Transaction {
+    pub fn client_id(&self) -> ClientId {
+        match self {
+            Transaction::Activity(AccountActivity::Deposit(c_id, _, _))
+            | Transaction::Activity(AccountActivity::Withdrawal(c_id, _, _))
+            | Transaction::Dispute(DisputeManagement::Dispute(c_id, _))
+            | Transaction::Dispute(DisputeManagement::Resolve(c_id, _))
+            | Transaction::Dispute(DisputeManagement::Chargeback(c_id, _))
+            | Transaction::Dispute(DisputeManagement::ChargebackReversal(c_id, _))
+            | Transaction::Admin(AdminHold::Hold(c_id, _, _))
+            | Transaction::Admin(AdminHold::Release(c_id, _)) => c_id.to_owned(),
+        }
+    }
+
+    pub fn tx_id(&self) -> TransactionId {
+        match self {
+            Transaction::Activity(AccountActivity::Deposit(_, tx_id, _))
+            | Transaction::Activity(AccountActivity::Withdrawal(_, tx_id, _))
+            | Transaction::Dispute(DisputeManagement::Dispute(_, tx_id))
+            | Transaction::Dispute(DisputeManagement::Resolve(_, tx_id))
+            | Transaction::Dispute(DisputeManagement::Chargeback(_, tx_id))
+            | Transaction::Dispute(DisputeManagement::ChargebackReversal(_, tx_id))
+            | Transaction::Admin(AdminHold::Hold(_, tx_id, _))
+            | Transaction::Admin(AdminHold::Release(_, tx_id)) => *tx_id,
+        }
+    }
+}
+
+/// A transaction rejected for lack of available funds which may be enacted once funds are freed.
+/// Only withdrawals are retried, other types get a variant of their own when they can be.
+#[derive(Clone, PartialEq, Eq)]
+pub enum RejectedActivity {
+    Withdrawal(ClientId, TransactionId, MonetaryAmount),
+}
+
+impl RejectedActivity {
+    /// Funds the transaction takes from available when enacted.
+    pub fn amount(&self) -> MonetaryAmount {
+        match self {
+            RejectedActivity::Withdrawal(_, _, amount) => *amount,
+        }
+    }
+
+    pub fn client_id(&self) -> ClientId {
+        match self {
+            RejectedActivity::Withdrawal(c_id, _, _) => c_id.to_owned(),
+        }
+    }
+
+    pub fn tx_id(&self) -> TransactionId {
+        match self {
+            RejectedActivity::Withdrawal(_, tx_id, _) => *tx_id,
+        }
+    }
+}
+
+/// Stores a transaction that has failed, and any disputes that have occured prior to the failed
+/// transaction. When disputed transactions are resolved this can be used to backfil failed
+/// transactions.
+#[derive(Clone)]
+pub struct RejectedTx {
+    pub activity: RejectedActivity,
+    pub disputed_transaction_snapshot: HashSet<TransactionId>,
+    /// Number of times the transaction has been retried without the funds being available.
+    pub attempts: u32,
+}
+
+/// A withdrawal waiting in the retry queue at the end of the run.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct QueuedRetry {
+    pub client: ClientKey,
+    pub tx: u32,
+    pub amount: Decimal,
+    pub attempts: u32,
+}
+
+impl fmt::Display for QueuedRetry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "withdrawal tx {} of {} for client {} queued for retry after {} attempts",
+            self.tx, self.amount, self.client, self.attempts
+        )
+    }
+}
+
+/// Why a withdrawal was dropped from the retry queue without being enacted.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RetryAbandonment {
+    MaxAttempts,
+    Expired,
+}
+
+impl fmt::Display for RetryAbandonment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RetryAbandonment::MaxAttempts => write!(f, "out of attempts"),
+            RetryAbandonment::Expired => write!(f, "expired"),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AbandonedRetry {
+    pub client: ClientKey,
+    pub tx: u32,
+    pub reason: RetryAbandonment,
+}
+
+impl fmt::Display for AbandonedRetry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "withdrawal tx {} for client {} dropped from the retry queue: {}",
+            self.tx, self.client, self.reason
+        )
+    }
+}
+
+/// Running counts of the activity on an account, kept as transactions are applied so that policy
+/// checks and statistics need not walk the history, which may be capped or not kept at all.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ActivityCounts {
+    /// Deposits credited.
+    pub deposits: usize,
+    /// Withdrawals debited, including queued withdrawals once enacted.
+    pub withdrawals: usize,
+    /// Disputes raised, whether held or queued behind the held funds limit.
+    pub disputes: usize,
+    /// Chargebacks applied, including any since reversed.
+    pub chargebacks: usize,
+}
+
+impl ActivityCounts {
+    /// Deposits and withdrawals applied.
+    pub fn transactions(&self) -> usize {
+        self.deposits + self.withdrawals
+    }
+}
+
+/// Contains data relating to previous transactions. A record of deposit and withdrawal transactions are kept for
+/// the use by resolve, dispute and chargeback transactions.
+/// Records of disputed and rejectedtransactions are stored so that previously rejected transactions can be backfilled.
+#[derive(Default, Clone)]
+pub struct TransactionHistory {
+    pub account_activity: HashMap<TransactionId, AccountActivity>,
+    pub disputed_txs: HashSet<TransactionId>,
+    /// Number of end of day rows each open dispute has stayed open across.
+    pub dispute_ages: HashMap<TransactionId, u32>,
+    /// Portion of a disputed amount which could not be held as the funds were not available.
+    pub dispute_shortfalls: HashMap<TransactionId, MonetaryAmount>,
+    /// Portion of each deposit not yet withdrawn, oldest first. Only tracked under the
+    /// `hold-unspent` dispute policy, fully spent deposits are dropped.
+    pub unspent_deposits: Vector<(TransactionId, MonetaryAmount)>,
+    /// Deposits charged back and not since reversed.
+    pub charged_back_txs: HashSet<TransactionId>,
+    /// Disputes waiting for earlier disputes to settle as they would exceed the held funds limit,
+    /// in the order they were raised.
+    pub queued_disputes: Vector<TransactionId>,
+    pub rejected_txs: Vector<RejectedTx>,
+    /// Withdrawals rejected for lack of funds, whether or not they were queued for retry.
+    pub rejected_withdrawals: usize,
+    pub counts: ActivityCounts,
+    pub abandoned_retries: Vector<AbandonedRetry>,
+    /// The most recent transaction applied to the client.
+    pub last_tx_id: Option<TransactionId>,
+    /// The last dispute management operation applied to each transaction, used to detect retries.
+    pub last_dispute_operations: HashMap<TransactionId, DisputeOperation>,
+    pub retried_operations: Vector<RetriedOperation>,
+    pub rolled_back_txs: Vector<RolledBackTransaction>,
+    /// Open administrative holds, kept apart from disputes as they refer to no deposit.
+    pub admin_holds: HashMap<TransactionId, MonetaryAmount>,
+}
+
+impl TransactionHistory {
+    pub fn map_account_activity<F>(&self, f: F) -> Self
+    where
+        F: FnOnce(
+            &HashMap<TransactionId, AccountActivity>,
+        ) -> HashMap<TransactionId, AccountActivity>,
+    {
+        Self {
+            account_activity: f(&self.account_activity),
+            ..self.clone()
+        }
+    }
+
+    pub fn map_disputed_tx<F>(&self, f: F) -> Self
+    where
+        F: FnOnce(&HashSet<TransactionId>) -> HashSet<TransactionId>,
+    {
+        Self {
+            disputed_txs: f(&self.disputed_txs),
+            ..self.clone()
+        }
+    }
+
+    pub fn map_dispute_ages<F>(&self, f: F) -> Self
+    where
+        F: FnOnce(&HashMap<TransactionId, u32>) -> HashMap<TransactionId, u32>,
+    {
+        Self {
+            dispute_ages: f(&self.dispute_ages),
+            ..self.clone()
+        }
+    }
+
+    pub fn map_charged_back_txs<F>(&self, f: F) -> Self
+    where
+        F: FnOnce(&HashSet<TransactionId>) -> HashSet<TransactionId>,
+    {
+        Self {
+            charged_back_txs: f(&self.charged_back_txs),
+            ..self.clone()
+        }
+    }
+
+    pub fn map_queued_disputes<F>(&self, f: F) -> Self
+    where
+        F: FnOnce(&Vector<TransactionId>) -> Vector<TransactionId>,
+    {
+        Self {
+            queued_disputes: f(&self.queued_disputes),
+            ..self.clone()
+        }
+    }
+
+    pub fn count_rejected_withdrawal(&self) -> Self {
+        Self {
+            rejected_withdrawals: self.rejected_withdrawals + 1,
+            ..self.clone()
+        }
+    }
+
+    pub fn map_counts<F>(&self, f: F) -> Self
+    where
+        F: FnOnce(ActivityCounts) -> ActivityCounts,
+    {
+        Self {
+            counts: f(self.counts),
+            ..self.clone()
+        }
+    }
+
+    pub fn update_last_tx_id(&self, tx_id: TransactionId) -> Self {
+        Self {
+            last_tx_id: Some(tx_id),
+            ..self.clone()
+        }
+    }
+
+    pub fn map_dispute_shortfalls<F>(&self, f: F) -> Self
+    where
+        F: FnOnce(
+            &HashMap<TransactionId, MonetaryAmount>,
+        ) -> HashMap<TransactionId, MonetaryAmount>,
+    {
+        Self {
+            dispute_shortfalls: f(&self.dispute_shortfalls),
+            ..self.clone()
+        }
+    }
+
+    pub fn map_unspent_deposits<F>(&self, f: F) -> Self
+    where
+        F: FnOnce(
+            &Vector<(TransactionId, MonetaryAmount)>,
+        ) -> Vector<(TransactionId, MonetaryAmount)>,
+    {
+        Self {
+            unspent_deposits: f(&self.unspent_deposits),
+            ..self.clone()
+        }
+    }
+
+    /// Portion of the deposit not yet withdrawn.
+    pub fn unspent(&self, tx_id: &TransactionId) -> MonetaryAmount {
+        self.unspent_deposits
+            .iter()
+            .find(|(id, _)| id == tx_id)
+            .map_or_else(MonetaryAmount::default, |(_, amount)| *amount)
+    }
+
+    /// Takes a withdrawal from the unspent deposits, oldest first. Disputed deposits are skipped
+    /// as their funds are held, and anything beyond the deposits came from the opening balance.
+    pub fn spend_deposits(&self, amount: MonetaryAmount) -> Self {
+        let (unspent, _) = self.unspent_deposits.iter().fold(
+            (Vector::new(), amount),
+            |(mut unspent, remaining), (tx_id, deposit)| {
+                if self.disputed_txs.contains(tx_id) {
+                    unspent.push_back((*tx_id, *deposit));
+                    return (unspent, remaining);
+                }
+                let spent = if remaining < *deposit {
+                    remaining
+                } else {
+                    *deposit
+                };
+                if spent < *deposit {
+                    unspent.push_back((*tx_id, *deposit - spent));
+                }
+                (unspent, remaining - spent)
+            },
+        );
+        Self {
+            unspent_deposits: unspent,
+            ..self.clone()
+        }
+    }
+
+    pub fn map_last_dispute_operations<F>(&self, f: F) -> Self
+    where
+        F: FnOnce(
+            &HashMap<TransactionId, DisputeOperation>,
+        ) -> HashMap<TransactionId, DisputeOperation>,
+    {
+        Self {
+            last_dispute_operations: f(&self.last_dispute_operations),
+            ..self.clone()
+        }
+    }
+
+    pub fn map_retried_operations<F>(&self, f: F) -> Self
+    where
+        F: FnOnce(&Vector<RetriedOperation>) -> Vector<RetriedOperation>,
+    {
+        Self {
+            retried_operations: f(&self.retried_operations),
+            ..self.clone()
+        }
+    }
+
+    pub fn map_rolled_back_txs<F>(&self, f: F) -> Self
+    where
+        F: FnOnce(&Vector<RolledBackTransaction>) -> Vector<RolledBackTransaction>,
+    {
+        Self {
+            rolled_back_txs: f(&self.rolled_back_txs),
+            ..self.clone()
+        }
+    }
+
+    pub fn map_admin_holds<F>(&self, f: F) -> Self
+    where
+        F: FnOnce(
+            &HashMap<TransactionId, MonetaryAmount>,
+        ) -> HashMap<TransactionId, MonetaryAmount>,
+    {
+        Self {
+            admin_holds: f(&self.admin_holds),
+            ..self.clone()
+        }
+    }
+
+    pub fn map_abandoned_retries<F>(&self, f: F) -> Self
+    where
+        F: FnOnce(&Vector<AbandonedRetry>) -> Vector<AbandonedRetry>,
+    {
+        Self {
+            abandoned_retries: f(&self.abandoned_retries),
+            ..self.clone()
+        }
+    }
+
+    pub fn map_rejected_activity<F>(&self, f: F) -> Self
+    where
+        F: FnOnce(&Vector<RejectedTx>) -> Vector<RejectedTx>,
+    {
+        Self {
+            rejected_txs: f(&self.rejected_txs),
+            ..self.clone()
+        }
+    }
+}
+
+/// Labels an operator attached to a client, e.g. `vip` or `under_review`, kept in order so that
+/// they are written the same way every run.
+pub type ClientFlags = OrdSet<String>;
+
+/// Flag blocking the client's withdrawals while they are investigated.
+pub const UNDER_REVIEW: &str = "under_review";
+
+/// Balances and history of a single client. The total is not stored, it is always derived from the
+/// available and held funds so the three cannot drift apart.
+#[derive(Default, Clone)]
+pub struct ClientState {
+    pub available: AvailableAmount,
+    pub held: HeldAmount,
+    pub is_locked: bool,
+    pub history: TransactionHistory,
+    /// Flags given to the client before their first transaction, see [`Policy`](crate::Policy).
+    pub flags: ClientFlags,
+}
+
+impl ClientState {
+    pub fn map_avail<F: FnOnce(AvailableAmount) -> AvailableAmount>(&self, f: F) -> Self {
+        Self {
+            available: f(self.available),
+            ..self.clone()
+        }
+    }
+
+    pub fn try_map_avail<F, E>(&self, f: F) -> Result<Self, E>
+    where
+        F: FnOnce(AvailableAmount) -> Result<AvailableAmount, E>,
+    {
+        Ok(Self {
+            available: f(self.available)?,
+            ..self.clone()
+        })
+    }
+
+    pub fn total(&self) -> MonetaryAmount {
+        self.available.value() + self.held.value()
+    }
+
+    /// The total, unless it would overflow. Updates which add funds check it so that
+    /// [`ClientState::total`] never can.
+    pub fn checked_total(&self) -> Result<MonetaryAmount, Overflow> {
+        self.available.value().checked_add(self.held.value())
+    }
+
+    pub fn map_held<F: FnOnce(HeldAmount) -> HeldAmount>(&self, f: F) -> Self {
+        Self {
+            held: f(self.held),
+            ..self.clone()
+        }
+    }
+
+    pub fn try_map_held<F, E>(&self, f: F) -> Result<Self, E>
+    where
+        F: FnOnce(HeldAmount) -> Result<HeldAmount, E>,
+    {
+        Ok(Self {
+            held: f(self.held)?,
+            ..self.clone()
+        })
+    }
+
+    pub fn map_history<F: FnOnce(&TransactionHistory) -> TransactionHistory>(&self, f: F) -> Self {
+        Self {
+            history: f(&self.history),
+            ..self.clone()
+        }
+    }
+
+    pub fn update_locked(&self, is_locked: bool) -> Self {
+        Self {
+            is_locked,
+            ..self.clone()
+        }
+    }
+
+    /// Funds which should be held for the currently disputed deposits, less any shortfall which
+    /// could not be held when they were disputed.
+    pub fn disputed_amount(&self) -> MonetaryAmount {
+        self.history
+            .disputed_txs
+            .iter()
+            .filter_map(|tx_id| match self.history.account_activity.get(tx_id) {
+                Some(AccountActivity::Deposit(_, _, amount)) => {
+                    Some(*amount - self.history.dispute_shortfalls.get_or_default(tx_id))
+                }
+                _ => None,
+            })
+            .fold(MonetaryAmount::default(), MonetaryAmount::saturating_add)
+    }
+
+    /// Funds held by open administrative holds.
+    pub fn admin_held(&self) -> MonetaryAmount {
+        self.history
+            .admin_holds
+            .values()
+            .fold(MonetaryAmount::default(), |acc, amount| {
+                acc.saturating_add(*amount)
+            })
+    }
+
+    /// Only disputes and administrative holds hold funds, so held funds should always equal
+    /// their sum.
+    pub fn is_held_consistent(&self) -> bool {
+        self.disputed_amount().checked_add(self.admin_held()) == Ok(self.held.value())
+    }
+}
+
+pub struct ClientLedger {
+    pub id: ClientId,
+    pub available: MonetaryAmount,
+    pub held: MonetaryAmount,
+    pub total: MonetaryAmount,
+    pub is_locked: bool,
+    /// Number of rejected withdrawals that may still be enacted by a future resolve or, when
+    /// retried on deposits, deposit.
+    pub pending_withdrawals: usize,
+    /// Sum of the rejected withdrawals that may still be enacted by a future resolve.
+    pub pending_withdrawal_amount: MonetaryAmount,
+    /// Number of disputes currently holding funds, not counting those queued behind the held
+    /// funds limit.
+    pub open_disputes: usize,
+    /// The most recent transaction applied to the client, None if none ever was.
+    pub last_tx_id: Option<TransactionId>,
+    pub retried_operations: Vec<RetriedOperation>,
+    pub rolled_back_txs: Vec<RolledBackTransaction>,
+    pub retry_queue: Vec<QueuedRetry>,
+    pub abandoned_retries: Vec<AbandonedRetry>,
+    pub queued_disputes: Vec<QueuedDispute>,
+    /// Whether a dispute management operation was ever applied to the client.
+    pub had_dispute: bool,
+    /// Number of deposits and withdrawals applied.
+    pub transactions: usize,
+    pub counts: ActivityCounts,
+    /// Number of transactions a dispute management operation was ever applied to.
+    pub disputed_txs: usize,
+    /// Number of chargebacks not since reversed.
+    pub chargebacks: usize,
+    /// Sum of the deposits charged back and not since reversed.
+    pub charged_back_amount: MonetaryAmount,
+    pub rejected_withdrawals: usize,
+    /// Set when risk scores are enabled, see [`crate::RiskScorer`].
+    pub risk_score: Option<Decimal>,
+    pub flags: ClientFlags,
+}
+
+impl ClientLedger {
+    pub fn from_state(id: ClientId, state: ClientState) -> Self {
+        let pending = &state.history.rejected_txs;
+        let client = id.value();
+        Self {
+            id,
+            available: state.available.value(),
+            held: state.held.value(),
+            total: state.total(),
+            is_locked: state.is_locked,
+            pending_withdrawals: pending.len(),
+            pending_withdrawal_amount: pending.iter().fold(MonetaryAmount::default(), |acc, r| {
+                acc.saturating_add(r.activity.amount())
+            }),
+            open_disputes: state.history.disputed_txs.len(),
+            last_tx_id: state.history.last_tx_id,
+            retried_operations: state.history.retried_operations.iter().cloned().collect(),
+            rolled_back_txs: state.history.rolled_back_txs.iter().cloned().collect(),
+            retry_queue: pending
+                .iter()
+                .map(|r| QueuedRetry {
+                    client: r.activity.client_id().value(),
+                    tx: r.activity.tx_id().value(),
+                    amount: r.activity.amount().value(),
+                    attempts: r.attempts,
+                })
+                .collect(),
+            abandoned_retries: state.history.abandoned_retries.iter().cloned().collect(),
+            queued_disputes: state
+                .history
+                .queued_disputes
+                .iter()
+                .map(|tx_id| QueuedDispute {
+                    client: client.to_owned(),
+                    tx: tx_id.value(),
+                    amount: state
+                        .history
+                        .account_activity
+                        .get(tx_id)
+                        .map_or(Decimal::ZERO, |activity| activity.amount().value()),
+                })
+                .collect(),
+            had_dispute: !state.history.last_dispute_operations.is_empty(),
+            transactions: state.history.counts.transactions(),
+            counts: state.history.counts,
+            disputed_txs: state.history.last_dispute_operations.len(),
+            chargebacks: state.history.charged_back_txs.len(),
+            charged_back_amount: state.history.charged_back_txs.iter().fold(
+                MonetaryAmount::default(),
+                |acc, tx_id| match state.history.account_activity.get(tx_id) {
+                    Some(deposit) => acc.saturating_add(deposit.amount()),
+                    None => acc,
+                },
+            ),
+            rejected_withdrawals: state.history.rejected_withdrawals,
+            risk_score: None,
+            flags: state.flags,
+        }
+    }
+
+    /// An account with no funds which was never disputed, e.g. a one-off test client.
+    pub fn is_empty(&self) -> bool {
+        let zero = MonetaryAmount::default();
+        self.available == zero && self.held == zero && self.total == zero && !self.had_dispute
+    }
+}
+
+/// Client rows in order of client id, so the output and anything comparing ledgers sees the same
+/// order on every run. String ids are ordered lexicographically.
+#[derive(Default)]
+pub struct Ledger(pub Vec<ClientLedger>);
+
+impl Ledger {
+    pub fn from_states(states: HashMap<ClientId, ClientState>) -> Self {
+        let states: BTreeMap<ClientId, ClientState> = states.into_iter().collect();
+        Ledger(
+            states
+                .into_iter()
+                .map(|(k, v)| ClientLedger::from_state(k, v))
+                .collect(),
+        )
+    }
+}
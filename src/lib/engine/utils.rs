@@ -20,6 +20,8 @@ where
 
 pub trait PushImmut<T> {
     fn push(&self, item: T) -> Vector<T>;
+    /// As `push`, leaving the vector as it is once it holds `cap` items.
+    fn push_capped(&self, item: T, cap: Option<usize>) -> Vector<T>;
 }
 impl<T: Clone> PushImmut<T> for Vector<T> {
     fn push(&self, item: T) -> Vector<T> {
@@ -27,15 +29,11 @@ impl<T: Clone> PushImmut<T> for Vector<T> {
         result.push_back(item);
         result
     }
-}
 
-pub trait RemoveImmut<T> {
-    fn remove_idx(&self, idx: usize) -> Vector<T>;
-}
-impl<T: Clone> RemoveImmut<T> for Vector<T> {
-    fn remove_idx(&self, idx: usize) -> Vector<T> {
-        let mut result = self.clone();
-        result.remove(idx);
-        result
+    fn push_capped(&self, item: T, cap: Option<usize>) -> Vector<T> {
+        match cap {
+            Some(cap) if self.len() >= cap => self.clone(),
+            _ => self.push(item),
+        }
     }
 }
@@ -0,0 +1,223 @@
+use std::{error::Error, fmt, io, path::PathBuf};
+
+/// Raised when an input exceeds one of the safety limits set in [`crate::Config`]. Processing is
+/// aborted rather than producing a partial ledger.
+#[derive(Debug, PartialEq, Eq)]
+pub enum LimitExceeded {
+    Bytes { limit: u64, actual: u64 },
+    Rows { limit: usize },
+    Clients { limit: usize },
+    Memory { limit: u64, estimated: u64 },
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitExceeded::Bytes { limit, actual } => write!(
+                f,
+                "input file is {} bytes, exceeding the limit of {} bytes",
+                actual, limit
+            ),
+            LimitExceeded::Rows { limit } => {
+                write!(f, "input contains more than the limit of {} rows", limit)
+            }
+            LimitExceeded::Clients { limit } => {
+                write!(f, "input contains more than the limit of {} clients", limit)
+            }
+            LimitExceeded::Memory { limit, estimated } => write!(
+                f,
+                "processing is estimated to need {} bytes, exceeding the limit of {} bytes",
+                estimated, limit
+            ),
+        }
+    }
+}
+
+impl Error for LimitExceeded {}
+
+/// Raised in strict ordering mode when a transaction id is not greater than the one before it.
+/// Rows are counted from 1, excluding the header.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct OrderingViolation {
+    pub tx: u32,
+    pub row: usize,
+    pub previous_tx: u32,
+    pub previous_row: usize,
+}
+
+impl fmt::Display for OrderingViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "tx {} at row {} does not follow tx {} at row {}",
+            self.tx, self.row, self.previous_tx, self.previous_row
+        )
+    }
+}
+
+impl Error for OrderingViolation {}
+
+/// Reason a single input row was skipped without aborting the run.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum RowRejection {
+    /// A deposit, withdrawal or hold without an amount.
+    MissingAmount,
+    /// A dispute, resolve, chargeback, chargeback reversal or release with an amount.
+    UnexpectedAmount,
+    /// A row listing several transaction ids when batch rows are not enabled, or a deposit or
+    /// withdrawal listing several transaction ids.
+    MultipleTxIds,
+    /// A row with a blank client.
+    MissingClient,
+    /// A row other than `eod` with a blank transaction id.
+    MissingTx,
+    /// A dispute, resolve or chargeback without a client, whose transaction is not a deposit or
+    /// withdrawal seen earlier in the input to take the client from.
+    UnresolvedClient,
+    /// A deposit or withdrawal failing the amount checks, when suspect amounts are rejected.
+    SuspectAmount,
+    /// A row of a client which is not registered, when only registered clients may transact.
+    UnregisteredClient,
+    /// A row whose transaction type is neither known nor a configured alias, as it appeared.
+    UnknownTxType(String),
+}
+
+impl fmt::Display for RowRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RowRejection::MissingAmount => write!(f, "missing amount"),
+            RowRejection::UnexpectedAmount => write!(f, "unexpected amount"),
+            RowRejection::MultipleTxIds => write!(f, "multiple transaction ids"),
+            RowRejection::MissingClient => write!(f, "missing client"),
+            RowRejection::MissingTx => write!(f, "missing transaction id"),
+            RowRejection::SuspectAmount => write!(f, "suspect amount"),
+            RowRejection::UnregisteredClient => write!(f, "unregistered client"),
+            RowRejection::UnknownTxType(tx_type) => {
+                write!(f, "unknown transaction type `{}`", tx_type)
+            }
+            RowRejection::UnresolvedClient => {
+                write!(f, "no earlier transaction to take the client from")
+            }
+        }
+    }
+}
+
+/// An input row which was skipped, identified by its position among the transaction rows
+/// (starting from 1, excluding the header).
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RejectedRow {
+    pub row: usize,
+    pub reason: RowRejection,
+}
+
+impl fmt::Display for RejectedRow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "row {} rejected: {}", self.row, self.reason)
+    }
+}
+
+/// Where an input file ends part way through its last row, e.g. after an interrupted transfer.
+/// Every row before it is processed and the ledger is reported as a partial result.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Truncation {
+    /// Offset of the start of the truncated row from the start of the file.
+    pub byte_offset: u64,
+    /// Line of the input file the truncated row starts on, counting the header as line 1.
+    pub line: u64,
+    pub reason: String,
+}
+
+impl fmt::Display for Truncation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "input truncated at byte {} (line {}): {}",
+            self.byte_offset, self.line, self.reason
+        )
+    }
+}
+
+/// The field an unparseable row failed on, so that a bad value can be found without reading the
+/// row by eye.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct FieldError {
+    /// Name of the column in the header.
+    pub column: String,
+    /// The value after trimming.
+    pub value: String,
+    /// What the column should hold, e.g. `a decimal amount`.
+    pub expected: &'static str,
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "column `{}` expected {}, found `{}`",
+            self.column, self.expected, self.value
+        )
+    }
+}
+
+/// An input row which could not be parsed at all, e.g. a malformed number. Aborts processing
+/// unless a quarantine file is configured, in which case the row is copied there instead.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct UnparseableRow {
+    /// Line of the input file the row starts on, counting the header as line 1.
+    pub line: u64,
+    /// The row as read, before any trimming.
+    pub raw: String,
+    pub reason: String,
+    /// The first field which does not parse, None when the row as a whole is malformed, e.g.
+    /// has the wrong number of fields.
+    pub field: Option<FieldError>,
+}
+
+impl fmt::Display for UnparseableRow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "could not parse line {} ({}): {}",
+            self.line, self.raw, self.reason
+        )?;
+        match &self.field {
+            Some(field) => write!(f, " ({})", field),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Error for UnparseableRow {}
+
+/// Failure to open an input file.
+#[derive(Debug)]
+pub enum InputError {
+    NotFound(PathBuf),
+    PermissionDenied(PathBuf),
+    Io(PathBuf, io::Error),
+    /// An `s3://` or `gs://` URL which could not be fetched.
+    Remote(String, Box<dyn Error + Send + Sync>),
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputError::NotFound(path) => write!(f, "file not found: {}", path.display()),
+            InputError::PermissionDenied(path) => {
+                write!(f, "permission denied: {}", path.display())
+            }
+            InputError::Io(path, e) => write!(f, "could not open {}: {}", path.display(), e),
+            InputError::Remote(url, e) => write!(f, "could not fetch {}: {}", url, e),
+        }
+    }
+}
+
+impl Error for InputError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            InputError::Io(_, e) => Some(e),
+            InputError::Remote(_, e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
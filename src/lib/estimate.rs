@@ -0,0 +1,79 @@
+use std::{collections::HashSet, error::Error, ffi::OsString, fmt, mem::size_of};
+
+use crate::engine::types::{AccountActivity, ClientId, ClientState, Transaction, TransactionId};
+use crate::paths::open_input;
+
+// im maps keep entries in shared nodes and leave slack in them, roughly doubling each entry.
+const MAP_OVERHEAD: usize = 2;
+
+/// Expected size of a run, from a scan of the input which does not parse transactions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryEstimate {
+    pub rows: usize,
+    pub clients: usize,
+    /// Deposits and withdrawals, which are kept in client histories for later disputes unless
+    /// built with the `no-history` feature.
+    pub activities: usize,
+    pub bytes: u64,
+}
+
+impl fmt::Display for MemoryEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "estimated memory: {} bytes for {} rows, {} clients and {} deposits and withdrawals",
+            self.bytes, self.rows, self.clients, self.activities
+        )
+    }
+}
+
+fn estimated_bytes(rows: usize, clients: usize, activities: usize) -> u64 {
+    let input = rows * (size_of::<Transaction>() + size_of::<usize>());
+    let client = MAP_OVERHEAD * (size_of::<ClientId>() + size_of::<ClientState>());
+    let activity = if cfg!(feature = "no-history") {
+        0
+    } else {
+        MAP_OVERHEAD * (size_of::<TransactionId>() + size_of::<AccountActivity>())
+    };
+    (input + clients * client + activities * activity) as u64
+}
+
+/// Counts rows, distinct clients and deposits and withdrawals in the input, without
+/// deserializing rows.
+pub fn estimate_memory(csv_path: &OsString) -> Result<MemoryEstimate, Box<dyn Error>> {
+    let file = open_input(csv_path)?;
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(file);
+    let headers = reader.byte_headers()?.clone();
+    let column = |name: &str| headers.iter().position(|h| h == name.as_bytes());
+    let (Some(type_idx), Some(client_idx)) = (column("type"), column("client")) else {
+        return Err("input is missing a type or client column".into());
+    };
+
+    let mut rows = 0;
+    let mut clients = HashSet::new();
+    let mut activities = 0;
+    let mut record = csv::ByteRecord::new();
+    while reader.read_byte_record(&mut record)? {
+        rows += 1;
+        if let Some(client) = record.get(client_idx) {
+            if !clients.contains(client) {
+                clients.insert(client.to_vec());
+            }
+        }
+        if matches!(
+            record.get(type_idx),
+            Some(b"deposit" | b"withdrawal" | b"Deposit" | b"Withdrawal")
+        ) {
+            activities += 1;
+        }
+    }
+
+    Ok(MemoryEstimate {
+        rows,
+        clients: clients.len(),
+        activities,
+        bytes: estimated_bytes(rows, clients.len(), activities),
+    })
+}
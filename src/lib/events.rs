@@ -0,0 +1,77 @@
+//! A broadcast stream of ledger updates, enabled with the `ledger-events` feature, for building
+//! dashboards on top of the engine. Several consumers may subscribe to the same run, each
+//! receiving every event sent after it subscribed.
+
+use tokio::sync::broadcast;
+
+use crate::detailed::ClientBalances;
+use crate::engine::middleware::TxMiddleware;
+use crate::engine::types::{ClientKey, ClientState, DisputeManagement, Transaction};
+
+/// A change to the ledger, sent once the transaction causing it has been applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LedgerEvent {
+    /// The balances or lock of a client changed, giving their balances after the change.
+    ClientUpdated(ClientBalances),
+    /// The account was locked, e.g. by a chargeback.
+    AccountLocked { client: ClientKey },
+    /// A dispute was opened and its funds held.
+    DisputeOpened { client: ClientKey, tx: u32 },
+}
+
+/// Middleware sending a [`LedgerEvent`] for every change it sees to its subscribers. Events are
+/// dropped rather than blocking the run when there are no subscribers, and a subscriber which
+/// falls more than `capacity` events behind misses the oldest, as with any `broadcast` channel.
+pub struct LedgerEventBroadcaster {
+    sender: broadcast::Sender<LedgerEvent>,
+}
+
+impl LedgerEventBroadcaster {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self { sender }
+    }
+
+    /// A new stream of the events sent from now on. Receive with `recv().await`.
+    pub fn subscribe(&self) -> broadcast::Receiver<LedgerEvent> {
+        self.sender.subscribe()
+    }
+
+    fn send(&self, event: LedgerEvent) {
+        // an error only means nobody is subscribed
+        let _ = self.sender.send(event);
+    }
+}
+
+impl TxMiddleware for LedgerEventBroadcaster {
+    fn after(&mut self, transaction: &Transaction, before: &ClientState, after: &ClientState) {
+        let client = transaction.client_id().value();
+        if let Transaction::Dispute(DisputeManagement::Dispute(_, tx_id)) = transaction {
+            if !before.history.disputed_txs.contains(tx_id)
+                && after.history.disputed_txs.contains(tx_id)
+            {
+                self.send(LedgerEvent::DisputeOpened {
+                    client: client.to_owned(),
+                    tx: tx_id.value(),
+                });
+            }
+        }
+        if !before.is_locked && after.is_locked {
+            self.send(LedgerEvent::AccountLocked {
+                client: client.to_owned(),
+            });
+        }
+        if before.available != after.available
+            || before.held != after.held
+            || before.is_locked != after.is_locked
+        {
+            self.send(LedgerEvent::ClientUpdated(ClientBalances {
+                client,
+                available: after.available.value().value(),
+                held: after.held.value().value(),
+                total: after.total().value(),
+                locked: after.is_locked,
+            }));
+        }
+    }
+}
@@ -0,0 +1,278 @@
+//! Hand-rolled tokenizer for the transaction file, enabled with the `fast-parse` feature.
+//!
+//! The input format never needs quoting, so rows are split on newlines and commas with `memchr`
+//! instead of going through the `csv` crate and serde. Rows containing quotes are refused rather
+//! than parsed differently from the default path, which remains the reference implementation.
+
+use std::{
+    error::Error,
+    io::{BufRead, BufReader, Read},
+    str::{self, FromStr},
+};
+
+use crate::engine::types::ClientKey;
+use crate::error::{FieldError, UnparseableRow};
+use crate::io::{invalid_field, parse_amount, RawTxRowEntity};
+
+const READ_BUFFER: usize = 64 * 1024;
+const MAX_FIELDS: usize = 16;
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Position of each known column in the header.
+struct Columns {
+    tx_type: usize,
+    client: usize,
+    tx: usize,
+    amount: Option<usize>,
+    len: usize,
+}
+
+pub struct FastRows<R> {
+    reader: BufReader<R>,
+    line: Vec<u8>,
+    /// None when the input has no header, and so no rows.
+    columns: Option<Columns>,
+    /// Line number of the last line read, counting the header as line 1.
+    line_number: u64,
+}
+
+/// Input rows as read by the fast tokenizer. Reads the header straight away.
+pub fn raw_rows<R: Read>(reader: R) -> Result<FastRows<R>, Box<dyn Error>> {
+    let mut reader = BufReader::with_capacity(READ_BUFFER, reader);
+    let mut line = Vec::new();
+    let mut line_number = 0;
+    let columns = loop {
+        if !read_line(&mut reader, &mut line)? {
+            break None;
+        }
+        line_number += 1;
+        let header = line.strip_prefix(UTF8_BOM).unwrap_or(&line);
+        if !header.is_empty() {
+            break Some(parse_header(header)?);
+        }
+    };
+    Ok(FastRows {
+        reader,
+        line,
+        columns,
+        line_number,
+    })
+}
+
+impl<R: Read> Iterator for FastRows<R> {
+    type Item = Result<RawTxRowEntity, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let columns = self.columns.as_ref()?;
+        loop {
+            match read_line(&mut self.reader, &mut self.line) {
+                Ok(false) => return None,
+                Err(e) => return Some(Err(e.into())),
+                // empty lines are skipped, as the csv crate does
+                Ok(true) if self.line.is_empty() => self.line_number += 1,
+                Ok(true) => {
+                    self.line_number += 1;
+                    return Some(parse_row(&self.line, columns).map_err(|e| {
+                        UnparseableRow {
+                            line: self.line_number,
+                            raw: String::from_utf8_lossy(&self.line).into_owned(),
+                            reason: e.to_string(),
+                            field: field_error(&self.line, columns),
+                        }
+                        .into()
+                    }));
+                }
+            }
+        }
+    }
+}
+
+/// Reads the next line into `line` without its terminator. Returns false at end of input.
+fn read_line(reader: &mut impl BufRead, line: &mut Vec<u8>) -> std::io::Result<bool> {
+    line.clear();
+    if reader.read_until(b'\n', line)? == 0 {
+        return Ok(false);
+    }
+    if line.last() == Some(&b'\n') {
+        line.pop();
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(true)
+}
+
+fn split_fields<'a>(
+    line: &'a [u8],
+    fields: &mut [&'a [u8]; MAX_FIELDS],
+) -> Result<usize, Box<dyn Error>> {
+    if memchr::memchr(b'"', line).is_some() {
+        return Err("quoted fields are not supported by the fast parser".into());
+    }
+    let mut count = 0;
+    let mut start = 0;
+    for end in memchr::memchr_iter(b',', line).chain(std::iter::once(line.len())) {
+        let field = fields
+            .get_mut(count)
+            .ok_or("too many fields for the fast parser")?;
+        *field = line[start..end].trim_ascii();
+        count += 1;
+        start = end + 1;
+    }
+    Ok(count)
+}
+
+fn parse_header(line: &[u8]) -> Result<Columns, Box<dyn Error>> {
+    let mut fields = [&[][..]; MAX_FIELDS];
+    let len = split_fields(line, &mut fields)?;
+    let position = |names: &[&[u8]]| fields[..len].iter().position(|f| names.contains(f));
+    let missing = |name: &str| format!("missing field `{}` in header", name);
+    Ok(Columns {
+        tx_type: position(&[b"tx_type", b"type"]).ok_or_else(|| missing("tx_type"))?,
+        client: position(&[b"client"]).ok_or_else(|| missing("client"))?,
+        tx: position(&[b"tx"]).ok_or_else(|| missing("tx"))?,
+        amount: position(&[b"amount"]),
+        len,
+    })
+}
+
+fn parse_row(line: &[u8], columns: &Columns) -> Result<RawTxRowEntity, Box<dyn Error>> {
+    let mut fields = [&[][..]; MAX_FIELDS];
+    let len = split_fields(line, &mut fields)?;
+    if len != columns.len {
+        return Err(format!(
+            "found record with {} fields, but the header has {} fields",
+            len, columns.len
+        )
+        .into());
+    }
+    Ok(RawTxRowEntity {
+        tx_type: str::from_utf8(fields[columns.tx_type])?.to_owned(),
+        client: match fields[columns.client] {
+            b"" => None,
+            client => Some(
+                ClientKey::from_str(str::from_utf8(client)?)
+                    .map_err(|e| format!("invalid client: {}", e))?,
+            ),
+        },
+        tx: match str::from_utf8(fields[columns.tx])? {
+            "" => Vec::new(),
+            tx => tx
+                .split(';')
+                .map(|id| id.trim().parse::<u32>())
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("invalid tx: {}", e))?,
+        },
+        amount: match columns.amount.map(|idx| fields[idx]) {
+            None | Some(b"") => None,
+            Some(amount) => Some(parse_amount(str::from_utf8(amount)?)?),
+        },
+    })
+}
+
+/// The first field of a row which does not parse, if the row splits into the expected fields.
+fn field_error(line: &[u8], columns: &Columns) -> Option<FieldError> {
+    let mut fields = [&[][..]; MAX_FIELDS];
+    if split_fields(line, &mut fields).ok()? != columns.len {
+        return None;
+    }
+    let value = |idx: usize| String::from_utf8_lossy(fields[idx]);
+    let values = [
+        ("client", value(columns.client)),
+        ("tx", value(columns.tx)),
+        ("amount", columns.amount.map(value).unwrap_or_default()),
+    ];
+    invalid_field(
+        values
+            .iter()
+            .map(|(column, value)| (*column, value.as_ref())),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    use super::*;
+    use crate::config::Config;
+    use crate::engine::transactions::create_ledger;
+    use crate::io::{collect_rows, output_csv};
+
+    fn outcome_via(
+        rows: impl Iterator<Item = Result<RawTxRowEntity, Box<dyn Error>>>,
+    ) -> Vec<String> {
+        // the quarantine file is only written by the caller, collecting rows just needs it set
+        let config = Config::default()
+            .with_batch_dispute_rows(true)
+            .with_quarantine_file(PathBuf::from("quarantine.csv"));
+        let parsed = collect_rows(rows, &config, 0, None).unwrap();
+        let ledger = create_ledger(Box::new(parsed.transactions.into_iter()), &config.policy());
+        // client order in the output is arbitrary
+        let mut balances: Vec<String> = output_csv(ledger.0, &config)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+        balances.sort();
+        parsed
+            .rejected_rows
+            .iter()
+            .map(|r| r.to_string())
+            .chain(parsed.transaction_rows.iter().map(|r| r.to_string()))
+            // reasons are worded by each parser
+            .chain(
+                parsed
+                    .quarantined_rows
+                    .iter()
+                    .map(|r| format!("{}: {}", r.line, r.raw)),
+            )
+            .chain(balances)
+            .collect()
+    }
+
+    #[test]
+    fn fast_parser_matches_csv_crate_on_fixtures() {
+        let resources = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/resources");
+        for entry in fs::read_dir(resources).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            // only transaction files, and the string id fixture needs the other feature
+            if !name.ends_with(".csv")
+                || name.starts_with("reconcile")
+                || name.starts_with("bisect_snapshot")
+                || name.starts_with("client_flags")
+                || name.starts_with("string_client_ids")
+            {
+                continue;
+            }
+            let input = fs::read(&path).unwrap();
+            assert_eq!(
+                outcome_via(raw_rows(input.as_slice()).unwrap()),
+                outcome_via(crate::io::raw_rows(input.as_slice()).unwrap()),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn fast_parser_handles_header_order_whitespace_and_crlf() {
+        let input =
+            b"\xEF\xBB\xBFclient, amount ,type,tx\r\n 1 , 2.5, deposit ,1\r\n\r\n1,,dispute,1\r\n";
+        assert_eq!(
+            outcome_via(raw_rows(&input[..]).unwrap()),
+            outcome_via(crate::io::raw_rows(&input[..]).unwrap())
+        );
+    }
+
+    #[test]
+    fn fast_parser_rejects_quotes_and_ragged_rows() {
+        let quoted = b"type,client,tx,amount\ndeposit,1,1,\"2.5\"\n";
+        assert!(raw_rows(&quoted[..]).unwrap().next().unwrap().is_err());
+        let ragged = b"type,client,tx,amount\ndispute,1,1\n";
+        assert!(raw_rows(&ragged[..]).unwrap().next().unwrap().is_err());
+    }
+}
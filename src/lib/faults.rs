@@ -0,0 +1,155 @@
+//! Faults injected while reading the input, for testing that checkpointing, partial results and
+//! error handling behave under failure. Injecting needs the `fault-injection` feature, so that
+//! production builds refuse a configuration which would break their runs.
+
+use std::{error::Error, fs::File, io::Read};
+
+use serde::Deserialize;
+
+/// Faults to inject while reading the input. None are by default.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FaultInjection {
+    /// Fails a read with an IO error once in every this many rows. The row is the last of each
+    /// window unless a seed is given.
+    pub io_error_every: Option<usize>,
+    /// Picks the failing row of each window at random from this seed, so a failing run can be
+    /// repeated.
+    pub seed: Option<u64>,
+    /// Sleeps for this many milliseconds after each row is read.
+    pub row_delay_ms: Option<u64>,
+}
+
+impl FaultInjection {
+    pub fn with_io_error_every(self, io_error_every: usize) -> Self {
+        Self {
+            io_error_every: Some(io_error_every),
+            ..self
+        }
+    }
+
+    pub fn with_seed(self, seed: u64) -> Self {
+        Self {
+            seed: Some(seed),
+            ..self
+        }
+    }
+
+    pub fn with_row_delay_ms(self, row_delay_ms: u64) -> Self {
+        Self {
+            row_delay_ms: Some(row_delay_ms),
+            ..self
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.io_error_every.is_none() && self.row_delay_ms.is_none()
+    }
+}
+
+/// The input, reading through a [`faulty::FaultyReader`] when any fault is configured.
+#[cfg(feature = "fault-injection")]
+pub fn inject(file: File, faults: &FaultInjection) -> Result<Box<dyn Read>, Box<dyn Error>> {
+    match faults.is_empty() {
+        true => Ok(Box::new(file)),
+        false => Ok(Box::new(faulty::FaultyReader::new(file, *faults))),
+    }
+}
+
+#[cfg(not(feature = "fault-injection"))]
+pub fn inject(file: File, faults: &FaultInjection) -> Result<Box<dyn Read>, Box<dyn Error>> {
+    match faults.is_empty() {
+        true => Ok(Box::new(file)),
+        false => Err("injecting faults requires building with the fault-injection feature".into()),
+    }
+}
+
+#[cfg(feature = "fault-injection")]
+mod faulty {
+    use std::{
+        io::{self, Read},
+        thread,
+        time::Duration,
+    };
+
+    use super::FaultInjection;
+
+    /// Counts rows by their line endings as they are read, sleeping and failing as configured.
+    pub struct FaultyReader<R> {
+        inner: R,
+        faults: FaultInjection,
+        /// Line endings read so far, the first being that of the header.
+        lines: usize,
+        /// Rows before the current window of `io_error_every` rows.
+        window_start: usize,
+        /// Row after which the next read fails.
+        fail_after: Option<usize>,
+        failing: bool,
+        rng: u64,
+    }
+
+    impl<R: Read> FaultyReader<R> {
+        pub fn new(inner: R, faults: FaultInjection) -> Self {
+            let mut reader = Self {
+                inner,
+                faults,
+                lines: 0,
+                window_start: 0,
+                fail_after: None,
+                failing: false,
+                // xorshift needs a non-zero state
+                rng: faults.seed.unwrap_or_default() | 1,
+            };
+            reader.fail_after = reader.next_fault();
+            reader
+        }
+
+        /// The failing row of the current window.
+        fn next_fault(&mut self) -> Option<usize> {
+            let every = self.faults.io_error_every?.max(1);
+            let offset = match self.faults.seed {
+                Some(_) => (self.next_random() % every as u64) as usize,
+                None => every - 1,
+            };
+            Some(self.window_start + offset + 1)
+        }
+
+        fn next_random(&mut self) -> u64 {
+            self.rng ^= self.rng << 13;
+            self.rng ^= self.rng >> 7;
+            self.rng ^= self.rng << 17;
+            self.rng
+        }
+    }
+
+    impl<R: Read> Read for FaultyReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.failing {
+                let row = self.lines - 1;
+                self.failing = false;
+                self.window_start += self.faults.io_error_every.unwrap_or(1).max(1);
+                self.fail_after = self.next_fault();
+                return Err(io::Error::other(format!(
+                    "injected fault after row {}",
+                    row
+                )));
+            }
+            let read = self.inner.read(buf)?;
+            for (idx, byte) in buf[..read].iter().enumerate() {
+                if *byte != b'\n' {
+                    continue;
+                }
+                self.lines += 1;
+                if let Some(delay) = self.faults.row_delay_ms {
+                    thread::sleep(Duration::from_millis(delay));
+                }
+                if self.fail_after.is_some_and(|row| self.lines == row + 1) {
+                    self.failing = true;
+                    // bytes beyond the failing row are lost, as they would be with a real fault
+                    return Ok(idx + 1);
+                }
+            }
+            Ok(read)
+        }
+    }
+}
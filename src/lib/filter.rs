@@ -0,0 +1,359 @@
+//! Filter expressions selecting the client rows written to the output, e.g.
+//! `total > 1000 && locked == true`, so that operators can slice a large ledger without loading
+//! it into another tool.
+//!
+//! A comparison takes a field of the client row, one of `==`, `!=`, `<`, `<=`, `>` or `>=`, and a
+//! number, `true`, `false` or a double quoted string. Comparisons combine with `&&`, `||`, `!` and
+//! parentheses, `&&` binding tighter than `||`.
+
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+use rust_decimal::Decimal;
+use serde::{de, Deserialize, Deserializer};
+
+use crate::engine::types::ClientLedger;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Client,
+    Available,
+    Held,
+    Total,
+    Locked,
+    OpenDisputes,
+    PendingWithdrawals,
+    RejectedWithdrawals,
+    Transactions,
+    Deposits,
+    Withdrawals,
+    Disputes,
+    Chargebacks,
+    RiskScore,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "client" => Field::Client,
+            "available" => Field::Available,
+            "held" => Field::Held,
+            "total" => Field::Total,
+            "locked" => Field::Locked,
+            "open_disputes" => Field::OpenDisputes,
+            "pending_withdrawals" => Field::PendingWithdrawals,
+            "rejected_withdrawals" => Field::RejectedWithdrawals,
+            "transactions" => Field::Transactions,
+            "deposits" => Field::Deposits,
+            "withdrawals" => Field::Withdrawals,
+            "disputes" => Field::Disputes,
+            "chargebacks" => Field::Chargebacks,
+            "risk_score" => Field::RiskScore,
+            _ => return None,
+        })
+    }
+
+    /// The value of the field for a client, None for a risk score which was not computed.
+    fn value(self, client: &ClientLedger) -> Option<Value> {
+        let count = |n: usize| Some(Value::Number(Decimal::from(n)));
+        match self {
+            Field::Client => Some(Value::Text(client.id.value().to_string())),
+            Field::Available => Some(Value::Number(client.available.value())),
+            Field::Held => Some(Value::Number(client.held.value())),
+            Field::Total => Some(Value::Number(client.total.value())),
+            Field::Locked => Some(Value::Bool(client.is_locked)),
+            Field::OpenDisputes => count(client.open_disputes),
+            Field::PendingWithdrawals => count(client.pending_withdrawals),
+            Field::RejectedWithdrawals => count(client.rejected_withdrawals),
+            Field::Transactions => count(client.transactions),
+            Field::Deposits => count(client.counts.deposits),
+            Field::Withdrawals => count(client.counts.withdrawals),
+            Field::Disputes => count(client.counts.disputes),
+            Field::Chargebacks => count(client.counts.chargebacks),
+            Field::RiskScore => client.risk_score.map(Value::Number),
+        }
+    }
+
+    /// Whether the field may be compared with the value, checked when the filter is parsed.
+    fn accepts(self, op: Op, value: &Value) -> bool {
+        match (self, value) {
+            (Field::Client, Value::Number(_) | Value::Text(_)) => true,
+            (Field::Locked, Value::Bool(_)) => matches!(op, Op::Eq | Op::Ne),
+            (Field::Client | Field::Locked, _) => false,
+            (_, value) => matches!(value, Value::Number(_)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            Op::Eq => ordering.is_eq(),
+            Op::Ne => ordering.is_ne(),
+            Op::Lt => ordering.is_lt(),
+            Op::Le => ordering.is_le(),
+            Op::Gt => ordering.is_gt(),
+            Op::Ge => ordering.is_ge(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Number(Decimal),
+    Bool(bool),
+    Text(String),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Text(text) => write!(f, "\"{}\"", text),
+        }
+    }
+}
+
+/// Compares a field value with a literal. Client ids are compared as numbers when both are.
+fn compare(field: &Value, op: Op, literal: &Value) -> bool {
+    match (field, literal) {
+        (Value::Number(a), Value::Number(b)) => op.holds(a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) => op.holds(a.cmp(b)),
+        (Value::Text(a), Value::Text(b)) => op.holds(a.cmp(b)),
+        (Value::Text(a), Value::Number(b)) => {
+            a.parse::<Decimal>().is_ok_and(|a| op.holds(a.cmp(b)))
+        }
+        _ => false,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Compare(Field, Op, Value),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn matches(&self, client: &ClientLedger) -> bool {
+        match self {
+            Expr::Compare(field, op, literal) => field
+                .value(client)
+                .is_some_and(|value| compare(&value, *op, literal)),
+            Expr::Not(expr) => !expr.matches(client),
+            Expr::And(lhs, rhs) => lhs.matches(client) && rhs.matches(client),
+            Expr::Or(lhs, rhs) => lhs.matches(client) || rhs.matches(client),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(Decimal),
+    Text(String),
+    Op(Op),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(ident) => write!(f, "`{}`", ident),
+            Token::Number(n) => write!(f, "`{}`", n),
+            Token::Text(text) => write!(f, "`\"{}\"`", text),
+            Token::Op(op) => {
+                let op = match op {
+                    Op::Eq => "==",
+                    Op::Ne => "!=",
+                    Op::Lt => "<",
+                    Op::Le => "<=",
+                    Op::Gt => ">",
+                    Op::Ge => ">=",
+                };
+                write!(f, "`{}`", op)
+            }
+            Token::And => write!(f, "`&&`"),
+            Token::Or => write!(f, "`||`"),
+            Token::Not => write!(f, "`!`"),
+            Token::Open => write!(f, "`(`"),
+            Token::Close => write!(f, "`)`"),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some((idx, c)) = chars.next() {
+        let mut next_is = |expected: char| chars.next_if(|(_, c)| *c == expected).is_some();
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '&' if next_is('&') => Token::And,
+            '|' if next_is('|') => Token::Or,
+            '=' if next_is('=') => Token::Op(Op::Eq),
+            '!' if next_is('=') => Token::Op(Op::Ne),
+            '!' => Token::Not,
+            '<' if next_is('=') => Token::Op(Op::Le),
+            '<' => Token::Op(Op::Lt),
+            '>' if next_is('=') => Token::Op(Op::Ge),
+            '>' => Token::Op(Op::Gt),
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, c)) => text.push(c),
+                        None => return Err(format!("unterminated string at {}", idx)),
+                    }
+                }
+                Token::Text(text)
+            }
+            c if c.is_ascii_digit() || c == '-' || c == '.' => {
+                let mut number = String::from(c);
+                while let Some((_, c)) = chars.next_if(|(_, c)| c.is_ascii_digit() || *c == '.') {
+                    number.push(c);
+                }
+                Token::Number(
+                    Decimal::from_str(&number)
+                        .map_err(|_| format!("invalid number `{}` at {}", number, idx))?,
+                )
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut ident = String::from(c);
+                while let Some((_, c)) = chars.next_if(|(_, c)| c.is_alphanumeric() || *c == '_') {
+                    ident.push(c);
+                }
+                Token::Ident(ident)
+            }
+            c => return Err(format!("unexpected `{}` at {}", c, idx)),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over the tokens, one method per level of precedence.
+struct Parser {
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token>>,
+}
+
+impl Parser {
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.tokens.next_if_eq(&Token::Or).is_some() {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while self.tokens.next_if_eq(&Token::And).is_some() {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.tokens.next() {
+            Some(Token::Not) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                match self.tokens.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err(String::from("missing `)`")),
+                }
+            }
+            Some(Token::Ident(name)) => self.comparison(&name),
+            Some(token) => Err(format!("expected a field, found {}", token)),
+            None => Err(String::from(
+                "expected a field, found the end of the filter",
+            )),
+        }
+    }
+
+    fn comparison(&mut self, name: &str) -> Result<Expr, String> {
+        let field = Field::from_name(name).ok_or_else(|| format!("unknown field `{}`", name))?;
+        let Some(Token::Op(op)) = self.tokens.next() else {
+            return Err(format!("expected a comparison after `{}`", name));
+        };
+        let value = match self.tokens.next() {
+            Some(Token::Number(n)) => Value::Number(n),
+            Some(Token::Text(text)) => Value::Text(text),
+            Some(Token::Ident(ident)) if ident == "true" => Value::Bool(true),
+            Some(Token::Ident(ident)) if ident == "false" => Value::Bool(false),
+            _ => return Err(format!("expected a value to compare `{}` with", name)),
+        };
+        if !field.accepts(op, &value) {
+            return Err(format!("`{}` cannot be compared with {}", name, value));
+        }
+        Ok(Expr::Compare(field, op, value))
+    }
+}
+
+/// A parsed filter expression, see the module documentation for the syntax.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LedgerFilter {
+    source: String,
+    expr: Expr,
+}
+
+impl LedgerFilter {
+    /// Whether the client row is selected by the filter.
+    pub fn matches(&self, client: &ClientLedger) -> bool {
+        self.expr.matches(client)
+    }
+}
+
+impl FromStr for LedgerFilter {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let expr = tokenize(source)
+            .and_then(|tokens| {
+                let mut parser = Parser {
+                    tokens: tokens.into_iter().peekable(),
+                };
+                let expr = parser.or()?;
+                match parser.tokens.next() {
+                    None => Ok(expr),
+                    Some(token) => Err(format!("unexpected {}", token)),
+                }
+            })
+            .map_err(|e| format!("invalid filter `{}`: {}", source, e))?;
+        Ok(Self {
+            source: source.to_owned(),
+            expr,
+        })
+    }
+}
+
+impl fmt::Display for LedgerFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for LedgerFilter {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        String::deserialize(d)?.parse().map_err(de::Error::custom)
+    }
+}
@@ -0,0 +1,69 @@
+use std::fmt;
+
+use rust_decimal::Decimal;
+
+use crate::engine::types::{AccountActivity, ClientKey, ClientState, Decision, Transaction};
+use crate::io::format_amount;
+use crate::replay::ReplayObserver;
+
+/// A locked account and the chargeback which locked it. The input carries no timestamps, so the
+/// chargeback is located by its input row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrozenAccount {
+    pub client: ClientKey,
+    /// The charged back deposit.
+    pub tx: u32,
+    pub row: usize,
+    pub amount: Decimal,
+}
+
+impl fmt::Display for FrozenAccount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "client {} locked at row {} by chargeback of tx {} ({})",
+            self.client,
+            self.row,
+            self.tx,
+            format_amount(self.amount)
+        )
+    }
+}
+
+/// Records the chargeback which locked each account during a replay. Accounts unlocked by a
+/// chargeback reversal are dropped again.
+#[derive(Default)]
+pub struct FrozenAccountCollector {
+    pub frozen_accounts: Vec<FrozenAccount>,
+}
+
+impl ReplayObserver for FrozenAccountCollector {
+    fn observe(
+        &mut self,
+        row: usize,
+        transaction: &Transaction,
+        _decision: Decision,
+        before: &ClientState,
+        after: &ClientState,
+    ) {
+        let client = transaction.client_id().value();
+        if before.is_locked && !after.is_locked {
+            self.frozen_accounts
+                .retain(|frozen| frozen.client != client);
+        }
+        if before.is_locked || !after.is_locked {
+            return;
+        }
+        let tx_id = transaction.tx_id();
+        let amount = match before.history.account_activity.get(&tx_id) {
+            Some(deposit @ AccountActivity::Deposit(..)) => deposit.amount().value(),
+            _ => Decimal::ZERO,
+        };
+        self.frozen_accounts.push(FrozenAccount {
+            client,
+            tx: tx_id.value(),
+            row,
+            amount,
+        });
+    }
+}
@@ -1,39 +1,259 @@
-use std::{error::Error, ffi::OsString, fs::File};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    ffi::OsString,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+    str::FromStr,
+};
 
-use ::serde::{Deserialize, Serialize, Serializer};
-use rust_decimal::Decimal;
+use ::serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use csv::{ByteRecord, StringRecord};
+use rust_decimal::{Decimal, RoundingStrategy};
 
-use crate::types::{
-    AccountActivity, ClientId, ClientLedger, DisputeManagement, MonetaryAmount,
-    Transaction, TransactionId,
+use crate::config::{Config, OutputFormat, OutputSchema};
+use crate::engine::types::{
+    AccountActivity, AdminHold, ClientFlags, ClientId, ClientKey, ClientLedger, DisputeManagement,
+    Ledger, MonetaryAmount, Transaction, TransactionId,
+};
+use crate::error::{
+    FieldError, LimitExceeded, RejectedRow, RowRejection, Truncation, UnparseableRow,
 };
+use crate::faults::inject;
+use crate::paths::open_input;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Copy)]
 pub enum TxTypeEntity {
-    #[serde(alias = "deposit")]
     Deposit,
-    #[serde(alias = "withdrawal")]
     Withdrawal,
-    #[serde(alias = "dispute")]
     Dispute,
-    #[serde(alias = "resolve")]
     Resolve,
-    #[serde(alias = "chargeback")]
     ChargeBack,
+    ChargebackReversal,
+    Hold,
+    Release,
+    /// Closes a business day, see [`crate::Config::dispute_expiry_days`]. Takes no client,
+    /// transaction id or amount.
+    EndOfDay,
+}
+
+/// A transaction type name in lower case without any `-`, `_` or spaces, so that `Deposit`,
+/// `DEPOSIT` and `charge-back` match the names they stand for.
+fn normalize_type_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| !matches!(c, '-' | '_' | ' '))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+impl FromStr for TxTypeEntity {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match normalize_type_name(name).as_str() {
+            "deposit" => Ok(TxTypeEntity::Deposit),
+            "withdrawal" | "withdraw" => Ok(TxTypeEntity::Withdrawal),
+            "dispute" => Ok(TxTypeEntity::Dispute),
+            "resolve" => Ok(TxTypeEntity::Resolve),
+            "chargeback" => Ok(TxTypeEntity::ChargeBack),
+            "chargebackreversal" => Ok(TxTypeEntity::ChargebackReversal),
+            "hold" => Ok(TxTypeEntity::Hold),
+            "release" => Ok(TxTypeEntity::Release),
+            "endofday" | "eod" => Ok(TxTypeEntity::EndOfDay),
+            _ => Err(format!("unknown transaction type `{}`", name)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for TxTypeEntity {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        String::deserialize(d)?.parse().map_err(de::Error::custom)
+    }
+}
+
+/// The transaction type of a row, looking its name up in the configured aliases first.
+fn resolve_tx_type(name: &str, config: &Config) -> Result<TxTypeEntity, RowRejection> {
+    let normalized = normalize_type_name(name);
+    let alias = config
+        .tx_type_aliases
+        .iter()
+        .find(|(alias, _)| normalize_type_name(alias) == normalized);
+    match alias {
+        Some((_, tx_type)) => tx_type.parse(),
+        None => name.parse(),
+    }
+    .map_err(|_| RowRejection::UnknownTxType(name.to_owned()))
+}
+
+/// Fails on an alias naming no transaction type, rather than rejecting every row using it.
+fn check_tx_type_aliases(config: &Config) -> Result<(), Box<dyn Error>> {
+    for (alias, tx_type) in &config.tx_type_aliases {
+        if tx_type.parse::<TxTypeEntity>().is_err() {
+            return Err(format!(
+                "transaction type alias `{}` names unknown type `{}`",
+                alias, tx_type
+            )
+            .into());
+        }
+    }
+    Ok(())
 }
 
 #[derive(Debug, Deserialize)]
 pub struct TxRowEntity {
     #[serde(alias = "type")]
     pub tx_type: TxTypeEntity,
-    pub client: u16,
+    pub client: ClientKey,
     pub tx: u32,
-    pub amount: Option<f64>,
+    pub amount: Option<Decimal>,
+}
+
+/// Transaction ids separated by semicolons, none when the field is blank as on `eod` rows.
+fn tx_ids<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u32>, D::Error> {
+    let tx = String::deserialize(d)?;
+    if tx.is_empty() {
+        return Ok(Vec::new());
+    }
+    tx.split(';')
+        .map(|id| id.trim().parse::<u32>().map_err(de::Error::custom))
+        .collect()
+}
+
+/// Parses an amount as a plain or scientific decimal. Digit separators such as `1_000`, which
+/// `Decimal` would otherwise accept, are refused as a sign of a corrupted field.
+pub(crate) fn parse_amount(amount: &str) -> Result<Decimal, String> {
+    if amount.contains('_') {
+        return Err(format!("invalid amount `{}`", amount));
+    }
+    Decimal::from_str(amount)
+        .or_else(|_| Decimal::from_scientific(amount))
+        .map_err(|e| format!("invalid amount `{}`: {}", amount, e))
+}
+
+/// The first of the known columns whose value does not parse, given the columns and their
+/// trimmed values. Unknown columns are ignored, as they are when deserializing.
+pub(crate) fn invalid_field<'a>(
+    fields: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> Option<FieldError> {
+    fields.into_iter().find_map(|(column, value)| {
+        let expected =
+            match column {
+                "client" => (!value.is_empty() && ClientKey::from_str(value).is_err())
+                    .then_some("a client id"),
+                "tx" => (!value.is_empty()
+                    && value.split(';').any(|id| id.trim().parse::<u32>().is_err()))
+                .then_some("transaction ids from 0 to 4294967295, separated by semicolons"),
+                "amount" => (!value.is_empty() && parse_amount(value).is_err())
+                    .then_some("a decimal amount"),
+                _ => None,
+            }?;
+        Some(FieldError {
+            column: column.to_owned(),
+            value: value.to_owned(),
+            expected,
+        })
+    })
+}
+
+fn amount<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Decimal>, D::Error> {
+    match Option::<String>::deserialize(d)? {
+        Some(amount) if !amount.is_empty() => {
+            parse_amount(&amount).map(Some).map_err(de::Error::custom)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// A row as it appears in the input. Some partner files list several transaction ids separated
+/// by semicolons in a single dispute, resolve, chargeback or chargeback reversal row.
+#[derive(Debug, Deserialize)]
+pub struct RawTxRowEntity {
+    /// The name of the transaction type as it appears, resolved once the configured aliases are
+    /// known so that an unknown type rejects only its row.
+    #[serde(alias = "type")]
+    pub tx_type: String,
+    /// None when the column is blank.
+    pub client: Option<ClientKey>,
+    #[serde(deserialize_with = "tx_ids")]
+    pub tx: Vec<u32>,
+    #[serde(default, deserialize_with = "amount")]
+    pub amount: Option<Decimal>,
+}
+
+impl RawTxRowEntity {
+    /// Splits the row into one row per transaction id. When dispute clients are looked up, a
+    /// dispute management row whose client is blank or zero takes the client of the deposit or
+    /// withdrawal with the same transaction id.
+    fn expand(
+        self,
+        tx_type: TxTypeEntity,
+        config: &Config,
+        tx_clients: &HashMap<u32, ClientKey>,
+    ) -> Result<Vec<TxRowEntity>, RowRejection> {
+        let is_dispute_management = matches!(
+            tx_type,
+            TxTypeEntity::Dispute
+                | TxTypeEntity::Resolve
+                | TxTypeEntity::ChargeBack
+                | TxTypeEntity::ChargebackReversal
+        );
+        if self.tx.is_empty() {
+            return Err(RowRejection::MissingTx);
+        }
+        if self.tx.len() > 1 && !(config.batch_dispute_rows && is_dispute_management) {
+            return Err(RowRejection::MultipleTxIds);
+        }
+        let lookup_client = config.lookup_dispute_clients
+            && is_dispute_management
+            // zero for numeric client ids, string ids have no zero client besides a blank one
+            && self.client.as_ref().is_none_or(|c| *c == ClientKey::default());
+        self.tx
+            .into_iter()
+            .map(|tx| {
+                let client = match &self.client {
+                    _ if lookup_client => tx_clients
+                        .get(&tx)
+                        .cloned()
+                        .ok_or(RowRejection::UnresolvedClient)?,
+                    Some(client) => client.to_owned(),
+                    None => return Err(RowRejection::MissingClient),
+                };
+                Ok(TxRowEntity {
+                    tx_type,
+                    client,
+                    tx,
+                    amount: self.amount,
+                })
+            })
+            .collect()
+    }
 }
 
 impl TxRowEntity {
-    fn into_domain(self) -> Transaction {
+    /// Takes a deposit or withdrawal without an amount as one of zero when so configured, rather
+    /// than rejecting it.
+    fn with_missing_amount(self, config: &Config) -> Self {
+        let is_activity = matches!(
+            self.tx_type,
+            TxTypeEntity::Deposit | TxTypeEntity::Withdrawal
+        );
         match self {
+            TxRowEntity { amount: None, .. } if is_activity && config.missing_amount_as_zero => {
+                TxRowEntity {
+                    amount: Some(Decimal::ZERO),
+                    ..self
+                }
+            }
+            _ => self,
+        }
+    }
+}
+
+impl TryFrom<TxRowEntity> for Transaction {
+    type Error = RowRejection;
+
+    fn try_from(row: TxRowEntity) -> Result<Self, Self::Error> {
+        let transaction = match row {
             TxRowEntity {
                 tx_type: TxTypeEntity::Deposit,
                 client,
@@ -42,7 +262,7 @@ impl TxRowEntity {
             } => Transaction::Activity(AccountActivity::Deposit(
                 ClientId::new(client),
                 TransactionId::new(tx),
-                MonetaryAmount::new(a),
+                MonetaryAmount::from(a),
             )),
             TxRowEntity {
                 tx_type: TxTypeEntity::Withdrawal,
@@ -52,7 +272,7 @@ impl TxRowEntity {
             } => Transaction::Activity(AccountActivity::Withdrawal(
                 ClientId::new(client),
                 TransactionId::new(tx),
-                MonetaryAmount::new(a),
+                MonetaryAmount::from(a),
             )),
             TxRowEntity {
                 tx_type: TxTypeEntity::Dispute,
@@ -81,62 +301,713 @@ impl TxRowEntity {
                 ClientId::new(client),
                 TransactionId::new(tx),
             )),
-            _ => panic!("Found unexpected row in the input: {:?}", self),
+            TxRowEntity {
+                tx_type: TxTypeEntity::ChargebackReversal,
+                client,
+                tx,
+                amount: None,
+            } => Transaction::Dispute(DisputeManagement::ChargebackReversal(
+                ClientId::new(client),
+                TransactionId::new(tx),
+            )),
+            TxRowEntity {
+                tx_type: TxTypeEntity::Hold,
+                client,
+                tx,
+                amount: Some(a),
+            } => Transaction::Admin(AdminHold::Hold(
+                ClientId::new(client),
+                TransactionId::new(tx),
+                MonetaryAmount::from(a),
+            )),
+            TxRowEntity {
+                tx_type: TxTypeEntity::Release,
+                client,
+                tx,
+                amount: None,
+            } => Transaction::Admin(AdminHold::Release(
+                ClientId::new(client),
+                TransactionId::new(tx),
+            )),
+            TxRowEntity { amount: None, .. } => return Err(RowRejection::MissingAmount),
+            TxRowEntity {
+                amount: Some(_), ..
+            } => return Err(RowRejection::UnexpectedAmount),
+        };
+        Ok(transaction)
+    }
+}
+
+/// Decimal places amounts are rounded to in the output.
+pub const OUTPUT_DP: u32 = 4;
+
+/// Rounds an amount to the output precision, half to even so that rounding differences tend to
+/// cancel out across clients. When preserving scale the amount is kept at the precision it was
+/// computed at.
+pub fn output_amount(x: Decimal, config: &Config) -> Decimal {
+    if config.preserve_scale {
+        x
+    } else {
+        x.round_dp_with_strategy(OUTPUT_DP, RoundingStrategy::MidpointNearestEven)
+    }
+}
+
+/// Formats an amount rounded to the output precision for messages. Unlike formatting with
+/// `{:.4}`, this does not panic on amounts near the largest a balance can hold.
+pub fn format_amount(x: Decimal) -> String {
+    format_fixed_width(
+        &x.round_dp_with_strategy(OUTPUT_DP, RoundingStrategy::MidpointNearestEven),
+        '.',
+    )
+}
+
+/// Formats an amount with at least the output precision. Amounts are expected to have been
+/// rounded with [`output_amount`].
+fn format_fixed_width(x: &Decimal, decimal_separator: char) -> String {
+    let mut x = x.normalize();
+    if x.scale() < OUTPUT_DP {
+        x.rescale(OUTPUT_DP);
+    }
+    let formatted = x.to_string();
+    if decimal_separator == '.' {
+        formatted
+    } else {
+        formatted.replace('.', &decimal_separator.to_string())
+    }
+}
+
+/// Writes an amount with at least the output precision and a `.` decimal separator.
+pub fn fixed_width<S: Serializer>(x: &Decimal, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&format_fixed_width(x, '.'))
+}
+
+pub fn fixed_width_opt<S: Serializer>(x: &Option<Decimal>, s: S) -> Result<S::Ok, S::Error> {
+    match x {
+        Some(x) => fixed_width(x, s),
+        None => s.serialize_none(),
+    }
+}
+
+/// An amount of the client ledger output, rounded and written with the configured decimal
+/// separator.
+#[derive(Debug, Clone, Copy)]
+pub struct LedgerAmount {
+    value: Decimal,
+    decimal_separator: char,
+}
+
+impl LedgerAmount {
+    fn new(x: Decimal, config: &Config) -> Self {
+        Self {
+            value: output_amount(x, config),
+            decimal_separator: config.output.decimal_separator,
         }
     }
 }
 
-fn fixed_width<S: Serializer>(x: &Decimal, s: S) -> Result<S::Ok, S::Error> {
-    s.serialize_str(&format!("{:.4}", x))
+impl Serialize for LedgerAmount {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&format_fixed_width(&self.value, self.decimal_separator))
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// The output row of a client, with amounts at their full precision. Which of the optional
+/// columns are written, and how amounts are rounded and formatted, is up to the config when the
+/// row is written.
+#[derive(Debug, Clone)]
 pub struct ClientLedgerEntity {
-    client: u16,
-    #[serde(serialize_with = "fixed_width")]
-    available: Decimal,
-    #[serde(serialize_with = "fixed_width")]
-    held: Decimal,
-    #[serde(serialize_with = "fixed_width")]
-    total: Decimal,
-    locked: bool,
+    pub client: ClientKey,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+    pub open_disputes: usize,
+    pub rejected_withdrawals: usize,
+    pub last_tx_id: Option<u32>,
+    pub risk_score: Option<Decimal>,
+    pub pending_withdrawals: usize,
+    pub pending_withdrawal_amount: Decimal,
+    pub flags: ClientFlags,
 }
 
-impl ClientLedgerEntity {
-    pub fn from_ledger(ledger: ClientLedger) -> Self {
+impl From<ClientLedger> for ClientLedgerEntity {
+    fn from(ledger: ClientLedger) -> Self {
         Self {
             client: ledger.id.value(),
             available: ledger.available.value(),
             held: ledger.held.value(),
             total: ledger.total.value(),
             locked: ledger.is_locked,
+            open_disputes: ledger.open_disputes,
+            rejected_withdrawals: ledger.rejected_withdrawals,
+            last_tx_id: ledger.last_tx_id.map(|tx| tx.value()),
+            risk_score: ledger.risk_score,
+            pending_withdrawals: ledger.pending_withdrawals,
+            pending_withdrawal_amount: ledger.pending_withdrawal_amount.value(),
+            flags: ledger.flags,
+        }
+    }
+}
+
+/// A client row as written, with the columns of the schema and options of the run.
+#[derive(Debug, Serialize)]
+struct OutputRowEntity {
+    client: ClientKey,
+    available: LedgerAmount,
+    held: LedgerAmount,
+    total: LedgerAmount,
+    locked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    open_disputes: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rejected_withdrawals: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_tx_id: Option<Option<u32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    risk_score: Option<LedgerAmount>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pending_withdrawals: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pending_withdrawal_amount: Option<LedgerAmount>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    flags: Option<String>,
+}
+
+impl ClientLedgerEntity {
+    fn output_row(self, config: &Config) -> OutputRowEntity {
+        let show_pending = config.show_pending_withdrawals;
+        let is_v2 = config.schema == OutputSchema::V2;
+        let amount = |x: Decimal| LedgerAmount::new(x, config);
+        OutputRowEntity {
+            client: self.client,
+            available: amount(self.available),
+            held: amount(self.held),
+            total: amount(self.total),
+            locked: self.locked,
+            open_disputes: is_v2.then_some(self.open_disputes),
+            rejected_withdrawals: is_v2.then_some(self.rejected_withdrawals),
+            last_tx_id: is_v2.then_some(self.last_tx_id),
+            risk_score: self.risk_score.filter(|_| is_v2).map(amount),
+            pending_withdrawals: show_pending.then_some(self.pending_withdrawals),
+            pending_withdrawal_amount: show_pending.then(|| amount(self.pending_withdrawal_amount)),
+            flags: (!config.client_flags.is_empty())
+                .then(|| self.flags.into_iter().collect::<Vec<_>>().join(";")),
         }
     }
 }
 
-pub fn process_csv(csv_path: &OsString) -> Result<Vec<Transaction>, Box<dyn Error>> {
-    let file = File::open(csv_path)?;
+/// Transactions read from the input, along with any rows which could not be turned into a
+/// transaction.
+pub struct ParsedInput {
+    pub transactions: Vec<Transaction>,
+    /// Input row each transaction was read from. A single row may produce several transactions.
+    pub transaction_rows: Vec<usize>,
+    pub rejected_rows: Vec<RejectedRow>,
+    /// Rows which could not be parsed, only collected when a quarantine file is configured.
+    pub quarantined_rows: Vec<UnparseableRow>,
+    /// Number of rows read, including rejected and quarantined rows.
+    pub rows: usize,
+    /// Rows of the `eod` control rows, which produce no transaction.
+    pub end_of_day_rows: Vec<usize>,
+    /// Set when the input ends part way through its last row, which is then left out.
+    pub truncation: Option<Truncation>,
+}
+
+pub fn process_csv(csv_path: &OsString, config: &Config) -> Result<ParsedInput, Box<dyn Error>> {
+    process_csv_with_capacity(csv_path, config, 0)
+}
+
+/// As [`process_csv`], reserving space for `capacity` transactions up front.
+pub fn process_csv_with_capacity(
+    csv_path: &OsString,
+    config: &Config,
+    capacity: usize,
+) -> Result<ParsedInput, Box<dyn Error>> {
+    let mut file = open_input(csv_path)?;
+    if let Some(limit) = config.max_bytes {
+        let actual = file.metadata()?.len();
+        if actual > limit {
+            return Err(Box::new(LimitExceeded::Bytes { limit, actual }));
+        }
+    }
+    let unterminated = unterminated_line(&mut file)?;
+    let file = inject(file, &config.faults)?;
+
+    #[cfg(feature = "fast-parse")]
+    let rows = crate::fast_parse::raw_rows(file)?;
+    #[cfg(not(feature = "fast-parse"))]
+    let rows = raw_rows(file)?;
+    collect_rows(rows, config, capacity, unterminated)
+}
+
+/// Offset of the start of the last line when the input does not end with a newline, leaving the
+/// reader at the start of the input. Only such a line can have been cut short.
+fn unterminated_line<R: Read + Seek>(reader: &mut R) -> std::io::Result<Option<u64>> {
+    const CHUNK: u64 = 4096;
+    let len = reader.seek(SeekFrom::End(0))?;
+    let mut end = len;
+    let mut offset = None;
+    while end > 0 {
+        let start = end.saturating_sub(CHUNK);
+        let mut chunk = vec![0; (end - start) as usize];
+        reader.seek(SeekFrom::Start(start))?;
+        reader.read_exact(&mut chunk)?;
+        if end == len && chunk.last() == Some(&b'\n') {
+            break;
+        }
+        if let Some(idx) = chunk.iter().rposition(|b| *b == b'\n') {
+            offset = Some(start + idx as u64 + 1);
+            break;
+        }
+        offset = Some(0);
+        end = start;
+    }
+    reader.seek(SeekFrom::Start(0))?;
+    Ok(offset)
+}
+
+/// Input rows as read by the `csv` crate. Rows which cannot be parsed are returned as
+/// [`UnparseableRow`] errors so that they can be quarantined.
+#[cfg_attr(feature = "fast-parse", allow(dead_code))]
+pub(crate) fn raw_rows<R: Read>(
+    reader: R,
+) -> Result<impl Iterator<Item = Result<RawTxRowEntity, Box<dyn Error>>>, Box<dyn Error>> {
+    // Fields are trimmed per row rather than by the reader so that unparseable rows can be
+    // copied as they were read, and the field count is checked per row for the same reason.
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(reader);
+    let mut headers = reader.byte_headers()?.clone();
+    headers.trim();
+    let headers = StringRecord::from_byte_record(headers)?;
+    Ok(reader.into_byte_records().map(move |record| {
+        let record = record?;
+        let line = record.position().map_or(0, |p| p.line());
+        let raw = record
+            .iter()
+            .map(String::from_utf8_lossy)
+            .collect::<Vec<_>>()
+            .join(",");
+        parse_record(record, &headers).map_err(|(reason, field)| {
+            UnparseableRow {
+                line,
+                raw,
+                reason,
+                field,
+            }
+            .into()
+        })
+    }))
+}
+
+fn parse_record(
+    mut record: ByteRecord,
+    headers: &StringRecord,
+) -> Result<RawTxRowEntity, (String, Option<FieldError>)> {
+    if record.len() != headers.len() {
+        return Err((
+            format!(
+                "found record with {} fields, but the header has {} fields",
+                record.len(),
+                headers.len()
+            ),
+            None,
+        ));
+    }
+    record.trim();
+    let record = StringRecord::from_byte_record(record).map_err(|e| (e.to_string(), None))?;
+    record.deserialize(Some(headers)).map_err(|e| {
+        let reason = match e.kind() {
+            // the line is reported alongside the reason, so only the field is kept
+            csv::ErrorKind::Deserialize { err, .. } => err.to_string(),
+            _ => e.to_string(),
+        };
+        (reason, invalid_field(headers.iter().zip(record.iter())))
+    })
+}
+
+/// Rejects the transactions of a row if any is for a client outside the registered clients.
+fn check_registered(
+    transactions: Vec<Transaction>,
+    config: &Config,
+) -> Result<Vec<Transaction>, RowRejection> {
+    match &config.registered_clients {
+        Some(registered)
+            if transactions
+                .iter()
+                .any(|t| !registered.contains(&t.client_id().value())) =>
+        {
+            Err(RowRejection::UnregisteredClient)
+        }
+        _ => Ok(transactions),
+    }
+}
+
+/// Turns the raw rows into transactions. `unterminated` is the byte offset of the last line when
+/// the input does not end with a newline; if that line cannot be parsed it is taken to have been
+/// cut short, and is reported as a truncation instead of failing or being quarantined.
+pub(crate) fn collect_rows(
+    raw_rows: impl Iterator<Item = Result<RawTxRowEntity, Box<dyn Error>>>,
+    config: &Config,
+    capacity: usize,
+    unterminated: Option<u64>,
+) -> Result<ParsedInput, Box<dyn Error>> {
+    check_tx_type_aliases(config)?;
+    let mut rows: Vec<Transaction> = Vec::with_capacity(capacity);
+    let mut transaction_rows: Vec<usize> = Vec::with_capacity(capacity);
+    let mut rejected_rows: Vec<RejectedRow> = Vec::new();
+    let mut quarantined_rows: Vec<UnparseableRow> = Vec::new();
+    let mut end_of_day_rows: Vec<usize> = Vec::new();
+    let mut clients = HashSet::new();
+    // Owner of each deposit and withdrawal, only kept when dispute clients are looked up
+    let mut tx_clients = HashMap::new();
+    let mut row_count = 0;
+    let mut truncation = None;
+    let mut raw_rows = raw_rows.enumerate().peekable();
+    while let Some((idx, row)) = raw_rows.next() {
+        row_count = idx + 1;
+        if let Some(limit) = config.max_rows.filter(|limit| idx >= *limit) {
+            return Err(Box::new(LimitExceeded::Rows { limit }));
+        }
+        let row = match (row, unterminated) {
+            (Ok(row), _) => row,
+            (Err(e), Some(byte_offset)) if raw_rows.peek().is_none() => {
+                let row = e.downcast::<UnparseableRow>()?;
+                truncation = Some(Truncation {
+                    byte_offset,
+                    line: row.line,
+                    reason: row.reason,
+                });
+                row_count = idx;
+                break;
+            }
+            (Err(e), _) if config.quarantine_file.is_some() => {
+                quarantined_rows.push(*e.downcast::<UnparseableRow>()?);
+                continue;
+            }
+            // fail if  cannot deserialise, no point in incomplete ledger
+            (Err(e), _) => return Err(e),
+        };
+        let tx_type = match resolve_tx_type(&row.tx_type, config) {
+            Ok(TxTypeEntity::EndOfDay) => {
+                end_of_day_rows.push(idx + 1);
+                continue;
+            }
+            Ok(tx_type) => tx_type,
+            Err(reason) => {
+                rejected_rows.push(RejectedRow {
+                    row: idx + 1,
+                    reason,
+                });
+                continue;
+            }
+        };
+        if let Some(client) = &row.client {
+            clients.insert(client.to_owned());
+        }
+        if let Some(limit) = config.max_clients.filter(|limit| clients.len() > *limit) {
+            return Err(Box::new(LimitExceeded::Clients { limit }));
+        }
+        let transactions: Result<Vec<Transaction>, RowRejection> = row
+            .expand(tx_type, config, &tx_clients)
+            .and_then(|expanded| {
+                expanded
+                    .into_iter()
+                    .map(|r| Transaction::try_from(r.with_missing_amount(config)))
+                    .collect()
+            })
+            .and_then(|transactions| check_registered(transactions, config));
+        match transactions {
+            Ok(transactions) => {
+                if config.lookup_dispute_clients {
+                    for transaction in &transactions {
+                        if let Transaction::Activity(activity) = transaction {
+                            tx_clients
+                                .insert(activity.tx_id().value(), activity.client_id().value());
+                        }
+                    }
+                }
+                transaction_rows.extend(transactions.iter().map(|_| idx + 1));
+                rows.extend(transactions);
+            }
+            Err(reason) => rejected_rows.push(RejectedRow {
+                row: idx + 1,
+                reason,
+            }),
+        }
+    }
+
+    Ok(ParsedInput {
+        transactions: rows,
+        transaction_rows,
+        rejected_rows,
+        quarantined_rows,
+        rows: row_count,
+        end_of_day_rows,
+        truncation,
+    })
+}
+
+/// Writes the rows which could not be parsed to a CSV file with their line number, the field
+/// which failed when known, and the reason.
+pub fn write_quarantine(path: &Path, rows: &[UnparseableRow]) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_path(path)?;
+    wtr.write_record(["line", "column", "value", "expected", "reason", "row"])?;
+    for row in rows {
+        let (column, value, expected) = match &row.field {
+            Some(field) => (field.column.as_str(), field.value.as_str(), field.expected),
+            None => ("", "", ""),
+        };
+        wtr.write_record([
+            row.line.to_string().as_str(),
+            column,
+            value,
+            expected,
+            &row.reason,
+            &row.raw,
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Flags of a client as listed in a sidecar file, separated by semicolons.
+#[derive(Debug, Deserialize)]
+struct ClientFlagsRowEntity {
+    client: ClientKey,
+    flags: String,
+}
+
+/// Reads the flags of each client from a csv file with the columns `client,flags`.
+pub fn read_client_flags(
+    flags_path: &OsString,
+) -> Result<im::HashMap<ClientKey, ClientFlags>, Box<dyn Error>> {
+    let file = open_input(flags_path)?;
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(file);
+
+    let mut client_flags = im::HashMap::new();
+    for row in reader.deserialize::<ClientFlagsRowEntity>() {
+        let row = row?;
+        let flags: ClientFlags = row
+            .flags
+            .split(';')
+            .map(str::trim)
+            .filter(|flag| !flag.is_empty())
+            .map(String::from)
+            .collect();
+        client_flags = client_flags.update_with(row.client, flags, |a, b| a.union(b));
+    }
+    Ok(client_flags)
+}
+
+/// Expected state of a client after the first `row` input transactions have been processed.
+#[derive(Debug, Deserialize)]
+pub struct SnapshotRowEntity {
+    pub row: usize,
+    pub client: ClientKey,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+impl SnapshotRowEntity {
+    pub fn matches(&self, ledger: &ClientLedger) -> bool {
+        self.available == ledger.available.value()
+            && self.held == ledger.held.value()
+            && self.total == ledger.total.value()
+            && self.locked == ledger.is_locked
+    }
+}
+
+pub fn read_snapshot(snapshot_path: &OsString) -> Result<Vec<SnapshotRowEntity>, Box<dyn Error>> {
+    let file = open_input(snapshot_path)?;
     let mut reader = csv::ReaderBuilder::new()
         .trim(csv::Trim::All)
         .from_reader(file);
 
-    let mut rows: Vec<Transaction> = Vec::new();
-    for row in reader.deserialize::<TxRowEntity>() {
-        // fail if  cannot deserialise, no point in incomplete ledger
-        rows.push(row?.into_domain());
+    let mut rows: Vec<SnapshotRowEntity> = Vec::new();
+    for row in reader.deserialize::<SnapshotRowEntity>() {
+        rows.push(row?);
+    }
+    rows.sort_by_key(|r| r.row);
+
+    Ok(rows)
+}
+
+/// Balances of a single client, as written by the engine or an external system. The locked column
+/// is optional as not every system tracks it.
+#[derive(Debug, Deserialize)]
+pub struct BalanceRowEntity {
+    pub client: ClientKey,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: Option<bool>,
+}
+
+pub fn read_balances(balances_path: &OsString) -> Result<Vec<BalanceRowEntity>, Box<dyn Error>> {
+    parse_balances(open_input(balances_path)?)
+}
+
+pub fn parse_balances<R: Read>(reader: R) -> Result<Vec<BalanceRowEntity>, Box<dyn Error>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+
+    let mut rows: Vec<BalanceRowEntity> = Vec::new();
+    for row in reader.deserialize::<BalanceRowEntity>() {
+        rows.push(row?);
     }
 
     Ok(rows)
 }
 
-pub fn output_csv(client_ledger: Vec<ClientLedger>) -> Result<String, Box<dyn Error>> {
-    let mut wtr = csv::Writer::from_writer(vec![]);
+/// Net difference between the rounded and exact totals of all clients. With rounding half to even
+/// this stays close to zero, a large difference means amounts are systematically lost or gained
+/// by rounding.
+pub fn rounding_difference(client_ledger: &[ClientLedger], config: &Config) -> Decimal {
+    client_ledger
+        .iter()
+        .map(|c| output_amount(c.total.value(), config) - c.total.value())
+        .sum()
+}
+
+pub fn output_csv(
+    client_ledger: Vec<ClientLedger>,
+    config: &Config,
+) -> Result<String, Box<dyn Error>> {
+    let delimiter = config.output.delimiter;
+    if !delimiter.is_ascii() {
+        return Err(format!("Output delimiter {:?} is not an ASCII character", delimiter).into());
+    }
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(delimiter as u8)
+        .from_writer(vec![]);
 
     for client in client_ledger {
-        wtr.serialize(ClientLedgerEntity::from_ledger(client))?
+        wtr.serialize(ClientLedgerEntity::from(client).output_row(config))?
     }
 
     wtr.flush()?;
     let data = String::from_utf8(wtr.into_inner()?)?;
-    Ok(data)
+    match config.output.format {
+        OutputFormat::Csv => Ok(data),
+        OutputFormat::Table => table(&data, delimiter as u8, config.output.decimal_separator),
+    }
+}
+
+/// The canonical form of a ledger written as csv, so that ledgers can be compared as strings or
+/// hashed: the header followed by the client rows in id order, with `\n` line endings. Columns
+/// and amounts are kept as written. The delimiter is the one following `client` in the header.
+pub fn canonical_ledger_csv(ledger: &str) -> Result<String, Box<dyn Error>> {
+    let delimiter = ledger
+        .strip_prefix("client")
+        .and_then(|rest| rest.bytes().next())
+        .filter(|b| !b.is_ascii_whitespace())
+        .unwrap_or(b',');
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .from_reader(ledger.as_bytes());
+    let header = rdr.headers()?.clone();
+    // e.g. the ledger was written to partitions instead
+    if header.is_empty() {
+        return Ok(String::new());
+    }
+    let client = header
+        .iter()
+        .position(|column| column == "client")
+        .ok_or("ledger is not csv with a client column")?;
+    let mut rows = rdr
+        .records()
+        .map(|record| {
+            let record = record?;
+            let id = record[client]
+                .parse::<ClientKey>()
+                .map_err(|e| format!("invalid client `{}`: {}", &record[client], e))?;
+            Ok((id, record))
+        })
+        .collect::<Result<Vec<(ClientKey, StringRecord)>, Box<dyn Error>>>()?;
+    rows.sort_by_key(|(id, _)| id.to_owned());
+
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(vec![]);
+    wtr.write_record(&header)?;
+    for (_, record) in rows {
+        wtr.write_record(&record)?;
+    }
+    Ok(String::from_utf8(wtr.into_inner()?)?)
+}
+
+impl Ledger {
+    /// The ledger in the default output format at output precision, in the canonical form of
+    /// [`canonical_ledger_csv`], whatever the order of the ledger and the options of the run.
+    pub fn canonical_csv(&self) -> Result<String, Box<dyn Error>> {
+        let mut wtr = csv::Writer::from_writer(vec![]);
+        wtr.write_record(["client", "available", "held", "total", "locked"])?;
+        for c in &self.0 {
+            wtr.write_record([
+                c.id.value().to_string(),
+                format_amount(c.available.value()),
+                format_amount(c.held.value()),
+                format_amount(c.total.value()),
+                c.is_locked.to_string(),
+            ])?;
+        }
+        canonical_ledger_csv(&String::from_utf8(wtr.into_inner()?)?)
+    }
+}
+
+/// Lays out csv as a table with a rule under the header. Columns whose values are all numbers are
+/// aligned right, the rest left.
+fn table(csv: &str, delimiter: u8, decimal_separator: char) -> Result<String, Box<dyn Error>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .from_reader(csv.as_bytes());
+    let records = rdr.records().collect::<Result<Vec<StringRecord>, _>>()?;
+    let Some((header, body)) = records.split_first() else {
+        return Ok(String::new());
+    };
+    let is_number = |cell: &str| {
+        !cell.is_empty()
+            && cell
+                .chars()
+                .all(|c| c.is_ascii_digit() || c == '-' || c == decimal_separator)
+    };
+    let columns: Vec<(usize, bool)> = (0..header.len())
+        .map(|idx| {
+            let cells = || records.iter().map(|r| r.get(idx).unwrap_or_default());
+            let width = cells().map(|cell| cell.chars().count()).max().unwrap_or(0);
+            let is_numeric = !body.is_empty()
+                && body
+                    .iter()
+                    .all(|r| is_number(r.get(idx).unwrap_or_default()));
+            (width, is_numeric)
+        })
+        .collect();
+    let line = |record: &StringRecord| {
+        record
+            .iter()
+            .zip(&columns)
+            .map(|(cell, (width, is_numeric))| match is_numeric {
+                true => format!("{:>width$}", cell),
+                false => format!("{:<width$}", cell),
+            })
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_owned()
+    };
+    let rule = columns
+        .iter()
+        .map(|(width, _)| "-".repeat(*width))
+        .collect::<Vec<_>>()
+        .join("  ");
+    Ok(std::iter::once(line(header))
+        .chain(std::iter::once(rule))
+        .chain(body.iter().map(line))
+        .map(|l| l + "\n")
+        .collect())
 }
@@ -1,18 +1,488 @@
+mod activity;
+mod amount_checks;
+mod annotate;
+mod anomaly;
+#[cfg(feature = "arrow")]
+mod arrow;
+mod bisect;
+mod config;
+mod dead_letter;
+mod decisions;
+mod detailed;
+mod dropbox;
+mod engine;
+mod error;
+mod estimate;
+#[cfg(feature = "ledger-events")]
+mod events;
+#[cfg(feature = "fast-parse")]
+mod fast_parse;
+mod faults;
+mod filter;
+mod frozen;
 mod io;
-mod transactions;
-mod types;
-mod utils;
+mod manifest;
+mod metadata;
+mod metrics;
+mod ordering;
+mod partitions;
+mod paths;
+mod profile;
+mod reconcile;
+mod remote;
+mod replay;
+mod risk;
+mod snapshots;
+mod statement;
+mod system_accounts;
+mod warning;
 
-use std::{error::Error, ffi::OsString};
+use std::{
+    error::Error,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
+};
 
-use io::{output_csv, process_csv};
-use transactions::create_ledger;
+use rust_decimal::Decimal;
+
+pub use activity::tx_range_activity;
+use amount_checks::{reject_suspect_amounts, suspect_amounts};
+pub use annotate::annotate_input;
+use anomaly::{detect_anomalies, StatisticalDetector};
+pub use anomaly::{Anomaly, AnomalyReason};
+#[cfg(feature = "arrow")]
+pub use arrow::process_record_batch;
+pub use bisect::bisect_payments;
+pub use config::{Config, OutputFormat, OutputOptions, OutputSchema, ENV_PREFIX};
+pub use dead_letter::DeadLetter;
+use dead_letter::{write_dead_letters, DeadLetterCollector};
+pub use decisions::decision_table;
+pub use detailed::{process_transactions_detailed, ClientBalances, RowOutcome, RowStatus};
+pub use dropbox::{
+    process_dropbox, read_checkpoint, DropboxCheckpoint, DropboxOutcome, ProcessedFile,
+};
+pub use engine::clock::{Clock, FixedClock, StepClock, SystemClock};
+pub use engine::content_hash::ContentHash;
+pub use engine::middleware::TxMiddleware;
+use engine::transactions::create_ledger_with_middleware;
+pub use engine::types::{
+    AbandonedRetry, AccountActivity, ActivityCounts, AdminHold, ClientFlags, ClientId, ClientKey,
+    ClientLedger, ClientState, Decision, DisputeManagement, DisputeOperation, Ignored,
+    InvariantViolation, Ledger, MonetaryAmount, QueuedDispute, QueuedRetry, RetriedOperation,
+    RetryAbandonment, RolledBackTransaction, SourceMetadata, Transaction, TransactionEnvelope,
+    TransactionId,
+};
+pub use engine::{DisputePolicy, Policy, RetryPolicy};
+pub use error::{
+    FieldError, InputError, LimitExceeded, OrderingViolation, RejectedRow, RowRejection,
+    Truncation, UnparseableRow,
+};
+pub use estimate::{estimate_memory, MemoryEstimate};
+#[cfg(feature = "ledger-events")]
+pub use events::{LedgerEvent, LedgerEventBroadcaster};
+pub use faults::FaultInjection;
+pub use filter::LedgerFilter;
+pub use frozen::FrozenAccount;
+use frozen::FrozenAccountCollector;
+pub use io::{canonical_ledger_csv, read_client_flags};
+use io::{output_csv, process_csv_with_capacity, rounding_difference, write_quarantine};
+use manifest::{create_manifest, write_manifest};
+pub use manifest::{verify_manifest, Manifest, VerificationFailure};
+pub use metadata::RunMetadata;
+use metadata::{create_metadata, write_metadata, RunCounts};
+pub use metrics::{Metrics, TypeMetrics};
+use ordering::check_ordering;
+use partitions::write_partitions;
+pub use partitions::{Partition, PartitionManifest};
+use paths::write_output;
+#[cfg(feature = "count-allocations")]
+pub use profile::CountingAllocator;
+pub use profile::Profile;
+pub use reconcile::{
+    compare_ledgers, reconcile, LedgerDiff, Mismatch, MismatchCategory, MismatchKind,
+};
+use replay::{replay, ReplayObserver};
+pub use risk::{RiskScorer, WeightedRiskScorer};
+use snapshots::create_ledger_with_snapshots;
+pub use statement::StatementFormat;
+pub use system_accounts::SystemAccounts;
+use warning::WarningCollector;
+pub use warning::{Warning, WarningKind};
 
 pub fn process_payments(csv_path: &OsString) -> Result<String, Box<dyn Error>> {
-    let transactions = process_csv(csv_path)?;
+    process_payments_with_config(csv_path, &Config::default())
+}
+
+pub fn process_payments_with_config(
+    csv_path: &OsString,
+    config: &Config,
+) -> Result<String, Box<dyn Error>> {
+    Ok(process_payments_report(csv_path, config)?.output)
+}
+
+/// Result of processing a batch of payments.
+pub struct PaymentsReport {
+    /// The client ledger as csv.
+    pub output: String,
+    /// Rows which were skipped rather than applied to the ledger.
+    pub rejected_rows: Vec<RejectedRow>,
+    /// Rows which could not be parsed and were copied to the quarantine file, present when one
+    /// is configured.
+    pub quarantined_rows: Vec<UnparseableRow>,
+    /// Set when the input was cut short part way through its last row, in which case the ledger
+    /// is a partial result of the rows before it.
+    pub truncation: Option<Truncation>,
+    /// Dispute management operations referring to unknown transactions, present when a dead
+    /// letter file is configured.
+    pub dead_letters: Vec<DeadLetter>,
+    /// Dispute management operations which were not applied as they repeated the previous
+    /// operation on the same transaction.
+    pub retried_operations: Vec<RetriedOperation>,
+    /// Transactions whose state change was rolled back as it broke an invariant of the ledger.
+    pub rolled_back_transactions: Vec<RolledBackTransaction>,
+    /// Rejected withdrawals still waiting to be retried at the end of the run.
+    pub retry_queue: Vec<QueuedRetry>,
+    /// Rejected withdrawals dropped from the retry queue as they ran out of attempts or expired.
+    pub abandoned_retries: Vec<AbandonedRetry>,
+    /// Disputes still held back at the end of the run as they would exceed the held funds limit.
+    pub queued_disputes: Vec<QueuedDispute>,
+    /// Hash of the final ledger, the same for any run reaching the same balances and history.
+    pub ledger_hash: ContentHash,
+    /// Number of empty accounts left out of the output, when enabled.
+    pub omitted_accounts: usize,
+    /// Intermediate ledger snapshots written during the run.
+    pub snapshots: Vec<PathBuf>,
+    /// Files the ledger was written to when partitioned, in which case the output is empty.
+    pub partitions: Vec<PathBuf>,
+    /// Transactions flagged as unusual, present when anomaly detection is enabled.
+    pub anomalies: Vec<Anomaly>,
+    /// Transactions which were ignored or are otherwise suspect, present when warnings are
+    /// enabled.
+    pub warnings: Vec<Warning>,
+    /// Applied and ignored transactions of each type, present when metrics are enabled.
+    pub metrics: Option<Metrics>,
+    /// Chargeback losses, held funds and rejected withdrawal liability over every client.
+    pub system_accounts: SystemAccounts,
+    /// Locked accounts and the chargebacks which locked them, present when enabled.
+    pub frozen_accounts: Vec<FrozenAccount>,
+    /// Net difference the rounding of amounts in the output made to the sum of client totals.
+    pub rounding_difference: Decimal,
+    /// Estimated memory use, present when estimation is enabled or a memory limit is set.
+    pub estimate: Option<MemoryEstimate>,
+    /// Hashes of the input and output, present when a manifest file is configured.
+    pub manifest: Option<Manifest>,
+    /// How the ledger was produced, present when a metadata file is configured.
+    pub metadata: Option<RunMetadata>,
+    /// Present when profiling is enabled.
+    pub profile: Option<Profile>,
+}
 
-    let ledger = create_ledger(Box::new(transactions.into_iter()));
+pub fn process_payments_report(
+    csv_path: &OsString,
+    config: &Config,
+) -> Result<PaymentsReport, Box<dyn Error>> {
+    process_payments_with_hooks(csv_path, config, RunHooks::default())
+}
+
+/// Extension points of a run which cannot be set in a config file. Each defaults to what a run
+/// from the command line uses.
+pub struct RunHooks<'a> {
+    /// Called around each transaction as it is applied to the ledger, none by default.
+    pub middleware: &'a mut [Box<dyn TxMiddleware>],
+    /// Read for the ingestion time and snapshot names, the system clock by default.
+    pub clock: &'a dyn Clock,
+    /// Scores clients when risk scores are enabled, a [`WeightedRiskScorer`] by default.
+    pub risk_scorer: Box<dyn RiskScorer + 'a>,
+}
+
+impl Default for RunHooks<'_> {
+    fn default() -> Self {
+        Self {
+            middleware: &mut [],
+            clock: &SystemClock,
+            risk_scorer: Box::new(WeightedRiskScorer::default()),
+        }
+    }
+}
+
+impl<'a> RunHooks<'a> {
+    pub fn with_middleware(self, middleware: &'a mut [Box<dyn TxMiddleware>]) -> Self {
+        Self { middleware, ..self }
+    }
+
+    pub fn with_clock(self, clock: &'a dyn Clock) -> Self {
+        Self { clock, ..self }
+    }
+
+    pub fn with_risk_scorer(self, risk_scorer: impl RiskScorer + 'a) -> Self {
+        Self {
+            risk_scorer: Box::new(risk_scorer),
+            ..self
+        }
+    }
+}
+
+/// Processes payments with the given hooks. Warnings, metrics and frozen accounts are gathered
+/// from the input alone, they do not reflect vetoes of the middleware.
+pub fn process_payments_with_hooks(
+    csv_path: &OsString,
+    config: &Config,
+    hooks: RunHooks,
+) -> Result<PaymentsReport, Box<dyn Error>> {
+    let RunHooks {
+        middleware,
+        clock,
+        risk_scorer: scorer,
+    } = hooks;
+    let start_allocations = profile::allocations();
+    let start = Instant::now();
+    let estimate = if config.estimate_memory || config.max_memory.is_some() {
+        Some(estimate_memory(csv_path)?)
+    } else {
+        None
+    };
+    if let Some((limit, estimate)) = config.max_memory.zip(estimate) {
+        if estimate.bytes > limit {
+            return Err(Box::new(LimitExceeded::Memory {
+                limit,
+                estimated: estimate.bytes,
+            }));
+        }
+    }
+    let capacity = estimate.map_or(0, |e| e.rows);
+    let input = process_csv_with_capacity(csv_path, config, capacity)?;
+    let ingested_at = clock.now();
+    if let Some(path) = &config.quarantine_file {
+        write_quarantine(path, &input.quarantined_rows)?;
+    }
+    if config.strict_ordering {
+        check_ordering(&input.transactions, &input.transaction_rows)?;
+    }
+    let suspect_amounts = if config.check_amounts {
+        suspect_amounts(
+            &input.transactions,
+            &input.transaction_rows,
+            config.opening_balance,
+            config.deposit_cap,
+            &config.overdraft_limits,
+        )
+    } else {
+        Vec::new()
+    };
+    let (input, suspect_amounts) = if config.reject_suspect_amounts {
+        (reject_suspect_amounts(input, &suspect_amounts), Vec::new())
+    } else {
+        (input, suspect_amounts)
+    };
+    let parsed = Instant::now();
+    let rows = input.rows;
+    let transactions = input.transactions.len();
+    let anomalies = if config.detect_anomalies {
+        detect_anomalies(
+            &input.transactions,
+            &input.transaction_rows,
+            &mut StatisticalDetector::default(),
+        )
+    } else {
+        Vec::new()
+    };
+    let mut warnings = WarningCollector::default();
+    let mut metrics = Metrics::default();
+    let mut frozen_accounts = FrozenAccountCollector::default();
+    let mut dead_letters = DeadLetterCollector::default();
+    let mut observers: Vec<&mut dyn ReplayObserver> = Vec::new();
+    if config.collect_warnings {
+        observers.push(&mut warnings);
+    }
+    if config.collect_metrics {
+        observers.push(&mut metrics);
+    }
+    if config.report_frozen_accounts {
+        observers.push(&mut frozen_accounts);
+    }
+    if config.dead_letter_file.is_some() {
+        observers.push(&mut dead_letters);
+    }
+    if !observers.is_empty() {
+        replay(
+            &input.transactions,
+            &input.transaction_rows,
+            &input.end_of_day_rows,
+            config,
+            &mut observers,
+        );
+    }
+    if let Some(path) = &config.dead_letter_file {
+        write_dead_letters(path, &dead_letters.dead_letters)?;
+    }
+
+    let source: Arc<Path> = Arc::from(Path::new(csv_path));
+    let envelopes: Vec<TransactionEnvelope> = input
+        .transactions
+        .into_iter()
+        .zip(input.transaction_rows)
+        .map(|(transaction, row)| TransactionEnvelope {
+            transaction,
+            metadata: SourceMetadata {
+                source: Arc::clone(&source),
+                row,
+                ingested_at,
+            },
+        })
+        .collect();
+    let (ledger, snapshots) = if config.snapshot_every.is_some()
+        || !input.end_of_day_rows.is_empty()
+    {
+        create_ledger_with_snapshots(envelopes, &input.end_of_day_rows, config, middleware, clock)?
+    } else {
+        (
+            create_ledger_with_middleware(
+                Box::new(envelopes.into_iter()),
+                &config.policy(),
+                middleware,
+            ),
+            Vec::new(),
+        )
+    };
+    let folded = Instant::now();
+
+    let retried_operations = ledger
+        .0
+        .iter()
+        .flat_map(|c| c.retried_operations.iter().cloned())
+        .collect();
+    let rolled_back_transactions = ledger
+        .0
+        .iter()
+        .flat_map(|c| c.rolled_back_txs.iter().cloned())
+        .collect();
+    let retry_queue = ledger
+        .0
+        .iter()
+        .flat_map(|c| c.retry_queue.iter().cloned())
+        .collect();
+    let abandoned_retries = ledger
+        .0
+        .iter()
+        .flat_map(|c| c.abandoned_retries.iter().cloned())
+        .collect();
+    let queued_disputes = ledger
+        .0
+        .iter()
+        .flat_map(|c| c.queued_disputes.iter().cloned())
+        .collect();
+    let client_count = ledger.0.len();
+    let ledger_hash = ledger.content_hash();
+    let system_accounts = SystemAccounts::from_ledger(&ledger);
+    let rounding_difference = rounding_difference(&ledger.0, config);
+    let (omitted, clients): (Vec<_>, Vec<_>) = ledger
+        .0
+        .into_iter()
+        .partition(|c| config.omit_empty_accounts && c.is_empty());
+    let clients = match config.risk_scores && config.schema == OutputSchema::V2 {
+        true => clients
+            .into_iter()
+            .map(|c| ClientLedger {
+                risk_score: Some(scorer.score(&c)),
+                ..c
+            })
+            .collect(),
+        false => clients,
+    };
+    let clients: Vec<ClientLedger> = match &config.filter {
+        Some(filter) => clients.into_iter().filter(|c| filter.matches(c)).collect(),
+        None => clients,
+    };
+    let (output, partitions) = match config.partitions {
+        Some(count) => {
+            let dir = config.partition_dir.clone().unwrap_or_default();
+            (
+                String::new(),
+                write_partitions(clients, count, &dir, config)?,
+            )
+        }
+        None => (output_csv(clients, config)?, Vec::new()),
+    };
+    if let Some(path) = &config.output_file {
+        write_output(path, output.clone())?;
+    }
+    let finished = Instant::now();
+    let manifest = match &config.manifest_file {
+        Some(path) => {
+            let manifest = create_manifest(csv_path, &output, config.signing_key_file.as_deref())?;
+            write_manifest(path, &manifest)?;
+            Some(manifest)
+        }
+        None => None,
+    };
+    let metadata = match &config.metadata_file {
+        Some(path) => {
+            let counts = RunCounts {
+                rows,
+                transactions,
+                rejected_rows: input.rejected_rows.len(),
+                quarantined_rows: input.quarantined_rows.len(),
+                clients: client_count,
+            };
+            let metadata = create_metadata(csv_path, counts, config.policy(), clock)?;
+            write_metadata(path, &metadata)?;
+            Some(metadata)
+        }
+        None => None,
+    };
+
+    let mut warnings: Vec<Warning> = suspect_amounts
+        .into_iter()
+        .chain(warnings.warnings)
+        .collect();
+    warnings.sort_by_key(|w| w.row);
+
+    let profile = config.profile.then(|| Profile {
+        parse: parsed - start,
+        fold: folded - parsed,
+        output: finished - folded,
+        rows,
+        allocations: start_allocations
+            .zip(profile::allocations())
+            .map(|(start, end)| end - start),
+    });
+    Ok(PaymentsReport {
+        output,
+        rejected_rows: input.rejected_rows,
+        quarantined_rows: input.quarantined_rows,
+        truncation: input.truncation,
+        dead_letters: dead_letters.dead_letters,
+        retried_operations,
+        rolled_back_transactions,
+        retry_queue,
+        abandoned_retries,
+        queued_disputes,
+        ledger_hash,
+        omitted_accounts: omitted.len(),
+        snapshots,
+        partitions,
+        anomalies,
+        warnings,
+        metrics: config.collect_metrics.then_some(metrics),
+        system_accounts,
+        frozen_accounts: frozen_accounts.frozen_accounts,
+        rounding_difference,
+        estimate,
+        manifest,
+        metadata,
+        profile,
+    })
+}
 
-    let result = output_csv(ledger.0)?;
-    Ok(result)
+/// Renders the balances of a single client after each of their transactions.
+pub fn client_statement(
+    csv_path: &OsString,
+    client: ClientKey,
+    format: StatementFormat,
+    config: &Config,
+) -> Result<String, Box<dyn Error>> {
+    let lines = statement::client_statement(csv_path, &client, config)?;
+    statement::output_statement(&client, lines, format)
 }
@@ -0,0 +1,254 @@
+use std::{
+    error::Error,
+    ffi::OsString,
+    fmt, fs,
+    io::{self, Read},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::io::canonical_ledger_csv;
+use crate::paths::open_input;
+
+/// Sidecar file recording hashes of the input consumed and the ledger produced by a run, so that
+/// a consumer can check neither was modified afterwards.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// SHA-256 of the input file, hex encoded.
+    pub input_sha256: String,
+    /// SHA-256 of the canonicalized ledger, hex encoded.
+    pub output_sha256: String,
+    /// Hex encoded ed25519 public key, present when the manifest is signed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+    /// Hex encoded ed25519 signature of [`Manifest::signed_message`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+impl Manifest {
+    /// The bytes covered by the signature, binding the input and output hashes together.
+    pub fn signed_message(&self) -> String {
+        format!("{}\n{}", self.input_sha256, self.output_sha256)
+    }
+}
+
+/// A check made by [`verify_manifest`] which did not hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerificationFailure {
+    OutputModified,
+    InputModified,
+    InvalidSignature,
+    /// A trusted key was given but the manifest is not signed.
+    MissingSignature,
+    /// The manifest was signed with a key other than the trusted one.
+    UntrustedKey,
+}
+
+impl fmt::Display for VerificationFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerificationFailure::OutputModified => {
+                write!(f, "ledger does not match the manifest")
+            }
+            VerificationFailure::InputModified => {
+                write!(f, "input does not match the manifest")
+            }
+            VerificationFailure::InvalidSignature => {
+                write!(f, "manifest signature is not valid")
+            }
+            VerificationFailure::MissingSignature => {
+                write!(f, "manifest is not signed")
+            }
+            VerificationFailure::UntrustedKey => {
+                write!(f, "manifest is signed with an untrusted key")
+            }
+        }
+    }
+}
+
+pub(crate) fn sha256_hex(reader: &mut impl Read) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(reader, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Hashes the input file and the ledger output, signing them when a key file is given.
+pub fn create_manifest(
+    csv_path: &OsString,
+    output: &str,
+    signing_key_file: Option<&Path>,
+) -> Result<Manifest, Box<dyn Error>> {
+    let manifest = Manifest {
+        input_sha256: sha256_hex(&mut open_input(csv_path)?)?,
+        output_sha256: sha256_hex(&mut canonical_ledger_csv(output)?.as_bytes())?,
+        public_key: None,
+        signature: None,
+    };
+    match signing_key_file {
+        Some(key_file) => sign(manifest, key_file),
+        None => Ok(manifest),
+    }
+}
+
+pub fn write_manifest(path: &Path, manifest: &Manifest) -> Result<(), Box<dyn Error>> {
+    fs::write(path, toml::to_string(manifest)?)?;
+    Ok(())
+}
+
+/// Checks a ledger, and optionally the input it was produced from, against a manifest. Given a
+/// file holding the hex encoded public key the manifest is trusted to be signed with, the
+/// manifest must also carry a valid signature by that key. The key recorded in the manifest is
+/// never trusted by itself, as anyone rewriting the hashes could sign them with their own.
+pub fn verify_manifest(
+    ledger_path: &OsString,
+    manifest_path: &OsString,
+    input_path: Option<&OsString>,
+    public_key_file: Option<&OsString>,
+) -> Result<Vec<VerificationFailure>, Box<dyn Error>> {
+    let mut manifest = String::new();
+    open_input(manifest_path)?.read_to_string(&mut manifest)?;
+    let manifest: Manifest = toml::from_str(&manifest)?;
+    let mut ledger = String::new();
+    open_input(ledger_path)?.read_to_string(&mut ledger)?;
+
+    let mut failures = Vec::new();
+    if sha256_hex(&mut canonical_ledger_csv(&ledger)?.as_bytes())? != manifest.output_sha256 {
+        failures.push(VerificationFailure::OutputModified);
+    }
+    if let Some(input_path) = input_path {
+        if sha256_hex(&mut open_input(input_path)?)? != manifest.input_sha256 {
+            failures.push(VerificationFailure::InputModified);
+        }
+    }
+    if let Some(key_file) = public_key_file {
+        let mut public_key = String::new();
+        open_input(key_file)?.read_to_string(&mut public_key)?;
+        let public_key = public_key.trim();
+        match (&manifest.public_key, &manifest.signature) {
+            (_, None) => failures.push(VerificationFailure::MissingSignature),
+            (Some(signed_with), _) if !signed_with.eq_ignore_ascii_case(public_key) => {
+                failures.push(VerificationFailure::UntrustedKey)
+            }
+            _ if !verify_signature(&manifest, public_key)? => {
+                failures.push(VerificationFailure::InvalidSignature)
+            }
+            _ => {}
+        }
+    }
+    Ok(failures)
+}
+
+/// Reads a hex encoded 32 byte ed25519 secret key.
+#[cfg(feature = "signing")]
+fn read_signing_key(key_file: &Path) -> Result<ed25519_dalek::SigningKey, Box<dyn Error>> {
+    let mut key = String::new();
+    open_input(key_file.as_os_str())?.read_to_string(&mut key)?;
+    let bytes: [u8; 32] = hex::decode(key.trim())?
+        .try_into()
+        .map_err(|_| "signing key must be 32 bytes")?;
+    Ok(ed25519_dalek::SigningKey::from_bytes(&bytes))
+}
+
+#[cfg(feature = "signing")]
+fn sign(manifest: Manifest, key_file: &Path) -> Result<Manifest, Box<dyn Error>> {
+    use ed25519_dalek::Signer;
+
+    let key = read_signing_key(key_file)?;
+    let signature = key.sign(manifest.signed_message().as_bytes());
+    Ok(Manifest {
+        public_key: Some(hex::encode(key.verifying_key().as_bytes())),
+        signature: Some(hex::encode(signature.to_bytes())),
+        ..manifest
+    })
+}
+
+#[cfg(not(feature = "signing"))]
+fn sign(_manifest: Manifest, _key_file: &Path) -> Result<Manifest, Box<dyn Error>> {
+    Err("signing manifests requires building with the signing feature".into())
+}
+
+/// Whether the manifest's signature is valid for the given hex encoded public key.
+#[cfg(feature = "signing")]
+fn verify_signature(manifest: &Manifest, public_key: &str) -> Result<bool, Box<dyn Error>> {
+    use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+    let public_key: [u8; 32] = hex::decode(public_key)?
+        .try_into()
+        .map_err(|_| "public key must be 32 bytes")?;
+    let signature: [u8; 64] = hex::decode(manifest.signature.as_deref().unwrap_or_default())?
+        .try_into()
+        .map_err(|_| "manifest signature must be 64 bytes")?;
+    let key = VerifyingKey::from_bytes(&public_key)?;
+    Ok(key
+        .verify(
+            manifest.signed_message().as_bytes(),
+            &Signature::from_bytes(&signature),
+        )
+        .is_ok())
+}
+
+#[cfg(not(feature = "signing"))]
+fn verify_signature(_manifest: &Manifest, _public_key: &str) -> Result<bool, Box<dyn Error>> {
+    Err("verifying signed manifests requires building with the signing feature".into())
+}
+
+#[cfg(all(test, feature = "signing"))]
+mod tests {
+    use test_utils::ScratchDir;
+
+    use super::*;
+
+    #[test]
+    fn signed_manifest_verifies_only_against_the_trusted_key() {
+        let dir = ScratchDir::new("signing");
+        let csv_path = OsString::from("tests/resources/basic_example.csv");
+        let output = "client,available\n2,2.0000\n1,1.5000\n";
+        let ledger_path = OsString::from(dir.join("ledger.csv"));
+        fs::write(&ledger_path, output).unwrap();
+        let sign_with = |name: &str, secret: [u8; 32]| {
+            let key_file = dir.join(name);
+            fs::write(&key_file, hex::encode(secret)).unwrap();
+            create_manifest(&csv_path, output, Some(&key_file)).unwrap()
+        };
+        let verify = |manifest: &Manifest| {
+            let manifest_path = OsString::from(dir.join("manifest.toml"));
+            write_manifest(Path::new(&manifest_path), manifest).unwrap();
+            let trusted_key = OsString::from(dir.join("trusted.pub"));
+            verify_manifest(&ledger_path, &manifest_path, None, Some(&trusted_key)).unwrap()
+        };
+        let manifest = sign_with("key", [7u8; 32]);
+        fs::write(
+            dir.join("trusted.pub"),
+            manifest.public_key.clone().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(verify(&manifest), vec![]);
+
+        let tampered = Manifest {
+            output_sha256: sha256_hex(&mut "client,available\n".as_bytes()).unwrap(),
+            ..manifest.clone()
+        };
+        assert_eq!(
+            verify(&tampered),
+            vec![
+                VerificationFailure::OutputModified,
+                VerificationFailure::InvalidSignature
+            ]
+        );
+        let stripped = Manifest {
+            public_key: None,
+            signature: None,
+            ..manifest
+        };
+        assert_eq!(
+            verify(&stripped),
+            vec![VerificationFailure::MissingSignature]
+        );
+        // re-signing with another key is caught even though the signature itself is valid
+        let resigned = sign_with("other_key", [8u8; 32]);
+        assert_eq!(verify(&resigned), vec![VerificationFailure::UntrustedKey]);
+    }
+}
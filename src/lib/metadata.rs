@@ -0,0 +1,66 @@
+//! Sidecar file describing how an output was produced, so that archived ledgers can be audited
+//! and reproduced long after the run.
+
+use std::{error::Error, ffi::OsString, fs, path::Path, time::UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::engine::clock::Clock;
+use crate::engine::Policy;
+use crate::manifest::sha256_hex;
+use crate::paths::open_input;
+
+/// The engine, input and policy a ledger was produced from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunMetadata {
+    /// Version of the engine which produced the ledger.
+    pub engine_version: String,
+    /// Seconds since the Unix epoch at which the run took place.
+    pub generated_at: u64,
+    pub input_file: String,
+    /// SHA-256 of the input file, hex encoded.
+    pub input_sha256: String,
+    /// Rows read, including rejected and quarantined rows.
+    pub rows: usize,
+    pub transactions: usize,
+    pub rejected_rows: usize,
+    pub quarantined_rows: usize,
+    /// Clients in the ledger, including any empty accounts left out of the output.
+    pub clients: usize,
+    /// The options which changed how transactions affected balances.
+    pub policy: Policy,
+}
+
+/// Row counts of a run, as recorded in its metadata.
+pub struct RunCounts {
+    pub rows: usize,
+    pub transactions: usize,
+    pub rejected_rows: usize,
+    pub quarantined_rows: usize,
+    pub clients: usize,
+}
+
+pub fn create_metadata(
+    csv_path: &OsString,
+    counts: RunCounts,
+    policy: Policy,
+    clock: &dyn Clock,
+) -> Result<RunMetadata, Box<dyn Error>> {
+    Ok(RunMetadata {
+        engine_version: String::from(env!("CARGO_PKG_VERSION")),
+        generated_at: clock.now().duration_since(UNIX_EPOCH)?.as_secs(),
+        input_file: Path::new(csv_path).display().to_string(),
+        input_sha256: sha256_hex(&mut open_input(csv_path)?)?,
+        rows: counts.rows,
+        transactions: counts.transactions,
+        rejected_rows: counts.rejected_rows,
+        quarantined_rows: counts.quarantined_rows,
+        clients: counts.clients,
+        policy,
+    })
+}
+
+pub fn write_metadata(path: &Path, metadata: &RunMetadata) -> Result<(), Box<dyn Error>> {
+    fs::write(path, toml::to_string(metadata)?)?;
+    Ok(())
+}
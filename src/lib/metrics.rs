@@ -0,0 +1,125 @@
+use std::fmt;
+
+use rust_decimal::Decimal;
+
+use crate::engine::types::{
+    AccountActivity, AdminHold, ClientState, Decision, DisputeManagement, Transaction,
+};
+use crate::io::format_amount;
+use crate::replay::ReplayObserver;
+
+/// Number and total amount of the transactions of one type which were applied or ignored. The
+/// amount of a dispute, resolve, chargeback or reversal is that of the deposit it refers to, and zero if
+/// the deposit is unknown. Likewise a release counts the amount of the hold it refers to.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TypeMetrics {
+    pub applied: usize,
+    pub applied_amount: Decimal,
+    pub ignored: usize,
+    pub ignored_amount: Decimal,
+}
+
+impl TypeMetrics {
+    fn record(self, applied: bool, amount: Decimal) -> Self {
+        if applied {
+            Self {
+                applied: self.applied + 1,
+                applied_amount: self.applied_amount.saturating_add(amount),
+                ..self
+            }
+        } else {
+            Self {
+                ignored: self.ignored + 1,
+                ignored_amount: self.ignored_amount.saturating_add(amount),
+                ..self
+            }
+        }
+    }
+}
+
+impl fmt::Display for TypeMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} applied ({}), {} ignored ({})",
+            self.applied,
+            format_amount(self.applied_amount),
+            self.ignored,
+            format_amount(self.ignored_amount)
+        )
+    }
+}
+
+/// Per transaction type summary of a run.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Metrics {
+    pub deposits: TypeMetrics,
+    pub withdrawals: TypeMetrics,
+    pub disputes: TypeMetrics,
+    pub resolves: TypeMetrics,
+    pub chargebacks: TypeMetrics,
+    pub chargeback_reversals: TypeMetrics,
+    pub holds: TypeMetrics,
+    pub releases: TypeMetrics,
+}
+
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "deposits: {}", self.deposits)?;
+        writeln!(f, "withdrawals: {}", self.withdrawals)?;
+        writeln!(f, "disputes: {}", self.disputes)?;
+        writeln!(f, "resolves: {}", self.resolves)?;
+        writeln!(f, "chargebacks: {}", self.chargebacks)?;
+        writeln!(f, "chargeback reversals: {}", self.chargeback_reversals)?;
+        writeln!(f, "holds: {}", self.holds)?;
+        write!(f, "releases: {}", self.releases)
+    }
+}
+
+fn amount(transaction: &Transaction, before: &ClientState) -> Decimal {
+    match transaction {
+        Transaction::Activity(activity) => activity.amount().value(),
+        Transaction::Dispute(
+            DisputeManagement::Dispute(_, tx_id)
+            | DisputeManagement::Resolve(_, tx_id)
+            | DisputeManagement::Chargeback(_, tx_id)
+            | DisputeManagement::ChargebackReversal(_, tx_id),
+        ) => match before.history.account_activity.get(tx_id) {
+            Some(deposit @ AccountActivity::Deposit(..)) => deposit.amount().value(),
+            _ => Decimal::ZERO,
+        },
+        Transaction::Admin(AdminHold::Hold(_, _, amount)) => amount.value(),
+        Transaction::Admin(AdminHold::Release(_, tx_id)) => before
+            .history
+            .admin_holds
+            .get(tx_id)
+            .map_or(Decimal::ZERO, |amount| amount.value()),
+    }
+}
+
+impl ReplayObserver for Metrics {
+    fn observe(
+        &mut self,
+        _row: usize,
+        transaction: &Transaction,
+        decision: Decision,
+        before: &ClientState,
+        _after: &ClientState,
+    ) {
+        let applied = decision.is_applied();
+        let amount = amount(transaction, before);
+        let metrics = match transaction {
+            Transaction::Activity(AccountActivity::Deposit(..)) => &mut self.deposits,
+            Transaction::Activity(AccountActivity::Withdrawal(..)) => &mut self.withdrawals,
+            Transaction::Dispute(DisputeManagement::Dispute(..)) => &mut self.disputes,
+            Transaction::Dispute(DisputeManagement::Resolve(..)) => &mut self.resolves,
+            Transaction::Dispute(DisputeManagement::Chargeback(..)) => &mut self.chargebacks,
+            Transaction::Dispute(DisputeManagement::ChargebackReversal(..)) => {
+                &mut self.chargeback_reversals
+            }
+            Transaction::Admin(AdminHold::Hold(..)) => &mut self.holds,
+            Transaction::Admin(AdminHold::Release(..)) => &mut self.releases,
+        };
+        *metrics = metrics.record(applied, amount);
+    }
+}
@@ -0,0 +1,32 @@
+use crate::engine::types::{AdminHold, Transaction};
+use crate::error::OrderingViolation;
+
+/// Checks that the transactions introducing a new id, i.e. deposits, withdrawals and holds, do so
+/// in strictly increasing order, as the spec describes ids as globally unique and chronological.
+/// Disputes, resolves, chargebacks, chargeback reversals and releases refer back to earlier ids
+/// and are not checked.
+pub fn check_ordering(
+    transactions: &[Transaction],
+    transaction_rows: &[usize],
+) -> Result<(), OrderingViolation> {
+    let mut previous: Option<(u32, usize)> = None;
+    for (transaction, row) in transactions.iter().zip(transaction_rows) {
+        if !matches!(
+            transaction,
+            Transaction::Activity(_) | Transaction::Admin(AdminHold::Hold(..))
+        ) {
+            continue;
+        }
+        let tx = transaction.tx_id().value();
+        if let Some((previous_tx, previous_row)) = previous.filter(|(prev, _)| tx <= *prev) {
+            return Err(OrderingViolation {
+                tx,
+                row: *row,
+                previous_tx,
+                previous_row,
+            });
+        }
+        previous = Some((tx, *row));
+    }
+    Ok(())
+}
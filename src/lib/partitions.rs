@@ -0,0 +1,77 @@
+//! The ledger split across several files by client id range, so that distributed loaders can
+//! ingest the parts in parallel.
+
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::engine::types::{ClientKey, ClientLedger};
+use crate::io::output_csv;
+use crate::manifest::sha256_hex;
+
+/// A file of the partitioned ledger.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Partition {
+    /// Name of the file within the partition directory.
+    pub file: String,
+    /// Number of clients in the file.
+    pub clients: usize,
+    /// Lowest and highest client ids in the file, absent when it is empty.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_client: Option<ClientKey>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_client: Option<ClientKey>,
+    /// SHA-256 of the file, hex encoded.
+    pub sha256: String,
+}
+
+/// Lists the files of a partitioned ledger, written to `manifest.toml` alongside them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartitionManifest {
+    pub partitions: Vec<Partition>,
+}
+
+/// Writes the clients, which are ordered by id, to `count` files named `part-000.csv` onwards in
+/// `dir`, each holding a contiguous range of ids and within one client of the same number, followed by
+/// their manifest. Returns the paths of the files written.
+pub fn write_partitions(
+    mut clients: Vec<ClientLedger>,
+    count: usize,
+    dir: &Path,
+    config: &Config,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let count = count.max(1);
+    let (size, remainder) = (clients.len() / count, clients.len() % count);
+    let mut paths = Vec::with_capacity(count);
+    let mut partitions = Vec::with_capacity(count);
+    for idx in 0..count {
+        // the first `remainder` partitions take one client more
+        let rest = clients.split_off(size + usize::from(idx < remainder));
+        let part = std::mem::replace(&mut clients, rest);
+        let file = format!("part-{:03}.csv", idx);
+        let path = dir.join(&file);
+        let first_client = part.first().map(|c| c.id.value());
+        let last_client = part.last().map(|c| c.id.value());
+        let part_clients = part.len();
+        let output = output_csv(part, config)?;
+        fs::write(&path, &output)?;
+        partitions.push(Partition {
+            file,
+            clients: part_clients,
+            first_client,
+            last_client,
+            sha256: sha256_hex(&mut output.as_bytes())?,
+        });
+        paths.push(path);
+    }
+    fs::write(
+        dir.join("manifest.toml"),
+        toml::to_string(&PartitionManifest { partitions })?,
+    )?;
+    Ok(paths)
+}
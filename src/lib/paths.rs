@@ -0,0 +1,107 @@
+use std::{
+    env,
+    error::Error,
+    ffi::{OsStr, OsString},
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::error::InputError;
+use crate::remote::{download, is_remote, upload};
+
+fn home_dir() -> Option<OsString> {
+    env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))
+}
+
+/// Replaces a leading `~` with the given home directory. `~user` forms are left untouched.
+fn expand_tilde(path: &Path, home: Option<OsString>) -> PathBuf {
+    match (path.strip_prefix("~"), home) {
+        (Ok(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Absolute Windows paths at or beyond `MAX_PATH` are only accepted by the OS with the `\\?\`
+/// prefix, or `\\?\UNC\` for network shares.
+#[cfg(windows)]
+fn extend_long_path(path: PathBuf) -> PathBuf {
+    use std::path::{Component, Prefix};
+
+    const MAX_PATH: usize = 260;
+    let Some(path_str) = path.to_str().filter(|p| p.len() >= MAX_PATH) else {
+        return path;
+    };
+    match path.components().next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::Disk(_) if path.is_absolute() => PathBuf::from(format!(r"\\?\{}", path_str)),
+            Prefix::UNC(_, _) => PathBuf::from(format!(r"\\?\UNC\{}", &path_str[2..])),
+            _ => path,
+        },
+        _ => path,
+    }
+}
+
+#[cfg(not(windows))]
+fn extend_long_path(path: PathBuf) -> PathBuf {
+    path
+}
+
+pub fn resolve_path(path: &OsStr) -> PathBuf {
+    extend_long_path(expand_tilde(Path::new(path), home_dir()))
+}
+
+/// Opens an input file, distinguishing the common failures so they can be reported precisely.
+/// `s3://` and `gs://` URLs are fetched from the object store.
+pub fn open_input(path: &OsStr) -> Result<File, InputError> {
+    if is_remote(path) {
+        let url = path.to_string_lossy().into_owned();
+        return download(&url).map_err(|e| InputError::Remote(url, e));
+    }
+    let resolved = resolve_path(path);
+    File::open(&resolved).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => InputError::NotFound(resolved),
+        io::ErrorKind::PermissionDenied => InputError::PermissionDenied(resolved),
+        _ => InputError::Io(resolved, e),
+    })
+}
+
+/// Writes an output file, or uploads it when the path is an `s3://` or `gs://` URL.
+pub fn write_output(path: &Path, contents: String) -> Result<(), Box<dyn Error>> {
+    if is_remote(path.as_os_str()) {
+        return upload(&path.to_string_lossy(), contents).map_err(|e| e as Box<dyn Error>);
+    }
+    fs::write(resolve_path(path.as_os_str()), contents)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsString, path::PathBuf};
+
+    use super::expand_tilde;
+
+    #[test]
+    fn tilde_is_expanded_to_home() {
+        let sut = expand_tilde(
+            &PathBuf::from("~/payments/tx.csv"),
+            Some(OsString::from("/home/ops")),
+        );
+        assert_eq!(sut, PathBuf::from("/home/ops/payments/tx.csv"));
+    }
+
+    #[test]
+    fn paths_without_tilde_are_unchanged() {
+        let sut = expand_tilde(
+            &PathBuf::from("payments/~tx.csv"),
+            Some(OsString::from("/home/ops")),
+        );
+        assert_eq!(sut, PathBuf::from("payments/~tx.csv"));
+    }
+
+    #[test]
+    fn tilde_is_kept_without_home() {
+        let sut = expand_tilde(&PathBuf::from("~/tx.csv"), None);
+        assert_eq!(sut, PathBuf::from("~/tx.csv"));
+    }
+}
@@ -0,0 +1,85 @@
+use std::{fmt, time::Duration};
+
+/// Time spent in each phase of a run, reported when profiling is enabled.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub parse: Duration,
+    pub fold: Duration,
+    pub output: Duration,
+    pub rows: usize,
+    /// Only counted when built with the `count-allocations` feature.
+    pub allocations: Option<u64>,
+}
+
+impl Profile {
+    pub fn total(&self) -> Duration {
+        self.parse + self.fold + self.output
+    }
+
+    pub fn rows_per_sec(&self) -> f64 {
+        let secs = self.total().as_secs_f64();
+        if secs > 0.0 {
+            self.rows as f64 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "parse: {:?}", self.parse)?;
+        writeln!(f, "ledger fold: {:?}", self.fold)?;
+        writeln!(f, "output: {:?}", self.output)?;
+        writeln!(
+            f,
+            "rows: {} ({:.0} rows/sec)",
+            self.rows,
+            self.rows_per_sec()
+        )?;
+        match self.allocations {
+            Some(allocations) => write!(f, "allocations: {}", allocations),
+            None => write!(f, "allocations: not counted"),
+        }
+    }
+}
+
+#[cfg(feature = "count-allocations")]
+mod counting {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+    /// The system allocator, counting allocations for the profile. Only counts once installed as
+    /// the `#[global_allocator]`, which is left to the binary so that embedders keep their own.
+    pub struct CountingAllocator;
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    pub fn allocations() -> u64 {
+        ALLOCATIONS.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(feature = "count-allocations")]
+pub use counting::CountingAllocator;
+
+#[cfg(feature = "count-allocations")]
+pub fn allocations() -> Option<u64> {
+    Some(counting::allocations())
+}
+
+#[cfg(not(feature = "count-allocations"))]
+pub fn allocations() -> Option<u64> {
+    None
+}
@@ -0,0 +1,197 @@
+use std::{collections::BTreeMap, error::Error, ffi::OsString, fmt};
+
+use rust_decimal::Decimal;
+
+use crate::engine::types::ClientKey;
+use crate::io::{parse_balances, read_balances, BalanceRowEntity, OUTPUT_DP};
+
+/// A way in which a client's balances in the ledger differ from the expected balances.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MismatchKind {
+    MissingFromLedger,
+    MissingFromExpected,
+    /// A balance column differing by more than the tolerance.
+    Balance {
+        column: &'static str,
+        expected: Decimal,
+        actual: Decimal,
+    },
+    Locked {
+        expected: bool,
+        actual: bool,
+    },
+}
+
+/// Whether a mismatch is explained by rounding or points at the ledgers disagreeing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MismatchCategory {
+    /// A balance differing by no more than one unit in the last decimal place of the ledger.
+    Rounding,
+    Structural,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub client: ClientKey,
+    pub kind: MismatchKind,
+}
+
+impl Mismatch {
+    pub fn category(&self) -> MismatchCategory {
+        match self.kind {
+            MismatchKind::Balance {
+                expected, actual, ..
+            } if (expected - actual).abs() <= Decimal::new(1, OUTPUT_DP) => {
+                MismatchCategory::Rounding
+            }
+            _ => MismatchCategory::Structural,
+        }
+    }
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            MismatchKind::MissingFromLedger => {
+                write!(
+                    f,
+                    "client {} is expected but missing from the ledger",
+                    self.client
+                )
+            }
+            MismatchKind::MissingFromExpected => {
+                write!(
+                    f,
+                    "client {} is in the ledger but not expected",
+                    self.client
+                )
+            }
+            MismatchKind::Balance {
+                column,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "client {} {}: expected {}, found {}",
+                self.client, column, expected, actual
+            ),
+            MismatchKind::Locked { expected, actual } => write!(
+                f,
+                "client {} locked: expected {}, found {}",
+                self.client, expected, actual
+            ),
+        }
+    }
+}
+
+fn compare(
+    expected: &BalanceRowEntity,
+    actual: &BalanceRowEntity,
+    tolerance: Decimal,
+) -> Vec<MismatchKind> {
+    let balances = [
+        ("available", expected.available, actual.available),
+        ("held", expected.held, actual.held),
+        ("total", expected.total, actual.total),
+    ];
+    let locked = expected.locked.zip(actual.locked);
+    balances
+        .into_iter()
+        .filter(|(_, expected, actual)| (*expected - *actual).abs() > tolerance)
+        .map(|(column, expected, actual)| MismatchKind::Balance {
+            column,
+            expected,
+            actual,
+        })
+        .chain(
+            locked
+                .filter(|(expected, actual)| expected != actual)
+                .map(|(expected, actual)| MismatchKind::Locked { expected, actual }),
+        )
+        .collect()
+}
+
+/// Compares the ledger written by the engine with balances expected by an external system,
+/// ordered by client. Balances within `tolerance` of each other are considered equal, the
+/// locked column is only compared when present in both files.
+pub fn reconcile(
+    ledger_path: &OsString,
+    expected_path: &OsString,
+    tolerance: Decimal,
+) -> Result<Vec<Mismatch>, Box<dyn Error>> {
+    Ok(mismatches(
+        read_balances(ledger_path)?,
+        read_balances(expected_path)?,
+        tolerance,
+    ))
+}
+
+/// Differences between two ledgers, one per line when displayed, e.g. as a test failure message.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LedgerDiff(pub Vec<Mismatch>);
+
+impl LedgerDiff {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Display for LedgerDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "ledgers are equal");
+        }
+        for (idx, mismatch) in self.0.iter().enumerate() {
+            if idx > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", mismatch)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares two ledgers given as csv, e.g. the output of a run with the expected output of a
+/// test. Client order and the precision amounts are written at make no difference, and any
+/// columns besides the balances and locked are ignored.
+pub fn compare_ledgers(expected: &str, actual: &str) -> Result<LedgerDiff, Box<dyn Error>> {
+    Ok(LedgerDiff(mismatches(
+        parse_balances(actual.as_bytes())?,
+        parse_balances(expected.as_bytes())?,
+        Decimal::ZERO,
+    )))
+}
+
+fn mismatches(
+    ledger: Vec<BalanceRowEntity>,
+    expected: Vec<BalanceRowEntity>,
+    tolerance: Decimal,
+) -> Vec<Mismatch> {
+    let ledger: BTreeMap<ClientKey, BalanceRowEntity> = ledger
+        .into_iter()
+        .map(|row| (row.client.to_owned(), row))
+        .collect();
+    let expected: BTreeMap<ClientKey, BalanceRowEntity> = expected
+        .into_iter()
+        .map(|row| (row.client.to_owned(), row))
+        .collect();
+
+    let mut clients: Vec<&ClientKey> = ledger.keys().chain(expected.keys()).collect();
+    clients.sort();
+    clients.dedup();
+
+    clients
+        .into_iter()
+        .flat_map(|client| {
+            let kinds = match (expected.get(client), ledger.get(client)) {
+                (Some(expected), Some(actual)) => compare(expected, actual, tolerance),
+                (Some(_), None) => vec![MismatchKind::MissingFromLedger],
+                (None, _) => vec![MismatchKind::MissingFromExpected],
+            };
+            kinds.into_iter().map(|kind| Mismatch {
+                client: client.to_owned(),
+                kind,
+            })
+        })
+        .collect()
+}
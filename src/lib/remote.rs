@@ -0,0 +1,100 @@
+//! Inputs and outputs addressed by `s3://` or `gs://` URLs rather than local paths, so batch jobs
+//! need no separate download and upload steps. Needs the `object-store` feature. Credentials and
+//! regions are read from the usual environment variables, e.g. `AWS_ACCESS_KEY_ID` or
+//! `GOOGLE_SERVICE_ACCOUNT`.
+
+use std::{error::Error, ffi::OsStr, fs::File};
+
+/// Whether the path is a URL of an object store rather than a local path.
+pub fn is_remote(path: &OsStr) -> bool {
+    path.to_str()
+        .is_some_and(|p| p.starts_with("s3://") || p.starts_with("gs://"))
+}
+
+#[cfg(feature = "object-store")]
+mod store {
+    use std::{
+        env,
+        error::Error,
+        fs::{self, File},
+        io::Write,
+        path::PathBuf,
+        process,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use futures::StreamExt;
+    use object_store::{parse_url_opts, path::Path, ObjectStore, PutPayload};
+    use tokio::runtime::{Builder, Runtime};
+    use url::Url;
+
+    static DOWNLOADS: AtomicUsize = AtomicUsize::new(0);
+
+    fn store(url: &str) -> Result<(Box<dyn ObjectStore>, Path), Box<dyn Error + Send + Sync>> {
+        let url = Url::parse(url)?;
+        // the builders take their config keys in lower case, and ignore variables which are not
+        let options = env::vars().map(|(k, v)| (k.to_ascii_lowercase(), v));
+        Ok(parse_url_opts(&url, options)?)
+    }
+
+    fn runtime() -> std::io::Result<Runtime> {
+        Builder::new_current_thread().enable_all().build()
+    }
+
+    /// Removes the temporary file when dropped, whether or not the download succeeded.
+    struct TempFile(PathBuf);
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    /// Streams the object to a temporary file, returned open for reading. The file is removed
+    /// once opened, where the platform allows it, or when the download fails, so nothing is left
+    /// behind.
+    pub fn download(url: &str) -> Result<File, Box<dyn Error + Send + Sync>> {
+        let (store, path) = store(url)?;
+        let tmp = TempFile(env::temp_dir().join(format!(
+            "toy_payments_download_{}_{}",
+            process::id(),
+            DOWNLOADS.fetch_add(1, Ordering::Relaxed)
+        )));
+        runtime()?.block_on(async {
+            let mut stream = store.get(&path).await?.into_stream();
+            let mut file = File::create(&tmp.0)?;
+            while let Some(chunk) = stream.next().await {
+                file.write_all(&chunk?)?;
+            }
+            file.flush()?;
+            Ok::<_, Box<dyn Error + Send + Sync>>(())
+        })?;
+        Ok(File::open(&tmp.0)?)
+    }
+
+    pub fn upload(url: &str, contents: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (store, path) = store(url)?;
+        runtime()?.block_on(store.put(&path, PutPayload::from(contents)))?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "object-store")]
+pub fn download(url: &str) -> Result<File, Box<dyn Error + Send + Sync>> {
+    store::download(url)
+}
+
+#[cfg(not(feature = "object-store"))]
+pub fn download(_url: &str) -> Result<File, Box<dyn Error + Send + Sync>> {
+    Err("reading s3:// and gs:// URLs requires building with the object-store feature".into())
+}
+
+#[cfg(feature = "object-store")]
+pub fn upload(url: &str, contents: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+    store::upload(url, contents)
+}
+
+#[cfg(not(feature = "object-store"))]
+pub fn upload(_url: &str, _contents: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Err("writing s3:// and gs:// URLs requires building with the object-store feature".into())
+}
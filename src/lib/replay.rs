@@ -0,0 +1,63 @@
+use std::iter::Peekable;
+
+use im::HashMap;
+
+use crate::config::Config;
+use crate::engine::transactions::{client_state_or_opening, decide_transaction, end_of_day};
+use crate::engine::types::{ClientId, ClientState, Decision, Transaction};
+use crate::engine::Policy;
+
+/// Invoked with every transaction in input order, along with the decision the engine took on it
+/// and the state of its client before and after the transaction was applied. Whether the
+/// transaction took effect is told by [`Decision::is_applied`], rather than derived from the
+/// states.
+pub trait ReplayObserver {
+    fn observe(
+        &mut self,
+        row: usize,
+        transaction: &Transaction,
+        decision: Decision,
+        before: &ClientState,
+        after: &ClientState,
+    );
+}
+
+/// Closes a business day for each of the `eod` rows before `row`, as the run producing the
+/// ledger does, so that disputes expire at the same point of the replay.
+pub(crate) fn close_days_before<'a>(
+    mut ledger: HashMap<ClientId, ClientState>,
+    end_of_days: &mut Peekable<impl Iterator<Item = &'a usize>>,
+    row: usize,
+    policy: &Policy,
+) -> HashMap<ClientId, ClientState> {
+    while end_of_days.next_if(|eod| **eod < row).is_some() {
+        ledger = end_of_day(ledger, policy, &mut []);
+    }
+    ledger
+}
+
+/// Applies the transactions to an empty ledger, passing each to the observers and closing a
+/// business day at each of the `eod` rows given. Returns the final ledger.
+pub fn replay(
+    transactions: &[Transaction],
+    transaction_rows: &[usize],
+    end_of_day_rows: &[usize],
+    config: &Config,
+    observers: &mut [&mut dyn ReplayObserver],
+) -> HashMap<ClientId, ClientState> {
+    let policy = config.policy();
+    let mut ledger: HashMap<ClientId, ClientState> = HashMap::default();
+    let mut end_of_days = end_of_day_rows.iter().peekable();
+    for (transaction, row) in transactions.iter().zip(transaction_rows) {
+        ledger = close_days_before(ledger, &mut end_of_days, *row, &policy);
+        let c_id = transaction.client_id();
+        let before = client_state_or_opening(&ledger, &c_id, &policy);
+        let decision;
+        (ledger, decision) = decide_transaction(transaction.clone(), ledger, &policy);
+        let after = client_state_or_opening(&ledger, &c_id, &policy);
+        for observer in observers.iter_mut() {
+            observer.observe(*row, transaction, decision, &before, &after);
+        }
+    }
+    close_days_before(ledger, &mut end_of_days, usize::MAX, &policy)
+}
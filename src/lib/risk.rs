@@ -0,0 +1,47 @@
+use rust_decimal::Decimal;
+
+use crate::engine::types::{ClientLedger, MonetaryAmount};
+
+/// Computes a risk score for a client from their final ledger row. Higher is riskier.
+pub trait RiskScorer {
+    fn score(&self, client: &ClientLedger) -> Decimal;
+}
+
+/// Weighted sum of the client's standing chargebacks, the share of their deposits and
+/// withdrawals which were disputed, their rejected withdrawals, and whether their available funds
+/// are negative.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WeightedRiskScorer {
+    pub chargeback: Decimal,
+    pub dispute_rate: Decimal,
+    pub rejected_withdrawal: Decimal,
+    pub negative_balance: Decimal,
+}
+
+impl Default for WeightedRiskScorer {
+    fn default() -> Self {
+        Self {
+            chargeback: Decimal::new(40, 0),
+            dispute_rate: Decimal::new(30, 0),
+            rejected_withdrawal: Decimal::new(5, 0),
+            negative_balance: Decimal::new(25, 0),
+        }
+    }
+}
+
+impl RiskScorer for WeightedRiskScorer {
+    fn score(&self, client: &ClientLedger) -> Decimal {
+        let dispute_rate = match client.transactions {
+            0 => Decimal::ZERO,
+            transactions => Decimal::from(client.disputed_txs) / Decimal::from(transactions),
+        };
+        let negative_balance = match client.available < MonetaryAmount::default() {
+            true => self.negative_balance,
+            false => Decimal::ZERO,
+        };
+        self.chargeback * Decimal::from(client.chargebacks)
+            + self.dispute_rate * dispute_rate
+            + self.rejected_withdrawal * Decimal::from(client.rejected_withdrawals)
+            + negative_balance
+    }
+}
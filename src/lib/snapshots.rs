@@ -0,0 +1,107 @@
+use std::{error::Error, fs, iter, path::PathBuf, time::UNIX_EPOCH};
+
+use im::HashMap;
+
+use crate::config::{Config, OutputOptions};
+use crate::engine::clock::Clock;
+use crate::engine::middleware::TxMiddleware;
+use crate::engine::transactions::{end_of_day, fold_envelopes};
+use crate::engine::types::{ClientId, ClientState, Ledger, TransactionEnvelope};
+use crate::io::output_csv;
+
+/// Writes the ledger after `processed` transactions to a timestamped file in `dir`, with the
+/// suffix appended to the name. The file is written under a temporary name and renamed, so a
+/// killed run never leaves a partial snapshot. Snapshots keep the default output format so that
+/// they can always be read back by `bisect`.
+fn write_snapshot(
+    states: &HashMap<ClientId, ClientState>,
+    processed: usize,
+    suffix: &str,
+    config: &Config,
+    clock: &dyn Clock,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let timestamp = clock.now().duration_since(UNIX_EPOCH)?.as_millis();
+    let dir = config.snapshot_dir.clone().unwrap_or_default();
+    let path = dir.join(format!("ledger_{}_{}{}.csv", timestamp, processed, suffix));
+    let tmp_path = path.with_extension("csv.tmp");
+
+    let config = config.clone().with_output_options(OutputOptions::default());
+    let output = output_csv(Ledger::from_states(states.clone()).0, &config)?;
+    fs::write(&tmp_path, output)?;
+    fs::rename(&tmp_path, &path)?;
+    Ok(path)
+}
+
+/// Closes the given business day, adding a snapshot of it to `snapshots` when configured to.
+fn close_day(
+    states: HashMap<ClientId, ClientState>,
+    day: usize,
+    processed: usize,
+    config: &Config,
+    middleware: &mut [Box<dyn TxMiddleware>],
+    clock: &dyn Clock,
+    snapshots: &mut Vec<PathBuf>,
+) -> Result<HashMap<ClientId, ClientState>, Box<dyn Error>> {
+    let states = end_of_day(states, &config.policy(), middleware);
+    if config.eod_snapshots {
+        let suffix = format!("_eod{}", day);
+        snapshots.push(write_snapshot(&states, processed, &suffix, config, clock)?);
+    }
+    Ok(states)
+}
+
+/// Folds the transactions into a ledger, closing a business day at each of the `eod` rows given.
+/// A snapshot is written after every `snapshot_every` transactions when set, and at the close of
+/// each day when `eod_snapshots` is, named with the day as `_eod<day>`.
+pub fn create_ledger_with_snapshots(
+    envelopes: Vec<TransactionEnvelope>,
+    end_of_day_rows: &[usize],
+    config: &Config,
+    middleware: &mut [Box<dyn TxMiddleware>],
+    clock: &dyn Clock,
+) -> Result<(Ledger, Vec<PathBuf>), Box<dyn Error>> {
+    let policy = config.policy();
+    let mut states = HashMap::default();
+    let mut snapshots = Vec::new();
+    let mut end_of_days = end_of_day_rows.iter().peekable();
+    let mut day = 0;
+    let mut processed = 0;
+    for envelope in envelopes {
+        while end_of_days
+            .next_if(|row| **row < envelope.metadata.row)
+            .is_some()
+        {
+            day += 1;
+            states = close_day(
+                states,
+                day,
+                processed,
+                config,
+                middleware,
+                clock,
+                &mut snapshots,
+            )?;
+        }
+        states = fold_envelopes(states, iter::once(envelope), &policy, middleware);
+        processed += 1;
+        if config
+            .snapshot_every
+            .is_some_and(|every| processed % every.max(1) == 0)
+        {
+            snapshots.push(write_snapshot(&states, processed, "", config, clock)?);
+        }
+    }
+    for _ in end_of_days {
+        day += 1;
+        states = close_day(
+            states,
+            day,
+            processed,
+            config,
+            middleware,
+            clock,
+            &mut snapshots,
+        )?;
+    }
+    Ok((Ledger::from_states(states), snapshots))
+}
@@ -0,0 +1,280 @@
+use std::{error::Error, ffi::OsString, fmt::Write, str::FromStr, time::Instant};
+
+use im::HashMap;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::engine::transactions::{client_state_or_opening, resolve_transaction};
+use crate::engine::types::{
+    AccountActivity, AdminHold, ClientId, ClientKey, ClientState, DisputeManagement,
+    MonetaryAmount, Transaction,
+};
+use crate::io::{fixed_width, fixed_width_opt, format_amount, output_amount, process_csv};
+use crate::replay::close_days_before;
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatementFormat {
+    #[default]
+    Csv,
+    Text,
+    /// JSON for the debugging front end, with the time taken between transactions and how each
+    /// interacted with the queue of rejected withdrawals.
+    Timeline,
+}
+
+impl FromStr for StatementFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(StatementFormat::Csv),
+            "text" => Ok(StatementFormat::Text),
+            "timeline" => Ok(StatementFormat::Timeline),
+            _ => Err(format!("Unknown statement format {}", s)),
+        }
+    }
+}
+
+/// The balances of a client after one of their transactions, in input order.
+#[derive(Debug, Serialize)]
+pub struct StatementLineEntity {
+    row: usize,
+    #[serde(rename = "type")]
+    tx_type: &'static str,
+    tx: u32,
+    #[serde(serialize_with = "fixed_width_opt")]
+    amount: Option<Decimal>,
+    #[serde(serialize_with = "fixed_width")]
+    available: Decimal,
+    #[serde(serialize_with = "fixed_width")]
+    held: Decimal,
+    #[serde(serialize_with = "fixed_width")]
+    total: Decimal,
+    locked: bool,
+    note: String,
+}
+
+/// A statement line along with how the transaction interacted with the retry queue.
+#[derive(Debug, Serialize)]
+pub struct TimelineEvent {
+    #[serde(flatten)]
+    line: StatementLineEntity,
+    /// Microseconds of wall-clock time since the client's previous transaction was applied.
+    delta_us: u128,
+    /// The withdrawal itself, when it was rejected and queued for retry.
+    queued: Option<u32>,
+    /// Queued withdrawals enacted by this transaction, e.g. by a resolve freeing funds.
+    backfilled: Vec<u32>,
+    /// Queued withdrawals dropped from the queue as out of attempts or expired.
+    abandoned: Vec<u32>,
+}
+
+#[derive(Serialize)]
+struct Timeline<'a> {
+    client: &'a ClientKey,
+    events: Vec<TimelineEvent>,
+}
+
+/// Withdrawals waiting in the retry queue of a client.
+fn queued_withdrawals(state: &ClientState) -> Vec<u32> {
+    state
+        .history
+        .rejected_txs
+        .iter()
+        .map(|rejected| rejected.activity.tx_id().value())
+        .collect()
+}
+
+pub(crate) fn describe(transaction: &Transaction) -> (&'static str, Option<MonetaryAmount>) {
+    match transaction {
+        Transaction::Activity(AccountActivity::Deposit(_, _, amount)) => ("deposit", Some(*amount)),
+        Transaction::Activity(AccountActivity::Withdrawal(_, _, amount)) => {
+            ("withdrawal", Some(*amount))
+        }
+        Transaction::Dispute(DisputeManagement::Dispute(_, _)) => ("dispute", None),
+        Transaction::Dispute(DisputeManagement::Resolve(_, _)) => ("resolve", None),
+        Transaction::Dispute(DisputeManagement::Chargeback(_, _)) => ("chargeback", None),
+        Transaction::Dispute(DisputeManagement::ChargebackReversal(_, _)) => {
+            ("chargeback_reversal", None)
+        }
+        Transaction::Admin(AdminHold::Hold(_, _, amount)) => ("hold", Some(*amount)),
+        Transaction::Admin(AdminHold::Release(_, _)) => ("release", None),
+    }
+}
+
+pub(crate) fn annotate(
+    transaction: &Transaction,
+    before: &ClientState,
+    after: &ClientState,
+) -> String {
+    let balances_changed = before.available != after.available || before.held != after.held;
+    let pending_before = before.history.rejected_txs.len();
+    let pending_after = after.history.rejected_txs.len();
+    let held_change = (after.held.value() - before.held.value()).value();
+    let queued_before = before.history.queued_disputes.len();
+    let queued_after = after.history.queued_disputes.len();
+    match transaction {
+        _ if !balances_changed && pending_before == pending_after && after.is_locked => {
+            String::from("ignored: account locked")
+        }
+        Transaction::Activity(AccountActivity::Withdrawal(..))
+            if pending_after > pending_before =>
+        {
+            String::from("rejected: pending resolution of open disputes")
+        }
+        Transaction::Activity(_) if !balances_changed => String::from("rejected"),
+        Transaction::Activity(_) => String::new(),
+        Transaction::Dispute(DisputeManagement::Dispute(..)) if queued_after > queued_before => {
+            String::from("queued: held funds at limit")
+        }
+        Transaction::Dispute(DisputeManagement::Resolve(..))
+            if !balances_changed && queued_after < queued_before =>
+        {
+            String::from("queued dispute withdrawn")
+        }
+        Transaction::Dispute(_) if !balances_changed && before.is_locked == after.is_locked => {
+            String::from("ignored")
+        }
+        Transaction::Dispute(DisputeManagement::Dispute(..)) => {
+            format!("held {}", format_amount(held_change))
+        }
+        Transaction::Dispute(DisputeManagement::Resolve(..)) => {
+            let released = format!("released {}", format_amount(-held_change));
+            match pending_before - pending_after {
+                0 => released,
+                backfilled => format!("{}, backfilled {} withdrawals", released, backfilled),
+            }
+        }
+        Transaction::Dispute(DisputeManagement::Chargeback(..)) => {
+            String::from("charged back, account locked")
+        }
+        Transaction::Dispute(DisputeManagement::ChargebackReversal(..)) => {
+            let restored = format!(
+                "restored {}",
+                format_amount((after.available.value() - before.available.value()).value())
+            );
+            if before.is_locked && !after.is_locked {
+                format!("{}, account unlocked", restored)
+            } else {
+                restored
+            }
+        }
+        Transaction::Admin(_) if !balances_changed => String::from("ignored"),
+        Transaction::Admin(AdminHold::Hold(..)) => format!("held {}", format_amount(held_change)),
+        Transaction::Admin(AdminHold::Release(..)) => {
+            format!("released {}", format_amount(-held_change))
+        }
+    }
+}
+
+/// Replays the transactions of a single client, recording their balances after each one.
+pub fn client_statement(
+    csv_path: &OsString,
+    client: &ClientKey,
+    config: &Config,
+) -> Result<Vec<TimelineEvent>, Box<dyn Error>> {
+    let input = process_csv(csv_path, config)?;
+    let c_id = ClientId::new(client.to_owned());
+    let policy = config.policy();
+
+    let mut ledger: HashMap<ClientId, ClientState> = HashMap::default();
+    let mut lines = Vec::new();
+    let mut last_applied = Instant::now();
+    let mut end_of_days = input.end_of_day_rows.iter().peekable();
+    for (transaction, row) in input.transactions.into_iter().zip(input.transaction_rows) {
+        ledger = close_days_before(ledger, &mut end_of_days, row, &policy);
+        if transaction.client_id() != c_id {
+            continue;
+        }
+        let before = client_state_or_opening(&ledger, &c_id, &policy);
+        let (tx_type, amount) = describe(&transaction);
+        let tx = transaction.tx_id().value();
+        let note_tx = transaction.clone();
+        ledger = resolve_transaction(transaction, ledger, &policy);
+        let applied = Instant::now();
+        let after = client_state_or_opening(&ledger, &c_id, &policy);
+        let (queued_before, queued_after) =
+            (queued_withdrawals(&before), queued_withdrawals(&after));
+        let abandoned: Vec<u32> = after
+            .history
+            .abandoned_retries
+            .iter()
+            .skip(before.history.abandoned_retries.len())
+            .map(|abandoned| abandoned.tx)
+            .collect();
+        let line = StatementLineEntity {
+            row,
+            tx_type,
+            tx,
+            amount: amount.map(|a| output_amount(a.value(), config)),
+            available: output_amount(after.available.value().value(), config),
+            held: output_amount(after.held.value().value(), config),
+            total: output_amount(after.total().value(), config),
+            locked: after.is_locked,
+            note: annotate(&note_tx, &before, &after),
+        };
+        lines.push(TimelineEvent {
+            line,
+            delta_us: applied.duration_since(last_applied).as_micros(),
+            queued: queued_after
+                .iter()
+                .find(|id| !queued_before.contains(id))
+                .copied(),
+            backfilled: queued_before
+                .iter()
+                .filter(|id| !queued_after.contains(id) && !abandoned.contains(id))
+                .copied()
+                .collect(),
+            abandoned,
+        });
+        last_applied = applied;
+    }
+    Ok(lines)
+}
+
+pub fn output_statement(
+    client: &ClientKey,
+    events: Vec<TimelineEvent>,
+    format: StatementFormat,
+) -> Result<String, Box<dyn Error>> {
+    let lines = || events.iter().map(|event| &event.line);
+    match format {
+        StatementFormat::Csv => {
+            let mut wtr = csv::Writer::from_writer(vec![]);
+            for line in lines() {
+                wtr.serialize(line)?
+            }
+            wtr.flush()?;
+            Ok(String::from_utf8(wtr.into_inner()?)?)
+        }
+        StatementFormat::Text => {
+            let mut out = String::new();
+            writeln!(
+                out,
+                "{:>6} {:<10} {:>8} {:>14} {:>14} {:>14} {:>14} {:<6} note",
+                "row", "type", "tx", "amount", "available", "held", "total", "locked"
+            )?;
+            for line in lines() {
+                let amount = line.amount.map(format_amount).unwrap_or_default();
+                writeln!(
+                    out,
+                    "{:>6} {:<10} {:>8} {:>14} {:>14.4} {:>14.4} {:>14.4} {:<6} {}",
+                    line.row,
+                    line.tx_type,
+                    line.tx,
+                    amount,
+                    line.available,
+                    line.held,
+                    line.total,
+                    line.locked,
+                    line.note
+                )?;
+            }
+            Ok(out)
+        }
+        StatementFormat::Timeline => {
+            Ok(serde_json::to_string_pretty(&Timeline { client, events })?)
+        }
+    }
+}
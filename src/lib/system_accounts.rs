@@ -0,0 +1,48 @@
+use std::fmt;
+
+use rust_decimal::Decimal;
+
+use crate::engine::types::Ledger;
+use crate::io::format_amount;
+
+/// Aggregates over every client for treasury reporting, as the system's own accounts would hold
+/// them at the end of a run.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SystemAccounts {
+    /// Deposits charged back and not since reversed, the funds lost to chargebacks.
+    pub chargeback_losses: Decimal,
+    /// Funds currently held by disputes and admin holds, the float.
+    pub held: Decimal,
+    /// Rejected withdrawals which may still be enacted, e.g. by a resolve, and so remain owed.
+    pub rejected_withdrawal_liability: Decimal,
+}
+
+impl SystemAccounts {
+    pub fn from_ledger(ledger: &Ledger) -> Self {
+        ledger.0.iter().fold(Self::default(), |acc, c| Self {
+            chargeback_losses: acc
+                .chargeback_losses
+                .saturating_add(c.charged_back_amount.value()),
+            held: acc.held.saturating_add(c.held.value()),
+            rejected_withdrawal_liability: acc
+                .rejected_withdrawal_liability
+                .saturating_add(c.pending_withdrawal_amount.value()),
+        })
+    }
+}
+
+impl fmt::Display for SystemAccounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "chargeback losses: {}",
+            format_amount(self.chargeback_losses)
+        )?;
+        writeln!(f, "held: {}", format_amount(self.held))?;
+        write!(
+            f,
+            "rejected withdrawal liability: {}",
+            format_amount(self.rejected_withdrawal_liability)
+        )
+    }
+}
@@ -0,0 +1,143 @@
+use std::fmt;
+
+use im::HashSet;
+
+use crate::engine::types::{
+    ClientKey, ClientState, Decision, DisputeManagement, DisputeOperation, Transaction,
+};
+use crate::replay::ReplayObserver;
+
+/// A transaction which was processed without error but may indicate a problem upstream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WarningKind {
+    /// A dispute, resolve, chargeback or chargeback reversal which did not apply, e.g. against an
+    /// unknown transaction or a transaction which was not under dispute.
+    IgnoredDisputeOperation(DisputeOperation),
+    /// A deposit or withdrawal reusing the id of an earlier deposit or withdrawal.
+    DuplicateTxId,
+    /// Any transaction besides a chargeback reversal against an account locked by an earlier
+    /// chargeback.
+    LockedAccountActivity,
+    /// A withdrawal larger than the client's opening balance and all of their earlier deposits.
+    WithdrawalExceedsDeposits,
+    /// A deposit larger than the configured cap.
+    DepositOverCap,
+    /// A deposit or withdrawal which applied but was not recorded as the client's history is
+    /// full, so it cannot be disputed.
+    HistoryFull,
+}
+
+impl fmt::Display for WarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WarningKind::IgnoredDisputeOperation(operation) => write!(f, "{} ignored", operation),
+            WarningKind::DuplicateTxId => write!(f, "duplicate transaction id"),
+            WarningKind::LockedAccountActivity => write!(f, "account is locked"),
+            WarningKind::WithdrawalExceedsDeposits => {
+                write!(f, "withdrawal exceeds the client's deposits")
+            }
+            WarningKind::DepositOverCap => write!(f, "deposit exceeds the cap"),
+            WarningKind::HistoryFull => {
+                write!(f, "client history is full, transaction cannot be disputed")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub row: usize,
+    pub client: ClientKey,
+    pub tx: u32,
+    pub kind: WarningKind,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "warning at row {} (client {}, tx {}): {}",
+            self.row, self.client, self.tx, self.kind
+        )
+    }
+}
+
+pub(crate) fn dispute_operation(transaction: &Transaction) -> Option<DisputeOperation> {
+    match transaction {
+        Transaction::Activity(_) | Transaction::Admin(_) => None,
+        Transaction::Dispute(DisputeManagement::Dispute(..)) => Some(DisputeOperation::Dispute),
+        Transaction::Dispute(DisputeManagement::Resolve(..)) => Some(DisputeOperation::Resolve),
+        Transaction::Dispute(DisputeManagement::Chargeback(..)) => {
+            Some(DisputeOperation::Chargeback)
+        }
+        Transaction::Dispute(DisputeManagement::ChargebackReversal(..)) => {
+            Some(DisputeOperation::ChargebackReversal)
+        }
+    }
+}
+
+/// Collects warnings for ignored or suspect transactions during a replay.
+#[derive(Default)]
+pub struct WarningCollector {
+    seen_tx_ids: HashSet<u32>,
+    pub warnings: Vec<Warning>,
+}
+
+impl WarningCollector {
+    fn inspect(
+        &self,
+        transaction: &Transaction,
+        decision: Decision,
+        before: &ClientState,
+        after: &ClientState,
+    ) -> Option<WarningKind> {
+        let tx_id = transaction.tx_id();
+        let operation = dispute_operation(transaction);
+        if before.is_locked && operation != Some(DisputeOperation::ChargebackReversal) {
+            return Some(WarningKind::LockedAccountActivity);
+        }
+        match (transaction, operation) {
+            (Transaction::Activity(_), _) if self.seen_tx_ids.contains(&tx_id.value()) => {
+                Some(WarningKind::DuplicateTxId)
+            }
+            (Transaction::Activity(_), _)
+                if matches!(decision, Decision::Credit | Decision::Debit)
+                    && !cfg!(feature = "no-history")
+                    && !after.history.account_activity.contains_key(&tx_id) =>
+            {
+                Some(WarningKind::HistoryFull)
+            }
+            (_, Some(operation)) => {
+                // Repeats of the last operation are reported as retries instead
+                let last = before.history.last_dispute_operations.get(&tx_id);
+                let is_retry = last == Some(&operation);
+                (!is_retry && !decision.is_applied())
+                    .then_some(WarningKind::IgnoredDisputeOperation(operation))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl ReplayObserver for WarningCollector {
+    fn observe(
+        &mut self,
+        row: usize,
+        transaction: &Transaction,
+        decision: Decision,
+        before: &ClientState,
+        after: &ClientState,
+    ) {
+        if let Some(kind) = self.inspect(transaction, decision, before, after) {
+            self.warnings.push(Warning {
+                row,
+                client: transaction.client_id().value(),
+                tx: transaction.tx_id().value(),
+                kind,
+            });
+        }
+        if let Transaction::Activity(_) = transaction {
+            self.seen_tx_ids.insert(transaction.tx_id().value());
+        }
+    }
+}
@@ -1,24 +1,459 @@
-use std::{env, process};
-use toy_payments_lib::process_payments;
+use std::{
+    env, error::Error, ffi::OsString, path::PathBuf, process, str::FromStr, thread, time::Duration,
+};
+use toy_payments_lib::{
+    annotate_input, bisect_payments, client_statement, decision_table, process_dropbox,
+    process_payments_report, read_client_flags, reconcile, tx_range_activity, verify_manifest,
+    Config, MismatchCategory, StatementFormat, SystemClock,
+};
+
+#[cfg(feature = "count-allocations")]
+#[global_allocator]
+static GLOBAL: toy_payments_lib::CountingAllocator = toy_payments_lib::CountingAllocator;
+
+fn flag_value<T: FromStr>(
+    flag: &str,
+    args: &mut impl Iterator<Item = OsString>,
+) -> Result<T, String> {
+    args.next()
+        .and_then(|v| v.to_str().and_then(|v| v.parse().ok()))
+        .ok_or_else(|| format!("Invalid or missing value for flag {}", flag))
+}
+
+/// Reads the config and flags, along with the csv file argument if there is one.
+fn parse_options(
+    args: impl Iterator<Item = OsString>,
+) -> Result<(Option<OsString>, Config), Box<dyn Error>> {
+    let args: Vec<OsString> = args.collect();
+    // Flags override environment variables, which override the config file, wherever they appear
+    let config_path = match args.iter().position(|arg| arg == "--config") {
+        Some(idx) => Some(args.get(idx + 1).ok_or("Missing value for flag --config")?),
+        None => None,
+    };
+    // env::vars would panic on a variable which is not unicode, these can be no option anyway
+    let vars =
+        env::vars_os().filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?)));
+    let (mut config, ignored) = Config::from_toml_file_with_env(config_path, vars)?;
+    for name in ignored {
+        eprintln!(
+            "ignoring environment variable {}, which names no option",
+            name
+        );
+    }
+    let mut csv_path = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--config") => {
+                args.next();
+            }
+            Some("--pending-withdrawals") => config.show_pending_withdrawals = true,
+            Some("--missing-amount-as-zero") => config.missing_amount_as_zero = true,
+            Some("--lookup-dispute-clients") => config.lookup_dispute_clients = true,
+            Some("--unlock-on-chargeback-reversal") => config.unlock_on_chargeback_reversal = true,
+            Some("--profile") => config.profile = true,
+            Some("--omit-empty-accounts") => config.omit_empty_accounts = true,
+            Some("--detect-anomalies") => config.detect_anomalies = true,
+            Some("--risk-scores") => config.risk_scores = true,
+            Some("--warnings") => config.collect_warnings = true,
+            Some("--check-amounts") => config.check_amounts = true,
+            Some("--reject-suspect-amounts") => config.reject_suspect_amounts = true,
+            Some("--metrics") => config.collect_metrics = true,
+            Some("--preserve-scale") => config.preserve_scale = true,
+            Some("--estimate-memory") => config.estimate_memory = true,
+            Some("--frozen-accounts") => config.report_frozen_accounts = true,
+            Some("--batch-dispute-rows") => config.batch_dispute_rows = true,
+            Some("--strict-ordering") => config.strict_ordering = true,
+            Some(flag @ "--schema") => config = config.with_schema(flag_value(flag, &mut args)?),
+            Some(flag @ "--output-format") => {
+                config.output = config.output.with_format(flag_value(flag, &mut args)?)
+            }
+            Some(flag @ "--delimiter") => {
+                config.output = config.output.with_delimiter(flag_value(flag, &mut args)?)
+            }
+            Some(flag @ "--decimal-separator") => {
+                config.output = config
+                    .output
+                    .with_decimal_separator(flag_value(flag, &mut args)?)
+            }
+            Some(flag @ "--dispute-policy") => {
+                config = config.with_dispute_policy(flag_value(flag, &mut args)?)
+            }
+            Some(flag @ "--deposit-cap") => {
+                config = config.with_deposit_cap(flag_value(flag, &mut args)?)
+            }
+            Some(flag @ "--opening-balance") => {
+                config = config.with_opening_balance(flag_value(flag, &mut args)?)
+            }
+            Some(flag @ "--max-held-percent") => {
+                config = config.with_max_held_percent(flag_value(flag, &mut args)?)
+            }
+            Some("--retry-on-deposit") => config.retry = config.retry.with_on_deposit(true),
+            Some(flag @ "--inject-io-error-every") => {
+                config.faults = config
+                    .faults
+                    .with_io_error_every(flag_value(flag, &mut args)?)
+            }
+            Some(flag @ "--inject-row-delay-ms") => {
+                config.faults = config
+                    .faults
+                    .with_row_delay_ms(flag_value(flag, &mut args)?)
+            }
+            Some(flag @ "--fault-seed") => {
+                config.faults = config.faults.with_seed(flag_value(flag, &mut args)?)
+            }
+            Some(flag @ "--retry-max-attempts") => {
+                config.retry = config.retry.with_max_attempts(flag_value(flag, &mut args)?)
+            }
+            Some(flag @ "--retry-expiry") => {
+                config.retry = config.retry.with_expiry(flag_value(flag, &mut args)?)
+            }
+            Some(flag @ "--snapshot-every") => {
+                config.snapshot_every = Some(flag_value(flag, &mut args)?)
+            }
+            Some(flag @ "--snapshot-dir") => {
+                config.snapshot_dir = Some(flag_value(flag, &mut args)?)
+            }
+            Some("--eod-snapshots") => config.eod_snapshots = true,
+            Some(flag @ "--dispute-expiry-days") => {
+                config = config.with_dispute_expiry_days(flag_value(flag, &mut args)?)
+            }
+            Some(flag @ "--partitions") => config.partitions = Some(flag_value(flag, &mut args)?),
+            Some(flag @ "--partition-dir") => {
+                config.partition_dir = Some(flag_value(flag, &mut args)?)
+            }
+            Some(flag @ "--output") => {
+                config = config.with_output_file(flag_value(flag, &mut args)?)
+            }
+            Some(flag @ "--manifest") => {
+                config = config.with_manifest_file(flag_value(flag, &mut args)?)
+            }
+            Some(flag @ "--signing-key") => {
+                config = config.with_signing_key_file(flag_value(flag, &mut args)?)
+            }
+            Some(flag @ "--quarantine") => {
+                config = config.with_quarantine_file(flag_value(flag, &mut args)?)
+            }
+            Some(flag @ "--dead-letter") => {
+                config = config.with_dead_letter_file(flag_value(flag, &mut args)?)
+            }
+            Some(flag @ "--metadata") => {
+                config = config.with_metadata_file(flag_value(flag, &mut args)?)
+            }
+            Some(flag @ "--filter") => {
+                let filter: String = flag_value(flag, &mut args)?;
+                config = config.with_filter(filter.parse()?)
+            }
+            Some(flag @ "--tx-type-alias") => {
+                let alias: String = flag_value(flag, &mut args)?;
+                let (alias, tx_type) = alias
+                    .split_once('=')
+                    .ok_or_else(|| format!("Expected alias=type for flag {}", flag))?;
+                config = config.with_tx_type_alias(alias, tx_type)
+            }
+            Some(flag @ "--overdraft-limit") => {
+                let limit: String = flag_value(flag, &mut args)?;
+                let (client, limit) = limit
+                    .split_once('=')
+                    .and_then(|(client, limit)| Some((client.parse().ok()?, limit.parse().ok()?)))
+                    .ok_or_else(|| format!("Expected client=amount for flag {}", flag))?;
+                config = config.with_overdraft_limit(client, limit)
+            }
+            Some(flag @ "--client-flags") => {
+                let path: OsString = flag_value(flag, &mut args)?;
+                config = config.with_client_flags(read_client_flags(&path)?)
+            }
+            Some(flag @ "--max-bytes") => {
+                config = config.with_max_bytes(flag_value(flag, &mut args)?)
+            }
+            Some(flag @ "--max-rows") => {
+                config = config.with_max_rows(flag_value(flag, &mut args)?)
+            }
+            Some(flag @ "--max-clients") => {
+                config = config.with_max_clients(flag_value(flag, &mut args)?)
+            }
+            Some(flag @ "--max-client-history") => {
+                config = config.with_max_client_history(flag_value(flag, &mut args)?)
+            }
+            Some(flag @ "--max-memory") => {
+                config = config.with_max_memory(flag_value(flag, &mut args)?)
+            }
+            Some(flag) if flag.starts_with("--") => {
+                return Err(format!("Unknown flag {}", flag).into());
+            }
+            _ => csv_path = Some(arg),
+        }
+    }
+    Ok((csv_path, config))
+}
+
+fn parse_args(args: impl Iterator<Item = OsString>) -> Result<(OsString, Config), Box<dyn Error>> {
+    match parse_options(args)? {
+        (Some(csv_path), config) => Ok((csv_path, config)),
+        (None, _) => Err("Missing csv file argument".into()),
+    }
+}
+
+fn run_process(args: impl Iterator<Item = OsString>) -> Result<String, Box<dyn Error>> {
+    let (csv_path, config) = parse_args(args)?;
+    let report = process_payments_report(&csv_path, &config)?;
+    if let Some(estimate) = report.estimate {
+        eprintln!("{}", estimate);
+    }
+    for rejected in report.rejected_rows {
+        eprintln!("{}", rejected);
+    }
+    if let Some(truncation) = &report.truncation {
+        eprintln!("partial result: {}", truncation);
+    }
+    if let (Some(path), false) = (&config.quarantine_file, report.quarantined_rows.is_empty()) {
+        eprintln!(
+            "{} unparseable rows quarantined to {}",
+            report.quarantined_rows.len(),
+            path.display()
+        );
+    }
+    if let (Some(path), false) = (&config.dead_letter_file, report.dead_letters.is_empty()) {
+        eprintln!(
+            "{} dispute operations on unknown transactions dead lettered to {}",
+            report.dead_letters.len(),
+            path.display()
+        );
+    }
+    for retried in &report.retried_operations {
+        eprintln!("{}", retried);
+    }
+    if !report.retried_operations.is_empty() {
+        eprintln!(
+            "repeated dispute operations: {}",
+            report.retried_operations.len()
+        );
+    }
+    if config.show_pending_withdrawals {
+        for queued in &report.retry_queue {
+            eprintln!("{}", queued);
+        }
+    }
+    for abandoned in &report.abandoned_retries {
+        eprintln!("{}", abandoned);
+    }
+    for queued in &report.queued_disputes {
+        eprintln!("{}", queued);
+    }
+    for rolled_back in &report.rolled_back_transactions {
+        eprintln!("{}", rolled_back);
+    }
+    for anomaly in &report.anomalies {
+        eprintln!("{}", anomaly);
+    }
+    for warning in &report.warnings {
+        eprintln!("{}", warning);
+    }
+    if !report.rounding_difference.is_zero() {
+        eprintln!(
+            "output rounding differences net to {}",
+            report.rounding_difference
+        );
+    }
+    if report.omitted_accounts > 0 {
+        eprintln!("empty accounts omitted: {}", report.omitted_accounts);
+    }
+    for frozen in &report.frozen_accounts {
+        eprintln!("{}", frozen);
+    }
+    if let Some(metrics) = report.metrics {
+        eprintln!("{}", metrics);
+    }
+    eprintln!("{}", report.system_accounts);
+    if !report.partitions.is_empty() {
+        eprintln!("ledger written to {} partitions", report.partitions.len());
+    }
+    if let Some(path) = &config.output_file {
+        eprintln!("ledger written to {}", path.display());
+    }
+    if let Some(path) = &config.manifest_file {
+        eprintln!("manifest written to {}", path.display());
+    }
+    if let Some(path) = &config.metadata_file {
+        eprintln!("run metadata written to {}", path.display());
+    }
+    if let Some(profile) = report.profile {
+        eprintln!("{}", profile);
+    }
+    Ok(report.output)
+}
+
+fn run_annotate(args: impl Iterator<Item = OsString>) -> Result<String, Box<dyn Error>> {
+    let (csv_path, config) = parse_args(args)?;
+    let mut output = Vec::new();
+    annotate_input(&csv_path, &config, &mut output)?;
+    Ok(String::from_utf8(output)?)
+}
+
+fn run_decisions(args: impl Iterator<Item = OsString>) -> Result<String, Box<dyn Error>> {
+    match parse_options(args)? {
+        (None, config) => decision_table(&config),
+        (Some(_), _) => Err("Usage: decisions [options]".into()),
+    }
+}
+
+fn run_bisect(mut args: impl Iterator<Item = OsString>) -> Result<String, Box<dyn Error>> {
+    let usage = "Usage: bisect <csv file> <snapshot file> [options]";
+    let (Some(csv_path), Some(snapshot_path)) = (args.next(), args.next()) else {
+        return Err(usage.into());
+    };
+    let config = match parse_options(args)? {
+        (None, config) => config,
+        (Some(_), _) => return Err(usage.into()),
+    };
+    match bisect_payments(&csv_path, &snapshot_path, &config)? {
+        Some(row) => Ok(format!("First divergence observed after row {}", row)),
+        None => Ok(String::from("No divergence from snapshot")),
+    }
+}
+
+fn run_statement(mut args: impl Iterator<Item = OsString>) -> Result<String, Box<dyn Error>> {
+    let usage = "Usage: statement <csv file> <client> [--format csv|text|timeline] [options]";
+    let csv_path = args.next().ok_or(usage)?;
+    let client = args
+        .next()
+        .and_then(|c| c.to_str().and_then(|c| c.parse().ok()))
+        .ok_or(usage)?;
+    let mut format = StatementFormat::default();
+    let mut options = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some(flag @ "--format") => format = flag_value(flag, &mut args)?,
+            _ => options.push(arg),
+        }
+    }
+    match parse_options(options.into_iter())? {
+        (None, config) => client_statement(&csv_path, client, format, &config),
+        (Some(_), _) => Err(usage.into()),
+    }
+}
+
+fn run_activity(mut args: impl Iterator<Item = OsString>) -> Result<String, Box<dyn Error>> {
+    let usage = "Usage: activity <csv file> <first tx> <last tx> [options]";
+    let csv_path = args.next().ok_or(usage)?;
+    let mut tx = || {
+        args.next()
+            .and_then(|tx| tx.to_str().and_then(|tx| tx.parse::<u32>().ok()))
+            .ok_or(usage)
+    };
+    let (first, last) = (tx()?, tx()?);
+    match parse_options(args)? {
+        (None, config) => tx_range_activity(&csv_path, first..=last, &config),
+        (Some(_), _) => Err(usage.into()),
+    }
+}
+
+fn run_reconcile(mut args: impl Iterator<Item = OsString>) -> Result<String, Box<dyn Error>> {
+    let usage = "Usage: reconcile <ledger file> <expected balances file> [--tolerance <amount>]";
+    let (Some(ledger_path), Some(expected_path)) = (args.next(), args.next()) else {
+        return Err(usage.into());
+    };
+    let tolerance = match args.next() {
+        None => Default::default(),
+        Some(flag) if flag == "--tolerance" => flag_value("--tolerance", &mut args)?,
+        Some(_) => return Err(usage.into()),
+    };
+    let mismatches = reconcile(&ledger_path, &expected_path, tolerance)?;
+    if mismatches.is_empty() {
+        return Ok(String::from("No mismatches"));
+    }
+    let rounding = mismatches
+        .iter()
+        .filter(|m| m.category() == MismatchCategory::Rounding)
+        .count();
+    Ok(mismatches
+        .iter()
+        .map(|m| m.to_string())
+        .chain(std::iter::once(format!(
+            "{} rounding, {} structural",
+            rounding,
+            mismatches.len() - rounding
+        )))
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+fn run_verify(mut args: impl Iterator<Item = OsString>) -> Result<String, Box<dyn Error>> {
+    let usage = "Usage: verify <ledger file> <manifest file> [--input <csv file>] \
+                 [--public-key <file>]";
+    let (Some(ledger_path), Some(manifest_path)) = (args.next(), args.next()) else {
+        return Err(usage.into());
+    };
+    let (mut input_path, mut public_key_file) = (None, None);
+    while let Some(flag) = args.next() {
+        match flag.to_str() {
+            Some("--input") => input_path = Some(args.next().ok_or(usage)?),
+            Some("--public-key") => public_key_file = Some(args.next().ok_or(usage)?),
+            _ => return Err(usage.into()),
+        }
+    }
+    let failures = verify_manifest(
+        &ledger_path,
+        &manifest_path,
+        input_path.as_ref(),
+        public_key_file.as_ref(),
+    )?;
+    if failures.is_empty() {
+        return Ok(String::from("Ledger matches manifest"));
+    }
+    Err(failures
+        .iter()
+        .map(|f| f.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+        .into())
+}
+
+fn run_watch(args: impl Iterator<Item = OsString>) -> Result<String, Box<dyn Error>> {
+    let usage = "Usage: watch <drop-box directory> [--poll-interval <seconds>] [options]";
+    let mut args: Vec<OsString> = args.collect();
+    let poll_interval = match args.iter().position(|arg| arg == "--poll-interval") {
+        Some(idx) => {
+            let mut value = args.drain(idx..(idx + 2).min(args.len())).skip(1);
+            flag_value("--poll-interval", &mut value)?
+        }
+        None => 5,
+    };
+    let (Some(dir), config) = parse_options(args.into_iter())? else {
+        return Err(usage.into());
+    };
+    let dir = PathBuf::from(dir);
+    loop {
+        for outcome in process_dropbox(&dir, &config, &SystemClock)? {
+            eprintln!("{}", outcome);
+        }
+        thread::sleep(Duration::from_secs(poll_interval));
+    }
+}
 
 fn main() {
-    match env::args_os().nth(1) {
-        None => {
-            eprintln!("Missing csv file argument");
-            process::exit(1);
+    let mut args = env::args_os().skip(1).peekable();
+    let output = match args.peek().and_then(|arg| arg.to_str()) {
+        Some("annotate") => run_annotate(args.skip(1)),
+        Some("decisions") => run_decisions(args.skip(1)),
+        Some("bisect") => run_bisect(args.skip(1)),
+        Some("statement") => run_statement(args.skip(1)),
+        Some("activity") => run_activity(args.skip(1)),
+        Some("reconcile") => run_reconcile(args.skip(1)),
+        Some("verify") => run_verify(args.skip(1)),
+        Some("watch") => run_watch(args.skip(1)),
+        _ => run_process(args),
+    };
+
+    match output {
+        Ok(result) => {
+            println!("{}", result);
+            process::exit(0);
         }
-        Some(csv_path) => {
-            match process_payments(&csv_path) {
-                Ok(result) => {
-                    println!("{}", result);
-                    process::exit(0);
-                }
-                Err(e) => {
-                    // error occurred
-                    eprintln!("an error occurred: {:#?}", e);
-                    process::exit(1);
-                }
-            }
+        Err(e) => {
+            // error occurred
+            eprintln!("an error occurred: {}", e);
+            process::exit(1);
         }
     }
 }
@@ -1,6 +1,12 @@
-use std::{collections::HashMap, str};
+use std::{
+    fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+    process,
+};
 
 use serde::Serialize;
+use toy_payments_lib::canonical_ledger_csv;
 
 #[derive(Serialize)]
 struct ClientRow {
@@ -45,41 +51,60 @@ pub fn create_csv(rows: Vec<[&'static str; 5]>) -> String {
     data
 }
 
-fn split_to_dict(csv: &String) -> HashMap<String, String> {
-    csv.split("\n")
-        .skip(1) // ignore row titles
-        .into_iter()
-        .map(|line| {
-            (
-                line.split(",").nth(0).unwrap().to_string(),
-                line.to_string(),
-            )
-        })
-        .collect()
+// Used when the output contains columns beyond the default client ledger
+pub fn create_csv_with_header(header: &[&str], rows: Vec<Vec<&str>>) -> String {
+    let mut wtr = csv::Writer::from_writer(vec![]);
+    wtr.write_record(header).unwrap();
+    for r in rows {
+        wtr.write_record(r).unwrap();
+    }
+    wtr.flush().unwrap();
+    String::from_utf8(wtr.into_inner().unwrap()).unwrap()
 }
 
-// Ordering of csv is not guaranteed during integration testing. This is used to ensure tests are
-// not flaky
+// Compares ledgers in the library's canonical form, so that tests do not depend on the order
+// clients are written in
 pub fn assert_unsorted_eq(s1: &String, s2: &String){
-    let sut1 = split_to_dict(s1);
-    let sut2 = split_to_dict(s2);
-    if sut1.len() != sut2.len(){
-        panic!("csvs do not contain the same number of rows");
+    assert_eq!(canonical_ledger_csv(s1).unwrap(), canonical_ledger_csv(s2).unwrap());
+}
+
+// A directory under the system temp dir for the files of a test, removed when dropped so that
+// nothing is left behind when an assertion fails
+pub struct ScratchDir(PathBuf);
+
+impl ScratchDir {
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("toy_payments_{}_{}", name, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
     }
+}
 
-    sut1.iter().for_each(|(k, v)| {
-        let maybe_sut2_row = sut2.get(k);
-        match maybe_sut2_row {
-            Some(row) => {assert_eq!(row, v)},
-            None => {panic!("client {} not found in both csvs", k)},
-        }
-    })
+impl Deref for ScratchDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for ScratchDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
 
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{create_csv, split_to_dict, assert_unsorted_eq};
+    use std::fs;
+
+    use crate::{create_csv, create_csv_with_header, assert_unsorted_eq, ScratchDir};
 
     #[test]
     fn create_csv_creates_single_row() {
@@ -98,11 +123,11 @@ mod tests {
     }
 
     #[test]
-    fn csvs_are_split_into_dicts() {
-        let csv = String::from("client,available,held,total,locked\n1,2,3,4,5\n2,2,3,4,5\n");
-        let sut = split_to_dict(&csv);
-        assert_eq!(sut["1"], String::from("1,2,3,4,5"));
-        assert_eq!(sut["2"], String::from("2,2,3,4,5"));
+    fn create_csv_with_header_uses_given_columns() {
+        let rows = vec![vec!["1", "2", "3"]];
+        let sut = create_csv_with_header(&["client", "a", "b"], rows);
+        let expected = String::from("client,a,b\n1,2,3\n");
+        assert_eq!(sut, expected);
     }
 
 
@@ -129,5 +154,13 @@ mod tests {
         let csv2 = String::from("client,available,held,total,locked\n2,2,3,4,5\n3,2,3,4,5\n1,2,3,4,5\n");
         assert_unsorted_eq(&csv1, &csv2);
     }
-}
 
+    #[test]
+    fn scratch_dir_is_removed_when_dropped() {
+        let dir = ScratchDir::new("scratch");
+        let path = dir.to_path_buf();
+        fs::write(dir.join("a.csv"), "client\n").unwrap();
+        drop(dir);
+        assert!(!path.exists());
+    }
+}
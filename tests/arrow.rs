@@ -0,0 +1,120 @@
+#![cfg(feature = "arrow")]
+// The batch test disputes a deposit, which needs the history the no-history feature does not keep
+#![cfg_attr(feature = "no-history", allow(unused_imports))]
+
+use std::sync::Arc;
+
+#[cfg(not(feature = "string-client-ids"))]
+use arrow_array::types::UInt16Type;
+use arrow_array::{
+    cast::AsArray, types::Decimal128Type, ArrayRef, Decimal128Array, Int32Array, Int64Array,
+    RecordBatch, StringArray,
+};
+use test_utils::ScratchDir;
+use toy_payments_lib::{process_record_batch, Config};
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn transaction_batch_is_processed_into_ledger_batch() {
+    // as a Spark job would send them, with signed ids and decimal amounts
+    let columns: Vec<(&str, ArrayRef)> = vec![
+        (
+            "type",
+            Arc::new(StringArray::from(vec![
+                "deposit",
+                "deposit",
+                "deposit",
+                "withdrawal",
+                "withdrawal",
+                "dispute",
+            ])),
+        ),
+        ("client", Arc::new(Int32Array::from(vec![1, 2, 1, 1, 2, 2]))),
+        ("tx", Arc::new(Int64Array::from(vec![1, 2, 3, 4, 5, 2]))),
+        (
+            "amount",
+            Arc::new(
+                Decimal128Array::from(vec![Some(10), Some(20), Some(20), Some(15), Some(30), None])
+                    .with_precision_and_scale(10, 1)
+                    .unwrap(),
+            ),
+        ),
+    ];
+    let batch = RecordBatch::try_from_iter(columns).unwrap();
+
+    let sut = process_record_batch(&batch, &Config::default()).unwrap();
+
+    let amounts = |name: &str| {
+        sut.column_by_name(name)
+            .unwrap()
+            .as_primitive::<Decimal128Type>()
+            .values()
+            .to_vec()
+    };
+    let clients = sut.column_by_name("client").unwrap();
+    #[cfg(not(feature = "string-client-ids"))]
+    assert_eq!(
+        clients.as_primitive::<UInt16Type>().values().to_vec(),
+        [1, 2]
+    );
+    #[cfg(feature = "string-client-ids")]
+    assert_eq!(
+        clients.as_string::<i32>().iter().collect::<Vec<_>>(),
+        [Some("1"), Some("2")]
+    );
+    assert_eq!(amounts("available"), [15000, 0]);
+    assert_eq!(amounts("held"), [0, 20000]);
+    assert_eq!(amounts("total"), [15000, 20000]);
+    let locked = sut.column_by_name("locked").unwrap().as_boolean();
+    assert!(!locked.value(0) && !locked.value(1));
+}
+
+#[test]
+fn unparseable_rows_fail_the_batch() {
+    let batch = RecordBatch::try_from_iter(vec![
+        (
+            "type",
+            Arc::new(StringArray::from(vec!["deposit", "deposit"])) as ArrayRef,
+        ),
+        ("client", Arc::new(Int32Array::from(vec![1, 1])) as ArrayRef),
+        ("tx", Arc::new(Int32Array::from(vec![1, 2])) as ArrayRef),
+        (
+            "amount",
+            Arc::new(StringArray::from(vec!["1.0", "two"])) as ArrayRef,
+        ),
+    ])
+    .unwrap();
+
+    let err = process_record_batch(&batch, &Config::default()).unwrap_err();
+
+    assert!(err.to_string().contains("two"), "{}", err);
+
+    // unless they are quarantined
+    let dir = ScratchDir::new("arrow_quarantine");
+    let config = Config::default().with_quarantine_file(dir.join("quarantine.csv"));
+    let sut = process_record_batch(&batch, &config).unwrap();
+    assert_eq!(sut.num_rows(), 1);
+    let quarantined = std::fs::read_to_string(dir.join("quarantine.csv")).unwrap();
+    assert!(quarantined.contains("deposit,1,2,two"), "{}", quarantined);
+}
+
+#[test]
+fn balances_too_large_for_the_output_scale_fail_the_batch() {
+    let batch = RecordBatch::try_from_iter(vec![
+        (
+            "type",
+            Arc::new(StringArray::from(vec!["deposit"])) as ArrayRef,
+        ),
+        ("client", Arc::new(Int32Array::from(vec![1])) as ArrayRef),
+        ("tx", Arc::new(Int32Array::from(vec![1])) as ArrayRef),
+        (
+            "amount",
+            Arc::new(StringArray::from(vec!["10000000000000000000000000"])) as ArrayRef,
+        ),
+    ])
+    .unwrap();
+
+    let err = process_record_batch(&batch, &Config::default()).unwrap_err();
+
+    assert!(err.to_string().contains("too many digits"), "{}", err);
+}
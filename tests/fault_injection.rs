@@ -0,0 +1,52 @@
+#![cfg(feature = "fault-injection")]
+
+use std::{
+    ffi::OsString,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use test_utils::ScratchDir;
+use toy_payments_lib::{
+    process_dropbox, process_payments_with_config, read_checkpoint, Config, DropboxOutcome,
+    FaultInjection, FixedClock,
+};
+
+fn injected_fault(config: &Config) -> String {
+    let csv_path = OsString::from("tests/resources/basic_example.csv");
+    process_payments_with_config(&csv_path, config)
+        .unwrap_err()
+        .to_string()
+}
+
+#[test]
+fn io_errors_are_injected_after_every_n_rows() {
+    let config = Config::default().with_faults(FaultInjection::default().with_io_error_every(3));
+    assert!(injected_fault(&config).contains("injected fault after row 3"));
+}
+
+#[test]
+fn seeded_faults_fail_the_same_row_of_each_window() {
+    let faults = FaultInjection::default()
+        .with_io_error_every(4)
+        .with_seed(42);
+    let config = Config::default().with_faults(faults);
+    let first = injected_fault(&config);
+    assert!((1..=4).any(|row| first.contains(&format!("injected fault after row {}", row))));
+    assert_eq!(injected_fault(&config), first);
+}
+
+#[test]
+fn files_failing_with_injected_faults_are_not_checkpointed() {
+    let dir = ScratchDir::new("faults");
+    std::fs::copy("tests/resources/basic_example.csv", dir.join("a.csv")).unwrap();
+    let config = Config::default().with_faults(FaultInjection::default().with_io_error_every(2));
+    let clock = FixedClock(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+
+    let outcomes = process_dropbox(&dir, &config, &clock).unwrap();
+    let checkpoint = read_checkpoint(&dir).unwrap();
+    let failed = dir.join("failed").join("a.csv").exists();
+
+    assert!(matches!(&outcomes[..], [DropboxOutcome::Failed(file, _)] if file == "a.csv"));
+    assert!(checkpoint.processed.is_empty());
+    assert!(failed);
+}
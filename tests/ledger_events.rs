@@ -0,0 +1,52 @@
+// The run disputes a deposit, which needs the history the no-history feature does not keep
+#![cfg(all(feature = "ledger-events", not(feature = "no-history")))]
+
+use std::ffi::OsString;
+
+use rust_decimal::Decimal;
+use toy_payments_lib::{
+    process_payments_with_hooks, ClientBalances, ClientKey, Config, LedgerEvent,
+    LedgerEventBroadcaster, RunHooks, TxMiddleware,
+};
+
+/// The key of a numbered client under either kind of key, so the test runs with string ids too.
+fn key(id: u16) -> ClientKey {
+    id.to_string().parse().unwrap()
+}
+
+#[test]
+fn every_subscriber_receives_the_ledger_events_of_a_run() {
+    let broadcaster = LedgerEventBroadcaster::new(64);
+    let mut dashboard = broadcaster.subscribe();
+    let mut audit = broadcaster.subscribe();
+    let mut middleware: Vec<Box<dyn TxMiddleware>> = vec![Box::new(broadcaster)];
+    let csv_path = OsString::from("tests/resources/upheld_chargeback.csv");
+    let hooks = RunHooks::default().with_middleware(&mut middleware);
+    process_payments_with_hooks(&csv_path, &Config::default(), hooks).unwrap();
+
+    let mut events = Vec::new();
+    while let Ok(event) = dashboard.try_recv() {
+        events.push(event);
+    }
+    assert!(events.contains(&LedgerEvent::DisputeOpened {
+        client: key(1),
+        tx: 1
+    }));
+    assert!(events.contains(&LedgerEvent::AccountLocked { client: key(1) }));
+    assert_eq!(
+        events.last(),
+        Some(&LedgerEvent::ClientUpdated(ClientBalances {
+            client: key(1),
+            available: Decimal::new(-50, 0),
+            held: Decimal::ZERO,
+            total: Decimal::new(-50, 0),
+            locked: true,
+        }))
+    );
+
+    let mut audited = Vec::new();
+    while let Ok(event) = audit.try_recv() {
+        audited.push(event);
+    }
+    assert_eq!(audited, events);
+}
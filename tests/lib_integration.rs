@@ -1,10 +1,39 @@
-use std::ffi::OsString;
+// Tests of disputes need the deposit history, which the no-history feature does not keep
+#![cfg_attr(feature = "no-history", allow(unused_imports, dead_code))]
 
-use test_utils::{assert_unsorted_eq, create_csv};
-use toy_payments_lib::process_payments;
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    ffi::OsString,
+    path::Path,
+    rc::Rc,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use rust_decimal::Decimal;
+use test_utils::{assert_unsorted_eq, create_csv, create_csv_with_header, ScratchDir};
+use toy_payments_lib::{
+    annotate_input, bisect_payments, client_statement, compare_ledgers, decision_table,
+    estimate_memory, process_dropbox, process_payments, process_payments_report,
+    process_payments_with_config, process_payments_with_hooks, process_transactions_detailed,
+    read_checkpoint, read_client_flags, reconcile, tx_range_activity, verify_manifest,
+    AbandonedRetry, AccountActivity, ActivityCounts, Anomaly, AnomalyReason, ClientKey,
+    ClientLedger, ClientState, Config, Decision, DisputeOperation, DisputePolicy, DropboxOutcome,
+    FieldError, FixedClock, FrozenAccount, Ignored, InputError, LedgerFilter, LimitExceeded,
+    Metrics, Mismatch, MismatchCategory, MismatchKind, MonetaryAmount, OrderingViolation,
+    OutputFormat, OutputOptions, OutputSchema, Policy, QueuedDispute, QueuedRetry, RejectedRow,
+    RetriedOperation, RetryAbandonment, RetryPolicy, RiskScorer, RowRejection, RowStatus, RunHooks,
+    RunMetadata, SourceMetadata, StatementFormat, StepClock, SystemAccounts, Transaction,
+    TxMiddleware, TypeMetrics, UnparseableRow, VerificationFailure, Warning, WarningKind,
+};
 
 extern crate test_utils;
 
+/// The key of a numbered client under either kind of key, so tests run with string ids too.
+fn key(id: u16) -> ClientKey {
+    id.to_string().parse().unwrap()
+}
+
 #[test]
 fn basic_example() {
     let sut = process_payments(&OsString::from("tests/resources/basic_example.csv")).unwrap();
@@ -15,6 +44,19 @@ fn basic_example() {
     assert_unsorted_eq(&sut, &expected);
 }
 
+#[cfg(not(feature = "string-client-ids"))]
+#[test]
+fn ledger_is_ordered_by_client_id() {
+    let sut = process_payments(&OsString::from("tests/resources/client_order.csv")).unwrap();
+    let expected = create_csv(vec![
+        ["1", "1.0000", "0.0000", "1.0000", "false"],
+        ["2", "2.0000", "0.0000", "2.0000", "false"],
+        ["3", "3.0000", "0.0000", "3.0000", "false"],
+        ["10", "10.0000", "0.0000", "10.0000", "false"],
+    ]);
+    assert_eq!(sut, expected);
+}
+
 #[test]
 fn cannot_withdraw_over_avail() {
     let sut = process_payments(&OsString::from("tests/resources/withdraw_over_avail.csv")).unwrap();
@@ -29,6 +71,7 @@ fn false_disputes_are_ignored() {
     assert_eq!(sut, expected)
 }
 
+#[cfg(not(feature = "no-history"))]
 #[test]
 fn disputes_correctly_modify_account() {
     let sut = process_payments(&OsString::from("tests/resources/dispute_example.csv")).unwrap();
@@ -43,6 +86,7 @@ fn resolves_correctly_modify_account() {
     assert_eq!(sut, expected)
 }
 
+#[cfg(not(feature = "no-history"))]
 #[test]
 fn false_resolves_are_ignored() {
     let sut = process_payments(&OsString::from("tests/resources/false_resolves.csv")).unwrap();
@@ -63,6 +107,7 @@ fn withdrawals_retroactively_resolved() {
 // after a dispute. If a transaction occurs before a dispute and is rejected, then it is based on
 // the available funds at the time of the transaction, and only retroactively
 // dependant on the disputes occurring prior to it
+#[cfg(not(feature = "no-history"))]
 #[test]
 fn no_retroactive_resolve_for_withdraw_prior_to_dispute() {
     let sut = process_payments(&OsString::from(
@@ -73,6 +118,178 @@ fn no_retroactive_resolve_for_withdraw_prior_to_dispute() {
     assert_eq!(sut, expected)
 }
 
+#[test]
+fn withdrawals_are_retried_on_deposits_until_out_of_attempts_or_expired() {
+    let csv_path = OsString::from("tests/resources/retry_queue.csv");
+    let sut = process_payments(&csv_path).unwrap();
+    let expected = create_csv(vec![
+        ["1", "35.0000", "0.0000", "35.0000", "false"],
+        ["2", "12.0000", "0.0000", "12.0000", "false"],
+        ["3", "11.0000", "0.0000", "11.0000", "false"],
+        ["4", "1.0000", "0.0000", "1.0000", "false"],
+    ]);
+    assert_unsorted_eq(&sut, &expected);
+
+    let config =
+        Config::from_toml("[retry]\nmax_attempts = 2\nexpiry = 5\non_deposit = true").unwrap();
+    let sut = process_payments_report(&csv_path, &config).unwrap();
+    let expected = create_csv(vec![
+        ["1", "5.0000", "0.0000", "5.0000", "false"],
+        ["2", "12.0000", "0.0000", "12.0000", "false"],
+        ["3", "11.0000", "0.0000", "11.0000", "false"],
+        ["4", "1.0000", "0.0000", "1.0000", "false"],
+    ]);
+    assert_unsorted_eq(&sut.output, &expected);
+    assert_eq!(
+        sut.retry_queue,
+        vec![QueuedRetry {
+            client: key(4),
+            tx: 22,
+            amount: Decimal::new(5, 0),
+            attempts: 0,
+        }]
+    );
+    let mut abandoned = sut.abandoned_retries;
+    abandoned.sort_by_key(|a| a.client.to_owned());
+    assert_eq!(
+        abandoned,
+        vec![
+            AbandonedRetry {
+                client: key(2),
+                tx: 6,
+                reason: RetryAbandonment::MaxAttempts,
+            },
+            AbandonedRetry {
+                client: key(3),
+                tx: 10,
+                reason: RetryAbandonment::Expired,
+            },
+        ]
+    );
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn empty_accounts_are_omitted_when_enabled() {
+    let csv_path = OsString::from("tests/resources/empty_accounts.csv");
+    let sut = process_payments_report(&csv_path, &Config::default()).unwrap();
+    assert_eq!(sut.output.lines().count(), 5);
+    assert_eq!(sut.omitted_accounts, 0);
+
+    let config = Config::default().with_omit_empty_accounts(true);
+    let sut = process_payments_report(&csv_path, &config).unwrap();
+    let expected = create_csv(vec![
+        ["1", "10.0000", "0.0000", "10.0000", "false"],
+        ["3", "0.0000", "0.0000", "0.0000", "false"],
+    ]);
+    assert_unsorted_eq(&sut.output, &expected);
+    assert_eq!(sut.omitted_accounts, 2);
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn chargeback_reversal_restores_funds_and_optionally_unlocks() {
+    let csv_path = OsString::from("tests/resources/chargeback_reversal.csv");
+    let sut = process_payments(&csv_path).unwrap();
+    let expected = create_csv(vec![
+        ["1", "15.0000", "0.0000", "15.0000", "true"],
+        ["2", "0.0000", "0.0000", "0.0000", "true"],
+    ]);
+    assert_unsorted_eq(&sut, &expected);
+
+    let config = Config::default().with_unlock_on_chargeback_reversal(true);
+    let sut = process_payments_report(&csv_path, &config).unwrap();
+    let expected = create_csv(vec![
+        ["1", "17.0000", "0.0000", "17.0000", "false"],
+        ["2", "0.0000", "0.0000", "0.0000", "true"],
+    ]);
+    assert_unsorted_eq(&sut.output, &expected);
+    assert_eq!(
+        sut.retried_operations,
+        vec![RetriedOperation {
+            client: key(1),
+            tx: 2,
+            operation: DisputeOperation::ChargebackReversal,
+        }]
+    );
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn disputes_over_the_held_limit_wait_for_earlier_disputes_to_settle() {
+    let csv_path = OsString::from("tests/resources/held_limit.csv");
+    let sut = process_payments(&csv_path).unwrap();
+    let expected = create_csv(vec![
+        ["1", "40.0000", "60.0000", "100.0000", "false"],
+        ["2", "10.0000", "10.0000", "20.0000", "false"],
+    ]);
+    assert_unsorted_eq(&sut, &expected);
+
+    let config = Config::default().with_max_held_percent(Decimal::new(50, 0));
+    let sut = process_payments_report(&csv_path, &config).unwrap();
+    let expected = create_csv(vec![
+        ["1", "70.0000", "30.0000", "100.0000", "false"],
+        ["2", "10.0000", "10.0000", "20.0000", "false"],
+    ]);
+    assert_unsorted_eq(&sut.output, &expected);
+    assert_eq!(
+        sut.queued_disputes,
+        vec![QueuedDispute {
+            client: key(1),
+            tx: 3,
+            amount: Decimal::new(30, 0),
+        }]
+    );
+}
+
+const RISK_HEADER: [&str; 9] = [
+    "client",
+    "available",
+    "held",
+    "total",
+    "locked",
+    "open_disputes",
+    "rejected_withdrawals",
+    "last_tx_id",
+    "risk_score",
+];
+
+struct ChargebackCount;
+
+impl RiskScorer for ChargebackCount {
+    fn score(&self, client: &ClientLedger) -> Decimal {
+        Decimal::from(client.chargebacks)
+    }
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn risk_scores_weigh_chargebacks_disputes_and_negative_balances() {
+    let csv_path = OsString::from("tests/resources/upheld_chargeback.csv");
+    let config = Config::default()
+        .with_schema(OutputSchema::V2)
+        .with_risk_scores(true);
+    let sut = process_payments_report(&csv_path, &config).unwrap();
+    // one chargeback, one of two transactions disputed, and negative available funds
+    let expected = create_csv_with_header(
+        &RISK_HEADER,
+        vec![vec![
+            "1", "-50.0000", "0.0000", "-50.0000", "true", "0", "0", "1", "80.0000",
+        ]],
+    );
+    assert_eq!(sut.output, expected);
+
+    let hooks = RunHooks::default().with_risk_scorer(ChargebackCount);
+    let sut = process_payments_with_hooks(&csv_path, &config, hooks).unwrap();
+    let expected = create_csv_with_header(
+        &RISK_HEADER,
+        vec![vec![
+            "1", "-50.0000", "0.0000", "-50.0000", "true", "0", "0", "1", "1.0000",
+        ]],
+    );
+    assert_eq!(sut.output, expected);
+}
+
 #[test]
 fn false_chargebacks_are_ignored() {
     let sut = process_payments(&OsString::from("tests/resources/false_chargebacks.csv")).unwrap();
@@ -80,9 +297,1940 @@ fn false_chargebacks_are_ignored() {
     assert_eq!(sut, expected)
 }
 
+#[cfg(not(feature = "no-history"))]
 #[test]
 fn chargeback_will_block_account_and_reduce_funds() {
     let sut = process_payments(&OsString::from("tests/resources/upheld_chargeback.csv")).unwrap();
     let expected = create_csv(vec![["1", "-50.0000", "0.0000", "-50.0000", "true"]]);
     assert_eq!(sut, expected)
 }
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn pending_withdrawals_are_reported_when_enabled() {
+    let config = Config::default().with_pending_withdrawals(true);
+    let sut = process_payments_with_config(
+        &OsString::from("tests/resources/pending_withdrawals.csv"),
+        &config,
+    )
+    .unwrap();
+    let expected = create_csv_with_header(
+        &[
+            "client",
+            "available",
+            "held",
+            "total",
+            "locked",
+            "pending_withdrawals",
+            "pending_withdrawal_amount",
+        ],
+        vec![
+            vec![
+                "1", "0.0000", "100.0000", "100.0000", "false", "2", "50.0000",
+            ],
+            vec!["2", "10.0000", "0.0000", "10.0000", "false", "0", "0.0000"],
+        ],
+    );
+    assert_unsorted_eq(&sut, &expected);
+}
+
+fn limit_error(config: Config) -> LimitExceeded {
+    let err = process_payments_with_config(
+        &OsString::from("tests/resources/basic_example.csv"),
+        &config,
+    )
+    .unwrap_err();
+    *err.downcast::<LimitExceeded>().unwrap()
+}
+
+#[test]
+fn row_limit_aborts_processing() {
+    let sut = limit_error(Config::default().with_max_rows(4));
+    assert_eq!(sut, LimitExceeded::Rows { limit: 4 });
+}
+
+#[test]
+fn client_limit_aborts_processing() {
+    let sut = limit_error(Config::default().with_max_clients(1));
+    assert_eq!(sut, LimitExceeded::Clients { limit: 1 });
+}
+
+#[test]
+fn byte_limit_aborts_processing() {
+    let sut = limit_error(Config::default().with_max_bytes(100));
+    assert!(matches!(sut, LimitExceeded::Bytes { limit: 100, .. }));
+}
+
+#[test]
+fn memory_limit_aborts_processing() {
+    let sut = limit_error(Config::default().with_max_memory(100));
+    assert!(matches!(sut, LimitExceeded::Memory { limit: 100, .. }));
+}
+
+#[test]
+fn memory_estimate_counts_clients_and_activities() {
+    let csv_path = OsString::from("tests/resources/warnings.csv");
+    let sut = estimate_memory(&csv_path).unwrap();
+    assert_eq!(sut.rows, 8);
+    assert_eq!(sut.clients, 2);
+    assert_eq!(sut.activities, 4);
+
+    let config = Config::default().with_max_memory(sut.bytes);
+    let report = process_payments_report(&csv_path, &config).unwrap();
+    assert_eq!(report.estimate, Some(sut));
+}
+
+#[test]
+fn input_within_limits_is_processed() {
+    let config = Config::default()
+        .with_max_rows(5)
+        .with_max_clients(2)
+        .with_max_bytes(1024);
+    let sut = process_payments_with_config(
+        &OsString::from("tests/resources/basic_example.csv"),
+        &config,
+    );
+    assert!(sut.is_ok());
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn bisect_finds_first_divergent_row() {
+    let sut = bisect_payments(
+        &OsString::from("tests/resources/upheld_chargeback.csv"),
+        &OsString::from("tests/resources/bisect_snapshot.csv"),
+        &Config::default(),
+    )
+    .unwrap();
+    assert_eq!(sut, Some(3))
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn bisect_finds_no_divergence_from_matching_snapshot() {
+    let sut = bisect_payments(
+        &OsString::from("tests/resources/upheld_chargeback.csv"),
+        &OsString::from("tests/resources/bisect_snapshot_matching.csv"),
+        &Config::default(),
+    )
+    .unwrap();
+    assert_eq!(sut, None)
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn bisect_searches_each_client_and_replays_with_the_config() {
+    let csv_path = OsString::from("tests/resources/bisect_clients.csv");
+    let config = Config::default().with_dispute_expiry_days(1);
+    // client 2 diverges at row 2 though the entries of client 1 around it match
+    let sut = bisect_payments(
+        &csv_path,
+        &OsString::from("tests/resources/bisect_snapshot_clients.csv"),
+        &config,
+    )
+    .unwrap();
+    assert_eq!(sut, Some(2));
+
+    // the withdrawal of client 1 is only made from funds released by the expired dispute
+    let snapshot_path = OsString::from("tests/resources/bisect_snapshot_clients_matching.csv");
+    let sut = bisect_payments(&csv_path, &snapshot_path, &config).unwrap();
+    assert_eq!(sut, None);
+    let sut = bisect_payments(&csv_path, &snapshot_path, &Config::default()).unwrap();
+    assert_eq!(sut, Some(5));
+}
+
+#[test]
+fn rows_with_missing_or_unexpected_amounts_are_rejected() {
+    let sut = process_payments_report(
+        &OsString::from("tests/resources/missing_amounts.csv"),
+        &Config::default(),
+    )
+    .unwrap();
+    let expected = create_csv(vec![["1", "60.0000", "0.0000", "60.0000", "false"]]);
+    assert_eq!(sut.output, expected);
+    assert_eq!(
+        sut.rejected_rows,
+        vec![
+            RejectedRow {
+                row: 2,
+                reason: RowRejection::MissingAmount
+            },
+            RejectedRow {
+                row: 3,
+                reason: RowRejection::MissingAmount
+            },
+            RejectedRow {
+                row: 4,
+                reason: RowRejection::UnexpectedAmount
+            },
+        ]
+    );
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn tx_types_match_any_case_and_configured_aliases() {
+    let csv_path = OsString::from("tests/resources/tx_type_aliases.csv");
+    let config = Config::default().with_tx_type_alias("Credit", "deposit");
+    let sut = process_payments_report(&csv_path, &config).unwrap();
+    let expected = create_csv(vec![["1", "80.0000", "0.0000", "80.0000", "true"]]);
+    assert_eq!(sut.output, expected);
+    assert_eq!(
+        sut.rejected_rows,
+        vec![RejectedRow {
+            row: 5,
+            reason: RowRejection::UnknownTxType(String::from("transfer"))
+        }]
+    );
+
+    let config = Config::default().with_tx_type_alias("credit", "refund");
+    let err = process_payments_report(&csv_path, &config).err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        "transaction type alias `credit` names unknown type `refund`"
+    );
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn dispute_clients_are_looked_up_by_tx_when_enabled() {
+    let csv_path = OsString::from("tests/resources/dispute_client_lookup.csv");
+    let sut = process_payments_report(&csv_path, &Config::default()).unwrap();
+    let expected = create_csv(vec![
+        ["1", "100.0000", "0.0000", "100.0000", "false"],
+        ["2", "50.0000", "0.0000", "50.0000", "false"],
+    ]);
+    assert_unsorted_eq(&sut.output, &expected);
+    assert_eq!(
+        sut.rejected_rows
+            .iter()
+            .map(|r| (r.row, r.reason.clone()))
+            .collect::<Vec<_>>(),
+        vec![
+            (3, RowRejection::MissingClient),
+            (5, RowRejection::MissingClient),
+            (6, RowRejection::MissingClient),
+        ]
+    );
+
+    let config = Config::default().with_lookup_dispute_clients(true);
+    let sut = process_payments_report(&csv_path, &config).unwrap();
+    let expected = create_csv(vec![
+        ["1", "0.0000", "100.0000", "100.0000", "false"],
+        ["2", "50.0000", "0.0000", "50.0000", "false"],
+    ]);
+    assert_unsorted_eq(&sut.output, &expected);
+    assert_eq!(
+        sut.rejected_rows,
+        vec![RejectedRow {
+            row: 6,
+            reason: RowRejection::UnresolvedClient
+        }]
+    );
+}
+
+#[test]
+fn missing_amounts_are_zero_when_lenient() {
+    let sut = process_payments_report(
+        &OsString::from("tests/resources/missing_amounts.csv"),
+        &Config::default().with_missing_amount_as_zero(true),
+    )
+    .unwrap();
+    let expected = create_csv(vec![["1", "60.0000", "0.0000", "60.0000", "false"]]);
+    assert_eq!(sut.output, expected);
+    assert_eq!(
+        sut.rejected_rows,
+        vec![RejectedRow {
+            row: 4,
+            reason: RowRejection::UnexpectedAmount
+        }]
+    );
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn v2_schema_adds_dispute_and_tx_columns() {
+    let sut = process_payments_with_config(
+        &OsString::from("tests/resources/pending_withdrawals.csv"),
+        &Config::default().with_schema(OutputSchema::V2),
+    )
+    .unwrap();
+    // both withdrawals of client 1 were rejected, so the dispute of tx 1 was applied last
+    let expected = create_csv_with_header(
+        &[
+            "client",
+            "available",
+            "held",
+            "total",
+            "locked",
+            "open_disputes",
+            "rejected_withdrawals",
+            "last_tx_id",
+        ],
+        vec![
+            vec![
+                "1", "0.0000", "100.0000", "100.0000", "false", "1", "2", "1",
+            ],
+            vec!["2", "10.0000", "0.0000", "10.0000", "false", "0", "0", "4"],
+        ],
+    );
+    assert_unsorted_eq(&sut, &expected);
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn v2_rejected_withdrawals_count_those_since_backfilled() {
+    let config = Config::default()
+        .with_schema(OutputSchema::V2)
+        .with_pending_withdrawals(true);
+    let sut = process_payments_with_config(
+        &OsString::from("tests/resources/retroactive_resolve.csv"),
+        &config,
+    )
+    .unwrap();
+    // the resolves enact both rejected withdrawals, so none is still pending
+    let expected = create_csv_with_header(
+        &[
+            "client",
+            "available",
+            "held",
+            "total",
+            "locked",
+            "open_disputes",
+            "rejected_withdrawals",
+            "last_tx_id",
+            "pending_withdrawals",
+            "pending_withdrawal_amount",
+        ],
+        vec![vec![
+            "1", "0.0000", "0.0000", "0.0000", "false", "0", "2", "3", "0", "0.0000",
+        ]],
+    );
+    assert_eq!(sut, expected);
+}
+
+#[test]
+fn profile_is_reported_when_enabled() {
+    let csv_path = OsString::from("tests/resources/missing_amounts.csv");
+    let sut = process_payments_report(&csv_path, &Config::default().with_profile(true)).unwrap();
+    let profile = sut.profile.unwrap();
+    assert_eq!(profile.rows, 5);
+    assert_eq!(
+        profile.total(),
+        profile.parse + profile.fold + profile.output
+    );
+
+    let sut = process_payments_report(&csv_path, &Config::default()).unwrap();
+    assert!(sut.profile.is_none());
+}
+
+#[test]
+fn batch_dispute_rows_are_rejected_by_default() {
+    let sut = process_payments_report(
+        &OsString::from("tests/resources/batch_disputes.csv"),
+        &Config::default(),
+    )
+    .unwrap();
+    let expected = create_csv(vec![["1", "175.0000", "0.0000", "175.0000", "false"]]);
+    assert_eq!(sut.output, expected);
+    let rejected: Vec<usize> = sut.rejected_rows.iter().map(|r| r.row).collect();
+    assert_eq!(rejected, vec![4, 5, 7]);
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn batch_dispute_rows_are_expanded_when_enabled() {
+    let sut = process_payments_report(
+        &OsString::from("tests/resources/batch_disputes.csv"),
+        &Config::default().with_batch_dispute_rows(true),
+    )
+    .unwrap();
+    let expected = create_csv(vec![["1", "125.0000", "0.0000", "125.0000", "true"]]);
+    assert_eq!(sut.output, expected);
+    // deposits and withdrawals can never list several transactions
+    assert_eq!(
+        sut.rejected_rows,
+        vec![RejectedRow {
+            row: 7,
+            reason: RowRejection::MultipleTxIds
+        }]
+    );
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn annotated_input_gives_the_outcome_of_each_row() {
+    let mut sut = Vec::new();
+    annotate_input(
+        &OsString::from("tests/resources/annotate.csv"),
+        &Config::default(),
+        &mut sut,
+    )
+    .unwrap();
+    let expected = [
+        "type,client,tx,amount,outcome",
+        "deposit,1,1,10,applied",
+        "withdrawal,1,2,15,rejected",
+        "deposit,1,3,,rejected: missing amount",
+        "dispute,1,1,,applied: held 10.0000",
+        "dispute,1,9,,ignored: unknown transaction",
+        "resolve,1,1,,applied: released 10.0000",
+        "withdrawal, 1, 4, 2.5,applied",
+        "",
+    ]
+    .join("\n");
+    assert_eq!(String::from_utf8(sut).unwrap(), expected);
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn decision_table_covers_every_state_and_transaction_type() {
+    let sut = decision_table(&Config::default()).unwrap();
+    let rows: Vec<&str> = sut.lines().collect();
+    assert_eq!(rows[0], "state,transaction,decision,reason");
+    // five account states by nine transaction types
+    assert_eq!(rows.len(), 1 + 5 * 9);
+    for row in [
+        "funded,withdrawal_within_funds,debit,",
+        "funded,withdrawal_over_funds,reject_withdrawal,",
+        "funded,resolve,ignore,not disputed",
+        "disputed,withdrawal_within_funds,queue_withdrawal,",
+        "disputed,dispute,ignore_repeat,",
+        "disputed,chargeback,charge_back,",
+        "charged_back,deposit,ignore,account locked",
+        "charged_back,chargeback_reversal,reverse_chargeback,",
+        "on_hold,hold,ignore,duplicate hold",
+        "on_hold,release,release_hold,",
+    ] {
+        assert!(rows.contains(&row), "{}", row);
+    }
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn detailed_processing_returns_the_outcome_of_each_row() {
+    let (ledger, outcomes) = process_transactions_detailed(
+        &OsString::from("tests/resources/annotate.csv"),
+        &Config::default(),
+    )
+    .unwrap();
+    assert_eq!(ledger.0.len(), 1);
+    assert_eq!(ledger.0[0].available.value(), Decimal::new(75, 1));
+
+    let statuses: Vec<(usize, RowStatus)> = outcomes
+        .iter()
+        .map(|outcome| (outcome.row, outcome.status.clone()))
+        .collect();
+    assert_eq!(
+        statuses,
+        vec![
+            (1, RowStatus::Applied(Decision::Credit)),
+            (2, RowStatus::Ignored(Decision::RejectWithdrawal)),
+            (3, RowStatus::Rejected(RowRejection::MissingAmount)),
+            (4, RowStatus::Applied(Decision::HoldDispute)),
+            (
+                5,
+                RowStatus::Ignored(Decision::Ignore(Ignored::UnknownTransaction))
+            ),
+            (6, RowStatus::Applied(Decision::ReleaseDispute)),
+            (7, RowStatus::Applied(Decision::Debit)),
+        ]
+    );
+    let held_after_dispute = outcomes[3].balances.as_ref().unwrap();
+    assert_eq!(
+        (held_after_dispute.available, held_after_dispute.held),
+        (Decimal::ZERO, Decimal::new(10, 0))
+    );
+    assert!(outcomes[2].balances.is_none());
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn balances_near_the_largest_amount_do_not_overflow() {
+    let csv_path = OsString::from("tests/resources/overflow.csv");
+    let config = Config::default()
+        .with_collect_metrics(true)
+        .with_check_amounts(true);
+    let sut = process_payments_report(&csv_path, &config).unwrap();
+    let max = "79228162514264337593543950335";
+    let expected = create_csv(vec![
+        ["1", max, "0.0000", max, "false"],
+        ["2", "0.0000", max, max, "false"],
+    ]);
+    assert_unsorted_eq(&sut.output, &expected);
+
+    let (_, outcomes) = process_transactions_detailed(&csv_path, &Config::default()).unwrap();
+    let overflowed: Vec<usize> = outcomes
+        .iter()
+        .filter(|outcome| outcome.status == RowStatus::Ignored(Decision::Ignore(Ignored::Overflow)))
+        .map(|outcome| outcome.row)
+        .collect();
+    assert_eq!(overflowed, vec![2, 7, 8]);
+
+    // queued withdrawals near the largest amount are summed without overflowing
+    let csv_path = OsString::from("tests/resources/overflow_retry_queue.csv");
+    let config = Config::default().with_retry(RetryPolicy::default().with_on_deposit(true));
+    let sut = process_payments_report(&csv_path, &config).unwrap();
+    assert_eq!(
+        sut.output,
+        create_csv(vec![["1", "1.0000", "0.0000", "1.0000", "false"]])
+    );
+    assert_eq!(sut.retry_queue.len(), 2);
+    assert_eq!(
+        sut.system_accounts.rejected_withdrawal_liability,
+        Decimal::MAX
+    );
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn statement_lists_running_balances_for_client() {
+    let sut = client_statement(
+        &OsString::from("tests/resources/upheld_chargeback.csv"),
+        key(1),
+        StatementFormat::Csv,
+        &Config::default(),
+    )
+    .unwrap();
+    let expected = create_csv_with_header(
+        &[
+            "row",
+            "type",
+            "tx",
+            "amount",
+            "available",
+            "held",
+            "total",
+            "locked",
+            "note",
+        ],
+        vec![
+            vec![
+                "1", "deposit", "1", "100.0000", "100.0000", "0.0000", "100.0000", "false", "",
+            ],
+            vec![
+                "2",
+                "withdrawal",
+                "2",
+                "50.0000",
+                "50.0000",
+                "0.0000",
+                "50.0000",
+                "false",
+                "",
+            ],
+            vec![
+                "3",
+                "dispute",
+                "1",
+                "",
+                "-50.0000",
+                "100.0000",
+                "50.0000",
+                "false",
+                "held 100.0000",
+            ],
+            vec![
+                "4",
+                "chargeback",
+                "1",
+                "",
+                "-50.0000",
+                "0.0000",
+                "-50.0000",
+                "true",
+                "charged back, account locked",
+            ],
+        ],
+    );
+    assert_eq!(sut, expected);
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn statement_annotates_pending_and_backfilled_withdrawals() {
+    let sut = client_statement(
+        &OsString::from("tests/resources/retroactive_resolve.csv"),
+        key(1),
+        StatementFormat::Text,
+        &Config::default(),
+    )
+    .unwrap();
+    let lines: Vec<&str> = sut.lines().collect();
+    assert_eq!(lines.len(), 10);
+    assert!(lines[3].ends_with("rejected: pending resolution of open disputes"));
+    assert!(lines[9].ends_with("released 100.0000, backfilled 1 withdrawals"));
+}
+
+#[test]
+fn statement_is_empty_for_unknown_client() {
+    let sut = client_statement(
+        &OsString::from("tests/resources/upheld_chargeback.csv"),
+        key(2),
+        StatementFormat::Csv,
+        &Config::default(),
+    )
+    .unwrap();
+    assert_eq!(sut, "");
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn repeated_dispute_operations_are_reported_as_retries() {
+    let sut = process_payments_report(
+        &OsString::from("tests/resources/repeated_dispute_operations.csv"),
+        &Config::default(),
+    )
+    .unwrap();
+    let expected = create_csv(vec![["1", "0.0000", "0.0000", "0.0000", "true"]]);
+    assert_eq!(sut.output, expected);
+    // the second dispute follows a resolve, so is not a retry
+    let retried = |operation| RetriedOperation {
+        client: key(1),
+        tx: 1,
+        operation,
+    };
+    assert_eq!(
+        sut.retried_operations,
+        vec![
+            retried(DisputeOperation::Dispute),
+            retried(DisputeOperation::Resolve),
+            retried(DisputeOperation::Chargeback),
+        ]
+    );
+}
+
+#[test]
+fn anomalies_are_flagged_when_enabled() {
+    let csv_path = OsString::from("tests/resources/anomalies.csv");
+    let sut =
+        process_payments_report(&csv_path, &Config::default().with_detect_anomalies(true)).unwrap();
+    assert_eq!(
+        sut.anomalies,
+        vec![
+            Anomaly {
+                row: 3,
+                client: key(1),
+                tx: 3,
+                reason: AnomalyReason::LargeDeposit {
+                    multiple: 200.into()
+                },
+            },
+            Anomaly {
+                row: 9,
+                client: key(2),
+                tx: 6,
+                reason: AnomalyReason::DisputeBurst {
+                    disputes: 3,
+                    window: 10
+                },
+            },
+        ]
+    );
+
+    let sut = process_payments_report(&csv_path, &Config::default()).unwrap();
+    assert!(sut.anomalies.is_empty());
+}
+
+#[test]
+fn missing_input_is_reported_as_not_found() {
+    let err = process_payments(&OsString::from("tests/resources/does_not_exist.csv")).unwrap_err();
+    let sut = err.downcast::<InputError>().unwrap();
+    assert!(matches!(*sut, InputError::NotFound(_)));
+}
+
+#[test]
+fn snapshots_are_named_from_the_injected_clock() {
+    let dir = ScratchDir::new("clock");
+    let csv_path = OsString::from("tests/resources/basic_example.csv");
+    let config = Config::default().with_snapshots(2, dir.to_path_buf());
+    let start = UNIX_EPOCH + Duration::from_millis(1_700_000_000_000);
+    let clock = StepClock::new(start, Duration::from_secs(1));
+
+    let hooks = RunHooks::default().with_clock(&clock);
+    let sut = process_payments_with_hooks(&csv_path, &config, hooks).unwrap();
+    assert_eq!(
+        sut.snapshots,
+        vec![
+            // the first reading of the clock is the time the input was ingested
+            dir.join("ledger_1700000001000_2.csv"),
+            dir.join("ledger_1700000002000_4.csv"),
+        ]
+    );
+}
+
+#[test]
+fn snapshots_are_written_every_n_transactions() {
+    let dir = ScratchDir::new("snapshots");
+    let csv_path = OsString::from("tests/resources/basic_example.csv");
+    let config = Config::default().with_snapshots(2, dir.to_path_buf());
+
+    let sut = process_payments_report(&csv_path, &config).unwrap();
+    assert_eq!(sut.snapshots.len(), 2);
+    assert!(sut.snapshots.iter().all(|path| path.starts_with(&dir)));
+
+    let first = std::fs::read_to_string(&sut.snapshots[0]).unwrap();
+    let expected = create_csv(vec![
+        ["1", "1.0000", "0.0000", "1.0000", "false"],
+        ["2", "2.0000", "0.0000", "2.0000", "false"],
+    ]);
+    assert_unsorted_eq(&first, &expected);
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn eod_rows_expire_disputes_and_snapshot_each_day() {
+    let dir = ScratchDir::new("eod");
+    let csv_path = OsString::from("tests/resources/end_of_day.csv");
+    let config = Config::default()
+        .with_dispute_expiry_days(2)
+        .with_eod_snapshots(dir.to_path_buf());
+    let start = UNIX_EPOCH + Duration::from_millis(1_700_000_000_000);
+    let clock = StepClock::new(start, Duration::from_secs(1));
+
+    let hooks = RunHooks::default().with_clock(&clock);
+    let sut = process_payments_with_hooks(&csv_path, &config, hooks).unwrap();
+    // the dispute of client 1 expires at the second eod row, that of client 2 is a day younger
+    let expected = create_csv(vec![
+        ["1", "15.0000", "0.0000", "15.0000", "false"],
+        ["2", "0.0000", "4.0000", "4.0000", "false"],
+    ]);
+    assert_unsorted_eq(&sut.output, &expected);
+    assert_eq!(
+        sut.snapshots,
+        vec![
+            dir.join("ledger_1700000001000_3_eod1.csv"),
+            dir.join("ledger_1700000002000_5_eod2.csv"),
+        ]
+    );
+    let first_day = std::fs::read_to_string(&sut.snapshots[0]).unwrap();
+    let expected = create_csv(vec![["1", "10.0000", "5.0000", "15.0000", "false"]]);
+    assert_eq!(first_day, expected);
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn reports_replay_disputes_expiring_at_eod_rows() {
+    let csv_path = OsString::from("tests/resources/dispute_expiry.csv");
+    let config = Config::default()
+        .with_dispute_expiry_days(1)
+        .with_collect_metrics(true);
+    let sut = process_payments_report(&csv_path, &config).unwrap();
+    let expected = create_csv(vec![["1", "5.0000", "0.0000", "5.0000", "false"]]);
+    assert_eq!(sut.output, expected);
+    // the withdrawal is made from funds released by the expired dispute
+    assert_eq!(
+        sut.metrics.unwrap().withdrawals,
+        TypeMetrics {
+            applied: 1,
+            applied_amount: Decimal::new(5, 0),
+            ignored: 0,
+            ignored_amount: Decimal::ZERO,
+        }
+    );
+
+    let statement = client_statement(&csv_path, key(1), StatementFormat::Csv, &config).unwrap();
+    assert_eq!(
+        statement.lines().last(),
+        Some("4,withdrawal,2,5.0000,5.0000,0.0000,5.0000,false,")
+    );
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn warnings_report_soft_issues_when_enabled() {
+    let csv_path = OsString::from("tests/resources/warnings.csv");
+    let config = Config::default().with_collect_warnings(true);
+    let sut = process_payments_report(&csv_path, &config).unwrap();
+    assert_eq!(
+        sut.warnings,
+        vec![
+            Warning {
+                row: 2,
+                client: key(1),
+                tx: 1,
+                kind: WarningKind::DuplicateTxId,
+            },
+            Warning {
+                row: 3,
+                client: key(1),
+                tx: 9,
+                kind: WarningKind::IgnoredDisputeOperation(DisputeOperation::Dispute),
+            },
+            Warning {
+                row: 4,
+                client: key(1),
+                tx: 1,
+                kind: WarningKind::IgnoredDisputeOperation(DisputeOperation::Resolve),
+            },
+            Warning {
+                row: 8,
+                client: key(2),
+                tx: 3,
+                kind: WarningKind::LockedAccountActivity,
+            },
+        ]
+    );
+
+    let sut = process_payments_report(&csv_path, &Config::default()).unwrap();
+    assert!(sut.warnings.is_empty());
+}
+
+#[test]
+fn suspect_amounts_are_warned_of_or_rejected() {
+    let csv_path = OsString::from("tests/resources/suspect_amounts.csv");
+    let config = Config::default()
+        .with_check_amounts(true)
+        .with_deposit_cap(Decimal::new(10000, 0));
+    let sut = process_payments_report(&csv_path, &config).unwrap();
+    let expected = create_csv(vec![
+        ["1", "999600.0000", "0.0000", "999600.0000", "false"],
+        ["2", "0.0000", "0.0000", "0.0000", "false"],
+    ]);
+    assert_unsorted_eq(&sut.output, &expected);
+    assert_eq!(
+        sut.warnings
+            .iter()
+            .map(|w| (w.row, w.kind.clone()))
+            .collect::<Vec<_>>(),
+        vec![
+            (2, WarningKind::WithdrawalExceedsDeposits),
+            (3, WarningKind::DepositOverCap),
+            (4, WarningKind::WithdrawalExceedsDeposits),
+        ]
+    );
+
+    let sut =
+        process_payments_report(&csv_path, &config.with_reject_suspect_amounts(true)).unwrap();
+    let expected = create_csv(vec![
+        ["1", "100.0000", "0.0000", "100.0000", "false"],
+        ["2", "0.0000", "0.0000", "0.0000", "false"],
+    ]);
+    assert_unsorted_eq(&sut.output, &expected);
+    assert!(sut.warnings.is_empty());
+    assert_eq!(
+        sut.rejected_rows.iter().map(|r| r.row).collect::<Vec<_>>(),
+        vec![2, 3, 4]
+    );
+    assert!(sut
+        .rejected_rows
+        .iter()
+        .all(|r| r.reason == RowRejection::SuspectAmount));
+}
+
+#[test]
+fn reconcile_reports_mismatches_beyond_tolerance() {
+    let ledger_path = OsString::from("tests/resources/reconcile_ledger.csv");
+    let expected_path = OsString::from("tests/resources/reconcile_expected.csv");
+    let sut = reconcile(&ledger_path, &expected_path, Decimal::new(1, 3)).unwrap();
+    assert_eq!(
+        sut,
+        vec![
+            Mismatch {
+                client: key(2),
+                kind: MismatchKind::Balance {
+                    column: "available",
+                    expected: Decimal::new(25, 1),
+                    actual: Decimal::new(2, 0),
+                },
+            },
+            Mismatch {
+                client: key(2),
+                kind: MismatchKind::Balance {
+                    column: "total",
+                    expected: Decimal::new(25, 1),
+                    actual: Decimal::new(2, 0),
+                },
+            },
+            Mismatch {
+                client: key(3),
+                kind: MismatchKind::MissingFromExpected,
+            },
+            Mismatch {
+                client: key(4),
+                kind: MismatchKind::MissingFromLedger,
+            },
+        ]
+    );
+
+    let sut = reconcile(&ledger_path, &expected_path, Decimal::ZERO).unwrap();
+    assert_eq!(sut.len(), 6);
+    let rounding: Vec<_> = sut
+        .iter()
+        .filter(|m| m.category() == MismatchCategory::Rounding)
+        .map(|m| m.client.to_owned())
+        .collect();
+    assert_eq!(rounding, vec![key(1), key(1)]);
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn system_accounts_total_losses_float_and_rejected_withdrawal_liability() {
+    let csv_path = OsString::from("tests/resources/system_accounts.csv");
+    let sut = process_payments_report(&csv_path, &Config::default())
+        .unwrap()
+        .system_accounts;
+    assert_eq!(
+        sut,
+        SystemAccounts {
+            chargeback_losses: Decimal::new(5, 0),
+            held: Decimal::new(95, 1),
+            rejected_withdrawal_liability: Decimal::new(3, 0),
+        }
+    );
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn metrics_count_applied_and_ignored_transactions_by_type() {
+    let config = Config::default().with_collect_metrics(true);
+    let csv_path = OsString::from("tests/resources/warnings.csv");
+    let sut = process_payments_report(&csv_path, &config)
+        .unwrap()
+        .metrics
+        .unwrap();
+    assert_eq!(
+        sut,
+        Metrics {
+            deposits: TypeMetrics {
+                applied: 3,
+                applied_amount: Decimal::new(18, 0),
+                ignored: 1,
+                ignored_amount: Decimal::new(1, 0),
+            },
+            withdrawals: TypeMetrics::default(),
+            disputes: TypeMetrics {
+                applied: 1,
+                applied_amount: Decimal::new(3, 0),
+                ignored: 1,
+                ignored_amount: Decimal::ZERO,
+            },
+            resolves: TypeMetrics {
+                applied: 0,
+                applied_amount: Decimal::ZERO,
+                ignored: 1,
+                ignored_amount: Decimal::new(5, 0),
+            },
+            chargebacks: TypeMetrics {
+                applied: 1,
+                applied_amount: Decimal::new(3, 0),
+                ignored: 0,
+                ignored_amount: Decimal::ZERO,
+            },
+            chargeback_reversals: TypeMetrics::default(),
+            holds: TypeMetrics::default(),
+            releases: TypeMetrics::default(),
+        }
+    );
+
+    let csv_path = OsString::from("tests/resources/basic_example.csv");
+    let sut = process_payments_report(&csv_path, &config)
+        .unwrap()
+        .metrics
+        .unwrap();
+    assert_eq!(
+        sut.withdrawals,
+        TypeMetrics {
+            applied: 1,
+            applied_amount: Decimal::new(15, 1),
+            ignored: 1,
+            ignored_amount: Decimal::new(3, 0),
+        }
+    );
+
+    let sut = process_payments_report(&csv_path, &Config::default()).unwrap();
+    assert!(sut.metrics.is_none());
+}
+
+struct WithdrawalLimit {
+    limit: MonetaryAmount,
+    applied: Rc<Cell<usize>>,
+}
+
+impl TxMiddleware for WithdrawalLimit {
+    fn before(&mut self, transaction: &Transaction, _state: &ClientState) -> bool {
+        !matches!(
+            transaction,
+            Transaction::Activity(AccountActivity::Withdrawal(_, _, amount)) if *amount > self.limit
+        )
+    }
+
+    fn after(&mut self, _transaction: &Transaction, _before: &ClientState, _after: &ClientState) {
+        self.applied.set(self.applied.get() + 1);
+    }
+}
+
+#[test]
+fn middleware_can_veto_and_observe_transactions() {
+    let csv_path = OsString::from("tests/resources/basic_example.csv");
+    let applied = Rc::new(Cell::new(0));
+    let mut middleware: Vec<Box<dyn TxMiddleware>> = vec![Box::new(WithdrawalLimit {
+        limit: MonetaryAmount::new(1.0),
+        applied: Rc::clone(&applied),
+    })];
+    let sut = process_payments_with_hooks(
+        &csv_path,
+        &Config::default(),
+        RunHooks::default().with_middleware(&mut middleware),
+    )
+    .unwrap();
+    let expected = create_csv(vec![
+        ["1", "3.0000", "0.0000", "3.0000", "false"],
+        ["2", "2.0000", "0.0000", "2.0000", "false"],
+    ]);
+    assert_unsorted_eq(&sut.output, &expected);
+    // both withdrawals exceed the limit and are vetoed
+    assert_eq!(applied.get(), 3);
+}
+
+#[test]
+fn amounts_are_rounded_half_to_even_for_output() {
+    let csv_path = OsString::from("tests/resources/amount_scale.csv");
+    let sut = process_payments_report(&csv_path, &Config::default()).unwrap();
+    let expected = create_csv(vec![
+        ["1", "1.2346", "0.0000", "1.2346", "false"],
+        ["2", "2.0000", "0.0000", "2.0000", "false"],
+        ["3", "0.0002", "0.0000", "0.0002", "false"],
+    ]);
+    assert_unsorted_eq(&sut.output, &expected);
+    assert_eq!(sut.rounding_difference, Decimal::new(4, 5));
+}
+
+#[test]
+fn amounts_keep_their_scale_when_preserved() {
+    let csv_path = OsString::from("tests/resources/amount_scale.csv");
+    let config = Config::default().with_preserve_scale(true);
+    let sut = process_payments_report(&csv_path, &config).unwrap();
+    let expected = create_csv(vec![
+        ["1", "1.23456", "0.0000", "1.23456", "false"],
+        ["2", "2.00005", "0.0000", "2.00005", "false"],
+        ["3", "0.00015", "0.0000", "0.00015", "false"],
+    ]);
+    assert_unsorted_eq(&sut.output, &expected);
+    assert!(sut.rounding_difference.is_zero());
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn frozen_accounts_report_the_locking_chargeback() {
+    let csv_path = OsString::from("tests/resources/warnings.csv");
+    let config = Config::default().with_report_frozen_accounts(true);
+    let sut = process_payments_report(&csv_path, &config).unwrap();
+    assert_eq!(
+        sut.frozen_accounts,
+        vec![FrozenAccount {
+            client: key(2),
+            tx: 2,
+            row: 7,
+            amount: Decimal::new(3, 0),
+        }]
+    );
+}
+
+struct SourceRecorder {
+    sources: Rc<RefCell<Vec<SourceMetadata>>>,
+}
+
+impl TxMiddleware for SourceRecorder {
+    fn source(&mut self, metadata: &SourceMetadata) {
+        self.sources.borrow_mut().push(metadata.clone());
+    }
+}
+
+#[test]
+fn middleware_is_given_the_source_of_each_transaction() {
+    let csv_path = OsString::from("tests/resources/basic_example.csv");
+    let sources = Rc::new(RefCell::new(Vec::new()));
+    let mut middleware: Vec<Box<dyn TxMiddleware>> = vec![Box::new(SourceRecorder {
+        sources: Rc::clone(&sources),
+    })];
+    let ingested_at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let clock = FixedClock(ingested_at);
+    let hooks = RunHooks::default()
+        .with_middleware(&mut middleware)
+        .with_clock(&clock);
+    process_payments_with_hooks(&csv_path, &Config::default(), hooks).unwrap();
+    let sources = sources.borrow();
+    assert_eq!(
+        sources.iter().map(|s| s.row).collect::<Vec<_>>(),
+        vec![1, 2, 3, 4, 5]
+    );
+    assert!(sources
+        .iter()
+        .all(|s| s.ingested_at == ingested_at && *s.source == *Path::new(&csv_path)));
+}
+
+#[test]
+fn config_is_read_from_toml() {
+    let sut = Config::from_toml_file(&OsString::from("tests/resources/engine.toml")).unwrap();
+    assert_eq!(sut.schema, OutputSchema::V2);
+    assert_eq!(sut.dispute_policy, DisputePolicy::HoldAvailable);
+    assert_eq!(sut.opening_balance, Decimal::new(10, 0));
+    assert!(sut.show_pending_withdrawals);
+    assert_eq!(sut.max_rows, Some(1000));
+    assert_eq!(sut.max_clients, None);
+}
+
+#[test]
+fn environment_variables_override_config_file() {
+    let vars = [
+        ("PAYMENTS_DISPUTE_POLICY", "reject"),
+        ("PAYMENTS_MAX_ROWS", "5"),
+        ("PAYMENTS_OUTPUT__DELIMITER", ";"),
+        ("PAYMENTS_RETRY__ON_DEPOSIT", "true"),
+        ("OTHER_MAX_CLIENTS", "1"),
+    ]
+    .map(|(k, v)| (k.to_owned(), v.to_owned()));
+    let (sut, ignored) =
+        Config::from_toml_file_with_env(Some(&OsString::from("tests/resources/engine.toml")), vars)
+            .unwrap();
+    assert!(ignored.is_empty());
+    assert_eq!(sut.schema, OutputSchema::V2);
+    assert_eq!(sut.dispute_policy, DisputePolicy::Reject);
+    assert_eq!(sut.max_rows, Some(5));
+    assert_eq!(sut.max_clients, None);
+    assert_eq!(sut.output.delimiter, ';');
+    assert!(sut.retry.on_deposit);
+
+    // variables naming no option, e.g. of another service, are ignored rather than failing the run
+    let stray = [
+        ("PAYMENTS_SERVICE_HOST", "10.0.0.1"),
+        ("PAYMENTS_DISPUTE_POLCY", "reject"),
+        ("PAYMENTS_MAX_ROWS", "5"),
+    ]
+    .map(|(k, v)| (k.to_owned(), v.to_owned()));
+    let (sut, ignored) = Config::from_toml_with_env("", stray).unwrap();
+    assert_eq!(sut.max_rows, Some(5));
+    assert_eq!(sut.dispute_policy, DisputePolicy::default());
+    assert_eq!(
+        ignored,
+        vec!["PAYMENTS_SERVICE_HOST", "PAYMENTS_DISPUTE_POLCY"]
+    );
+    // unknown keys in the config file still are
+    assert!(Config::from_toml_with_env("dispute_polcy = \"reject\"", []).is_err());
+}
+
+#[test]
+fn unknown_config_keys_are_rejected() {
+    assert!(Config::from_toml("dispute_polcy = \"reject\"").is_err());
+}
+
+#[test]
+fn admin_holds_move_funds_to_held_until_released() {
+    let csv_path = OsString::from("tests/resources/admin_holds.csv");
+    // ignored holds and releases of unknown clients open no account
+    let sut = process_payments(&csv_path).unwrap();
+    let expected = create_csv(vec![
+        ["1", "6.0000", "4.0000", "10.0000", "false"],
+        ["2", "5.0000", "0.0000", "5.0000", "false"],
+    ]);
+    assert_unsorted_eq(&sut, &expected);
+
+    let config = Config::default().with_collect_metrics(true);
+    let metrics = process_payments_report(&csv_path, &config)
+        .unwrap()
+        .metrics
+        .unwrap();
+    assert_eq!(
+        metrics.releases,
+        TypeMetrics {
+            applied: 1,
+            applied_amount: Decimal::new(2, 0),
+            ignored: 2,
+            ignored_amount: Decimal::ZERO,
+        }
+    );
+}
+
+#[test]
+fn output_uses_configured_delimiter_and_decimal_separator() {
+    let config = Config::default().with_output_options(
+        OutputOptions::default()
+            .with_delimiter(';')
+            .with_decimal_separator(','),
+    );
+    let sut = process_payments_with_config(
+        &OsString::from("tests/resources/basic_example.csv"),
+        &config,
+    )
+    .unwrap();
+    let mut lines: Vec<&str> = sut.lines().collect();
+    lines.sort();
+    assert_eq!(
+        lines,
+        vec![
+            "1;1,5000;0,0000;1,5000;false",
+            "2;2,0000;0,0000;2,0000;false",
+            "client;available;held;total;locked",
+        ]
+    );
+
+    let config =
+        Config::from_toml("[output]\ndelimiter = \";\"\ndecimal_separator = \",\"").unwrap();
+    assert_eq!(
+        config.output,
+        OutputOptions::default()
+            .with_delimiter(';')
+            .with_decimal_separator(',')
+    );
+}
+
+#[test]
+fn output_can_be_an_aligned_table() {
+    let config = Config::default()
+        .with_output_options(OutputOptions::default().with_format(OutputFormat::Table));
+    let sut = process_payments_with_config(
+        &OsString::from("tests/resources/withdraw_over_avail.csv"),
+        &config,
+    )
+    .unwrap();
+    let expected = "\
+client  available    held    total  locked
+------  ---------  ------  -------  ------
+     1    20.0000  0.0000  20.0000  false
+";
+    assert_eq!(sut, expected);
+}
+
+#[test]
+fn unparseable_rows_abort_processing_without_a_quarantine_file() {
+    let csv_path = OsString::from("tests/resources/corrupted_amounts.csv");
+    let err = process_payments(&csv_path).err().unwrap();
+    let row = err.downcast_ref::<UnparseableRow>().unwrap();
+    assert_eq!(row.line, 3);
+    assert_eq!(
+        row.field,
+        Some(FieldError {
+            column: "amount".to_owned(),
+            value: "1 0.5".to_owned(),
+            expected: "a decimal amount",
+        })
+    );
+}
+
+#[test]
+fn unparseable_rows_are_quarantined_and_processing_continues() {
+    let csv_path = OsString::from("tests/resources/corrupted_amounts.csv");
+    let dir = ScratchDir::new("quarantine");
+    let quarantine_path = dir.join("quarantine.csv");
+    let config = Config::default().with_quarantine_file(quarantine_path.clone());
+    let sut = process_payments_report(&csv_path, &config).unwrap();
+
+    let expected = create_csv(vec![["1", "8.0000", "0.0000", "8.0000", "false"]]);
+    assert_eq!(sut.output, expected);
+    assert_eq!(
+        sut.quarantined_rows
+            .iter()
+            .map(|r| (r.line, r.raw.as_str()))
+            .collect::<Vec<_>>(),
+        vec![(3, "deposit, 1, 2,  1 0.5"), (4, "deposit, 1, 3, 1_000")]
+    );
+
+    let quarantine = std::fs::read_to_string(&quarantine_path).unwrap();
+    let lines: Vec<&str> = quarantine.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], "line,column,value,expected,reason,row");
+    assert!(lines[1].starts_with("3,amount,1 0.5,a decimal amount,"));
+    assert!(lines[1].ends_with(",\"deposit, 1, 2,  1 0.5\""));
+}
+
+#[test]
+fn a_truncated_last_row_gives_a_partial_ledger_of_the_rows_before_it() {
+    let csv_path = OsString::from("tests/resources/truncated.csv");
+    let sut = process_payments_report(&csv_path, &Config::default()).unwrap();
+
+    let expected = create_csv(vec![
+        ["1", "3.5000", "0.0000", "3.5000", "false"],
+        ["2", "3.0000", "0.0000", "3.0000", "false"],
+    ]);
+    assert_unsorted_eq(&sut.output, &expected);
+    let truncation = sut.truncation.unwrap();
+    assert_eq!((truncation.byte_offset, truncation.line), (73, 5));
+    assert_eq!(
+        truncation.to_string(),
+        "input truncated at byte 73 (line 5): found record with 3 fields, but the header has 4 fields"
+    );
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn dispute_operations_on_unknown_transactions_are_dead_lettered() {
+    let csv_path = OsString::from("tests/resources/dead_letters.csv");
+    let dir = ScratchDir::new("dead_letters");
+    let dead_letter_path = dir.join("dead_letters.csv");
+    let config = Config::default().with_dead_letter_file(dead_letter_path.clone());
+    let sut = process_payments_report(&csv_path, &config).unwrap();
+
+    // a resolve of an undisputed deposit and a dispute of a withdrawal refer to known transactions
+    assert_eq!(
+        sut.dead_letters
+            .iter()
+            .map(|d| (d.row, d.operation, d.tx))
+            .collect::<Vec<_>>(),
+        vec![
+            (2, DisputeOperation::Dispute, 9),
+            (6, DisputeOperation::Chargeback, 5)
+        ]
+    );
+
+    let dead_letters = std::fs::read_to_string(&dead_letter_path).unwrap();
+    assert_eq!(
+        dead_letters,
+        "row,type,client,tx,reason\n\
+         2,dispute,1,9,unknown transaction\n\
+         6,chargeback,2,5,unknown transaction\n"
+    );
+}
+
+#[test]
+fn manifest_verifies_ledger_and_input_until_modified() {
+    let dir = ScratchDir::new("manifest");
+    let csv_path = OsString::from("tests/resources/basic_example.csv");
+    let manifest_path = dir.join("manifest.toml");
+    let ledger_path = dir.join("ledger.csv");
+    let config = Config::default().with_manifest_file(manifest_path.clone());
+    let report = process_payments_report(&csv_path, &config).unwrap();
+    assert!(report.manifest.unwrap().signature.is_none());
+
+    // rows in a different order are the same ledger
+    let mut lines: Vec<&str> = report.output.lines().collect();
+    lines[1..].reverse();
+    std::fs::write(&ledger_path, lines.join("\r\n")).unwrap();
+    let sut = verify_manifest(
+        &ledger_path.clone().into(),
+        &manifest_path.clone().into(),
+        Some(&csv_path),
+        None,
+    )
+    .unwrap();
+    assert_eq!(sut, vec![]);
+    // a trusted key requires a signature
+    let public_key_path = dir.join("trusted.pub");
+    std::fs::write(&public_key_path, "ab".repeat(32)).unwrap();
+    let sut = verify_manifest(
+        &ledger_path.clone().into(),
+        &manifest_path.clone().into(),
+        None,
+        Some(&public_key_path.into()),
+    )
+    .unwrap();
+    assert_eq!(sut, vec![VerificationFailure::MissingSignature]);
+
+    std::fs::write(&ledger_path, report.output.replace("1.5000", "1.6000")).unwrap();
+    let sut = verify_manifest(
+        &ledger_path.into(),
+        &manifest_path.into(),
+        Some(&OsString::from("tests/resources/withdraw_over_avail.csv")),
+        None,
+    )
+    .unwrap();
+    assert_eq!(
+        sut,
+        vec![
+            VerificationFailure::OutputModified,
+            VerificationFailure::InputModified
+        ]
+    );
+}
+
+#[test]
+fn strict_ordering_reports_the_first_out_of_order_tx() {
+    let config = Config::default().with_strict_ordering(true);
+    let ordered = OsString::from("tests/resources/basic_example.csv");
+    assert!(process_payments_report(&ordered, &config).is_ok());
+
+    let csv_path = OsString::from("tests/resources/warnings.csv");
+    let err = process_payments_report(&csv_path, &config).err().unwrap();
+    assert_eq!(
+        *err.downcast::<OrderingViolation>().unwrap(),
+        OrderingViolation {
+            tx: 1,
+            row: 2,
+            previous_tx: 1,
+            previous_row: 1,
+        }
+    );
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn activity_lists_transactions_in_range_across_clients() {
+    let sut = tx_range_activity(
+        &OsString::from("tests/resources/dead_letters.csv"),
+        2..=5,
+        &Config::default(),
+    )
+    .unwrap();
+    let expected = create_csv_with_header(
+        &[
+            "row",
+            "client",
+            "type",
+            "tx",
+            "amount",
+            "decision",
+            "available",
+            "held",
+            "total",
+            "locked",
+            "note",
+        ],
+        vec![
+            vec![
+                "4",
+                "1",
+                "withdrawal",
+                "2",
+                "3.0000",
+                "debit",
+                "7.0000",
+                "0.0000",
+                "7.0000",
+                "false",
+                "",
+            ],
+            vec![
+                "5",
+                "1",
+                "dispute",
+                "2",
+                "",
+                "ignore: not a deposit",
+                "7.0000",
+                "0.0000",
+                "7.0000",
+                "false",
+                "ignored",
+            ],
+            vec![
+                "6",
+                "2",
+                "chargeback",
+                "5",
+                "",
+                "ignore: unknown transaction",
+                "0.0000",
+                "0.0000",
+                "0.0000",
+                "false",
+                "ignored",
+            ],
+        ],
+    );
+    assert_eq!(sut, expected);
+}
+
+#[test]
+fn metadata_records_version_policy_counts_and_time_of_run() {
+    let csv_path = OsString::from("tests/resources/missing_amounts.csv");
+    let dir = ScratchDir::new("metadata");
+    let metadata_path = dir.join("metadata.toml");
+    let config = Config::default()
+        .with_dispute_policy(DisputePolicy::HoldAvailable)
+        .with_metadata_file(metadata_path.clone());
+    let clock = FixedClock(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+    let hooks = RunHooks::default().with_clock(&clock);
+    let report = process_payments_with_hooks(&csv_path, &config, hooks).unwrap();
+
+    let written = std::fs::read_to_string(&metadata_path).unwrap();
+    let sut: RunMetadata = toml::from_str(&written).unwrap();
+    assert_eq!(report.metadata, Some(sut.clone()));
+    assert_eq!(sut.engine_version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(sut.generated_at, 1_700_000_000);
+    assert_eq!(sut.input_file, "tests/resources/missing_amounts.csv");
+    assert_eq!(sut.input_sha256.len(), 64);
+    assert_eq!(
+        (
+            sut.rows,
+            sut.transactions,
+            sut.rejected_rows,
+            sut.quarantined_rows,
+            sut.clients
+        ),
+        (5, 2, 3, 0, 1)
+    );
+    assert_eq!(
+        sut.policy,
+        Policy::default().with_dispute_policy(DisputePolicy::HoldAvailable)
+    );
+}
+
+#[cfg(not(feature = "string-client-ids"))]
+#[test]
+fn partitions_split_the_ledger_by_client_id_range() {
+    let dir = ScratchDir::new("partitions");
+    let csv_path = OsString::from("tests/resources/client_order.csv");
+    let config = Config::default().with_partitions(3, dir.to_path_buf());
+    let report = process_payments_report(&csv_path, &config).unwrap();
+
+    let parts: Vec<String> = report
+        .partitions
+        .iter()
+        .map(|path| std::fs::read_to_string(path).unwrap())
+        .collect();
+    let manifest = std::fs::read_to_string(dir.join("manifest.toml")).unwrap();
+    assert!(report.output.is_empty());
+    assert_eq!(
+        report.partitions,
+        vec![
+            dir.join("part-000.csv"),
+            dir.join("part-001.csv"),
+            dir.join("part-002.csv")
+        ]
+    );
+    assert_eq!(
+        parts,
+        vec![
+            create_csv(vec![
+                ["1", "1.0000", "0.0000", "1.0000", "false"],
+                ["2", "2.0000", "0.0000", "2.0000", "false"],
+            ]),
+            create_csv(vec![["3", "3.0000", "0.0000", "3.0000", "false"]]),
+            create_csv(vec![["10", "10.0000", "0.0000", "10.0000", "false"]]),
+        ]
+    );
+
+    let sut: toy_payments_lib::PartitionManifest = toml::from_str(&manifest).unwrap();
+    assert_eq!(
+        sut.partitions
+            .iter()
+            .map(|p| (p.file.as_str(), p.clients, p.first_client, p.last_client))
+            .collect::<Vec<_>>(),
+        vec![
+            ("part-000.csv", 2, Some(1), Some(2)),
+            ("part-001.csv", 1, Some(3), Some(3)),
+            ("part-002.csv", 1, Some(10), Some(10)),
+        ]
+    );
+    assert!(sut.partitions.iter().all(|p| p.sha256.len() == 64));
+}
+
+#[test]
+fn rows_of_unregistered_clients_are_rejected() {
+    let config = Config::default().with_registered_clients([key(1)]);
+    let sut = process_payments_report(
+        &OsString::from("tests/resources/registered_clients.csv"),
+        &config,
+    )
+    .unwrap();
+    let expected = create_csv(vec![["1", "12.0000", "0.0000", "12.0000", "false"]]);
+    assert_eq!(sut.output, expected);
+    assert_eq!(
+        sut.rejected_rows
+            .iter()
+            .map(|r| (r.row, r.reason.clone()))
+            .collect::<Vec<_>>(),
+        vec![
+            (2, RowRejection::UnregisteredClient),
+            (3, RowRejection::UnregisteredClient),
+            (5, RowRejection::UnregisteredClient),
+        ]
+    );
+}
+
+#[test]
+fn withdrawals_may_overdraw_up_to_the_clients_overdraft_limit() {
+    let dir = ScratchDir::new("overdraft_limits");
+    let config = Config::from_toml("check_amounts = true\n[overdraft_limits]\n1 = 50.0")
+        .unwrap()
+        .with_collect_metrics(true)
+        .with_metadata_file(dir.join("metadata.toml"));
+    assert_eq!(
+        config.overdraft_limits,
+        BTreeMap::from([(key(1), Decimal::new(50, 0))])
+    );
+    let sut = process_payments_report(
+        &OsString::from("tests/resources/overdraft_limits.csv"),
+        &config,
+    )
+    .unwrap();
+    // the second withdrawal of client 1 would exceed the limit, client 2 has no overdraft
+    let expected = create_csv(vec![
+        ["1", "-40.0000", "0.0000", "-40.0000", "false"],
+        ["2", "10.0000", "0.0000", "10.0000", "false"],
+    ]);
+    assert_unsorted_eq(&sut.output, &expected);
+    // withdrawals within the overdraft are not suspect
+    assert_eq!(
+        sut.warnings
+            .iter()
+            .map(|w| (w.row, w.kind.clone()))
+            .collect::<Vec<_>>(),
+        vec![(5, WarningKind::WithdrawalExceedsDeposits)]
+    );
+    // reports agree with the ledger on which withdrawals applied
+    assert_eq!(
+        sut.metrics.unwrap().withdrawals,
+        TypeMetrics {
+            applied: 1,
+            applied_amount: Decimal::new(50, 0),
+            ignored: 2,
+            ignored_amount: Decimal::new(40, 0),
+        }
+    );
+    // and the metadata records the limits which governed the run
+    let written = std::fs::read_to_string(dir.join("metadata.toml")).unwrap();
+    let metadata: RunMetadata = toml::from_str(&written).unwrap();
+    assert_eq!(metadata.policy.overdraft_limits, config.overdraft_limits);
+}
+
+#[test]
+fn client_flags_are_output_and_under_review_blocks_withdrawals() {
+    let client_flags =
+        read_client_flags(&OsString::from("tests/resources/client_flags.csv")).unwrap();
+    let config = Config::default().with_client_flags(client_flags);
+    let sut = process_payments_with_config(
+        &OsString::from("tests/resources/flagged_clients.csv"),
+        &config,
+    )
+    .unwrap();
+    let expected = create_csv_with_header(
+        &["client", "available", "held", "total", "locked", "flags"],
+        vec![
+            vec![
+                "1",
+                "10.0000",
+                "0.0000",
+                "10.0000",
+                "false",
+                "under_review;vip",
+            ],
+            vec!["2", "5.0000", "0.0000", "5.0000", "false", "vip"],
+            vec!["3", "1.0000", "0.0000", "1.0000", "false", ""],
+        ],
+    );
+    assert_eq!(sut, expected);
+}
+
+#[test]
+fn ledger_is_also_written_to_the_output_file() {
+    let dir = ScratchDir::new("output");
+    let output_path = dir.join("output.csv");
+    let config = Config::default().with_output_file(output_path.clone());
+    let sut = process_payments_with_config(
+        &OsString::from("tests/resources/basic_example.csv"),
+        &config,
+    )
+    .unwrap();
+    let written = std::fs::read_to_string(&output_path).unwrap();
+    assert_eq!(written, sut);
+}
+
+#[cfg(not(feature = "object-store"))]
+#[test]
+fn object_store_urls_need_the_object_store_feature() {
+    let err = process_payments(&OsString::from("s3://bucket/transactions.csv")).unwrap_err();
+    let sut = err.downcast::<InputError>().unwrap();
+    assert!(
+        matches!(*sut, InputError::Remote(ref url, _) if url == "s3://bucket/transactions.csv")
+    );
+}
+
+#[cfg(not(feature = "fault-injection"))]
+#[test]
+fn faults_need_the_fault_injection_feature() {
+    let csv_path = OsString::from("tests/resources/basic_example.csv");
+    let config = Config::default()
+        .with_faults(toy_payments_lib::FaultInjection::default().with_io_error_every(1));
+    let err = process_payments_with_config(&csv_path, &config).unwrap_err();
+    assert!(err.to_string().contains("fault-injection feature"));
+}
+
+#[test]
+fn dropbox_files_are_processed_checkpointed_and_moved_once() {
+    let dir = ScratchDir::new("dropbox");
+    std::fs::copy("tests/resources/basic_example.csv", dir.join("a.csv")).unwrap();
+    let rows = std::fs::read_to_string("tests/resources/basic_example.csv").unwrap();
+    std::fs::write(dir.join("b.csv"), rows.clone() + &rows).unwrap();
+    let config = Config::default().with_max_rows(6);
+    let clock = FixedClock(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+
+    let first = process_dropbox(&dir, &config, &clock).unwrap();
+    let output = std::fs::read_to_string(dir.join("output").join("a.csv")).unwrap();
+    let checkpoint = read_checkpoint(&dir).unwrap();
+    // Dropping the same file again, as after a crash before it was moved, does not reprocess it
+    std::fs::copy("tests/resources/basic_example.csv", dir.join("a.csv")).unwrap();
+    let second = process_dropbox(&dir, &config, &clock).unwrap();
+    let done = dir.join("done").join("a.csv").exists();
+    let failed = dir.join("failed").join("b.csv").exists();
+
+    assert_eq!(
+        first[0],
+        DropboxOutcome::Processed(String::from("a.csv"), dir.join("output").join("a.csv"))
+    );
+    assert!(matches!(&first[1], DropboxOutcome::Failed(file, _) if file == "b.csv"));
+    assert_eq!(
+        output,
+        process_payments(&OsString::from("tests/resources/basic_example.csv")).unwrap()
+    );
+    assert_eq!(checkpoint.processed.len(), 1);
+    assert_eq!(checkpoint.processed[0].file, "a.csv");
+    assert_eq!(checkpoint.processed[0].processed_at, 1_700_000_000);
+    assert_eq!(
+        second,
+        vec![DropboxOutcome::AlreadyProcessed(String::from("a.csv"))]
+    );
+    assert!(done && failed);
+}
+
+#[test]
+fn dropbox_files_write_their_own_outputs_and_failing_files_do_not_stop_the_rest() {
+    let dir = ScratchDir::new("dropbox_per_file");
+    let reports = ScratchDir::new("dropbox_per_file_reports");
+    std::fs::copy("tests/resources/basic_example.csv", dir.join("a.csv")).unwrap();
+    std::fs::copy("tests/resources/dispute_expiry.csv", dir.join("b.csv")).unwrap();
+    let config = Config::default()
+        .with_output_file(reports.join("ledger.csv"))
+        .with_metadata_file(reports.join("metadata.toml"));
+    let clock = FixedClock(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+
+    // a file in place of the done directory fails moving each file there
+    std::fs::write(dir.join("done"), "").unwrap();
+    let sut = process_dropbox(&dir, &config, &clock).unwrap();
+
+    assert!(
+        matches!(&sut[..], [DropboxOutcome::Failed(a, _), DropboxOutcome::Failed(b, _)]
+            if a == "a.csv" && b == "b.csv"),
+        "{:?}",
+        sut
+    );
+    assert!(dir.join("failed").join("a.csv").exists());
+    assert!(dir.join("failed").join("b.csv").exists());
+    for name in [
+        "ledger_a.csv",
+        "ledger_b.csv",
+        "metadata_a.toml",
+        "metadata_b.toml",
+    ] {
+        assert!(reports.join(name).exists(), "{}", name);
+    }
+    assert_eq!(
+        std::fs::read_to_string(reports.join("ledger_b.csv")).unwrap(),
+        process_payments(&OsString::from("tests/resources/dispute_expiry.csv")).unwrap()
+    );
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn transactions_beyond_client_history_cap_apply_but_cannot_be_disputed() {
+    let csv_path = OsString::from("tests/resources/history_cap.csv");
+    let config = Config::default()
+        .with_max_client_history(2)
+        .with_collect_warnings(true);
+    let sut = process_payments_report(&csv_path, &config).unwrap();
+    assert_eq!(
+        sut.output,
+        create_csv(vec![["1", "5.0000", "1.0000", "6.0000", "false"]])
+    );
+    assert_eq!(
+        sut.warnings,
+        vec![
+            Warning {
+                row: 3,
+                client: key(1),
+                tx: 3,
+                kind: WarningKind::HistoryFull,
+            },
+            Warning {
+                row: 4,
+                client: key(1),
+                tx: 3,
+                kind: WarningKind::IgnoredDisputeOperation(DisputeOperation::Dispute),
+            },
+        ]
+    );
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn activity_counts_include_transactions_beyond_client_history_cap() {
+    let csv_path = OsString::from("tests/resources/history_cap.csv");
+    let config = Config::default().with_max_client_history(2);
+    let (ledger, _) = process_transactions_detailed(&csv_path, &config).unwrap();
+    let client = &ledger.0[0];
+    assert_eq!(
+        client.counts,
+        ActivityCounts {
+            deposits: 3,
+            withdrawals: 0,
+            disputes: 1,
+            chargebacks: 0,
+        }
+    );
+    assert_eq!(client.transactions, 3);
+    assert_eq!(client.open_disputes, 1);
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn client_history_cap_bounds_retried_operations_and_queued_withdrawals() {
+    let csv_path = OsString::from("tests/resources/history_cap_logs.csv");
+    let config = Config::default().with_max_client_history(2);
+    let (ledger, _) = process_transactions_detailed(&csv_path, &config).unwrap();
+    let client = &ledger.0[0];
+    // three retries of the dispute, and three withdrawals waiting on its resolution
+    assert_eq!(client.retried_operations.len(), 2);
+    assert_eq!(client.pending_withdrawals, 2);
+    assert_eq!(client.rejected_withdrawals, 3);
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn filter_expressions_select_output_rows() {
+    let csv_path = OsString::from("tests/resources/filter.csv");
+    let filter = "total > 1000 && locked == true".parse().unwrap();
+    let sut = process_payments_report(&csv_path, &Config::default().with_filter(filter)).unwrap();
+    assert_eq!(
+        sut.output,
+        create_csv(vec![["3", "1500.0000", "0.0000", "1500.0000", "true"]])
+    );
+    assert_eq!(sut.system_accounts.chargeback_losses, Decimal::from(1200));
+
+    let filter = "client == 2 || !(available >= 1000 || chargebacks > 0)"
+        .parse()
+        .unwrap();
+    let sut = process_payments_report(&csv_path, &Config::default().with_filter(filter)).unwrap();
+    assert_eq!(
+        sut.output,
+        create_csv(vec![["2", "500.0000", "0.0000", "500.0000", "false"]])
+    );
+}
+
+#[test]
+fn filter_expressions_report_what_cannot_be_parsed() {
+    let err = |filter: &str| filter.parse::<LedgerFilter>().unwrap_err();
+    assert_eq!(
+        err("totl > 1"),
+        "invalid filter `totl > 1`: unknown field `totl`"
+    );
+    assert_eq!(
+        err("locked > true"),
+        "invalid filter `locked > true`: `locked` cannot be compared with true"
+    );
+    assert_eq!(
+        err("(held == 0"),
+        "invalid filter `(held == 0`: missing `)`"
+    );
+    assert_eq!(
+        err("held == 0 total"),
+        "invalid filter `held == 0 total`: unexpected `total`"
+    );
+}
+
+#[cfg(not(feature = "string-client-ids"))]
+#[test]
+fn canonical_csv_orders_clients_by_id_at_output_precision() {
+    let config =
+        Config::default().with_output_options(OutputOptions::default().with_delimiter(';'));
+    let (ledger, _) =
+        process_transactions_detailed(&OsString::from("tests/resources/client_order.csv"), &config)
+            .unwrap();
+    let sut = ledger.canonical_csv().unwrap();
+    let expected = create_csv(vec![
+        ["1", "1.0000", "0.0000", "1.0000", "false"],
+        ["2", "2.0000", "0.0000", "2.0000", "false"],
+        ["3", "3.0000", "0.0000", "3.0000", "false"],
+        ["10", "10.0000", "0.0000", "10.0000", "false"],
+    ]);
+    assert_eq!(sut, expected);
+}
+
+#[cfg(not(feature = "string-client-ids"))]
+#[test]
+fn canonical_ledger_csv_orders_clients_by_id_keeping_columns() {
+    let sut =
+        toy_payments_lib::canonical_ledger_csv("client;available;note\r\n10;1.5;a\r\n2;2.00;b\r\n")
+            .unwrap();
+    assert_eq!(sut, "client;available;note\n2;2.00;b\n10;1.5;a\n");
+}
+
+#[test]
+fn compare_ledgers_ignores_order_and_precision_and_lists_differences() {
+    let actual = String::from(
+        "client,available,held,total,locked\n2,2.0000,0.0000,2.0000,false\n1,1.5000,0.0000,1.5000,false\n3,1.0000,0.0000,1.0000,true\n",
+    );
+    let equal = String::from(
+        "client,available,held,total,locked\n1,1.5,0,1.5,false\n2,2,0,2,false\n3,1,0,1,true\n",
+    );
+    let expected = String::from(
+        "client,available,held,total,locked\n1,1.5,0,1.5,false\n2,1,1,2,false\n4,0,0,0,false\n",
+    );
+
+    assert!(compare_ledgers(&equal, &actual).unwrap().is_empty());
+    let sut = compare_ledgers(&expected, &actual).unwrap();
+    assert_eq!(
+        sut.to_string(),
+        [
+            "client 2 available: expected 1, found 2",
+            "client 2 held: expected 1, found 0",
+            "client 3 is in the ledger but not expected",
+            "client 4 is expected but missing from the ledger",
+        ]
+        .join("\n")
+    );
+}
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn statement_timeline_shows_which_resolve_backfilled_which_withdrawal() {
+    let sut = client_statement(
+        &OsString::from("tests/resources/retroactive_resolve.csv"),
+        key(1),
+        StatementFormat::Timeline,
+        &Config::default(),
+    )
+    .unwrap();
+    let timeline: serde_json::Value = serde_json::from_str(&sut).unwrap();
+    let events = timeline["events"].as_array().unwrap();
+    let interactions: Vec<(u64, &str, u64, serde_json::Value, serde_json::Value)> = events
+        .iter()
+        .map(|e| {
+            (
+                e["row"].as_u64().unwrap(),
+                e["type"].as_str().unwrap(),
+                e["tx"].as_u64().unwrap(),
+                e["queued"].clone(),
+                e["backfilled"].clone(),
+            )
+        })
+        .filter(|(.., queued, backfilled)| {
+            !queued.is_null() || backfilled != &serde_json::json!([])
+        })
+        .collect();
+    assert_eq!(timeline["client"], serde_json::json!(key(1)));
+    assert_eq!(events.len(), 9);
+    assert!(events.iter().all(|e| e["delta_us"].is_u64()));
+    assert_eq!(
+        interactions,
+        vec![
+            (
+                3,
+                "withdrawal",
+                2,
+                serde_json::json!(2),
+                serde_json::json!([])
+            ),
+            (
+                7,
+                "withdrawal",
+                5,
+                serde_json::json!(5),
+                serde_json::json!([])
+            ),
+            (
+                8,
+                "resolve",
+                1,
+                serde_json::Value::Null,
+                serde_json::json!([2])
+            ),
+            (
+                9,
+                "resolve",
+                3,
+                serde_json::Value::Null,
+                serde_json::json!([5])
+            ),
+        ]
+    );
+}
@@ -0,0 +1,46 @@
+#![cfg(feature = "no-history")]
+
+use std::ffi::OsString;
+
+use test_utils::{assert_unsorted_eq, create_csv};
+use toy_payments_lib::{
+    process_payments, process_transactions_detailed, ActivityCounts, Config, RowStatus,
+};
+
+#[test]
+fn dispute_operations_are_ignored_without_history() {
+    let csv_path = OsString::from("tests/resources/upheld_chargeback.csv");
+    let sut = process_payments(&csv_path).unwrap();
+    let expected = create_csv(vec![["1", "50.0000", "0.0000", "50.0000", "false"]]);
+    assert_eq!(sut, expected);
+}
+
+#[test]
+fn deposits_and_withdrawals_apply_without_history() {
+    let csv_path = OsString::from("tests/resources/basic_example.csv");
+    let sut = process_payments(&csv_path).unwrap();
+    let expected = create_csv(vec![
+        ["1", "1.5000", "0.0000", "1.5000", "false"],
+        ["2", "2.0000", "0.0000", "2.0000", "false"],
+    ]);
+    assert_unsorted_eq(&sut, &expected);
+}
+
+#[test]
+fn activity_is_counted_without_history() {
+    let csv_path = OsString::from("tests/resources/upheld_chargeback.csv");
+    let (ledger, outcomes) = process_transactions_detailed(&csv_path, &Config::default()).unwrap();
+    assert_eq!(
+        ledger.0[0].counts,
+        ActivityCounts {
+            deposits: 1,
+            withdrawals: 1,
+            disputes: 0,
+            chargebacks: 0,
+        }
+    );
+    assert!(outcomes
+        .iter()
+        .skip(2)
+        .all(|outcome| matches!(outcome.status, RowStatus::Ignored(_))));
+}
@@ -0,0 +1,40 @@
+#![cfg(feature = "string-client-ids")]
+// The ledger test charges back a deposit, which needs the history the no-history feature does
+// not keep
+#![cfg_attr(feature = "no-history", allow(unused_imports))]
+
+use std::ffi::OsString;
+
+use test_utils::{assert_unsorted_eq, create_csv};
+use toy_payments_lib::{client_statement, process_payments, Config, StatementFormat};
+
+#[cfg(not(feature = "no-history"))]
+#[test]
+fn accounts_are_keyed_by_string_ids() {
+    let csv_path = OsString::from("tests/resources/string_client_ids.csv");
+    let sut = process_payments(&csv_path).unwrap();
+    let expected = create_csv(vec![
+        [
+            "7c9e6679-7425-40de-944b-e07fc1f90ae7",
+            "6.0000",
+            "0.0000",
+            "6.0000",
+            "false",
+        ],
+        ["merchant-42", "0.0000", "0.0000", "0.0000", "true"],
+    ]);
+    assert_unsorted_eq(&sut, &expected);
+}
+
+#[test]
+fn statement_selects_client_by_string_id() {
+    let csv_path = OsString::from("tests/resources/string_client_ids.csv");
+    let sut = client_statement(
+        &csv_path,
+        String::from("merchant-42"),
+        StatementFormat::Csv,
+        &Config::default(),
+    )
+    .unwrap();
+    assert_eq!(sut.lines().count(), 4);
+}